@@ -0,0 +1,104 @@
+use std::{
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::{
+        BufWriter,
+        Write,
+    },
+    path::Path,
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use engine::{
+    Action,
+    PlayerId,
+};
+use players::SearchInfo;
+use proto::GameUid;
+
+/// The number of candidates written for each turn.
+pub const CANDIDATES_PER_RECORD: usize = 5;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CandidateRecord {
+    pub action: String,
+    pub visits: i32,
+    pub expected_value: f64,
+}
+
+/// What the player thought about in a turn.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AnalysisRecord {
+    /// Hex formatted as same as the server's log.
+    pub game_uid: String,
+    pub player_id: String,
+    pub turn: i32,
+    pub action: String,
+    pub time_spent_ms: u128,
+    pub candidates: Vec<CandidateRecord>,
+    pub pv: Vec<String>,
+}
+
+impl AnalysisRecord {
+    pub fn new(
+        game_uid: GameUid,
+        player_id: PlayerId,
+        turn: i32,
+        action: &Action,
+        time_spent: Duration,
+        search_info: Option<SearchInfo>,
+    ) -> Self {
+        let search_info = search_info.unwrap_or_default();
+        AnalysisRecord {
+            game_uid: format!("{:016x}", game_uid),
+            player_id: player_id.to_string(),
+            turn,
+            action: action.to_string(),
+            time_spent_ms: time_spent.as_millis(),
+            candidates: search_info
+                .candidates
+                .iter()
+                .take(CANDIDATES_PER_RECORD)
+                .map(|c| CandidateRecord {
+                    action: c.action.to_string(),
+                    visits: c.visits,
+                    expected_value: c.expected_value,
+                })
+                .collect(),
+            pv: search_info
+                .pv
+                .iter()
+                .map(|(player_id, action)| format!("{}: {}", player_id, action))
+                .collect(),
+        }
+    }
+}
+
+/// Appends an `AnalysisRecord` per turn to a file as newline delimited JSON.
+pub struct AnalysisLog {
+    writer: BufWriter<File>,
+}
+
+impl AnalysisLog {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Couldn't open the analysis log {:?}: {}", path, e))?;
+        Ok(AnalysisLog {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn write(&mut self, record: &AnalysisRecord) -> Result<(), String> {
+        let line = serde_json::to_string(record).map_err(|e| e.to_string())?;
+        writeln!(self.writer, "{}", line)
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| format!("Failed to write the analysis log: {}", e))
+    }
+}
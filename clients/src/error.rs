@@ -0,0 +1,84 @@
+use std::{
+    fmt::Display,
+    io,
+};
+
+use proto::{
+    connection,
+    ErrorCode,
+    TakoyakiResponse,
+};
+
+/// Why a game played by `Client` failed.
+#[derive(Debug)]
+pub enum ClientError {
+    /// Couldn't connect to the server. `source` is the error of the last attempt.
+    Connect { attempts: u32, source: io::Error },
+
+    /// Sending or receiving a message failed, or the analysis log couldn't be written.
+    Io(String),
+
+    /// The server sent something this client can't play with, e.g. a board it can't parse or
+    /// a different number of cards than it expects.
+    Protocol(String),
+
+    /// The server responded with `ErrorResponse`.
+    Server(ErrorCode, String),
+
+    /// The server responded with a message for another request.
+    UnexpectedMessage {
+        expected: &'static str,
+        received: Box<TakoyakiResponse>,
+    },
+
+    /// The player chose an action which can't be taken. See `InvalidActionPolicy`.
+    InvalidAction(String),
+}
+
+impl ClientError {
+    pub(crate) fn from_connection(context: &str, e: connection::Error) -> Self {
+        ClientError::Io(format!("{}: {:?}: {}", context, e.code, e.message))
+    }
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Connect {
+                attempts,
+                source,
+            } => {
+                write!(
+                    f,
+                    "Connection failed after {} attempts: {}",
+                    attempts, source
+                )
+            }
+            ClientError::Io(message) => write!(f, "{}", message),
+            ClientError::Protocol(message) => write!(f, "{}", message),
+            ClientError::Server(code, message) => {
+                write!(f, "The server returned an error: {:?}: {}", code, message)
+            }
+            ClientError::UnexpectedMessage {
+                expected,
+                received,
+            } => write!(
+                f,
+                "Recv unexpected message: Expected {} but: {:?}",
+                expected, received
+            ),
+            ClientError::InvalidAction(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Connect {
+                source, ..
+            } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -1,14 +1,26 @@
+pub mod analysis;
+pub mod error;
+
 use std::{
-    fmt::{
-        Display,
-        Formatter,
-    },
+    future::Future,
+    io,
+    path::PathBuf,
     sync::Arc,
-    time::Duration,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
 use paste::paste;
-use tokio::net::TcpStream;
+use tokio::{
+    net::TcpStream,
+    runtime::Runtime,
+    sync::{
+        oneshot,
+        Mutex,
+    },
+};
 
 use log::*;
 
@@ -19,30 +31,158 @@ use proto::{
 };
 
 use engine::{
+    Action,
     Card,
     Context,
+    GameOutcome,
+    NamedScore,
     PlayerId,
     State,
 };
 
-pub type GamePickerFn = Box<dyn Fn(&[GameInfo]) -> (GameId, Vec<Card>)>;
+use crate::{
+    analysis::{
+        AnalysisLog,
+        AnalysisRecord,
+    },
+    error::ClientError,
+};
+
+pub type GamePickerFn = Box<dyn Fn(&[GameInfo]) -> (GameId, Vec<Card>) + Send>;
+
+/// What the client does when its player chooses an action which can't be taken at the state.
+#[derive(clap::ArgEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InvalidActionPolicy {
+    /// Gives up the game with an error without sending the action.
+    #[default]
+    Error,
+
+    /// Passes with the first card in the hands instead, which is always legal.
+    Fallback,
+}
+
+/// How `Client` retries connecting to the server, e.g. when the server isn't up yet.
+/// Only establishing the connection is retried. Errors in a game are never retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectPolicy {
+    /// The number of connection attempts including the first one.
+    pub max_attempts: u32,
+
+    /// The wait before the second attempt, which is doubled for each attempt after it.
+    pub initial_backoff: Duration,
+
+    /// The cap of the wait between attempts.
+    pub max_backoff: Duration,
+}
 
-pub struct GameResult {
-    pub my_score: u32,
-    pub opponent_score: u32,
+impl Default for ConnectPolicy {
+    /// Gives up at the first failure.
+    fn default() -> Self {
+        ConnectPolicy {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ConnectPolicy {
+    pub fn new(max_attempts: u32, initial_backoff: Duration) -> Self {
+        ConnectPolicy {
+            max_attempts,
+            initial_backoff,
+            ..Default::default()
+        }
+    }
+
+    /// The wait after the `failures`-th failed attempt.
+    fn get_backoff(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Calls `connect` until it succeeds or `max_attempts` attempts fail.
+    /// Returns the error of the last attempt if all of them fail.
+    async fn connect<T, F, Fut>(&self, mut connect: F) -> Result<T, ClientError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = io::Result<T>>,
+    {
+        let max_attempts = self.max_attempts.max(1);
+        let mut failures = 0;
+        loop {
+            let e = match connect().await {
+                Ok(v) => return Ok(v),
+                Err(e) => e,
+            };
+            failures += 1;
+            if failures >= max_attempts {
+                return Err(ClientError::Connect {
+                    attempts: failures,
+                    source: e,
+                });
+            }
+            let backoff = self.get_backoff(failures);
+            warn!(
+                "Connection failed ({}/{}): {}. Retrying in {:?}",
+                failures, max_attempts, e, backoff
+            );
+            tokio::time::sleep(backoff).await;
+        }
+    }
 }
 
-impl Display for GameResult {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "GameResult[")?;
-        match self.my_score.cmp(&self.opponent_score) {
-            std::cmp::Ordering::Less => write!(f, "LOSE")?,
-            std::cmp::Ordering::Equal => write!(f, "DRAW")?,
-            std::cmp::Ordering::Greater => write!(f, "WIN")?,
+/// Decides how long the player can think for each decision under the time control of the game.
+struct TimeBudget {
+    time_control: TimeControl,
+
+    /// Time left on the clock under `TimeControl::Increment`.
+    remaining: Duration,
+}
+
+impl TimeBudget {
+    /// Time kept for the network latency.
+    const BUFFER: Duration = Duration::from_millis(100);
+
+    fn new(time_control: TimeControl) -> Self {
+        let remaining = match time_control {
+            TimeControl::Increment {
+                base_seconds, ..
+            } => Duration::from_secs(base_seconds.into()),
+            _ => Duration::MAX,
+        };
+        TimeBudget {
+            time_control,
+            remaining,
+        }
+    }
+
+    /// Time the player can spend for the next decision when `turns_left` turns are left.
+    /// The clock is shared evenly between the rest of the turns.
+    fn get_time_limit(&self, turns_left: i32) -> Duration {
+        let time_limit = match self.time_control {
+            TimeControl::Infinite => Duration::MAX,
+            TimeControl::PerAction {
+                time_limit_in_seconds,
+            } => Duration::from_secs(time_limit_in_seconds.into()),
+            TimeControl::Increment {
+                ..
+            } => self.remaining / turns_left.max(1) as u32,
         };
-        write!(f, " ({}, {})]", self.my_score, self.opponent_score)?;
+        time_limit.saturating_sub(Self::BUFFER)
+    }
 
-        Ok(())
+    /// Deducts time spent for an action from the clock and adds the increment.
+    fn consume(&mut self, elapsed: Duration) {
+        if let TimeControl::Increment {
+            increment_seconds, ..
+        } = self.time_control
+        {
+            self.remaining = self.remaining.saturating_sub(elapsed)
+                + Duration::from_secs(increment_seconds.into());
+        }
     }
 }
 
@@ -53,11 +193,25 @@ pub struct Client<P: Player> {
     player_id: engine::PlayerId,
     game_picker: GamePickerFn,
     game_info: Option<GameInfo>,
+    analysis_log: Option<PathBuf>,
+    invalid_action_policy: InvalidActionPolicy,
+    connect_policy: ConnectPolicy,
+
+    /// Whether to ask the server for board deltas and verify the board replayed by the client
+    /// with them.
+    verify_board_delta: bool,
+
+    /// The runtime `start` plays games on. It's made at the first `start` rather than in `new`
+    /// since clients playing on the caller's runtime with `start_async` don't need it, and
+    /// dropping a runtime in an asynchronous context panics.
+    runtime: Option<Arc<Runtime>>,
 }
 
 struct Session<'p, P: Player> {
     client: &'p mut Client<P>,
-    connection: Connection,
+
+    /// Shared with the task answering pings while the player is thinking. See `Session::think`.
+    connection: Arc<Mutex<Connection>>,
 }
 
 impl<P: Player> Client<P> {
@@ -74,48 +228,171 @@ impl<P: Player> Client<P> {
             player_id: PlayerId::North,
             game_picker,
             game_info: None,
+            analysis_log: None,
+            invalid_action_policy: InvalidActionPolicy::default(),
+            connect_policy: ConnectPolicy::default(),
+            verify_board_delta: false,
+            runtime: None,
         }
     }
 
-    pub fn start(&mut self, host: &str) -> Result<GameResult, String> {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(async move {
-            let mut session = self.join_game_async(host).await?;
-            let result = session.start().await?;
-            Ok(match self.player_id {
-                PlayerId::South => GameResult {
-                    my_score: result.south_score,
-                    opponent_score: result.north_score,
-                },
-                PlayerId::North => GameResult {
-                    my_score: result.north_score,
-                    opponent_score: result.south_score,
-                },
-            })
-        })
+    /// Appends what the player thought about in each turn to the file.
+    pub fn with_analysis_log(mut self, path: Option<PathBuf>) -> Self {
+        self.analysis_log = path;
+        self
     }
 
-    async fn join_game_async<'p>(&'p mut self, host: &str) -> Result<Session<'p, P>, String> {
-        let stream = TcpStream::connect(host).await;
-        let stream = match stream {
-            Ok(v) => v,
-            Err(e) => {
-                return Err(format!("Connection failed: {}", e));
-            }
-        };
+    pub fn with_invalid_action_policy(mut self, policy: InvalidActionPolicy) -> Self {
+        self.invalid_action_policy = policy;
+        self
+    }
+
+    pub fn with_connect_policy(mut self, policy: ConnectPolicy) -> Self {
+        self.connect_policy = policy;
+        self
+    }
+
+    pub fn with_board_delta_verification(mut self, enabled: bool) -> Self {
+        self.verify_board_delta = enabled;
+        self
+    }
+
+    pub fn get_player_id(&self) -> PlayerId {
+        self.player_id
+    }
+
+    /// Plays a game. Use `GameOutcome::view` with `get_player_id` to see the result from this
+    /// client. The name of the opponent is left empty since the server doesn't tell it.
+    pub fn start(&mut self, host: &str) -> Result<GameOutcome, ClientError> {
+        let rt = Arc::clone(
+            self.runtime
+                .get_or_insert_with(|| Arc::new(Runtime::new().unwrap())),
+        );
+        rt.block_on(self.start_async(host))
+    }
+
+    /// Plays `count` games one after another on the same runtime.
+    /// Stops at the first game which fails.
+    pub fn play_n(&mut self, host: &str, count: usize) -> Result<Vec<GameOutcome>, ClientError> {
+        (0..count).map(|_| self.start(host)).collect()
+    }
+
+    /// Same as `start`, including the empty name of the opponent in the outcome.
+    /// Plays a game on the caller's runtime, which must be a multi-thread one so that pings from
+    /// the server are answered while the player is thinking.
+    /// Useful when multiple clients share a single runtime (e.g. self-match). Thinking blocks the
+    /// task playing the game, so such clients must run in their own tasks rather than being
+    /// joined in one.
+    pub async fn start_async(&mut self, host: &str) -> Result<GameOutcome, ClientError> {
+        let mut session = self.join_game_async(host).await?;
+        session.start().await
+    }
+
+    async fn join_game_async<'p>(&'p mut self, host: &str) -> Result<Session<'p, P>, ClientError> {
+        let stream = self
+            .connect_policy
+            .connect(|| TcpStream::connect(host))
+            .await?;
         Ok(Session {
             client: self,
-            connection: Connection::new(stream),
+            connection: Arc::new(Mutex::new(Connection::new(stream))),
         })
     }
 }
 
+fn check_card_count(name: &str, count: usize, expected: u32) -> Result<(), ClientError> {
+    if count != expected as usize {
+        return Err(ClientError::Protocol(format!(
+            "Unexpected number of cards in the {}: got {} but the server expects {}",
+            name, count, expected
+        )));
+    }
+    Ok(())
+}
+
+/// Checks the player's action against the local state before sending it so that a bug of the
+/// player is caught here rather than by the server.
+fn check_action(
+    state: &State,
+    hands: &[Card],
+    player_id: PlayerId,
+    action: Action,
+    policy: InvalidActionPolicy,
+) -> Result<Action, ClientError> {
+    let card_id = action.get_consumed_card().get_id();
+    if hands.iter().any(|card| card.get_id() == card_id)
+        && engine::is_valid_action(state, player_id, &action)
+    {
+        return Ok(action);
+    }
+    let message = format!(
+        "The player chose an invalid action at turn {}: {}\nHands: {}\nState: {}",
+        state.get_turn(),
+        action,
+        engine::format_cards(hands),
+        state
+    );
+    match policy {
+        InvalidActionPolicy::Error => Err(ClientError::InvalidAction(message)),
+        InvalidActionPolicy::Fallback => {
+            error!("{}", message);
+            let fallback = Action::Pass(hands[0].clone());
+            warn!("Sending a fallback action instead: {}", fallback);
+            Ok(fallback)
+        }
+    }
+}
+
+async fn answer_ping(
+    connection: &mut Connection,
+    ping: Ping,
+) -> Result<(), proto::connection::Error> {
+    connection
+        .send(&TakoyakiRequest::Pong(Pong {
+            id: ping.id,
+        }))
+        .await
+}
+
+/// Answers pings from the server until `stop` is notified. The server sends nothing else while
+/// the client is thinking, so any other message is an error.
+async fn answer_pings(
+    connection: Arc<Mutex<Connection>>,
+    mut stop: oneshot::Receiver<()>,
+) -> Result<(), ClientError> {
+    let mut connection = connection.lock().await;
+    loop {
+        tokio::select! {
+            _ = &mut stop => return Ok(()),
+            response = connection.recv() => match response {
+                Ok(TakoyakiResponse::Ping(ping)) => {
+                    if let Err(e) = answer_ping(&mut connection, ping).await {
+                        return Err(ClientError::from_connection("Send RPC error", e));
+                    }
+                }
+                Ok(TakoyakiResponse::Error(e)) => {
+                    return Err(ClientError::Server(e.code, e.message));
+                }
+                Ok(v) => {
+                    error!("Unexpected message: {:?}", v);
+                    return Err(ClientError::UnexpectedMessage {
+                        expected: "Ping",
+                        received: Box::new(v),
+                    });
+                }
+                Err(e) => return Err(ClientError::from_connection("Recv RPC error", e)),
+            }
+        }
+    }
+}
+
 macro_rules! def_rpc {
     ($root:ty) => {
         paste! {
-            async fn [<send_ $root:snake>](&mut self, req: [<$root Request>]) -> Result<[<$root Response>], String> {
-                if let Err(e) = self.connection.send(&TakoyakiRequest::$root(req)).await {
-                    return Err(format!("Send RPC error: {:?}", e));
+            async fn [<send_ $root:snake>](&mut self, req: [<$root Request>]) -> Result<[<$root Response>], ClientError> {
+                let mut connection = self.connection.lock().await;
+                if let Err(e) = connection.send(&TakoyakiRequest::$root(req)).await {
+                    return Err(ClientError::from_connection("Send RPC error", e));
                 }
 
                 // TODO: Fix me... it's sad to set the format here.
@@ -123,18 +400,32 @@ macro_rules! def_rpc {
                 // We can use our preferred one from next message.
                 // Note that we must set the format before start receiving a next message
                 // since the server will sent next message encoded as preferred one.
-                self.connection.set_preferred_format(self.client.preferred_format);
+                connection.set_preferred_format(self.client.preferred_format);
 
-                let res: [<$root Response>] = match self.connection.recv().await {
-                    Ok(TakoyakiResponse::$root(v)) => v,
+                let res: [<$root Response>] = loop {
+                    match connection.recv().await {
+                    Ok(TakoyakiResponse::$root(v)) => break v,
+                    Ok(TakoyakiResponse::Ping(ping)) => {
+                        // The server checks that we are alive.
+                        if let Err(e) = answer_ping(&mut connection, ping).await {
+                            return Err(ClientError::from_connection("Send RPC error", e));
+                        }
+                    }
+                    Ok(TakoyakiResponse::Error(e)) => {
+                        return Err(ClientError::Server(e.code, e.message));
+                    }
                     Ok(v) => {
                         error!("Unexpected message: {:?}", v);
-                        return Err(format!("Recv unexpected message: Expected {} but: {:?}", stringify!($root), v));
+                        return Err(ClientError::UnexpectedMessage {
+                            expected: stringify!($root),
+                            received: Box::new(v),
+                        });
                     },
                     Err(e) => {
                         error!("Network error: {:?}", e);
-                        return Err(format!("Recv RPC error: {:?}", e));
+                        return Err(ClientError::from_connection("Recv RPC error", e));
                     },
+                    }
                 };
                 Ok(res)
             }
@@ -143,59 +434,112 @@ macro_rules! def_rpc {
 }
 
 impl<'p, P: Player> Session<'p, P> {
-    async fn start(&mut self) -> Result<proto::Scores, String> {
+    async fn start(&mut self) -> Result<GameOutcome, ClientError> {
         let game_list = self.manmenmi().await?;
         let (game_id, deck) = (*self.client.game_picker)(&game_list);
         let game_info: GameInfo = game_list
             .into_iter()
             .find(|g| g.game_id == game_id)
             .unwrap_or_else(|| panic!("Couldn't find a game with id: {}", game_id));
+        if game_info.hand_size as usize != engine::HAND_SIZE {
+            return Err(ClientError::Protocol(format!(
+                "The server deals {} cards in hands but this client is built for {}",
+                game_info.hand_size,
+                engine::HAND_SIZE
+            )));
+        }
+        check_card_count("deck", deck.len(), game_info.deck_size)?;
+        let hand_size = game_info.hand_size;
         let join_game = self
             .send_join_game(JoinGameRequest {
                 game_id,
                 deck: engine::to_ids(&deck),
+                board_delta: self.client.verify_board_delta,
             })
             .await?;
         self.client.player_id = join_game.player_id.into();
         self.client.game_info = Some(game_info);
 
-        let board: engine::Board = self.client.game_info.as_ref().unwrap().board.clone().into();
+        let board: engine::Board = self
+            .client
+            .game_info
+            .as_ref()
+            .unwrap()
+            .board
+            .clone()
+            .try_into()
+            .map_err(|e| ClientError::Protocol(format!("Received an invalid board: {}", e)))?;
 
-        self.client.player.init_game(
-            self.client.player_id.into(),
-            &self.client.context,
-            &board,
-            deck,
-        );
+        let player_id = self.client.player_id;
+        let context = Arc::clone(&self.client.context);
+        self.think(|player| player.init_game(player_id, &context, &board, deck))
+            .await?;
 
-        let time_limit = match self.client.game_info.as_ref().unwrap().time_control {
-            TimeControl::Infinite => Duration::MAX,
-            TimeControl::PerAction {
-                time_limit_in_seconds,
-            } => Duration::from_secs(time_limit_in_seconds.into()),
-        };
-        let time_buffer = Duration::from_millis(100);
-        let time_limit = time_limit.saturating_sub(time_buffer);
+        let time_control = self.client.game_info.as_ref().unwrap().time_control.clone();
+        let mut time_budget = TimeBudget::new(time_control);
 
+        check_card_count("initial hands", join_game.initial_hands.len(), hand_size)?;
         let hands = self.client.context.get_cards(&join_game.initial_hands);
         info!("Initial Hand dealed: {}", engine::format_cards(&hands));
-        let need_redeal = self.client.player.need_redeal_hands(&hands, &time_limit);
+        let time_limit = time_budget.get_time_limit(engine::TURN_COUNT);
+        let need_redeal = self
+            .think(|player| player.need_redeal_hands(&hands, &time_limit))
+            .await?;
         let accept_hands_res = self
             .send_accept_hands(AcceptHandsRequest {
                 accept: !need_redeal,
             })
             .await?;
 
+        check_card_count("accepted hands", accept_hands_res.hands.len(), hand_size)?;
         let mut state = State::new(board, 0, 0, 0, vec![], vec![]);
         let mut hands = self.client.context.get_cards(&accept_hands_res.hands);
 
+        let mut analysis_log = match &self.client.analysis_log {
+            Some(path) => Some(AnalysisLog::open(path).map_err(ClientError::Io)?),
+            None => None,
+        };
+
+        let t_start_game = Instant::now();
         loop {
-            let action = self.client.player.get_action(&state, &hands, &time_limit);
+            let timer = Instant::now();
+            let time_limit = time_budget.get_time_limit(engine::TURN_COUNT - state.get_turn());
+            let action = self
+                .think(|player| player.get_action(&state, &hands, &time_limit))
+                .await?;
+            let action = check_action(
+                &state,
+                &hands,
+                self.client.player_id,
+                action,
+                self.client.invalid_action_policy,
+            )?;
+            if let Some(log) = analysis_log.as_mut() {
+                log.write(&AnalysisRecord::new(
+                    join_game.game_uid,
+                    self.client.player_id,
+                    state.get_turn(),
+                    &action,
+                    timer.elapsed(),
+                    self.client.player.get_last_search_info(),
+                ))
+                .map_err(ClientError::Io)?;
+            }
+            time_budget.consume(timer.elapsed());
             let res = self
                 .send_select_action(SelectActionRequest {
                     action: action.clone().into(),
                 })
                 .await?;
+            if let Some(scores) = res.game_result.as_ref() {
+                if let Some(player_id) = scores.forfeit {
+                    // The opponent may not have taken any action, e.g. it disconnected.
+                    warn!("{} forfeited the game", engine::PlayerId::from(player_id));
+                    return Ok(self
+                        .to_outcome(scores, &state, t_start_game.elapsed())
+                        .with_forfeit(player_id.into()));
+                }
+            }
             let opponent_action = res.opponent_action.convert(&self.client.context);
             hands = self.client.context.get_cards(&res.hands);
 
@@ -204,32 +548,89 @@ impl<'p, P: Player> Session<'p, P> {
                 PlayerId::North => (opponent_action, action),
             };
 
-            engine::update_state(&mut state, &action_s, &action_n);
+            let prev_board = self.client.verify_board_delta.then(|| state.board.clone());
+            if let Err(violation) = engine::update_state(&mut state, &action_s, &action_n) {
+                // The server has ended the game since the action was illegal.
+                warn!("{}", violation);
+                let (south_score, north_score) = state.get_scores();
+                let scores = res.game_result.unwrap_or(proto::Scores {
+                    south_score,
+                    north_score,
+                    forfeit: None,
+                });
+                return Ok(self
+                    .to_outcome(&scores, &state, t_start_game.elapsed())
+                    .with_forfeit(violation.player_id));
+            }
+            if let (Some(delta), Some(prev_board)) = (res.board_delta, prev_board) {
+                let delta = engine::BoardDelta::from(delta);
+                if let Some((position, _)) = delta
+                    .changes
+                    .iter()
+                    .find(|(position, _)| !prev_board.contains(*position))
+                {
+                    return Err(ClientError::Protocol(format!(
+                        "Received a board delta out side of the board: {}",
+                        position
+                    )));
+                }
+                let mut board = prev_board;
+                board.apply_delta(&delta);
+                if board != state.board {
+                    return Err(ClientError::Protocol(format!(
+                        "The board replayed by the client differs from the server's one.\nClient: {}\nServer: {}",
+                        state.board, board
+                    )));
+                }
+            }
             info!("State updated: {}", state);
             info!("Act-South: {}", action_s);
             info!("Act-North: {}", action_n);
             info!("Player ID: {:?}", self.client.player_id);
 
             if let Some(result) = res.game_result {
-                return Ok(result);
+                return Ok(self.to_outcome(&result, &state, t_start_game.elapsed()));
             }
         }
     }
 
-    async fn manmenmi(&mut self) -> Result<Vec<GameInfo>, String> {
-        let res = self
+    /// Runs `think` with the player while another task answers pings from the server, which
+    /// forfeits clients that stop answering even while their players are thinking.
+    async fn think<T>(&mut self, think: impl FnOnce(&mut P) -> T) -> Result<T, ClientError> {
+        let (stop, stopped) = oneshot::channel();
+        let pinger = tokio::spawn(answer_pings(Arc::clone(&self.connection), stopped));
+        let result = tokio::task::block_in_place(|| think(&mut self.client.player));
+        // The receiver is gone if the task has already failed.
+        let _ = stop.send(());
+        pinger.await.expect("The task answering pings panicked")?;
+        Ok(result)
+    }
+
+    fn to_outcome(&self, scores: &proto::Scores, state: &State, duration: Duration) -> GameOutcome {
+        let me = self.client.player.get_name();
+        // Neither `GameInfo` nor `JoinGameResponse` carries the opponent's name, so it's left
+        // empty rather than guessed.
+        let (south_name, north_name) = match self.client.player_id {
+            PlayerId::South => (me, ""),
+            PlayerId::North => ("", me),
+        };
+        GameOutcome::new(
+            NamedScore::new(south_name, scores.south_score),
+            NamedScore::new(north_name, scores.north_score),
+            state.get_turn() as u32,
+        )
+        .with_duration(duration)
+    }
+
+    async fn manmenmi(&mut self) -> Result<Vec<GameInfo>, ClientError> {
+        let res: ManmenmiResponse = self
             .send_manmenmi(ManmenmiRequest {
                 name: self.client.player.get_name().into(),
                 preferred_format: self.client.preferred_format,
+                protocol_version: CURRENT_PROTOCOL_VERSION,
             })
-            .await;
-
-        let res: ManmenmiResponse = match res {
-            Ok(v) => v,
-            Err(e) => {
-                return Err(format!("Got error at Manmenmi: {}", e));
-            }
-        };
+            .await?;
+        debug!("Protocol version: {}", res.protocol_version);
         Ok(res.available_games)
     }
 
@@ -239,3 +640,615 @@ impl<'p, P: Player> Session<'p, P> {
     def_rpc!(AcceptHands);
     def_rpc!(SelectAction);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use players::{
+        mcts::{
+            self,
+            MctsPlayer,
+        },
+        random::RandomPlayer,
+    };
+    use proto::connection::Connection;
+    use rand_mt::Mt64;
+    use server::{
+        lobby::Lobby,
+        session::{
+            self,
+            GameSession,
+        },
+    };
+    use tokio::{
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    use super::*;
+
+    fn data_path(path: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../data")
+            .join(path)
+    }
+
+    /// Accepts two clients and plays a game between them.
+    /// The clients are pinged every `keepalive_interval` if given.
+    async fn serve_one_game(
+        listener: &TcpListener,
+        context: Context,
+        board: engine::Board,
+        keepalive_interval: Option<Duration>,
+    ) {
+        let (sender, mut receiver) = mpsc::channel(2);
+        let lobby = Arc::new(
+            Lobby::new(Arc::new(context), vec![board], TimeControl::Infinite)
+                .with_keepalive_interval(keepalive_interval),
+        );
+        for seed in 0..2 {
+            let (stream, _) = listener.accept().await.unwrap();
+            session::try_establish_connection(stream, lobby.clone(), sender.clone(), seed).await;
+        }
+        let south = receiver.recv().await.unwrap();
+        let north = receiver.recv().await.unwrap();
+        GameSession::new(
+            lobby.get_context().clone(),
+            lobby.get_board(0).unwrap().clone(),
+            TimeControl::Infinite,
+            south,
+            north,
+            Mt64::new(42),
+        )
+        .start()
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_analysis_log_per_turn() {
+        let context = Context {
+            all_cards: engine::load_cards(data_path("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        };
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        let log_path =
+            std::env::temp_dir().join(format!("takoyaki-analysis-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&log_path);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let server_context = context.clone();
+        let server = tokio::spawn(async move {
+            serve_one_game(&listener, server_context, board, None).await
+        });
+
+        let picker = |deck: Vec<Card>| -> GamePickerFn {
+            Box::new(move |games: &[GameInfo]| (games[0].game_id, deck.clone()))
+        };
+        let mut mcts_client = Client::new(
+            context.clone(),
+            WireFormat::Json,
+            MctsPlayer::new("mcts".into(), 1, 10, mcts::UCT_CONST_DEFAULT),
+            picker(deck.clone()),
+        )
+        .with_analysis_log(Some(log_path.clone()));
+        let mut random_client = Client::new(
+            context,
+            WireFormat::Json,
+            RandomPlayer::new("rand".into(), 2),
+            picker(deck),
+        )
+        .with_board_delta_verification(true);
+        let (mcts_result, random_result) = tokio::join!(
+            mcts_client.start_async(&host),
+            random_client.start_async(&host)
+        );
+        let mcts_outcome = mcts_result.unwrap();
+        let random_outcome = random_result.unwrap();
+        server.await.unwrap();
+
+        // Both clients see the same game from their own side.
+        let mcts_id = mcts_client.get_player_id();
+        assert_eq!(mcts_id.another(), random_client.get_player_id());
+        assert_eq!("mcts", mcts_outcome.get_score(mcts_id).name);
+        assert_eq!(
+            mcts_outcome.get_score(PlayerId::South).score,
+            random_outcome.get_score(PlayerId::South).score
+        );
+        assert_eq!(
+            mcts_outcome.get_score(PlayerId::North).score,
+            random_outcome.get_score(PlayerId::North).score
+        );
+        assert_eq!(engine::TURN_COUNT as u32, mcts_outcome.turns_played);
+        assert!(mcts_outcome.duration.is_some());
+
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        std::fs::remove_file(&log_path).unwrap();
+        let records: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(engine::TURN_COUNT as usize, records.len());
+        for (turn, record) in records.iter().enumerate() {
+            assert_eq!(turn as u64, record["turn"]);
+            assert_eq!(records[0]["game_uid"], record["game_uid"]);
+            assert!(!record["candidates"].as_array().unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_retry() {
+        let policy = ConnectPolicy::new(3, Duration::from_millis(1));
+        // Refuses the first two attempts.
+        let mut attempts = 0;
+        let result = policy
+            .connect(|| {
+                attempts += 1;
+                let result = match attempts {
+                    1 | 2 => Err(io::Error::from(io::ErrorKind::ConnectionRefused)),
+                    _ => Ok(attempts),
+                };
+                async move { result }
+            })
+            .await;
+        assert_eq!(3, result.unwrap());
+
+        // Gives up with the last error.
+        let mut attempts = 0;
+        let result: Result<(), ClientError> = policy
+            .connect(|| {
+                attempts += 1;
+                let kind = if attempts < 3 {
+                    io::ErrorKind::ConnectionRefused
+                } else {
+                    io::ErrorKind::TimedOut
+                };
+                async move { Err(io::Error::from(kind)) }
+            })
+            .await;
+        assert_eq!(3, attempts);
+        match result.unwrap_err() {
+            ClientError::Connect {
+                attempts,
+                source,
+            } => {
+                assert_eq!(3, attempts);
+                assert_eq!(io::ErrorKind::TimedOut, source.kind());
+            }
+            e => panic!("Unexpected error: {}", e),
+        }
+
+        let policy = ConnectPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+        };
+        assert_eq!(Duration::from_millis(100), policy.get_backoff(1));
+        assert_eq!(Duration::from_millis(200), policy.get_backoff(2));
+        assert_eq!(Duration::from_millis(400), policy.get_backoff(3));
+        assert_eq!(Duration::from_millis(500), policy.get_backoff(4));
+        assert_eq!(Duration::from_millis(500), policy.get_backoff(40));
+    }
+
+    #[test]
+    fn test_sequential_games_share_runtime() {
+        let context = Context {
+            all_cards: engine::load_cards(data_path("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        };
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        let picker = |deck: Vec<Card>| -> GamePickerFn {
+            Box::new(move |games: &[GameInfo]| (games[0].game_id, deck.clone()))
+        };
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        // The server and the opponent run on their own runtime in another thread.
+        let server = std::thread::spawn({
+            let context = context.clone();
+            let host = host.clone();
+            let deck = deck.clone();
+            move || {
+                let mut opponent = Client::new(
+                    context.clone(),
+                    WireFormat::Json,
+                    RandomPlayer::new("opponent".into(), 2),
+                    picker(deck),
+                );
+                Runtime::new().unwrap().block_on(async move {
+                    let listener = TcpListener::from_std(listener).unwrap();
+                    for _ in 0..2 {
+                        let (_, result) = tokio::join!(
+                            serve_one_game(&listener, context.clone(), board.clone(), None),
+                            opponent.start_async(&host)
+                        );
+                        result.unwrap();
+                    }
+                });
+            }
+        });
+
+        let mut client = Client::new(
+            context,
+            WireFormat::Json,
+            RandomPlayer::new("rand".into(), 1),
+            picker(deck),
+        );
+        let first = client.start(&host).unwrap();
+        let runtime = Arc::clone(client.runtime.as_ref().unwrap());
+        let rest = client.play_n(&host, 1).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(1, rest.len());
+        for outcome in [&first, &rest[0]] {
+            assert_eq!(engine::TURN_COUNT as u32, outcome.turns_played);
+        }
+        assert!(Arc::ptr_eq(&runtime, client.runtime.as_ref().unwrap()));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_deck_size_mismatch_at_join() {
+        let context = Context {
+            all_cards: engine::load_cards(data_path("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        };
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        // A server which requires one more card than the client's deck.
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut conn = Connection::new(stream);
+            let _: TakoyakiRequest = conn.recv().await.unwrap();
+            conn.send(&TakoyakiResponse::Manmenmi(ManmenmiResponse {
+                available_games: vec![GameInfo {
+                    game_id: 0,
+                    time_control: TimeControl::Infinite,
+                    board: proto::Board::from(&board),
+                    hand_size: engine::HAND_SIZE as u32,
+                    deck_size: engine::DECK_SIZE as u32 + 1,
+                }],
+                protocol_version: CURRENT_PROTOCOL_VERSION,
+            }))
+            .await
+            .unwrap();
+            // The client must give up before sending JoinGame.
+            conn.recv::<TakoyakiRequest>().await.is_err()
+        });
+
+        let mut client = Client::new(
+            context,
+            WireFormat::Json,
+            RandomPlayer::new("rand".into(), 1),
+            Box::new(move |games: &[GameInfo]| (games[0].game_id, deck.clone())),
+        );
+        let err = client.start_async(&host).await.err().unwrap();
+
+        assert!(matches!(err, ClientError::Protocol(_)), "{}", err);
+        assert!(err.to_string().contains("deck"), "{}", err);
+        assert!(server.await.unwrap());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_server_error_response() {
+        let context = Context {
+            all_cards: engine::load_cards(data_path("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        };
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        // A server which doesn't support the client's protocol version.
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut conn = Connection::new(stream);
+            let _: TakoyakiRequest = conn.recv().await.unwrap();
+            conn.send(&TakoyakiResponse::Error(ErrorResponse {
+                code: ErrorCode::UnsupportedVersion,
+                message: "Too new".into(),
+            }))
+            .await
+            .unwrap();
+        });
+
+        let mut client = Client::new(
+            context,
+            WireFormat::Json,
+            RandomPlayer::new("rand".into(), 1),
+            Box::new(move |games: &[GameInfo]| (games[0].game_id, deck.clone())),
+        );
+        match client.start_async(&host).await {
+            Err(ClientError::Server(code, message)) => {
+                assert_eq!(ErrorCode::UnsupportedVersion, code);
+                assert_eq!("Too new", message);
+            }
+            result => panic!("Unexpected result: {:?}", result.map(|_| ())),
+        }
+        server.await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_board_delta_out_of_board() {
+        let context = Context {
+            all_cards: engine::load_cards(data_path("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        };
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        let deck_ids = engine::to_ids(&deck);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        // A server which sends a delta for a cell out side of the board in the first turn.
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut conn = Connection::new(stream);
+            let _: TakoyakiRequest = conn.recv().await.unwrap();
+            conn.send(&TakoyakiResponse::Manmenmi(ManmenmiResponse {
+                available_games: vec![GameInfo {
+                    game_id: 0,
+                    time_control: TimeControl::Infinite,
+                    board: proto::Board::from(&board),
+                    hand_size: engine::HAND_SIZE as u32,
+                    deck_size: engine::DECK_SIZE as u32,
+                }],
+                protocol_version: CURRENT_PROTOCOL_VERSION,
+            }))
+            .await
+            .unwrap();
+            match conn.recv().await.unwrap() {
+                TakoyakiRequest::JoinGame(req) => assert!(req.board_delta),
+                req => panic!("Unexpected request: {:?}", req),
+            }
+            let hands = deck_ids[..engine::HAND_SIZE].to_vec();
+            conn.send(&TakoyakiResponse::JoinGame(JoinGameResponse {
+                player_id: proto::PlayerId::South,
+                initial_hands: hands.clone(),
+                game_uid: 0,
+            }))
+            .await
+            .unwrap();
+            let _: TakoyakiRequest = conn.recv().await.unwrap();
+            conn.send(&TakoyakiResponse::AcceptHands(AcceptHandsResponse {
+                hands: hands.clone(),
+            }))
+            .await
+            .unwrap();
+            let _: TakoyakiRequest = conn.recv().await.unwrap();
+            conn.send(&TakoyakiResponse::SelectAction(SelectActionResponse {
+                opponent_action: proto::Action::Pass(hands[0]),
+                hands,
+                game_result: None,
+                board_delta: Some(proto::BoardDelta {
+                    changes: vec![proto::BoardCellChange {
+                        x: 100,
+                        y: 100,
+                        cell: proto::BoardCell::InkNorth,
+                    }],
+                }),
+            }))
+            .await
+            .unwrap();
+        });
+
+        let mut client = Client::new(
+            context,
+            WireFormat::Json,
+            RandomPlayer::new("rand".into(), 1),
+            Box::new(move |games: &[GameInfo]| (games[0].game_id, deck.clone())),
+        )
+        .with_board_delta_verification(true);
+        let err = client.start_async(&host).await.err().unwrap();
+
+        assert!(matches!(err, ClientError::Protocol(_)), "{}", err);
+        assert!(err.to_string().contains("out side of the board"), "{}", err);
+        server.await.unwrap();
+    }
+
+    /// A player which thinks for `delay` in its first turn.
+    struct SlowPlayer {
+        player: RandomPlayer,
+        delay: Option<Duration>,
+    }
+
+    impl Player for SlowPlayer {
+        fn get_name(&self) -> &str {
+            self.player.get_name()
+        }
+
+        fn init_game(
+            &mut self,
+            player_id: PlayerId,
+            context: &Arc<Context>,
+            board: &engine::Board,
+            deck: Vec<Card>,
+        ) {
+            self.player.init_game(player_id, context, board, deck);
+        }
+
+        fn need_redeal_hands(&mut self, dealed_cards: &[Card], time_limit: &Duration) -> bool {
+            self.player.need_redeal_hands(dealed_cards, time_limit)
+        }
+
+        fn get_action(&mut self, state: &State, hands: &[Card], time_limit: &Duration) -> Action {
+            if let Some(delay) = self.delay.take() {
+                std::thread::sleep(delay);
+            }
+            self.player.get_action(state, hands, time_limit)
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_answer_pings_while_thinking() {
+        let context = Context {
+            all_cards: engine::load_cards(data_path("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        };
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        let interval = Duration::from_millis(200);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let server_context = context.clone();
+        let server = tokio::spawn(async move {
+            serve_one_game(&listener, server_context, board, Some(interval)).await
+        });
+
+        let picker = |deck: Vec<Card>| -> GamePickerFn {
+            Box::new(move |games: &[GameInfo]| (games[0].game_id, deck.clone()))
+        };
+        // Both think through several pings at the same time, which the server forfeits if nobody
+        // answers them. Each client runs in its own task since thinking blocks the task.
+        let clients: Vec<_> = (1..=2)
+            .map(|seed| {
+                let mut client = Client::new(
+                    context.clone(),
+                    WireFormat::Json,
+                    SlowPlayer {
+                        player: RandomPlayer::new(format!("slow{}", seed), seed),
+                        delay: Some(interval * 3),
+                    },
+                    picker(deck.clone()),
+                );
+                let host = host.clone();
+                tokio::spawn(async move { client.start_async(&host).await })
+            })
+            .collect();
+        server.await.unwrap();
+
+        for client in clients {
+            let outcome = client.await.unwrap().unwrap();
+            assert_eq!(None, outcome.forfeit);
+            assert_eq!(engine::TURN_COUNT as u32, outcome.turns_played);
+        }
+    }
+
+    /// A player which always tries to put a card on the wall.
+    struct IllegalPlayer;
+
+    impl Player for IllegalPlayer {
+        fn get_name(&self) -> &str {
+            "illegal"
+        }
+
+        fn init_game(&mut self, _: PlayerId, _: &Arc<Context>, _: &engine::Board, _: Vec<Card>) {}
+
+        fn need_redeal_hands(&mut self, _: &[Card], _: &Duration) -> bool {
+            false
+        }
+
+        fn get_action(&mut self, _: &State, hands: &[Card], _: &Duration) -> Action {
+            Action::Put(
+                hands[0].clone(),
+                engine::CardPosition {
+                    x: 0,
+                    y: 0,
+                    rotation: engine::Rotation::Up,
+                },
+            )
+        }
+    }
+
+    /// Plays as the server until the client sends its first action, which is returned.
+    async fn serve_until_first_action(
+        listener: TcpListener,
+        board: engine::Board,
+        hands: Vec<u32>,
+    ) -> Option<proto::Action> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut conn = Connection::new(stream);
+        let _: TakoyakiRequest = conn.recv().await.unwrap();
+        conn.send(&TakoyakiResponse::Manmenmi(ManmenmiResponse {
+            available_games: vec![GameInfo {
+                game_id: 0,
+                time_control: TimeControl::Infinite,
+                board: proto::Board::from(&board),
+                hand_size: engine::HAND_SIZE as u32,
+                deck_size: engine::DECK_SIZE as u32,
+            }],
+            protocol_version: CURRENT_PROTOCOL_VERSION,
+        }))
+        .await
+        .unwrap();
+        let _: TakoyakiRequest = conn.recv().await.unwrap();
+        conn.send(&TakoyakiResponse::JoinGame(JoinGameResponse {
+            player_id: proto::PlayerId::South,
+            initial_hands: hands.clone(),
+            game_uid: 0,
+        }))
+        .await
+        .unwrap();
+        let _: TakoyakiRequest = conn.recv().await.unwrap();
+        conn.send(&TakoyakiResponse::AcceptHands(AcceptHandsResponse {
+            hands,
+        }))
+        .await
+        .unwrap();
+        match conn.recv().await {
+            Ok(TakoyakiRequest::SelectAction(req)) => Some(req.action),
+            Ok(v) => panic!("Unexpected request: {:?}", v),
+            Err(_) => None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_invalid_action_caught_before_send() {
+        let context = Context {
+            all_cards: engine::load_cards(data_path("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        };
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck_ids = engine::load_deck(&data_path("decks/starter"));
+        let hands = deck_ids[0..engine::HAND_SIZE].to_vec();
+
+        for policy in [InvalidActionPolicy::Error, InvalidActionPolicy::Fallback] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let host = listener.local_addr().unwrap().to_string();
+            let server = tokio::spawn(serve_until_first_action(
+                listener,
+                board.clone(),
+                hands.clone(),
+            ));
+
+            let deck = context.get_cards(&deck_ids);
+            let mut client = Client::new(
+                context.clone(),
+                WireFormat::Json,
+                IllegalPlayer,
+                Box::new(move |games: &[GameInfo]| (games[0].game_id, deck.clone())),
+            )
+            .with_invalid_action_policy(policy);
+            let result = client.start_async(&host).await;
+
+            match policy {
+                InvalidActionPolicy::Error => {
+                    let err = result.err().unwrap();
+                    assert!(matches!(err, ClientError::InvalidAction(_)), "{}", err);
+                    assert!(err.to_string().contains("invalid action"), "{}", err);
+                    // Nothing is sent to the server.
+                    assert_eq!(None, server.await.unwrap());
+                }
+                InvalidActionPolicy::Fallback => {
+                    // The fake server leaves after the first action.
+                    assert!(result.is_err());
+                    assert_eq!(Some(proto::Action::Pass(hands[0])), server.await.unwrap());
+                }
+            }
+        }
+    }
+}
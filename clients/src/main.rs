@@ -1,4 +1,7 @@
-use std::path::PathBuf;
+use std::{
+    path::PathBuf,
+    time::Duration,
+};
 
 use clap::{
     Args,
@@ -10,33 +13,59 @@ use git_version::git_version;
 use log::{
     error,
     info,
+    warn,
 };
 
 use clients::{
+    error::ClientError,
     Client,
-    GameResult,
+    ConnectPolicy,
+    InvalidActionPolicy,
 };
 use engine::{
     Card,
     Context,
+    DataDirArgs,
+    GameData,
+    GameOutcome,
+    PlayerId,
+    RunConfig,
 };
 use players::{
-    mcts::MctsPlayer,
+    greedy::GreedyPlayer,
+    mcts::{
+        self,
+        MctsPlayer,
+        NodeBudget,
+        RewardKind,
+    },
+    minimax::MinimaxPlayer,
     random::RandomPlayer,
+    Player,
+    PlayerType,
 };
 use proto::{
+    ErrorCode,
     GameInfo,
     WireFormat,
 };
+use rand_mt::Mt64;
 
 const GIT_VERSION: &str = git_version!();
 
+// Use fixed seeds for reproducible results.
+const PLAYER_SEED: u64 = 42;
+const SELF_MATCH_SEED: u64 = 0x42;
+
 #[derive(Parser)]
 pub struct ClientArgs {
     /// a directory path where holds all card data. no need to specify for many cases.
     #[clap(long, value_parser, default_value = "data/cards")]
     pub card_dir: String,
 
+    #[clap(flatten)]
+    pub data: DataDirArgs,
+
     /// A file path which is a list of cards the player use for the game.
     #[clap(
         short,
@@ -50,6 +79,32 @@ pub struct ClientArgs {
     #[clap(long, short, value_parser, default_value = "localhost:3333")]
     pub server: String,
 
+    /// Append what the player thought about in each turn (the chosen action, top candidates,
+    /// the principal variation and time spent) to the file as newline delimited JSON.
+    /// `self-match` writes the logs of both sides to the path suffixed with `.player` and
+    /// `.opponent` respectively.
+    #[clap(long, value_parser, value_hint=ValueHint::FilePath)]
+    pub analysis_log: Option<PathBuf>,
+
+    /// What to do when the player chooses an action which can't be taken.
+    /// the action is checked before being sent so the server never sees it.
+    #[clap(long, value_parser, arg_enum, default_value_t = InvalidActionPolicy::Error)]
+    pub invalid_action_policy: InvalidActionPolicy,
+
+    /// The number of attempts to connect to the server, e.g. to wait for a server starting up.
+    /// Only connecting is retried. Errors in a game are not.
+    #[clap(long, value_parser, default_value_t = 1)]
+    pub connect_attempts: u32,
+
+    /// The wait before retrying to connect in milliseconds, which is doubled for each retry.
+    #[clap(long, value_parser, default_value_t = 500)]
+    pub connect_backoff_ms: u64,
+
+    /// Ask the server for the cells changed in each turn and check that the board replayed by
+    /// the client matches them.
+    #[clap(long, value_parser, default_value_t = false)]
+    pub verify_board_delta: bool,
+
     #[clap(subcommand)]
     command: Commands,
 }
@@ -59,8 +114,25 @@ enum Commands {
     /// Run a player choose a random action
     Rand,
 
+    /// Run a player choose an action which inks the most cells in the turn
+    Greedy,
+
     /// Run Monte Carlo Tree Search client
     Mcts(MctsArgs),
+
+    /// Run a player which searches a few turns ahead assuming the opponent's best responses
+    Minimax(MinimaxArgs),
+
+    /// Open two connections and let two players play against each other through the server.
+    /// Useful for smoke-testing the whole network stack.
+    SelfMatch(SelfMatchArgs),
+}
+
+#[derive(Args)]
+struct MinimaxArgs {
+    /// The number of turns searched ahead.
+    #[clap(long, short, value_parser, default_value_t = 1)]
+    depth: usize,
 }
 
 #[derive(Args)]
@@ -68,17 +140,89 @@ struct MctsArgs {
     #[clap(long, short, value_parser)]
     iterations: usize,
 
-    #[clap(long, short = 'C', value_parser, default_value_t = 0.9)]
+    /// The number of determinizations sampled for each search. Defaults to `--iterations`.
+    #[clap(long, value_parser)]
+    determinizations: Option<usize>,
+
+    /// The number of iterations run for each determinization.
+    #[clap(long, value_parser, default_value_t = 1)]
+    iterations_per_det: usize,
+
+    /// The exploration constant of UCT. A bigger value explores less visited actions more.
+    #[clap(
+        long = "uct",
+        short = 'C',
+        alias = "mcts-const",
+        value_parser,
+        default_value_t = mcts::UCT_CONST_DEFAULT
+    )]
     mcts_const: f64,
+
+    /// The maximum number of redealt hands sampled when deciding whether to redeal the
+    /// initial hands.
+    #[clap(long, value_parser, default_value_t = mcts::REDEAL_SAMPLES_DEFAULT)]
+    redeal_samples: usize,
+
+    /// What the search maximizes: the score difference or the win rate.
+    #[clap(long, value_parser, arg_enum, default_value_t = RewardKind::Margin)]
+    reward: RewardKind,
+
+    /// Log how much time is spent in each phase of the search for every action.
+    #[clap(long, value_parser, default_value_t = false)]
+    profile: bool,
+
+    /// Log the search tree of every action as JSON for debugging. Slows down searches.
+    #[clap(long, value_parser, default_value_t = false)]
+    export_tree: bool,
+
+    /// Keep the search tree between turns and continue searching from the subtree reached by
+    /// the actions actually played.
+    #[clap(long, value_parser, default_value_t = false)]
+    reuse_tree: bool,
+
+    /// Share statistics between nodes which reach the same board by different orders of actions.
+    #[clap(long, value_parser, default_value_t = false)]
+    transpositions: bool,
+
+    /// Search this many independent trees in parallel and merge their visit counts.
+    /// Each tree runs `--iterations` iterations.
+    #[clap(long, value_parser, default_value_t = 1)]
+    mcts_threads: usize,
+
+    /// The maximum number of tree nodes expanded by the search in total, shared by all
+    /// `--mcts-threads` trees. Leaves are played out without being expanded once it's exhausted.
+    #[clap(long, value_parser)]
+    node_budget: Option<usize>,
+}
+
+#[derive(Args)]
+struct SelfMatchArgs {
+    #[clap(long, value_parser, default_value = "random")]
+    player: PlayerType,
+
+    #[clap(long, value_parser, default_value = "random")]
+    opponent: PlayerType,
 }
 
 pub fn init_common(args: &ClientArgs) -> (Context, Vec<Card>) {
-    let all_cards = engine::load_cards(&args.card_dir);
-    let context = Context {
-        all_cards,
-        enabled_step_execution: false,
+    let data: Option<GameData> = args.data.load_or_exit();
+    let (context, deck_ids) = match data {
+        Some(data) => {
+            let deck_ids = data.resolve_deck(&args.deck_path);
+            (data.context, deck_ids)
+        }
+        None => (
+            Context {
+                all_cards: engine::load_cards(&args.card_dir).unwrap_or_else(|e| {
+                    error!("Failed to load cards: {}", e);
+                    std::process::exit(1);
+                }),
+                enabled_step_execution: false,
+                rules: engine::RuleSet::default(),
+            },
+            engine::load_deck(&args.deck_path),
+        ),
     };
-    let deck_ids = engine::load_deck(&args.deck_path);
     let deck: Vec<Card> = context.get_cards(&deck_ids);
 
     (context, deck)
@@ -101,15 +245,42 @@ fn main() {
         .into_string()
         .unwrap();
     let (context, deck) = init_common(&args);
+    info!("Config: {}", run_config(&args));
+    let options = ClientOptions {
+        analysis_log: args.analysis_log,
+        invalid_action_policy: args.invalid_action_policy,
+        connect_policy: ConnectPolicy::new(
+            args.connect_attempts,
+            Duration::from_millis(args.connect_backoff_ms),
+        ),
+        verify_board_delta: args.verify_board_delta,
+    };
     match args.command {
         Commands::Rand => run_rand(
             &args.server,
+            options,
             context,
             format!("rand/{}@{}", deck_name, GIT_VERSION),
             deck,
         ),
+        Commands::Greedy => run_greedy(
+            &args.server,
+            options,
+            context,
+            format!("greedy/{}@{}", deck_name, GIT_VERSION),
+            deck,
+        ),
+        Commands::Minimax(m) => run_minimax(
+            &args.server,
+            options,
+            context,
+            format!("minimax-{}/{}@{}", m.depth, deck_name, GIT_VERSION),
+            deck,
+            m.depth,
+        ),
         Commands::Mcts(m) => run_mcts(
             &args.server,
+            options,
             context,
             format!(
                 "mcts-{}-C={}/{}@{}",
@@ -118,45 +289,285 @@ fn main() {
             deck,
             m,
         ),
+        Commands::SelfMatch(m) => {
+            if !run_self_match(&args.server, options, context, deck, m) {
+                std::process::exit(1);
+            }
+        }
+    };
+}
+
+fn run_config(args: &ClientArgs) -> RunConfig {
+    let seed = match args.command {
+        Commands::SelfMatch(_) => SELF_MATCH_SEED,
+        _ => PLAYER_SEED,
     };
+    let mut config = RunConfig::new("clients", seed)
+        .with_deck(args.deck_path.display())
+        .with_param("server", &args.server)
+        .with_param("git_version", GIT_VERSION)
+        .with_param(
+            "invalid_action_policy",
+            format!("{:?}", args.invalid_action_policy),
+        )
+        .with_param("connect_attempts", args.connect_attempts)
+        .with_param("connect_backoff_ms", args.connect_backoff_ms)
+        .with_param("verify_board_delta", args.verify_board_delta);
+    if let Some(data_dir) = &args.data.data_dir {
+        config = config.with_param("data_dir", data_dir.display());
+    }
+    if let Some(analysis_log) = &args.analysis_log {
+        config = config.with_param("analysis_log", analysis_log.display());
+    }
+    match &args.command {
+        Commands::Rand => config.with_player("rand"),
+        Commands::Greedy => config.with_player("greedy"),
+        Commands::Minimax(m) => config.with_player("minimax").with_param("depth", m.depth),
+        Commands::Mcts(m) => config
+            .with_player("mcts")
+            .with_param("iterations", m.iterations)
+            .with_param(
+                "determinizations",
+                m.determinizations.unwrap_or(m.iterations),
+            )
+            .with_param("iterations_per_det", m.iterations_per_det)
+            .with_param("mcts_const", m.mcts_const)
+            .with_param("redeal_samples", m.redeal_samples)
+            .with_param("reward", format!("{:?}", m.reward))
+            .with_param("mcts_threads", m.mcts_threads)
+            .with_param(
+                "node_budget",
+                m.node_budget
+                    .map_or("unlimited".to_string(), |cap| cap.to_string()),
+            ),
+        Commands::SelfMatch(m) => config.with_player(&m.player).with_player(&m.opponent),
+    }
 }
 
-fn handle_result(game_result: Result<GameResult, String>) {
+/// Options shared by all kinds of clients.
+#[derive(Clone)]
+struct ClientOptions {
+    analysis_log: Option<PathBuf>,
+    invalid_action_policy: InvalidActionPolicy,
+    connect_policy: ConnectPolicy,
+    verify_board_delta: bool,
+}
+
+impl ClientOptions {
+    /// Appends `suffix` to the file name of the analysis log so that clients running together
+    /// don't write to the same file.
+    fn with_analysis_log_suffix(mut self, suffix: &str) -> Self {
+        self.analysis_log = self.analysis_log.map(|path| {
+            let mut path = path.into_os_string();
+            path.push(suffix);
+            PathBuf::from(path)
+        });
+        self
+    }
+
+    fn apply<P: Player>(self, client: Client<P>) -> Client<P> {
+        client
+            .with_analysis_log(self.analysis_log)
+            .with_invalid_action_policy(self.invalid_action_policy)
+            .with_connect_policy(self.connect_policy)
+            .with_board_delta_verification(self.verify_board_delta)
+    }
+}
+
+fn handle_result(player_id: PlayerId, game_result: Result<GameOutcome, ClientError>) {
     match game_result {
+        Err(ClientError::Server(ErrorCode::ServerBusy, message)) => {
+            warn!("The server is busy. Try again later: {}", message);
+        }
         Err(e) => {
             error!("me: {}", e);
         }
-        Ok(result) => {
-            info!("{}", result);
+        Ok(outcome) => {
+            info!("{}", outcome.view(player_id));
         }
     };
 }
 
-fn run_rand(server: &str, context: Context, name: String, deck: Vec<Card>) {
-    let mut client: Client<RandomPlayer> = Client::new(
+fn run_rand(server: &str, options: ClientOptions, context: Context, name: String, deck: Vec<Card>) {
+    let client: Client<RandomPlayer> = Client::new(
+        context,
+        WireFormat::Flexbuffers,
+        RandomPlayer::new(name, PLAYER_SEED),
+        Box::new(move |games: &[GameInfo]| {
+            let game_id = games[0].game_id;
+            (game_id, deck.to_vec())
+        }),
+    );
+    let mut client = options.apply(client);
+
+    let result = client.start(server);
+    handle_result(client.get_player_id(), result);
+}
+
+fn run_greedy(
+    server: &str,
+    options: ClientOptions,
+    context: Context,
+    name: String,
+    deck: Vec<Card>,
+) {
+    let client: Client<GreedyPlayer> = Client::new(
         context,
         WireFormat::Flexbuffers,
-        RandomPlayer::new(name, 42),
+        GreedyPlayer::new(name),
         Box::new(move |games: &[GameInfo]| {
             let game_id = games[0].game_id;
             (game_id, deck.to_vec())
         }),
     );
+    let mut client = options.apply(client);
 
     let result = client.start(server);
-    handle_result(result);
+    handle_result(client.get_player_id(), result);
 }
 
-fn run_mcts(server: &str, context: Context, name: String, deck: Vec<Card>, mcts_args: MctsArgs) {
-    let mut client: Client<MctsPlayer> = Client::new(
+fn run_minimax(
+    server: &str,
+    options: ClientOptions,
+    context: Context,
+    name: String,
+    deck: Vec<Card>,
+    depth: usize,
+) {
+    let client: Client<MinimaxPlayer> = Client::new(
         context,
         WireFormat::Flexbuffers,
-        MctsPlayer::new(name, 42, mcts_args.iterations, mcts_args.mcts_const),
+        MinimaxPlayer::new(name, depth),
         Box::new(move |games: &[GameInfo]| {
             let game_id = games[0].game_id;
             (game_id, deck.to_vec())
         }),
     );
+    let mut client = options.apply(client);
+
     let result = client.start(server);
-    handle_result(result);
+    handle_result(client.get_player_id(), result);
+}
+
+fn run_mcts(
+    server: &str,
+    options: ClientOptions,
+    context: Context,
+    name: String,
+    deck: Vec<Card>,
+    mcts_args: MctsArgs,
+) {
+    let mut player = MctsPlayer::new(
+        name,
+        PLAYER_SEED,
+        mcts_args.determinizations.unwrap_or(mcts_args.iterations),
+        mcts_args.mcts_const,
+    )
+    .with_iterations_per_determinization(mcts_args.iterations_per_det)
+    .with_redeal_samples(mcts_args.redeal_samples)
+    .with_reward_kind(mcts_args.reward)
+    .with_profile(mcts_args.profile)
+    .with_tree_export(mcts_args.export_tree)
+    .with_tree_reuse(mcts_args.reuse_tree)
+    .with_transpositions(mcts_args.transpositions)
+    .with_threads(mcts_args.mcts_threads);
+    if let Some(cap) = mcts_args.node_budget {
+        player = player.with_node_budget(NodeBudget::new(cap));
+    }
+    let client: Client<MctsPlayer> = Client::new(
+        context,
+        WireFormat::Flexbuffers,
+        player,
+        Box::new(move |games: &[GameInfo]| {
+            let game_id = games[0].game_id;
+            (game_id, deck.to_vec())
+        }),
+    );
+    let mut client = options.apply(client);
+    let result = client.start(server);
+    handle_result(client.get_player_id(), result);
+}
+
+fn new_boxed_client(
+    context: Context,
+    player: Box<dyn Player>,
+    deck: Vec<Card>,
+    options: ClientOptions,
+) -> Client<Box<dyn Player>> {
+    options.apply(Client::new(
+        context,
+        WireFormat::Flexbuffers,
+        player,
+        Box::new(move |games: &[GameInfo]| {
+            let game_id = games[0].game_id;
+            (game_id, deck.to_vec())
+        }),
+    ))
+}
+
+/// Returns true if the game completed and both sides agree on the result.
+fn run_self_match(
+    server: &str,
+    options: ClientOptions,
+    context: Context,
+    deck: Vec<Card>,
+    self_match_args: SelfMatchArgs,
+) -> bool {
+    let mut rng = Mt64::new(SELF_MATCH_SEED);
+    let player = self_match_args
+        .player
+        .create_player(&context, rng.next_u64());
+    let opponent = self_match_args
+        .opponent
+        .create_player(&context, rng.next_u64());
+
+    let player_client = new_boxed_client(
+        context.clone(),
+        player,
+        deck.clone(),
+        options.clone().with_analysis_log_suffix(".player"),
+    );
+    let opponent_client = new_boxed_client(
+        context,
+        opponent,
+        deck,
+        options.with_analysis_log_suffix(".opponent"),
+    );
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    // Each client plays in its own task since the player's thinking blocks the task.
+    let play = |mut client: Client<Box<dyn Player>>| {
+        let server = server.to_string();
+        rt.spawn(async move {
+            let result = client.start_async(&server).await;
+            (client, result)
+        })
+    };
+    let player_game = play(player_client);
+    let opponent_game = play(opponent_client);
+    let ((player_client, player_result), (opponent_client, opponent_result)) =
+        rt.block_on(async { (player_game.await.unwrap(), opponent_game.await.unwrap()) });
+
+    match (player_result, opponent_result) {
+        (Ok(p), Ok(o)) => {
+            let player_id = player_client.get_player_id();
+            let opponent_id = opponent_client.get_player_id();
+            info!("Player ({}): {}", player_id, p.view(player_id));
+            info!("Opponent ({}): {}", opponent_id, o.view(opponent_id));
+            if p.south.score != o.south.score || p.north.score != o.north.score {
+                error!("Both sides reported inconsistent results: {} v.s. {}", p, o);
+                return false;
+            }
+            true
+        }
+        (p, o) => {
+            if let Err(e) = p {
+                error!("Player: {}", e);
+            }
+            if let Err(e) = o {
+                error!("Opponent: {}", e);
+            }
+            false
+        }
+    }
 }
@@ -0,0 +1,91 @@
+/// Raises the mutation rate while the best fitness of the population doesn't improve, which
+/// usually means the population lost its diversity.
+///
+/// The rate is doubled for each `window` generations without improvement up to `max_rate`, and
+/// goes back to `base_rate` once the best fitness improves.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdaptiveMutation {
+    base_rate: f64,
+    max_rate: f64,
+
+    /// The number of consecutive generations without improvement to raise the rate at.
+    window: u32,
+
+    best_fitness: Option<f64>,
+    stale_generations: u32,
+}
+
+impl AdaptiveMutation {
+    pub fn new(base_rate: f64, max_rate: f64, window: u32) -> Self {
+        AdaptiveMutation {
+            base_rate,
+            max_rate,
+            window: window.max(1),
+            best_fitness: None,
+            stale_generations: 0,
+        }
+    }
+
+    pub fn get_best_fitness(&self) -> Option<f64> {
+        self.best_fitness
+    }
+
+    pub fn get_stale_generations(&self) -> u32 {
+        self.stale_generations
+    }
+
+    /// Restores the progress saved with `get_best_fitness` and `get_stale_generations`.
+    pub fn restore(&mut self, best_fitness: Option<f64>, stale_generations: u32) {
+        self.best_fitness = best_fitness;
+        self.stale_generations = stale_generations;
+    }
+
+    /// The mutation rate to use for the next generation.
+    pub fn get_rate(&self) -> f64 {
+        let steps = (self.stale_generations / self.window).min(i32::MAX as u32) as i32;
+        (self.base_rate * 2f64.powi(steps))
+            .min(self.max_rate)
+            .max(self.base_rate)
+    }
+
+    /// Records the best fitness of a generation.
+    pub fn update(&mut self, best_fitness: f64) {
+        match self.best_fitness {
+            Some(best) if best_fitness <= best => {
+                self.stale_generations += 1;
+            }
+            _ => {
+                self.best_fitness = Some(best_fitness);
+                self.stale_generations = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stagnation_raises_rate() {
+        let mut mutation = AdaptiveMutation::new(0.01, 0.03, 2);
+        let rates: Vec<f64> = [5.0, 6.0, 6.0, 5.0, 6.0, 6.0, 4.0, 7.0]
+            .iter()
+            .map(|fitness| {
+                mutation.update(*fitness);
+                mutation.get_rate()
+            })
+            .collect();
+        assert_eq!(vec![0.01, 0.01, 0.01, 0.02, 0.02, 0.03, 0.03, 0.01], rates);
+        assert_eq!(Some(7.0), mutation.get_best_fitness());
+        assert_eq!(0, mutation.get_stale_generations());
+    }
+
+    #[test]
+    fn test_max_rate_below_base_rate() {
+        let mut mutation = AdaptiveMutation::new(0.1, 0.05, 1);
+        mutation.update(1.0);
+        mutation.update(1.0);
+        assert_eq!(0.1, mutation.get_rate());
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::Path,
+};
+
+const HEADER: &str = "# deck_builder checkpoint";
+
+/// Progress of a training saved after each generation so that an interrupted training can be
+/// resumed. Resuming from a checkpoint continues exactly as the original run would have.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    /// The generation which `population` is evaluated in next.
+    pub generation: u32,
+
+    /// The seed of the random source used from `generation`.
+    pub seed: u64,
+
+    /// The number of games played so far, used to index CSV rows.
+    pub game_cnt: u32,
+
+    /// The best validation win rate and the number of generations since it, if early stopping
+    /// is enabled.
+    pub early_stopping: Option<(Option<f64>, u32)>,
+
+    /// The best fitness and the number of generations since it, if the mutation rate is
+    /// adaptive.
+    pub adaptive_mutation: Option<(Option<f64>, u32)>,
+
+    /// Card IDs of each deck.
+    pub population: Vec<Vec<u32>>,
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint to a temporary file first so that an interruption while saving
+    /// never breaks the last checkpoint.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, self.to_string())
+            .map_err(|e| format!("Failed to write {:?}: {}", tmp_path, e))?;
+        fs::rename(&tmp_path, path)
+            .map_err(|e| format!("Failed to rename {:?} to {:?}: {}", tmp_path, path, e))
+    }
+
+    pub fn load(path: &Path) -> Result<Checkpoint, String> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+        text.parse()
+            .map_err(|e| format!("Invalid checkpoint {:?}: {}", path, e))
+    }
+}
+
+impl Display for Checkpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "generation {}", self.generation)?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "games {}", self.game_cnt)?;
+        for (key, progress) in [
+            ("early_stopping", self.early_stopping),
+            ("adaptive_mutation", self.adaptive_mutation),
+        ] {
+            if let Some((best, stale_generations)) = progress {
+                let best = best.map_or("none".to_string(), |best| best.to_string());
+                writeln!(f, "{} {} {}", key, best, stale_generations)?;
+            }
+        }
+        for deck in &self.population {
+            let ids: Vec<String> = deck.iter().map(|id| id.to_string()).collect();
+            writeln!(f, "deck {}", ids.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Checkpoint {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line.trim()) != Some(HEADER) {
+            return Err(format!("Expected `{}` at the first line", HEADER));
+        }
+
+        let mut generation = None;
+        let mut seed = None;
+        let mut game_cnt = None;
+        let mut early_stopping = None;
+        let mut adaptive_mutation = None;
+        let mut population = vec![];
+        for (line_index, line) in lines {
+            let error = |msg: String| format!("Line {} ({:?}): {}", line_index + 1, line, msg);
+            let mut tokens = line.split_whitespace();
+            let key = match tokens.next() {
+                Some(key) => key,
+                None => continue,
+            };
+            let values: Vec<&str> = tokens.collect();
+            match (key, values.as_slice()) {
+                ("generation", [value]) => {
+                    generation = Some(value.parse().map_err(|e| error(format!("{}", e)))?)
+                }
+                ("seed", [value]) => {
+                    seed = Some(value.parse().map_err(|e| error(format!("{}", e)))?)
+                }
+                ("games", [value]) => {
+                    game_cnt = Some(value.parse().map_err(|e| error(format!("{}", e)))?)
+                }
+                ("early_stopping" | "adaptive_mutation", [best, stale_generations]) => {
+                    let best = match *best {
+                        "none" => None,
+                        best => Some(best.parse().map_err(|e| error(format!("{}", e)))?),
+                    };
+                    let stale_generations = stale_generations
+                        .parse()
+                        .map_err(|e| error(format!("{}", e)))?;
+                    let progress = Some((best, stale_generations));
+                    if key == "early_stopping" {
+                        early_stopping = progress;
+                    } else {
+                        adaptive_mutation = progress;
+                    }
+                }
+                ("deck", ids) => {
+                    let deck = ids
+                        .iter()
+                        .map(|id| id.parse::<u32>())
+                        .collect::<Result<Vec<u32>, _>>()
+                        .map_err(|e| error(format!("Invalid card id: {}", e)))?;
+                    population.push(deck);
+                }
+                _ => return Err(error("Unknown line".to_string())),
+            }
+        }
+        Ok(Checkpoint {
+            generation: generation.ok_or("Missing `generation`")?,
+            seed: seed.ok_or("Missing `seed`")?,
+            game_cnt: game_cnt.ok_or("Missing `games`")?,
+            early_stopping,
+            adaptive_mutation,
+            population,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut checkpoint = Checkpoint {
+            generation: 3,
+            seed: u64::MAX,
+            game_cnt: 42,
+            early_stopping: Some((Some(0.625), 2)),
+            adaptive_mutation: Some((Some(12.5), 3)),
+            population: vec![vec![1, 2, 3], vec![4, 4, 5]],
+        };
+        assert_eq!(Ok(checkpoint.clone()), checkpoint.to_string().parse());
+
+        checkpoint.early_stopping = Some((None, 0));
+        assert_eq!(Ok(checkpoint.clone()), checkpoint.to_string().parse());
+        checkpoint.early_stopping = None;
+        checkpoint.adaptive_mutation = None;
+        assert_eq!(Ok(checkpoint.clone()), checkpoint.to_string().parse());
+
+        assert!("generation 3\n".parse::<Checkpoint>().is_err());
+        let broken = checkpoint.to_string().replace("deck 4 4 5", "deck 4 x 5");
+        assert!(broken.parse::<Checkpoint>().is_err());
+    }
+}
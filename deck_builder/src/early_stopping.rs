@@ -0,0 +1,80 @@
+/// Stops training once the best validation win rate hasn't improved for a while.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EarlyStopping {
+    /// The number of consecutive generations without improvement to stop at.
+    patience: u32,
+
+    /// A win rate must exceed the best one by more than this to count as an improvement.
+    epsilon: f64,
+
+    best_win_rate: Option<f64>,
+    stale_generations: u32,
+}
+
+impl EarlyStopping {
+    pub fn new(patience: u32, epsilon: f64) -> Self {
+        EarlyStopping {
+            patience,
+            epsilon,
+            best_win_rate: None,
+            stale_generations: 0,
+        }
+    }
+
+    pub fn get_best_win_rate(&self) -> Option<f64> {
+        self.best_win_rate
+    }
+
+    pub fn get_stale_generations(&self) -> u32 {
+        self.stale_generations
+    }
+
+    /// Restores the progress saved with `get_best_win_rate` and `get_stale_generations`.
+    pub fn restore(&mut self, best_win_rate: Option<f64>, stale_generations: u32) {
+        self.best_win_rate = best_win_rate;
+        self.stale_generations = stale_generations;
+    }
+
+    /// Records the validation win rate of a generation and returns true if training should stop.
+    pub fn update(&mut self, win_rate: f64) -> bool {
+        match self.best_win_rate {
+            Some(best) if win_rate <= best + self.epsilon => {
+                self.stale_generations += 1;
+            }
+            _ => {
+                self.best_win_rate = Some(win_rate);
+                self.stale_generations = 0;
+            }
+        }
+        self.stale_generations >= self.patience
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the generation where training stops.
+    fn stop_generation(early_stopping: &mut EarlyStopping, win_rates: &[f64]) -> Option<usize> {
+        win_rates
+            .iter()
+            .position(|win_rate| early_stopping.update(*win_rate))
+    }
+
+    #[test]
+    fn test_plateau() {
+        let mut early_stopping = EarlyStopping::new(3, 0.01);
+        let win_rates = [0.5, 0.6, 0.6, 0.605, 0.59, 0.7];
+        assert_eq!(Some(4), stop_generation(&mut early_stopping, &win_rates));
+        assert_eq!(Some(0.6), early_stopping.get_best_win_rate());
+    }
+
+    #[test]
+    fn test_improvement_resets_patience() {
+        let mut early_stopping = EarlyStopping::new(2, 0.0);
+        let win_rates = [0.5, 0.5, 0.6, 0.6, 0.7, 0.7];
+        assert_eq!(None, stop_generation(&mut early_stopping, &win_rates));
+        assert_eq!(1, early_stopping.get_stale_generations());
+        assert!(early_stopping.update(0.7));
+    }
+}
@@ -1,10 +1,20 @@
+mod adaptive_mutation;
+mod checkpoint;
+mod early_stopping;
+mod tempo;
+
 use std::{
-    collections::{
-        HashMap,
-        HashSet,
-    },
+    collections::HashMap,
     fmt::Display,
-    path::PathBuf,
+    fs::{
+        File,
+        OpenOptions,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::Arc,
 };
 
 use clap::{
@@ -12,11 +22,17 @@ use clap::{
     ValueHint,
 };
 use log::*;
-use more_asserts::assert_le;
+use more_asserts::{
+    assert_ge,
+    assert_le,
+};
 use players::PlayerType;
 use rand::{
     prelude::Distribution,
-    seq::IteratorRandom,
+    seq::{
+        index,
+        IteratorRandom,
+    },
     Rng,
 };
 use rand_distr::{
@@ -24,21 +40,64 @@ use rand_distr::{
     WeightedIndex,
 };
 use rand_mt::Mt64;
+use rayon::{
+    prelude::*,
+    ThreadPool,
+};
 
 use engine::{
     Board,
     Card,
     Context,
+    DataDirArgs,
+    GameCsvWriter,
+    GameData,
+    Replay,
+    RunConfig,
 };
 
+use local::{
+    board_selector::{
+        BoardBreakdown,
+        BoardSelector,
+    },
+    script::Script,
+    Contestant,
+    DealPolicy,
+    PlayedBattle,
+};
 use players::Player;
 
+use crate::{
+    adaptive_mutation::AdaptiveMutation,
+    checkpoint::Checkpoint,
+    early_stopping::EarlyStopping,
+    tempo::ScoreRecorder,
+};
+
+/// The default of `--seed`.
+const DEFAULT_SEED: u64 = 42;
+
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Selection {
+    /// Fitness-proportional selection. A deck which dominates the population tends to be chosen
+    /// for most crossovers.
+    Roulette,
+
+    /// The best of `--tournament-size` randomly sampled decks, which keeps weaker decks in play
+    /// regardless of how far the best one leads.
+    Tournament,
+}
+
 #[derive(Parser)]
 pub struct DeckBuilderArgs {
     /// a directory path where holds all card data. no need to specify for many cases.
     #[clap(long, value_parser, default_value_t = String::from("data/cards"))]
     card_dir: String,
 
+    #[clap(flatten)]
+    data: DataDirArgs,
+
     /// a file path to a board file. the selected board is used for games/training.
     #[clap(
         long,
@@ -87,11 +146,18 @@ pub struct DeckBuilderArgs {
     max_generation: u32,
 
     /// How many battles should be held for each epoch.
-    /// Note that specified amount of battles happen for each deck variations so
+    /// Each deck variation plays specified amount of battles against the evaluation deck, or
+    /// against each other variation with `--league` so that
     /// `C(variations, 2) * battles_per_epoch` battle simulations happen for each epoch.
     #[clap(long, short = 'b', value_parser, default_value_t = 1)]
     battles_per_epoch: usize,
 
+    /// Evaluate decks by a round-robin league in the population instead of battles against the
+    /// evaluation deck. Fitness is the total of the wins across the league.
+    /// In each pairing, the deck which comes first in the population plays as `--player`.
+    #[clap(long, value_parser, conflicts_with = "evaluation-deck-path")]
+    league: bool,
+
     /// How many deck variations should be made for each epoch.
     #[clap(long, short = 'p', value_parser, default_value_t = 10)]
     population_size: usize,
@@ -103,17 +169,145 @@ pub struct DeckBuilderArgs {
 
     #[clap(long, short, value_parser, default_value_t = 0.01)]
     mutation_rate: f64,
+
+    /// Double the mutation rate for each N consecutive generations where the best fitness
+    /// doesn't improve, up to `--max-mutation-rate`. The rate goes back to `--mutation-rate`
+    /// once it improves. The rate is fixed if not specified.
+    #[clap(long, value_parser)]
+    stagnation_window: Option<u32>,
+
+    /// The cap of the mutation rate raised by `--stagnation-window`.
+    #[clap(long, value_parser, default_value_t = 0.1)]
+    max_mutation_rate: f64,
+
+    /// How parents of crossover are chosen from the population.
+    #[clap(long, value_parser, default_value = "roulette")]
+    selection: Selection,
+
+    /// The number of decks sampled for each tournament of `--selection tournament`.
+    /// Bigger values favor the best decks more.
+    #[clap(long, value_parser, default_value_t = 2)]
+    tournament_size: usize,
+
+    /// Fitness earned by a draw while a win earns 1.
+    /// Use a negative value to steer evolution away from decks which draw a lot.
+    #[clap(long, value_parser, default_value_t = 0.0, allow_hyphen_values = true)]
+    draw_reward: f64,
+
+    /// Fitness earned by taking a lasting lead early. A game where the deck leads from the first
+    /// turn to the end earns this value, and one where it takes the lead at the last turn earns 0.
+    #[clap(long, value_parser, default_value_t = 0.0)]
+    tempo_weight: f64,
+
+    /// a directory of boards. a board is randomly picked from them for each battle and
+    /// validation results are reported for each board. `--board-path` is ignored if specified.
+    #[clap(long, value_parser, value_hint=ValueHint::DirPath)]
+    random_board_dir: Option<PathBuf>,
+
+    /// Stop training early if the validation win rate hasn't improved for N consecutive
+    /// generations.
+    #[clap(long, value_parser)]
+    patience: Option<u32>,
+
+    /// The validation win rate must exceed the best one by more than this value to reset
+    /// `--patience`.
+    #[clap(long, value_parser, default_value_t = 0.0)]
+    patience_epsilon: f64,
+
+    /// a file path where a CSV row is written for each game including validation games.
+    #[clap(long, value_parser, value_hint=ValueHint::FilePath)]
+    csv: Option<PathBuf>,
+
+    /// The number of threads which evaluate deck variations in parallel.
+    /// Defaults to the number of cores.
+    #[clap(long, value_parser)]
+    threads: Option<usize>,
+
+    /// The maximum number of copies of a card in a deck. The inventory may list a card with a
+    /// count like `42 x3`.
+    #[clap(long, value_parser, default_value_t = 1)]
+    max_card_copies: u32,
+
+    /// The number of battles against the validation deck for each generation.
+    #[clap(long, value_parser, default_value_t = 1000)]
+    validation_battles: usize,
+
+    /// a file path where the population and the random state are saved after each generation.
+    #[clap(long, value_parser, value_hint=ValueHint::FilePath)]
+    checkpoint_path: Option<PathBuf>,
+
+    /// Resume the training from `--checkpoint-path` instead of starting from a random population.
+    /// Other options must be the same as the interrupted run to continue it exactly.
+    #[clap(long, value_parser, requires = "checkpoint-path")]
+    resume: bool,
+
+    /// a file path where the best deck of the last generation is written in the deck format.
+    /// it's the deck validated in the generation, which has the highest fitness.
+    #[clap(long, value_parser, value_hint=ValueHint::FilePath)]
+    output_deck: Option<PathBuf>,
+
+    /// The seed of the random source of the training, which also seeds players and the board
+    /// selection. Runs with the same seed and options produce the same decks.
+    #[clap(long, value_parser, default_value_t = DEFAULT_SEED)]
+    seed: u64,
+}
+
+#[derive(Debug, Default)]
+struct BattleResults {
+    win_cnt: u32,
+    lose_cnt: u32,
+    draw_cnt: u32,
+
+    /// Sum of `tempo::tempo_score` of all battles.
+    tempo: f64,
+
+    /// Sum of `tempo::tempo_score` of all battles from the opponent's view.
+    opponent_tempo: f64,
+
+    breakdown: BoardBreakdown,
+
+    /// Battles in the order they were played, to be written to the CSV file.
+    games: Vec<PlayedBattle>,
+}
+
+/// Players and random sources used by a series of battles.
+/// Each thread has its own so that battles don't depend on how they are scheduled.
+struct Arena<'p> {
+    player: &'p mut dyn Player,
+    opponent: &'p mut dyn Player,
+    rng: Mt64,
+    board_selector: Option<BoardSelector>,
 }
 
 #[derive(Debug)]
 struct Report<'b> {
     deck: &'b [Card],
     win_cnt: u32,
+    draw_cnt: u32,
+    tempo: f64,
+    fitness: f64,
 }
 
 impl<'b> Report<'b> {
-    fn get_weight(&self) -> u32 {
-        self.win_cnt
+    fn new(deck: &'b [Card], win_cnt: u32, draw_cnt: u32, draw_reward: f64) -> Self {
+        Report {
+            deck,
+            win_cnt,
+            draw_cnt,
+            tempo: 0.0,
+            fitness: win_cnt as f64 + draw_cnt as f64 * draw_reward,
+        }
+    }
+
+    fn with_tempo(mut self, tempo: f64, tempo_weight: f64) -> Self {
+        self.tempo = tempo;
+        self.fitness += tempo * tempo_weight;
+        self
+    }
+
+    /// Weights for random selection can't be negative.
+    fn get_weight(&self) -> f64 {
+        self.fitness.max(0.0)
     }
 }
 
@@ -121,103 +315,326 @@ impl<'b> Display for Report<'b> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "deck: {}, win: {}",
+            "deck: {}, win: {}, draw: {}, tempo: {:.2}, fitness: {:.2}",
             engine::format_cards(self.deck),
-            self.win_cnt
+            self.win_cnt,
+            self.draw_cnt,
+            self.tempo,
+            self.fitness
         )
     }
 }
 
+/// Chooses a parent of crossover from reports sorted by `sort_reports`.
+enum ParentSelector {
+    Roulette(WeightedAliasIndex<f64>),
+    Tournament(usize),
+}
+
+impl ParentSelector {
+    fn new(selection: Selection, tournament_size: usize, reports: &[Report]) -> Self {
+        match selection {
+            // We use WeightedAliasIndex instead of WeightedIndex becaues we'll take 2*N genes here.
+            // Initialization cost + taking costs would be:
+            //   WeightedIndex: N * O(logN) => O(NlogN)
+            //   WeightedAliasIndex: O(N) + N * O(1) => O(N)
+            Selection::Roulette => ParentSelector::Roulette(
+                WeightedAliasIndex::new(reports.iter().map(|r| r.get_weight()).collect()).unwrap(),
+            ),
+            Selection::Tournament => {
+                ParentSelector::Tournament(tournament_size.clamp(1, reports.len()))
+            }
+        }
+    }
+
+    /// Returns the index of the chosen report in `0..len`.
+    fn select<R: Rng>(&self, rng: &mut R, len: usize) -> usize {
+        match self {
+            ParentSelector::Roulette(weights) => weights.sample(rng),
+            // Reports are sorted from the best one, so the smallest index wins.
+            ParentSelector::Tournament(size) => {
+                index::sample(rng, len, *size).iter().min().unwrap()
+            }
+        }
+    }
+}
+
+/// Sorts reports by their fitness in descending order.
+/// Ties are broken by card IDs of the decks so that elites are chosen deterministically.
+fn sort_reports(reports: &mut [Report]) {
+    reports.sort_by(|a, b| {
+        b.fitness.total_cmp(&a.fitness).then_with(|| {
+            let mut a_ids = engine::to_ids(a.deck);
+            let mut b_ids = engine::to_ids(b.deck);
+            a_ids.sort();
+            b_ids.sort();
+            a_ids.cmp(&b_ids)
+        })
+    });
+}
+
 struct DeckBuilder<'a> {
     rng: Mt64,
-    context: &'a Context,
+    context: &'a Arc<Context>,
     board: Board,
+    board_selector: Option<BoardSelector>,
+    early_stopping: Option<EarlyStopping>,
+    adaptive_mutation: Option<AdaptiveMutation>,
     args: DeckBuilderArgs,
-    inventory_cards: HashMap<u32, Card>,
+
+    /// Cards in the inventory including copies, sorted by id.
+    inventory_cards: Vec<Card>,
+    data: Option<GameData>,
+    csv: Option<GameCsvWriter<File>>,
+
+    /// The number of games played so far, used to index rows of `csv`.
+    game_cnt: u32,
+
+    thread_pool: ThreadPool,
 }
 
 impl<'c> DeckBuilder<'c> {
     fn new(
-        context: &'c Context,
+        context: &'c Arc<Context>,
         board: Board,
+        board_selector: Option<BoardSelector>,
         args: DeckBuilderArgs,
-        inventory_cards: HashMap<u32, Card>,
+        inventory_cards: Vec<Card>,
+        data: Option<GameData>,
     ) -> DeckBuilder<'c> {
         DeckBuilder {
-            rng: Mt64::new(42),
+            rng: Mt64::new(args.seed),
             context,
             board,
-            args,
+            board_selector,
+            early_stopping: args
+                .patience
+                .map(|patience| EarlyStopping::new(patience, args.patience_epsilon)),
+            adaptive_mutation: args.stagnation_window.map(|window| {
+                AdaptiveMutation::new(args.mutation_rate, args.max_mutation_rate, window)
+            }),
             inventory_cards,
+            data,
+            csv: None,
+            game_cnt: 0,
+            thread_pool: rayon::ThreadPoolBuilder::new()
+                // 0 lets rayon use all cores.
+                .num_threads(args.threads.unwrap_or(0))
+                .build()
+                .expect("Failed to create a thread pool"),
+            args,
         }
     }
 
+    fn with_csv(mut self, csv: GameCsvWriter<File>) -> Self {
+        self.csv = Some(csv);
+        self
+    }
+
+    fn load_deck(&self, deck_path: &PathBuf) -> Vec<Card> {
+        let ids = match &self.data {
+            Some(data) => data.resolve_deck(deck_path),
+            None => engine::load_deck(deck_path),
+        };
+        if let Err(e) = self.context.validate_deck(&ids) {
+            error!("Invalid deck {:?}: {}", deck_path, e);
+            std::process::exit(1);
+        }
+        self.context.get_cards(&ids)
+    }
+
     fn run_battles(
-        &mut self,
+        &self,
+        arena: &mut Arena,
         battle_count: usize,
         player_deck: &[Card],
         opponent_deck: &[Card],
-        player: &mut dyn Player,
-        opponent: &mut dyn Player,
-    ) -> (u32, u32, u32) {
-        let mut player_won_cnt = 0;
-        let mut opponent_won_cnt = 0;
-        let mut draw_cnt = 0;
-
-        for _i in 0..battle_count {
-            let (p, o) = local::run(
+    ) -> BattleResults {
+        let mut results = BattleResults::default();
+        let mut player = ScoreRecorder::new(&mut *arena.player);
+
+        for i in 0..battle_count {
+            let board = match arena.board_selector.as_mut() {
+                Some(selector) => selector.next_board(),
+                None => &self.board,
+            };
+            let player_side = local::player_side(i as u32);
+            let mut replay = Replay::new(board.clone());
+            let (outcome, _) = local::run_with_script_on_side(
                 self.context,
-                &self.board,
+                board,
                 player_deck,
                 opponent_deck,
-                player,
-                opponent,
-                &mut self.rng,
-            );
-            match p.cmp(&o) {
-                std::cmp::Ordering::Less => {
+                &mut player,
+                &mut *arena.opponent,
+                &mut arena.rng,
+                player_side,
+                [DealPolicy::Shuffle; 2],
+                &Script::default(),
+                Some(&mut replay),
+            )
+            .expect("An empty script never fails");
+            let winner = outcome
+                .get_winner()
+                .map(|side| Contestant::on_side(side, player_side));
+            match winner {
+                Some(Contestant::Opponent) => {
                     debug!("Opponent win!");
-                    opponent_won_cnt += 1;
+                    results.lose_cnt += 1;
                 }
-                std::cmp::Ordering::Equal => {
+                None => {
                     debug!("Draw");
-                    draw_cnt += 1;
+                    results.draw_cnt += 1;
                 }
-                std::cmp::Ordering::Greater => {
+                Some(Contestant::Player) => {
                     debug!("Player win!");
-                    player_won_cnt += 1;
+                    results.win_cnt += 1;
                 }
             }
+            let view = outcome.view(player_side);
+            let score_history =
+                player.get_score_history((view.get_my_score(), view.get_opponent_score()));
+            results.tempo += tempo::tempo_score(&score_history);
+            let opponent_history: Vec<(u32, u32)> =
+                score_history.iter().map(|(p, o)| (*o, *p)).collect();
+            results.opponent_tempo += tempo::tempo_score(&opponent_history);
+            results.breakdown.push(board, winner);
+            results
+                .games
+                .push(PlayedBattle::new(&replay, player_side, outcome));
         }
-        (player_won_cnt, opponent_won_cnt, draw_cnt)
+        results
     }
 
+    /// Writes CSV rows of `games` if `--csv` is specified.
+    fn write_games(
+        &mut self,
+        games: &[PlayedBattle],
+        player_deck: &[Card],
+        opponent_deck: &[Card],
+    ) {
+        for game in games {
+            if let Some(csv) = self.csv.as_mut() {
+                let result = game.write_csv_row(
+                    csv,
+                    self.game_cnt,
+                    self.args.seed,
+                    [player_deck, opponent_deck],
+                    [
+                        &self.args.player.to_string(),
+                        &self.args.opponent.to_string(),
+                    ],
+                );
+                if let Err(e) = result {
+                    error!("Failed to write a CSV row: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            self.game_cnt += 1;
+        }
+    }
+
+    /// Makes an arena whose players and random sources are seeded by `seed`.
+    fn run_in_new_arena<'b>(
+        &self,
+        seed: u64,
+        player_deck: &'b [Card],
+        opponent_deck: &'b [Card],
+    ) -> BattleResults {
+        let mut rng = Mt64::new(seed);
+        let mut player = self.args.player.create_player(self.context, rng.next_u64());
+        let mut opponent = self
+            .args
+            .opponent
+            .create_player(self.context, rng.next_u64());
+        let board_selector = self
+            .board_selector
+            .as_ref()
+            .map(|selector| selector.with_seed(rng.next_u64()));
+        let mut arena = Arena {
+            player: &mut *player,
+            opponent: &mut *opponent,
+            rng,
+            board_selector,
+        };
+        self.run_battles(
+            &mut arena,
+            self.args.battles_per_epoch,
+            player_deck,
+            opponent_deck,
+        )
+    }
+
+    /// Runs battles of deck variations in parallel. Each variation has its own players seeded
+    /// by its index so that the results don't depend on the number of threads.
     fn evaluate_population<'b>(
         &mut self,
         population: &'b [Vec<Card>],
         opponent_deck: &'b [Card],
-        player: &mut dyn Player,
-        opponent: &mut dyn Player,
     ) -> Vec<Report<'b>> {
-        // key: variation_index
-        // value: won count
-        let mut won_cnts: HashMap<usize, u32> = HashMap::new();
-        (0..population.len()).for_each(|p_deck_index| {
-            let player_deck = &population[p_deck_index];
-            let (win, _lose, _draw) = self.run_battles(
-                self.args.battles_per_epoch,
-                player_deck,
-                opponent_deck,
-                player,
-                opponent,
+        let seed = self.rng.next_u64();
+        let results: Vec<BattleResults> = self.thread_pool.install(|| {
+            population
+                .par_iter()
+                .enumerate()
+                .map(|(index, player_deck)| {
+                    self.run_in_new_arena(seed ^ index as u64, player_deck, opponent_deck)
+                })
+                .collect()
+        });
+
+        let mut reports = vec![];
+        for (player_deck, results) in population.iter().zip(results) {
+            self.write_games(&results.games, player_deck, opponent_deck);
+            reports.push(
+                Report::new(
+                    player_deck,
+                    results.win_cnt,
+                    results.draw_cnt,
+                    self.args.draw_reward,
+                )
+                .with_tempo(results.tempo, self.args.tempo_weight),
             );
-            *won_cnts.entry(p_deck_index).or_insert(0) += win;
+        }
+        reports
+    }
+
+    /// Runs battles of every pair of deck variations in parallel. Each pair has its own players
+    /// seeded by its index so that the results don't depend on the number of threads.
+    fn evaluate_league<'b>(&mut self, population: &'b [Vec<Card>]) -> Vec<Report<'b>> {
+        let pairs: Vec<(usize, usize)> = (0..population.len())
+            .flat_map(|i| (i + 1..population.len()).map(move |j| (i, j)))
+            .collect();
+        let seed = self.rng.next_u64();
+        let results: Vec<BattleResults> = self.thread_pool.install(|| {
+            pairs
+                .par_iter()
+                .enumerate()
+                .map(|(index, (i, j))| {
+                    self.run_in_new_arena(seed ^ index as u64, &population[*i], &population[*j])
+                })
+                .collect()
         });
-        won_cnts
+
+        let mut totals: Vec<BattleResults> = population
+            .iter()
+            .map(|_| BattleResults::default())
+            .collect();
+        for ((i, j), results) in pairs.iter().zip(results) {
+            self.write_games(&results.games, &population[*i], &population[*j]);
+            totals[*i].win_cnt += results.win_cnt;
+            totals[*i].draw_cnt += results.draw_cnt;
+            totals[*i].tempo += results.tempo;
+            totals[*j].win_cnt += results.lose_cnt;
+            totals[*j].draw_cnt += results.draw_cnt;
+            totals[*j].tempo += results.opponent_tempo;
+        }
+        population
             .iter()
-            .map(|(index, cnt)| Report {
-                deck: &population[*index],
-                win_cnt: *cnt,
+            .zip(totals)
+            .map(|(deck, total)| {
+                Report::new(deck, total.win_cnt, total.draw_cnt, self.args.draw_reward)
+                    .with_tempo(total.tempo, self.args.tempo_weight)
             })
             .collect()
     }
@@ -227,7 +644,7 @@ impl<'c> DeckBuilder<'c> {
         for _ in 0..self.args.population_size {
             let mut deck: Vec<Card> = self
                 .inventory_cards
-                .values()
+                .iter()
                 .cloned()
                 .choose_multiple(&mut self.rng, engine::DECK_SIZE);
             deck.sort();
@@ -237,59 +654,70 @@ impl<'c> DeckBuilder<'c> {
     }
 
     fn crossover<'b>(&mut self, a: &Report<'b>, b: &Report<'b>) -> Vec<Card> {
-        // key: card id
+        // key: (card id, copy index) so that copies of a card are inherited separately
         // value: weight
-        let mut card_weights: HashMap<u32, u32> = HashMap::new();
-        a.deck.iter().for_each(|card| {
-            card_weights.insert(card.get_id(), a.get_weight());
-        });
-        b.deck.iter().for_each(|card| {
-            let e = card_weights.entry(card.get_id()).or_insert(0);
-            *e += b.get_weight();
-        });
+        let mut card_weights: HashMap<(u32, u32), f64> = HashMap::new();
+        for report in [a, b] {
+            let mut copies: HashMap<u32, u32> = HashMap::new();
+            report.deck.iter().for_each(|card| {
+                let copy = copies.entry(card.get_id()).or_insert(0);
+                let e = card_weights.entry((card.get_id(), *copy)).or_insert(0.0);
+                *e += report.get_weight();
+                *copy += 1;
+            });
+        }
 
         if log_enabled!(log::Level::Debug) {
             debug!("Weighted cards: # of cards: {}", card_weights.len());
-            card_weights.iter().for_each(|(id, w)| {
-                debug!("    w: {}: {}", w, id);
+            card_weights.iter().for_each(|((id, copy), w)| {
+                debug!("    w: {}: {} (copy {})", w, id, copy);
             });
         }
 
-        let mut card_weights: Vec<(u32, u32)> =
+        let mut card_weights: Vec<((u32, u32), f64)> =
             card_weights.iter().map(|(k, v)| (*k, *v)).collect();
+        // The order of HashMap differs between runs. Sort them to pick the same cards with the
+        // same rng.
+        card_weights.sort_by_key(|(key, _)| *key);
         let mut new_deck: Vec<Card> = vec![];
         (0..engine::DECK_SIZE).for_each(|_| {
             let dist = WeightedIndex::new(card_weights.iter().map(|e| e.1)).unwrap();
             let index: usize = dist.sample(&mut self.rng);
-            let (selected_card_id, _weight) = card_weights.remove(index);
-            new_deck.push(self.inventory_cards[&selected_card_id].clone());
+            let ((selected_card_id, _copy), _weight) = card_weights.remove(index);
+            new_deck.push(self.context.get_card(selected_card_id));
         });
         new_deck
     }
 
-    fn mutation(&mut self, deck: &mut [Card]) {
-        let mut pool: HashSet<u32> = HashSet::new();
-        self.inventory_cards.keys().for_each(|card_id| {
-            pool.insert(*card_id);
-        });
+    /// The mutation rate for the current generation.
+    fn get_mutation_rate(&self) -> f64 {
+        self.adaptive_mutation
+            .as_ref()
+            .map_or(self.args.mutation_rate, |mutation| mutation.get_rate())
+    }
 
+    fn mutation(&mut self, deck: &mut [Card]) {
+        let mutation_rate = self.get_mutation_rate();
+        // Copies of cards in the inventory which aren't in the deck.
+        let mut pool: Vec<u32> = engine::to_ids(&self.inventory_cards);
         deck.iter().for_each(|card| {
-            pool.remove(&card.get_id());
+            if let Some(index) = pool.iter().position(|id| *id == card.get_id()) {
+                pool.swap_remove(index);
+            }
         });
 
         debug!("Pool: {:?}", pool);
 
         let mut mutated = false;
         (0..deck.len()).for_each(|i| {
-            if self.rng.gen_bool(self.args.mutation_rate) {
+            if self.rng.gen_bool(mutation_rate) {
                 let removing = &deck[i];
-                let replacing_id: u32 = *pool.iter().choose(&mut self.rng).unwrap();
+                let replacing_id: u32 = pool.swap_remove(self.rng.gen_range(0..pool.len()));
 
-                pool.insert(removing.get_id());
-                pool.remove(&replacing_id);
+                pool.push(removing.get_id());
                 debug!("swapping: from:{} to:{}", removing.get_id(), &replacing_id);
 
-                deck[i] = self.inventory_cards[&replacing_id].clone();
+                deck[i] = self.context.get_card(replacing_id);
                 mutated = true;
             }
         });
@@ -303,11 +731,18 @@ impl<'c> DeckBuilder<'c> {
     fn create_next_generation<'b>(&mut self, reports: &mut [Report<'b>]) -> Vec<Vec<Card>> {
         assert_eq!(self.args.population_size, reports.len());
 
-        reports.sort_by(|a, b| b.win_cnt.cmp(&a.win_cnt));
+        sort_reports(reports);
         if log_enabled!(log::Level::Debug) {
             debug!("League result:");
             reports.iter().for_each(|r| {
-                debug!("  win: {}: {}", r.win_cnt, engine::format_cards(r.deck));
+                debug!(
+                    "  fitness: {:.2} (win: {}, draw: {}, tempo: {:.2}): {}",
+                    r.fitness,
+                    r.win_cnt,
+                    r.draw_cnt,
+                    r.tempo,
+                    engine::format_cards(r.deck)
+                );
             });
         }
 
@@ -320,25 +755,19 @@ impl<'c> DeckBuilder<'c> {
             next_gen.push(deck);
         });
 
-        // let weights = WeightedIndex::new(reports.iter().map(|r| r.get_weight())).unwrap();
-        // We use WeightedAliasIndex instead of WeightedIndex becaues we'll take 2*N genes here.
-        // Initialization cost + taking costs would be:
-        //   WeightedIndex: N * O(logN) => O(NlogN)
-        //   WeightedAliasIndex: O(N) + N * O(1) => O(N)
-        let weights =
-            WeightedAliasIndex::new(reports.iter().map(|r| r.get_weight()).collect()).unwrap();
+        let selector = ParentSelector::new(self.args.selection, self.args.tournament_size, reports);
         while next_gen.len() < self.args.population_size {
-            let a_index = weights.sample(&mut self.rng);
-            let b_index = weights.sample(&mut self.rng);
+            let a_index = selector.select(&mut self.rng, reports.len());
+            let b_index = selector.select(&mut self.rng, reports.len());
             debug!("Crossover");
             debug!(
                 "    #{}: {}",
-                reports[a_index].win_cnt,
+                reports[a_index].fitness,
                 engine::format_cards(reports[a_index].deck),
             );
             debug!(
                 "    #{}: {}",
-                reports[b_index].win_cnt,
+                reports[b_index].fitness,
                 engine::format_cards(reports[b_index].deck),
             );
             let mut deck = self.crossover(&reports[a_index], &reports[b_index]);
@@ -354,29 +783,117 @@ impl<'c> DeckBuilder<'c> {
         next_gen
     }
 
-    fn run(&mut self, player: &mut dyn Player, opponent: &mut dyn Player) {
+    /// Restores the progress saved at `--checkpoint-path`. Returns the generation to start from
+    /// and its population.
+    fn resume(&mut self) -> Result<(u32, Vec<Vec<Card>>), String> {
+        let path = self.args.checkpoint_path.as_ref().unwrap();
+        let checkpoint = Checkpoint::load(path)?;
+        if checkpoint.population.len() != self.args.population_size {
+            return Err(format!(
+                "The checkpoint has {} decks but population-size is {}",
+                checkpoint.population.len(),
+                self.args.population_size
+            ));
+        }
+        let mut population = vec![];
+        for ids in &checkpoint.population {
+            self.context.validate_deck(ids)?;
+            population.push(self.context.get_cards(ids));
+        }
+        self.rng = Mt64::new(checkpoint.seed);
+        self.game_cnt = checkpoint.game_cnt;
+        if let (Some(early_stopping), Some((best_win_rate, stale_generations))) =
+            (self.early_stopping.as_mut(), checkpoint.early_stopping)
+        {
+            early_stopping.restore(best_win_rate, stale_generations);
+        }
+        if let (Some(mutation), Some((best_fitness, stale_generations))) = (
+            self.adaptive_mutation.as_mut(),
+            checkpoint.adaptive_mutation,
+        ) {
+            mutation.restore(best_fitness, stale_generations);
+        }
+        info!(
+            "Resumed from {:?} at generation {}",
+            path, checkpoint.generation
+        );
+        Ok((checkpoint.generation, population))
+    }
+
+    /// Reseeds the random source so that the rest of the training only depends on the saved
+    /// seed, and saves the progress to `--checkpoint-path` if specified.
+    fn save_checkpoint(&mut self, generation: u32, population: &[Vec<Card>]) {
+        let seed = self.rng.next_u64();
+        self.rng = Mt64::new(seed);
+        let path = match &self.args.checkpoint_path {
+            Some(path) => path,
+            None => return,
+        };
+        let checkpoint = Checkpoint {
+            generation,
+            seed,
+            game_cnt: self.game_cnt,
+            early_stopping: self.early_stopping.as_ref().map(|early_stopping| {
+                (
+                    early_stopping.get_best_win_rate(),
+                    early_stopping.get_stale_generations(),
+                )
+            }),
+            adaptive_mutation: self.adaptive_mutation.as_ref().map(|mutation| {
+                (
+                    mutation.get_best_fitness(),
+                    mutation.get_stale_generations(),
+                )
+            }),
+            population: population.iter().map(|deck| engine::to_ids(deck)).collect(),
+        };
+        if let Err(e) = checkpoint.save(path) {
+            error!("Failed to save the checkpoint: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    /// Returns the population of the last generation.
+    fn run(&mut self) -> Vec<Vec<Card>> {
         assert_le!(
             self.args.elite_count,
             self.args.population_size,
             "elite-count must be smaller than population-size"
         );
+        assert_ge!(
+            self.args.tournament_size,
+            1,
+            "tournament-size must be positive"
+        );
 
-        let validation_deck = self
-            .context
-            .get_cards(&engine::load_deck(&self.args.validation_deck_path));
+        let validation_deck = self.load_deck(&self.args.validation_deck_path);
 
         let loaded_evaluation_deck: Vec<Card> =
             if let Some(eval_deck_path) = &self.args.evaluation_deck_path {
-                self.context.get_cards(&engine::load_deck(eval_deck_path))
+                self.load_deck(eval_deck_path)
             } else {
                 // it's not used.
                 vec![]
             };
 
-        let mut population = self.create_initial_population();
+        let (first_generation, mut population) = if self.args.resume {
+            self.resume().unwrap_or_else(|e| {
+                error!("Failed to resume: {}", e);
+                std::process::exit(1);
+            })
+        } else {
+            (0, self.create_initial_population())
+        };
         let max_epoch = self.args.max_generation;
-        let battles_count = self.args.battles_per_epoch * self.args.population_size;
-        for n in 0..max_epoch {
+        let population_size = self.args.population_size;
+        let battles_count = if self.args.league {
+            self.args.battles_per_epoch * population_size * (population_size - 1) / 2
+        } else {
+            self.args.battles_per_epoch * population_size
+        };
+        // The deck validated in the last generation and its validation win rate.
+        let mut last_best: Option<(u32, Vec<Card>, f64)> = None;
+        for n in first_generation..max_epoch {
             info!("# Generation {}", n);
             info!("Best {}", self.args.elite_count);
             population
@@ -385,57 +902,661 @@ impl<'c> DeckBuilder<'c> {
                 .take(self.args.elite_count)
                 .for_each(|(i, v)| info!("  {}: {}", i, engine::format_cards(v)));
 
-            let evaluation_deck: &Vec<Card> = if self.args.evaluation_deck_path.is_none() {
-                info!(
-                    "Opponent uses the best deck: {}",
-                    engine::format_cards(&population[0])
-                );
-                &population[0]
+            let mut reports = if self.args.league {
+                info!("Running  {} battles in the league...", battles_count);
+                self.evaluate_league(&population)
             } else {
-                info!(
-                    "Opponent uses the loaded deck: {}",
-                    engine::format_cards(&loaded_evaluation_deck)
-                );
-                &loaded_evaluation_deck
-            };
+                let evaluation_deck: &Vec<Card> = if self.args.evaluation_deck_path.is_none() {
+                    info!(
+                        "Opponent uses the best deck: {}",
+                        engine::format_cards(&population[0])
+                    );
+                    &population[0]
+                } else {
+                    info!(
+                        "Opponent uses the loaded deck: {}",
+                        engine::format_cards(&loaded_evaluation_deck)
+                    );
+                    &loaded_evaluation_deck
+                };
 
-            info!("Running  {} battles...", battles_count);
-            let mut reports =
-                self.evaluate_population(&population, evaluation_deck, player, opponent);
+                info!("Running  {} battles...", battles_count);
+                self.evaluate_population(&population, evaluation_deck)
+            };
 
             // Validation
             info!("Validating...");
             let best_deck = &reports
                 .iter()
-                .max_by(|a, b| a.win_cnt.cmp(&b.win_cnt))
+                .max_by(|a, b| a.fitness.total_cmp(&b.fitness))
                 .unwrap()
                 .deck;
-            let (w, l, d) = self.run_battles(1000, best_deck, &validation_deck, player, opponent);
-            info!("Validation: Win rate: {:.3}", w as f64 / (w + l + d) as f64);
-            info!("Board: {}", self.board.get_name());
+            // Players are made for each generation so that a resumed training validates decks
+            // in the same way.
+            let mut player = self
+                .args
+                .player
+                .create_player(self.context, self.rng.next_u64());
+            let mut opponent = self
+                .args
+                .opponent
+                .create_player(self.context, self.rng.next_u64());
+            let mut arena = Arena {
+                player: &mut *player,
+                opponent: &mut *opponent,
+                rng: Mt64::new(self.rng.next_u64()),
+                board_selector: self
+                    .board_selector
+                    .as_ref()
+                    .map(|selector| selector.with_seed(self.rng.next_u64())),
+            };
+            let results = self.run_battles(
+                &mut arena,
+                self.args.validation_battles,
+                best_deck,
+                &validation_deck,
+            );
+            self.write_games(&results.games, best_deck, &validation_deck);
+            let win_rate = results.win_cnt as f64
+                / (results.win_cnt + results.lose_cnt + results.draw_cnt) as f64;
+            info!("Validation: Win rate: {:.3}", win_rate);
+            last_best = Some((n, best_deck.to_vec(), win_rate));
+            match &self.board_selector {
+                Some(_) => info!("Validation for each board:\n{}", results.breakdown),
+                None => info!("Board: {}", self.board.get_name()),
+            }
+
+            if let Some(early_stopping) = self.early_stopping.as_mut() {
+                if early_stopping.update(win_rate) {
+                    info!(
+                        "Stopped at generation {}: the validation win rate hasn't improved for {} generations (best: {:.3})",
+                        n,
+                        early_stopping.get_stale_generations(),
+                        early_stopping.get_best_win_rate().unwrap()
+                    );
+                    break;
+                }
+            }
+
+            if let Some(mutation) = self.adaptive_mutation.as_mut() {
+                mutation.update(reports.iter().map(|r| r.fitness).fold(f64::MIN, f64::max));
+            }
+            info!("Mutation rate: {:.3}", self.get_mutation_rate());
 
             let next_generation = self.create_next_generation(&mut reports);
             population = next_generation;
+            self.save_checkpoint(n + 1, &population);
+        }
+
+        if let Some(path) = &self.args.output_deck {
+            match &last_best {
+                Some((generation, deck, win_rate)) => {
+                    if let Err(e) = self.write_best_deck(path, *generation, deck, *win_rate) {
+                        error!("Failed to write the best deck: {}", e);
+                        std::process::exit(1);
+                    }
+                    info!("Wrote the best deck to {:?}", path);
+                }
+                None => warn!("No generation was run. {:?} isn't written", path),
+            }
         }
+        population
+    }
+
+    /// Writes `deck` with its validation win rate as a comment.
+    fn write_best_deck(
+        &self,
+        path: &Path,
+        generation: u32,
+        deck: &[Card],
+        win_rate: f64,
+    ) -> Result<(), String> {
+        let comments = [format!(
+            "validation win rate: {:.3} (generation {}, against {})",
+            win_rate,
+            generation,
+            self.args.validation_deck_path.display()
+        )];
+        engine::save_deck(deck, path, &comments)
+    }
+}
+
+fn run_config(args: &DeckBuilderArgs, board: &Board) -> RunConfig {
+    let mut config = RunConfig::new("deck_builder", args.seed)
+        .with_board(board.get_name())
+        .with_deck(args.inventory_path.display())
+        .with_deck(args.validation_deck_path.display())
+        .with_player(&args.player)
+        .with_player(&args.opponent)
+        .with_param("max_generation", args.max_generation)
+        .with_param("battles_per_epoch", args.battles_per_epoch)
+        .with_param("league", args.league)
+        .with_param("population_size", args.population_size)
+        .with_param("elite_count", args.elite_count)
+        .with_param("mutation_rate", args.mutation_rate)
+        .with_param("selection", format!("{:?}", args.selection))
+        .with_param("draw_reward", args.draw_reward)
+        .with_param("tempo_weight", args.tempo_weight)
+        .with_param("max_card_copies", args.max_card_copies)
+        .with_param("validation_battles", args.validation_battles);
+    if args.selection == Selection::Tournament {
+        config = config.with_param("tournament_size", args.tournament_size);
+    }
+    if let Some(threads) = args.threads {
+        config = config.with_param("threads", threads);
+    }
+    if let Some(evaluation_deck_path) = &args.evaluation_deck_path {
+        config = config.with_deck(evaluation_deck_path.display());
+    }
+    if let Some(data_dir) = &args.data.data_dir {
+        config = config.with_param("data_dir", data_dir.display());
     }
+    if let Some(random_board_dir) = &args.random_board_dir {
+        config = config.with_param("random_board_dir", random_board_dir.display());
+    }
+    if let Some(stagnation_window) = args.stagnation_window {
+        config = config
+            .with_param("stagnation_window", stagnation_window)
+            .with_param("max_mutation_rate", args.max_mutation_rate);
+    }
+    if let Some(patience) = args.patience {
+        config = config
+            .with_param("patience", patience)
+            .with_param("patience_epsilon", args.patience_epsilon);
+    }
+    if let Some(csv) = &args.csv {
+        config = config.with_param("csv", csv.display());
+    }
+    if let Some(output_deck) = &args.output_deck {
+        config = config.with_param("output_deck", output_deck.display());
+    }
+    if let Some(checkpoint_path) = &args.checkpoint_path {
+        config = config
+            .with_param("checkpoint_path", checkpoint_path.display())
+            .with_param("resume", args.resume);
+    }
+    config
 }
 
 pub fn train_deck<'p, 'c: 'p>(args: DeckBuilderArgs) {
-    let all_cards = engine::load_cards(&args.card_dir);
-    let board = engine::load_board(&args.board_path);
+    let data: Option<GameData> = args.data.load_or_exit();
+    let (mut context, board) = match &data {
+        Some(data) => (data.context.clone(), data.resolve_board(&args.board_path)),
+        None => (
+            Context {
+                all_cards: engine::load_cards(&args.card_dir).unwrap_or_else(|e| {
+                    error!("Failed to load cards: {}", e);
+                    std::process::exit(1);
+                }),
+                enabled_step_execution: false,
+                rules: engine::RuleSet::default(),
+            },
+            engine::load_board(&args.board_path),
+        ),
+    };
+
+    context.rules.max_card_copies = args.max_card_copies;
+
+    let board_selector = args.random_board_dir.as_ref().map(|dir| {
+        BoardSelector::load(&context, dir, args.seed).unwrap_or_else(|e| {
+            error!("Failed to load boards: {}", e);
+            std::process::exit(1);
+        })
+    });
+
+    info!("Seed: {}", args.seed);
+    info!("Config: {}", run_config(&args, &board));
+
+    let ids = match &data {
+        Some(data) => data.resolve_deck(&args.inventory_path),
+        None => engine::load_deck(&args.inventory_path),
+    };
+    if let Err(e) = context.rules.check_card_copies(&ids) {
+        error!("Invalid inventory {:?}: {}", args.inventory_path, e);
+        std::process::exit(1);
+    }
+    let mut inventory = context.get_cards(&ids);
+    engine::sort_by_id(&mut inventory);
+    let csv = args.csv.as_ref().map(|path| {
+        let csv = if args.resume {
+            OpenOptions::new()
+                .append(true)
+                .open(path)
+                .map(GameCsvWriter::without_header)
+        } else {
+            File::create(path).and_then(GameCsvWriter::new)
+        };
+        csv.unwrap_or_else(|e| {
+            error!("Failed to open the CSV file {:?}: {}", path, e);
+            std::process::exit(1);
+        })
+    });
+    let context = Arc::new(context);
+    let mut deck_builder = DeckBuilder::new(&context, board, board_selector, args, inventory, data);
+    if let Some(csv) = csv {
+        deck_builder = deck_builder.with_csv(csv);
+    }
+    deck_builder.run();
+}
 
-    let context = Context {
-        all_cards,
-        enabled_step_execution: false,
+#[cfg(test)]
+mod tests {
+    use more_asserts::{
+        assert_gt,
+        assert_lt,
     };
 
-    // Use fixed seed for reproducible results.
-    let mut rng = Mt64::new(0x42);
+    use super::*;
+
+    fn load_test_cards() -> HashMap<u32, Card> {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../data/cards");
+        engine::load_cards(dir.to_str().unwrap()).unwrap()
+    }
+
+    fn new_test_args(threads: usize) -> DeckBuilderArgs {
+        let data = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../data");
+        DeckBuilderArgs {
+            card_dir: data.join("cards").display().to_string(),
+            data: DataDirArgs::default(),
+            board_path: data.join("boards/massugu_street"),
+            player: PlayerType::Random,
+            opponent: PlayerType::Random,
+            inventory_path: data.join("decks/starter"),
+            evaluation_deck_path: None,
+            validation_deck_path: data.join("decks/starter"),
+            max_generation: 1,
+            battles_per_epoch: 3,
+            league: false,
+            population_size: 6,
+            elite_count: 1,
+            mutation_rate: 0.01,
+            stagnation_window: None,
+            max_mutation_rate: 0.1,
+            selection: Selection::Roulette,
+            tournament_size: 2,
+            draw_reward: 0.0,
+            tempo_weight: 0.0,
+            random_board_dir: None,
+            patience: None,
+            patience_epsilon: 0.0,
+            csv: None,
+            threads: Some(threads),
+            max_card_copies: 1,
+            validation_battles: 10,
+            checkpoint_path: None,
+            resume: false,
+            output_deck: None,
+            seed: DEFAULT_SEED,
+        }
+    }
+
+    #[test]
+    fn test_evaluation_independent_of_threads() {
+        let context = Arc::new(Context {
+            all_cards: load_test_cards(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        });
+        let evaluate = |threads: usize| {
+            let args = new_test_args(threads);
+            let board = engine::load_board(&args.board_path);
+            let inventory = context.get_cards(&engine::load_deck(&args.inventory_path));
+            let mut deck_builder = DeckBuilder::new(&context, board, None, args, inventory, None);
+            let population = deck_builder.create_initial_population();
+            let opponent_deck = population[0].clone();
+            deck_builder
+                .evaluate_population(&population, &opponent_deck)
+                .iter()
+                .map(|r| (engine::to_ids(r.deck), r.win_cnt, r.draw_cnt))
+                .collect::<Vec<_>>()
+        };
+
+        let sequential = evaluate(1);
+        assert_eq!(6, sequential.len());
+        assert_eq!(sequential, evaluate(3));
+    }
+
+    #[test]
+    fn test_league() {
+        let context = Arc::new(Context {
+            all_cards: load_test_cards(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        });
+        let mut args = new_test_args(2);
+        args.league = true;
+        args.population_size = 4;
+        let board = engine::load_board(&args.board_path);
+        let inventory = context.get_cards(&(1..=30).collect::<Vec<u32>>());
+        let mut deck_builder = DeckBuilder::new(&context, board, None, args, inventory, None);
+
+        let population = deck_builder.create_initial_population();
+        let reports = deck_builder.evaluate_league(&population);
+        // C(4, 2) pairs play 3 battles each.
+        assert_eq!(18, deck_builder.game_cnt);
+        assert_eq!(4, reports.len());
+        let wins: u32 = reports.iter().map(|r| r.win_cnt).sum();
+        let draws: u32 = reports.iter().map(|r| r.draw_cnt).sum();
+        assert_eq!(18 * 2, wins * 2 + draws);
+        for report in &reports {
+            // Each deck plays 3 battles against each of the other 3 decks.
+            assert!(report.win_cnt + report.draw_cnt <= 9, "{:?}", report);
+        }
+    }
+
+    #[test]
+    fn test_seed() {
+        let context = Arc::new(Context {
+            all_cards: load_test_cards(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        });
+        let initial_population = |seed: u64| {
+            let mut args = new_test_args(1);
+            args.seed = seed;
+            let board = engine::load_board(&args.board_path);
+            let inventory = context.get_cards(&(1..=30).collect::<Vec<u32>>());
+            let mut deck_builder = DeckBuilder::new(&context, board, None, args, inventory, None);
+            deck_builder
+                .create_initial_population()
+                .iter()
+                .map(|deck| engine::to_ids(deck))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(initial_population(1), initial_population(1));
+        assert_ne!(initial_population(1), initial_population(2));
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let context = Arc::new(Context {
+            all_cards: load_test_cards(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        });
+        let path = std::env::temp_dir().join(format!("takoyaki-checkpoint-{}", std::process::id()));
+        let run = |max_generation: u32, checkpoint_path: Option<PathBuf>, resume: bool| {
+            let mut args = new_test_args(2);
+            args.max_generation = max_generation;
+            args.mutation_rate = 0.1;
+            args.checkpoint_path = checkpoint_path;
+            args.resume = resume;
+            let board = engine::load_board(&args.board_path);
+            // More cards than a deck so that decks evolve.
+            let inventory = context.get_cards(&(1..=30).collect::<Vec<u32>>());
+            let mut deck_builder = DeckBuilder::new(&context, board, None, args, inventory, None);
+            deck_builder
+                .run()
+                .iter()
+                .map(|deck| engine::to_ids(deck))
+                .collect::<Vec<_>>()
+        };
+
+        let straight = run(3, None, false);
+        let interrupted = run(2, Some(path.clone()), false);
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        assert_eq!(2, checkpoint.generation);
+        assert_eq!(interrupted, checkpoint.population);
+        assert_ne!(straight, interrupted);
+
+        let resumed = run(3, Some(path.clone()), true);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(straight, resumed);
+    }
+
+    #[test]
+    fn test_output_deck() {
+        let context = Arc::new(Context {
+            all_cards: load_test_cards(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        });
+        let path =
+            std::env::temp_dir().join(format!("takoyaki-output-deck-{}", std::process::id()));
+        let mut args = new_test_args(2);
+        args.output_deck = Some(path.clone());
+        let board = engine::load_board(&args.board_path);
+        let inventory = context.get_cards(&(1..=30).collect::<Vec<u32>>());
+        let mut deck_builder = DeckBuilder::new(&context, board, None, args, inventory, None);
+
+        let deck = deck_builder.create_initial_population().remove(0);
+        deck_builder
+            .write_best_deck(&path, 7, &deck, 0.625)
+            .unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            text.contains("\n# validation win rate: 0.625 (generation 7, against "),
+            "{}",
+            text
+        );
+        assert_eq!(engine::to_ids(&deck), engine::load_deck(&path));
+        std::fs::remove_file(&path).unwrap();
+
+        // Training writes one of the evaluated decks.
+        deck_builder.run();
+        let written = engine::load_deck(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(Ok(()), context.validate_deck(&written));
+    }
+
+    #[test]
+    fn test_crossover_keeps_duplicates() {
+        let context = Arc::new(Context {
+            all_cards: load_test_cards(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet {
+                max_card_copies: 3,
+                ..engine::RuleSet::default()
+            },
+        });
+        let mut args = new_test_args(1);
+        args.max_card_copies = 3;
+        let board = engine::load_board(&args.board_path);
+        let mut ids: Vec<u32> = vec![42, 42, 42];
+        ids.extend(1..=12);
+        let deck = context.get_cards(&ids);
+        let mut deck_builder = DeckBuilder::new(&context, board, None, args, deck.clone(), None);
+
+        // Both parents have all copies, so the child inherits all of them.
+        let a = Report::new(&deck, 1, 0, 0.0);
+        let b = Report::new(&deck, 2, 0, 0.0);
+        let mut child = deck_builder.crossover(&a, &b);
+        engine::sort_by_id(&mut child);
+        let mut expected = deck.clone();
+        engine::sort_by_id(&mut expected);
+        assert_eq!(engine::to_ids(&expected), engine::to_ids(&child));
+        assert_eq!(
+            Ok(()),
+            context.rules.check_card_copies(&engine::to_ids(&child))
+        );
+    }
+
+    #[test]
+    fn test_sort_reports_ties() {
+        let all_cards = load_test_cards();
+        let decks: Vec<Vec<Card>> = [[4, 1, 3], [1, 2, 4], [1, 2, 3], [2, 5, 1]]
+            .iter()
+            .map(|ids| ids.iter().map(|id| all_cards[id].clone()).collect())
+            .collect();
+        let new_reports = |order: &[usize]| -> Vec<Report> {
+            order
+                .iter()
+                // Only the last deck wins more.
+                .map(|i| Report::new(&decks[*i], if *i == 3 { 5 } else { 2 }, 0, 0.0))
+                .collect()
+        };
+
+        let mut reports = new_reports(&[0, 1, 2, 3]);
+        sort_reports(&mut reports);
+        let sorted_ids = reports
+            .iter()
+            .map(|r| engine::to_ids(r.deck))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![vec![2, 5, 1], vec![1, 2, 3], vec![1, 2, 4], vec![4, 1, 3]],
+            sorted_ids
+        );
+
+        // The order doesn't depend on the original order.
+        let mut reports = new_reports(&[2, 3, 1, 0]);
+        sort_reports(&mut reports);
+        assert_eq!(
+            sorted_ids,
+            reports
+                .iter()
+                .map(|r| engine::to_ids(r.deck))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_tournament_selection() {
+        let all_cards = load_test_cards();
+        let decks: Vec<Vec<Card>> = (1..=4).map(|id| vec![all_cards[&id].clone()]).collect();
+        let mut reports: Vec<Report> = decks
+            .iter()
+            .enumerate()
+            .map(|(i, deck)| Report::new(deck, i as u32, 0, 0.0))
+            .collect();
+        sort_reports(&mut reports);
+
+        let mut rng = Mt64::new(42);
+        let selector = ParentSelector::new(Selection::Tournament, reports.len(), &reports);
+        for _ in 0..100 {
+            assert_eq!(0, selector.select(&mut rng, reports.len()));
+        }
+
+        // Too big tournaments are the same as the whole population.
+        let selector = ParentSelector::new(Selection::Tournament, 10, &reports);
+        assert_eq!(0, selector.select(&mut rng, reports.len()));
+
+        // Otherwise weaker decks are chosen sometimes.
+        let selector = ParentSelector::new(Selection::Tournament, 2, &reports);
+        let selected: Vec<usize> = (0..100)
+            .map(|_| selector.select(&mut rng, reports.len()))
+            .collect();
+        assert!(selected.contains(&0));
+        assert!(selected.iter().any(|i| *i != 0));
+        assert!(!selected.contains(&3));
+    }
+
+    #[test]
+    fn test_draw_reward() {
+        let all_cards = load_test_cards();
+        let decisive: Vec<Card> = [1, 2, 3].iter().map(|id| all_cards[id].clone()).collect();
+        let drawish: Vec<Card> = [4, 5, 6].iter().map(|id| all_cards[id].clone()).collect();
+
+        // Same win counts but `drawish` draws the rest of games.
+        let new_reports = |draw_reward: f64| {
+            (
+                Report::new(&decisive, 3, 0, draw_reward),
+                Report::new(&drawish, 3, 7, draw_reward),
+            )
+        };
+
+        let (d, o) = new_reports(0.0);
+        assert_eq!(d.fitness, o.fitness);
+
+        let (d, o) = new_reports(0.5);
+        assert_lt!(d.fitness, o.fitness);
+
+        let (d, o) = new_reports(-0.5);
+        assert_gt!(d.fitness, o.fitness);
+        assert_eq!(0.0, o.get_weight());
+    }
+
+    /// Scores indexed by the number of turns played where the player overtakes the opponent
+    /// after `lead_turn` turns and keeps the lead.
+    fn score_history_with_lead(lead_turn: i32) -> Vec<(u32, u32)> {
+        (0..=engine::TURN_COUNT)
+            .map(|turn| {
+                let player = 1 + 3 * turn as u32;
+                let opponent = 1 + 2 * turn as u32 + if turn < lead_turn { 10 } else { 0 };
+                (player, opponent)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tempo() {
+        let early = score_history_with_lead(6);
+        let late = score_history_with_lead(11);
+        assert_eq!(Some(6), tempo::lasting_lead_turn(&early));
+        assert_eq!(Some(11), tempo::lasting_lead_turn(&late));
+        // Both win at the end.
+        assert_gt!(early.last().unwrap().0, early.last().unwrap().1);
+        assert_gt!(late.last().unwrap().0, late.last().unwrap().1);
+
+        let all_cards = load_test_cards();
+        let fast: Vec<Card> = [1, 2, 3].iter().map(|id| all_cards[id].clone()).collect();
+        let slow: Vec<Card> = [4, 5, 6].iter().map(|id| all_cards[id].clone()).collect();
+        let new_reports = |tempo_weight: f64| {
+            (
+                Report::new(&fast, 1, 0, 0.0).with_tempo(tempo::tempo_score(&early), tempo_weight),
+                Report::new(&slow, 1, 0, 0.0).with_tempo(tempo::tempo_score(&late), tempo_weight),
+            )
+        };
 
-    let mut player = args.player.create_player(&context, rng.next_u64());
-    let mut opponent = args.opponent.create_player(&context, rng.next_u64());
+        let (f, s) = new_reports(0.0);
+        assert_eq!(f.fitness, s.fitness);
+        assert_gt!(f.tempo, s.tempo);
+
+        let (f, s) = new_reports(0.5);
+        assert_gt!(f.fitness, s.fitness);
+    }
 
-    let ids = engine::load_deck(&args.inventory_path);
-    let card_map = ids.iter().map(|id| (*id, context.get_card(*id))).collect();
-    DeckBuilder::new(&context, board, args, card_map).run(&mut *player, &mut *opponent);
+    #[test]
+    fn test_score_history_on_north() {
+        let context = Arc::new(Context {
+            all_cards: load_test_cards(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        });
+        let args = new_test_args(1);
+        let board = engine::load_board(&args.board_path);
+        let deck = context.get_cards(&engine::load_deck(&args.inventory_path)[..engine::DECK_SIZE]);
+        let mut greedy = players::greedy::GreedyPlayer::new("greedy".into());
+        let mut random = players::random::RandomPlayer::new("random".into(), 42);
+        let mut player = ScoreRecorder::new(&mut greedy);
+        let (outcome, state) = local::run_with_script_on_side(
+            &context,
+            &board,
+            &deck,
+            &deck,
+            &mut player,
+            &mut random,
+            &mut Mt64::new(42),
+            engine::PlayerId::North,
+            [DealPolicy::Shuffle; 2],
+            &Script::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!("greedy", outcome.north.name);
+        let view = outcome.view(engine::PlayerId::North);
+        let (p, o) = (view.get_my_score(), view.get_opponent_score());
+        let (south, north) = state.get_scores();
+        assert_eq!((north, south), (p, o));
+
+        let history = player.get_score_history((p, o));
+        assert_eq!(engine::TURN_COUNT as usize + 1, history.len());
+        assert_eq!(Some(&(p, o)), history.last());
+        // The greedy player outpaints the random one, so it leads before the end from its view.
+        assert_gt!(p, o);
+        let before_end = history[history.len() - 2];
+        assert_gt!(before_end.0, before_end.1);
+    }
+
+    #[test]
+    fn test_lasting_lead_turn() {
+        // The player leads in the middle but loses it.
+        assert_eq!(None, tempo::lasting_lead_turn(&[(1, 1), (5, 3), (5, 8)]));
+        assert_eq!(None, tempo::lasting_lead_turn(&[(1, 1), (5, 5)]));
+        assert_eq!(
+            Some(2),
+            tempo::lasting_lead_turn(&[(1, 1), (2, 3), (4, 3), (6, 3)])
+        );
+        assert_eq!(0.0, tempo::tempo_score(&[(1, 1), (5, 8)]));
+    }
 }
@@ -0,0 +1,109 @@
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use engine::{
+    Action,
+    Board,
+    Card,
+    Context,
+    PlayerId,
+    State,
+};
+use players::{
+    Player,
+    SearchInfo,
+};
+
+/// A player which remembers scores of the board at the beginning of each turn.
+pub struct ScoreRecorder<'p> {
+    player: &'p mut dyn Player,
+    player_id: PlayerId,
+    scores: Vec<(u32, u32)>,
+}
+
+impl<'p> ScoreRecorder<'p> {
+    pub fn new(player: &'p mut dyn Player) -> Self {
+        ScoreRecorder {
+            player,
+            player_id: PlayerId::South,
+            scores: vec![],
+        }
+    }
+
+    /// Returns (the player's score, the opponent's score) indexed by the number of turns played.
+    /// `final_scores` are (the player's score, the opponent's score) at the end of the game,
+    /// which the player never sees, e.g. the ones `local::run_with_script_on_side` returns.
+    pub fn get_score_history(&self, final_scores: (u32, u32)) -> Vec<(u32, u32)> {
+        let mut history: Vec<(u32, u32)> = self
+            .scores
+            .iter()
+            .map(|&(south, north)| match self.player_id {
+                PlayerId::South => (south, north),
+                PlayerId::North => (north, south),
+            })
+            .collect();
+        history.push(final_scores);
+        history
+    }
+}
+
+impl<'p> Player for ScoreRecorder<'p> {
+    fn get_name(&self) -> &str {
+        self.player.get_name()
+    }
+
+    fn init_game(
+        &mut self,
+        player_id: PlayerId,
+        context: &Arc<Context>,
+        board: &Board,
+        deck: Vec<Card>,
+    ) {
+        self.player_id = player_id;
+        self.scores.clear();
+        self.player.init_game(player_id, context, board, deck);
+    }
+
+    fn need_redeal_hands(&mut self, dealed_cards: &[Card], time_limit: &Duration) -> bool {
+        self.player.need_redeal_hands(dealed_cards, time_limit)
+    }
+
+    fn get_action(&mut self, state: &State, hands: &[Card], time_limit: &Duration) -> Action {
+        self.scores.push(state.get_scores());
+        self.player.get_action(state, hands, time_limit)
+    }
+
+    fn get_last_search_info(&self) -> Option<SearchInfo> {
+        self.player.get_last_search_info()
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.player.reseed(seed)
+    }
+}
+
+/// Returns the number of turns played when the player took the lead and kept it until the end.
+/// `None` if the player doesn't lead at the end of the game.
+pub fn lasting_lead_turn(score_history: &[(u32, u32)]) -> Option<i32> {
+    let trailing_turns = score_history
+        .iter()
+        .rev()
+        .take_while(|(player, opponent)| player > opponent)
+        .count();
+    if trailing_turns == 0 {
+        return None;
+    }
+    Some((score_history.len() - trailing_turns) as i32)
+}
+
+/// How early the player took the lasting lead, between 0 (never or at the last turn) and
+/// 1 (at the first turn).
+pub fn tempo_score(score_history: &[(u32, u32)]) -> f64 {
+    match lasting_lead_turn(score_history) {
+        // Leading before the first turn is possible only on asymmetric boards.
+        Some(turn) => (engine::TURN_COUNT - turn.max(1)) as f64 / (engine::TURN_COUNT - 1) as f64,
+        None => 0.0,
+    }
+}
@@ -0,0 +1,182 @@
+//! Benchmarks of the hot paths of the engine.
+//!
+//! Run with `cargo bench -p engine`. States are made by random playouts with fixed seeds on
+//! `massugu_street` with the starter deck so that results are comparable between runs.
+
+use std::path::PathBuf;
+
+use criterion::{
+    criterion_group,
+    criterion_main,
+    BatchSize,
+    Criterion,
+};
+use rand::seq::SliceRandom;
+use rand_mt::Mt64;
+
+use engine::{
+    Action,
+    Board,
+    Card,
+    Context,
+    PlayerCardState,
+    PlayerId,
+    State,
+};
+
+const SEED: u64 = 0x42;
+
+/// Turns where states of each game phase are taken.
+const PHASES: [(&str, i32); 3] = [("early", 1), ("mid", 6), ("late", 11)];
+
+struct Fixture {
+    board: Board,
+    deck: Vec<Card>,
+}
+
+impl Fixture {
+    fn load() -> Fixture {
+        let data_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../data");
+        let context = Context {
+            all_cards: engine::load_cards(data_dir.join("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        };
+        Fixture {
+            board: engine::load_board(&data_dir.join("boards/massugu_street")),
+            deck: context.get_cards(&engine::load_deck(&data_dir.join("decks/starter"))),
+        }
+    }
+
+    fn new_game(&self, rng: &mut Mt64) -> (State, [PlayerCardState; 2]) {
+        let deal = |player_id: PlayerId, rng: &mut Mt64| {
+            let mut deck = self.deck.clone();
+            deck.shuffle(rng);
+            let deck_cards = deck.split_off(engine::HAND_SIZE);
+            PlayerCardState::new(player_id, deck, deck_cards)
+        };
+        let south = deal(PlayerId::South, rng);
+        let north = deal(PlayerId::North, rng);
+        (
+            State::new(self.board.clone(), 0, 0, 0, vec![], vec![]),
+            [south, north],
+        )
+    }
+}
+
+fn random_action(state: &State, player_state: &PlayerCardState, rng: &mut Mt64) -> Action {
+    let mut actions = vec![];
+    engine::append_valid_actions(
+        state,
+        player_state.get_hands(),
+        player_state.get_player_id(),
+        &mut actions,
+    );
+    actions.choose(rng).unwrap().clone()
+}
+
+/// Plays random actions until the turn (starting from 0) reaches `until_turn`.
+fn playout(
+    state: &mut State,
+    player_states: &mut [PlayerCardState; 2],
+    until_turn: i32,
+    rng: &mut Mt64,
+) {
+    while state.get_turn() < until_turn {
+        let south_action = random_action(state, &player_states[0], rng);
+        let north_action = random_action(state, &player_states[1], rng);
+        engine::update_state(state, &south_action, &north_action).unwrap();
+        engine::update_player_state(state, &mut player_states[0], &south_action);
+        engine::update_player_state(state, &mut player_states[1], &north_action);
+    }
+}
+
+/// States at the beginning of the turns listed in `PHASES`.
+fn phase_states(fixture: &Fixture) -> Vec<(&'static str, State, [PlayerCardState; 2])> {
+    PHASES
+        .iter()
+        .map(|(name, turn)| {
+            let mut rng = Mt64::new(SEED);
+            let (mut state, mut player_states) = fixture.new_game(&mut rng);
+            playout(&mut state, &mut player_states, *turn, &mut rng);
+            (*name, state, player_states)
+        })
+        .collect()
+}
+
+fn bench_move_generation(c: &mut Criterion) {
+    let fixture = Fixture::load();
+    let mut group = c.benchmark_group("append_valid_actions");
+    for (name, state, player_states) in phase_states(&fixture) {
+        let hands = player_states[0].get_hands();
+        let mut actions = vec![];
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                actions.clear();
+                engine::append_valid_actions(&state, hands, PlayerId::South, &mut actions);
+                actions.len()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_update_state(c: &mut Criterion) {
+    let fixture = Fixture::load();
+    let mut group = c.benchmark_group("update_state");
+    for (name, state, player_states) in phase_states(&fixture) {
+        let mut rng = Mt64::new(SEED);
+        let south_action = random_action(&state, &player_states[0], &mut rng);
+        let north_action = random_action(&state, &player_states[1], &mut rng);
+        group.bench_function(name, |b| {
+            b.iter_batched_ref(
+                || state.clone(),
+                |state| engine::update_state(state, &south_action, &north_action),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_get_scores(c: &mut Criterion) {
+    let fixture = Fixture::load();
+    let mut group = c.benchmark_group("get_scores");
+    for (name, state, _) in phase_states(&fixture) {
+        group.bench_function(name, |b| b.iter(|| state.board.get_scores()));
+    }
+    group.finish();
+}
+
+fn bench_count_surrounded_special_ink(c: &mut Criterion) {
+    let fixture = Fixture::load();
+    let mut group = c.benchmark_group("count_surrounded_special_ink");
+    for (name, state, _) in phase_states(&fixture) {
+        group.bench_function(name, |b| {
+            b.iter(|| state.board.count_surrounded_special_ink())
+        });
+    }
+    group.finish();
+}
+
+fn bench_playout(c: &mut Criterion) {
+    let fixture = Fixture::load();
+    c.bench_function("playout", |b| {
+        let mut rng = Mt64::new(SEED);
+        b.iter(|| {
+            let (mut state, mut player_states) = fixture.new_game(&mut rng);
+            playout(&mut state, &mut player_states, engine::TURN_COUNT, &mut rng);
+            state.board.get_scores()
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_move_generation,
+    bench_update_state,
+    bench_get_scores,
+    bench_count_surrounded_special_ink,
+    bench_playout
+);
+criterion_main!(benches);
@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+
+use log::*;
+
+use super::{
+    board::{
+        Board,
+        BoardCell,
+        BoardPosition,
+    },
+    card::{
+        Card,
+        CardPosition,
+    },
+    game::{
+        Action,
+        PlayerId,
+        Rotation,
+    },
+    state::{
+        self,
+        State,
+    },
+};
+
+/// Positions where each card fits in a board without overlapping its walls.
+///
+/// Walls of a board never disappear during a game, so positions blocked by them can be skipped
+/// before checking ink and special ink. Walls which appear during the game (e.g. both players
+/// inked a cell at once) are still checked at runtime, so the index is always a superset of the
+/// valid positions.
+///
+/// Cards which aren't indexed and boards which lack any wall of the indexed one fall back to
+/// `engine::append_valid_actions`.
+///
+/// MCTS uses the index to expand nodes and, through `get_fitting_positions`, to skip positions
+/// in playouts, which dominate the search. Playouts often try positions sticking out of the
+/// board, which are checked cell by cell without the index. `mcts-1000` v.s. `mcts-1000` with
+/// the starter deck runs ~1,060 -> ~1,290 iterations/s on massugu_street and ~960 -> ~1,440 on
+/// pokkari_lake with the index (a single core, release build, 3 games each).
+#[derive(Clone, Debug, Default)]
+pub struct ActionIndex {
+    board_size: (i32, i32),
+
+    /// Walls of the indexed board. Boards with the same name can still have different walls.
+    walls: Vec<BoardPosition>,
+
+    /// Positions of each card id in the same order as `engine::append_valid_actions` visits.
+    positions: HashMap<u32, Vec<CardPosition>>,
+
+    /// Whether each position is in `positions`, indexed by `position_index`.
+    fits: HashMap<u32, Vec<bool>>,
+}
+
+impl ActionIndex {
+    pub fn new<'a>(board: &Board, cards: impl IntoIterator<Item = &'a Card>) -> Self {
+        let positions: HashMap<u32, Vec<CardPosition>> = cards
+            .into_iter()
+            .map(|card| (card.get_id(), fitting_positions(board, card)))
+            .collect();
+        debug!(
+            "Indexed positions of {} cards on {}",
+            positions.len(),
+            board.get_name()
+        );
+        let (width, height) = board.get_size();
+        let size = (Rotation::VALUES.len() as i32 * width * height) as usize;
+        let fits = positions
+            .iter()
+            .map(|(id, positions)| {
+                let mut fits = vec![false; size];
+                for pos in positions {
+                    fits[position_index(board.get_size(), pos).unwrap()] = true;
+                }
+                (*id, fits)
+            })
+            .collect();
+        let walls = (0..height)
+            .flat_map(|y| {
+                (0..width).map(move |x| BoardPosition {
+                    x,
+                    y,
+                })
+            })
+            .filter(|pos| board.get_cell(*pos) == BoardCell::Wall)
+            .collect();
+        ActionIndex {
+            board_size: board.get_size(),
+            walls,
+            positions,
+            fits,
+        }
+    }
+
+    /// Whether positions skipped by the index are invalid on `board` too, i.e. `board` keeps
+    /// all walls of the indexed board.
+    pub fn is_for(&self, board: &Board) -> bool {
+        self.board_size == board.get_size()
+            && self
+                .walls
+                .iter()
+                .all(|pos| board.get_cell(*pos) == BoardCell::Wall)
+    }
+
+    /// Positions of `card` in the index, or `None` if the card isn't indexed. Look it up once
+    /// per card rather than once per position since it's a hash map lookup. Check `is_for` with
+    /// the board first.
+    pub fn get_fitting_positions(&self, card: &Card) -> Option<FittingPositions<'_>> {
+        self.fits.get(&card.get_id()).map(|fits| FittingPositions {
+            board_size: self.board_size,
+            fits,
+        })
+    }
+
+    /// Same as `engine::append_valid_actions` including the order of the actions.
+    pub fn append_valid_actions(
+        &self,
+        state: &State,
+        cards: &[Card],
+        player_id: PlayerId,
+        actions: &mut Vec<Action>,
+    ) {
+        if !self.is_for(&state.board) {
+            state::append_valid_actions(state, cards, player_id, actions);
+            return;
+        }
+        let blocked_rows = state::blocked_row_masks(&state.board);
+        for card in cards {
+            let positions = match self.positions.get(&card.get_id()) {
+                Some(positions) => positions,
+                None => {
+                    state::append_valid_actions(
+                        state,
+                        std::slice::from_ref(card),
+                        player_id,
+                        actions,
+                    );
+                    continue;
+                }
+            };
+            actions.push(Action::Pass(card.clone()));
+            for pos in positions {
+                if let Some(blocked_rows) = &blocked_rows {
+                    let footprint = card.get_footprint(pos.rotation);
+                    if state::overlaps(blocked_rows, footprint, pos.x, pos.y) {
+                        continue;
+                    }
+                }
+                for action in [
+                    Action::Put(card.clone(), *pos),
+                    Action::Special(card.clone(), *pos),
+                ] {
+                    if state::is_valid_action(state, player_id, &action) {
+                        actions.push(action);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Positions of a card returned by `ActionIndex::get_fitting_positions`.
+#[derive(Clone, Copy, Debug)]
+pub struct FittingPositions<'a> {
+    board_size: (i32, i32),
+    fits: &'a [bool],
+}
+
+impl<'a> FittingPositions<'a> {
+    /// Whether the card at `position` is inside of the indexed board and doesn't overlap its
+    /// walls.
+    pub fn contains(&self, position: &CardPosition) -> bool {
+        position_index(self.board_size, position).is_some_and(|i| self.fits[i])
+    }
+}
+
+fn position_index((width, height): (i32, i32), pos: &CardPosition) -> Option<usize> {
+    if !(0..width).contains(&pos.x) || !(0..height).contains(&pos.y) {
+        return None;
+    }
+    Some((((pos.rotation as i32 * height) + pos.y) * width + pos.x) as usize)
+}
+
+/// Positions where `card` is inside of the board and doesn't overlap walls.
+fn fitting_positions(board: &Board, card: &Card) -> Vec<CardPosition> {
+    let (width, height) = board.get_size();
+    let mut positions = vec![];
+    for rotation in Rotation::VALUES {
+        let card_width = card.calculate_width(rotation);
+        let card_height = card.calculate_height(rotation);
+        for y in 1..=height - 1 - card_height {
+            for x in 1..=width - 1 - card_width {
+                let pos = CardPosition {
+                    x,
+                    y,
+                    rotation,
+                };
+                let hits_wall = card
+                    .get_cells_on_board_coord(&pos)
+                    .any(|(board_pos, _)| board.get_cell(board_pos) == BoardCell::Wall);
+                if !hits_wall {
+                    positions.push(pos);
+                }
+            }
+        }
+    }
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use rand::seq::SliceRandom;
+    use rand_mt::Mt64;
+
+    use super::*;
+    use crate::{
+        state::tests::update_state_checked,
+        *,
+    };
+
+    #[test]
+    fn test_same_as_brute_force() {
+        let data_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../data");
+        let context = Context {
+            all_cards: load_cards(data_dir.join("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: RuleSet::default(),
+        };
+        let mut all_cards: Vec<Card> = context.all_cards.values().cloned().collect();
+        sort_by_id(&mut all_cards);
+
+        let mut rng = Mt64::new(42);
+        for board in load_boards(data_dir.join("boards").to_str().unwrap()) {
+            // Leave some cards out of the index to check the fallback.
+            let index = ActionIndex::new(&board, all_cards.iter().skip(1));
+            let mut state = State::new(board.clone(), 0, 10, 10, vec![], vec![]);
+            while !state.is_end() {
+                let hands: Vec<Card> = all_cards.choose_multiple(&mut rng, 4).cloned().collect();
+                let mut turn_actions = vec![];
+                for player_id in [PlayerId::South, PlayerId::North] {
+                    let mut expected = vec![];
+                    append_valid_actions(&state, &hands, player_id, &mut expected);
+                    let mut actual = vec![];
+                    index.append_valid_actions(&state, &hands, player_id, &mut actual);
+                    assert_eq!(expected, actual, "{}\n{}", board.get_name(), state);
+                    turn_actions.push(actual.choose(&mut rng).unwrap().clone());
+                }
+                update_state_checked(&mut state, &turn_actions[0], &turn_actions[1]).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_board_with_other_walls() {
+        let card = load_card_from_lines(1, "dot".into(), 1, 1, &["=".to_string()]);
+        #[rustfmt::skip]
+        let indexed = load_board_from_lines(
+            String::from("test_board"),
+            &[
+            "#####",
+            "#.#O#",
+            "#P..#",
+            "#####"
+            ]);
+        #[rustfmt::skip]
+        let board = load_board_from_lines(
+            String::from("test_board"),
+            &[
+            "#####",
+            "#..O#",
+            "#P..#",
+            "#####"
+            ]);
+        let index = ActionIndex::new(&indexed, [&card]);
+        let hands = [card];
+        let state = State::new(board, 0, 0, 0, vec![], vec![]);
+
+        let mut expected = vec![];
+        append_valid_actions(&state, &hands, PlayerId::South, &mut expected);
+        let mut actual = vec![];
+        index.append_valid_actions(&state, &hands, PlayerId::South, &mut actual);
+        assert_eq!(expected, actual);
+    }
+}
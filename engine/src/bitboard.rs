@@ -0,0 +1,257 @@
+use super::{
+    board::{
+        BoardCell,
+        BoardPosition,
+    },
+    game::PlayerId,
+};
+
+/// Cells of a board as bit masks of each row, one set of rows per kind of cells.
+/// Bit `x` of the `y`-th row is set if the cell at `(x, y)` is of the kind.
+///
+/// The layout is the same as `CardImpl::get_footprint` so that checks of a card against a board
+/// are bitwise operations on a few rows. Only boards up to 64 cells wide can be represented.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BitBoard {
+    width: i32,
+    height: i32,
+
+    walls: Vec<u64>,
+    /// Normal ink, indexed by `PlayerId::to_index`.
+    ink: [Vec<u64>; 2],
+    /// Special ink, indexed by `PlayerId::to_index`.
+    special: [Vec<u64>; 2],
+}
+
+impl BitBoard {
+    /// Returns None if the board is too wide for the masks.
+    pub fn new(cells: &[Vec<BoardCell>]) -> Option<Self> {
+        let width = cells.first().map_or(0, |row| row.len());
+        if width > u64::BITS as usize {
+            return None;
+        }
+        let rows = vec![0; cells.len()];
+        let mut bits = BitBoard {
+            width: width as i32,
+            height: cells.len() as i32,
+            walls: rows.clone(),
+            ink: [rows.clone(), rows.clone()],
+            special: [rows.clone(), rows],
+        };
+        for (y, row) in cells.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                let position = BoardPosition {
+                    x: x as i32,
+                    y: y as i32,
+                };
+                bits.put_cell(position, *cell);
+            }
+        }
+        Some(bits)
+    }
+
+    /// The position must be on the board.
+    pub fn put_cell(&mut self, position: BoardPosition, cell: BoardCell) {
+        let y = position.y as usize;
+        let bit = 1 << position.x;
+        self.walls[y] &= !bit;
+        for player_id in [PlayerId::South, PlayerId::North] {
+            self.ink[player_id.to_index()][y] &= !bit;
+            self.special[player_id.to_index()][y] &= !bit;
+        }
+        let rows = match cell {
+            BoardCell::None => return,
+            BoardCell::Wall => &mut self.walls,
+            BoardCell::Ink(player_id) => &mut self.ink[player_id.to_index()],
+            BoardCell::Special(player_id) => &mut self.special[player_id.to_index()],
+        };
+        rows[y] |= bit;
+    }
+
+    /// Walls and special ink of both players, which no card can be put on.
+    pub fn get_blocked_rows(&self) -> Vec<u64> {
+        (0..self.height as usize)
+            .map(|y| self.walls[y] | self.special[0][y] | self.special[1][y])
+            .collect()
+    }
+
+    /// Cells which are not `BoardCell::None`.
+    fn get_filled_row(&self, y: usize) -> u64 {
+        self.walls[y] | self.ink[0][y] | self.ink[1][y] | self.special[0][y] | self.special[1][y]
+    }
+
+    /// Whether a card of `footprint` at `(x, y)` and the cells around it are on the board,
+    /// which the checks below require.
+    pub fn fits(&self, footprint: &[u64], x: i32, y: i32) -> bool {
+        let columns = footprint.iter().fold(0, |columns, row| columns | row);
+        let width = (u64::BITS - columns.leading_zeros()) as i32;
+        let height = footprint.len() as i32;
+        x >= 1 && y >= 1 && x + width < self.width && y + height < self.height
+    }
+
+    /// Whether a card of `footprint` at `(x, y)` overlaps cells it can't be put on.
+    /// Special attacks can overwrite normal ink. The card must `fits` the board.
+    pub fn has_conflict(&self, footprint: &[u64], x: i32, y: i32, special: bool) -> bool {
+        footprint.iter().enumerate().any(|(dy, card_row)| {
+            let board_y = y as usize + dy;
+            let mut blocked =
+                self.walls[board_y] | self.special[0][board_y] | self.special[1][board_y];
+            if !special {
+                blocked |= self.ink[0][board_y] | self.ink[1][board_y];
+            }
+            card_row << x & blocked != 0
+        })
+    }
+
+    /// Whether a card of `surroundings` (see `CardImpl::get_surroundings`) at `(x, y)` touches
+    /// ink of `player_id`. Special attacks need to touch special ink.
+    /// The card must `fits` the board.
+    pub fn has_touching_point(
+        &self,
+        player_id: PlayerId,
+        surroundings: &[u64],
+        x: i32,
+        y: i32,
+        special: bool,
+    ) -> bool {
+        let index = player_id.to_index();
+        surroundings.iter().enumerate().any(|(dy, around_row)| {
+            let board_y = (y - 1) as usize + dy;
+            let mut touching = self.special[index][board_y];
+            if !special {
+                touching |= self.ink[index][board_y];
+            }
+            around_row << (x - 1) & touching != 0
+        })
+    }
+
+    /// Same as `Board::count_surrounded_special_ink`.
+    pub fn count_surrounded_special_ink(&self) -> (i32, i32) {
+        let all = self.all_columns();
+        self.count_surrounded_special_ink_in_rows((0..self.height).map(|y| (y, all)))
+    }
+
+    /// Same as `count_surrounded_special_ink` but only counts special ink in `area`, bit masks of
+    /// each row in the same layout as the board.
+    pub fn count_surrounded_special_ink_in(&self, area: &[u64]) -> (i32, i32) {
+        self.count_surrounded_special_ink_in_rows(
+            area.iter()
+                .enumerate()
+                .filter(|(_, columns)| **columns != 0)
+                .map(|(y, columns)| (y as i32, *columns)),
+        )
+    }
+
+    fn all_columns(&self) -> u64 {
+        u64::MAX
+            .checked_shr(u64::BITS - self.width as u32)
+            .unwrap_or(0)
+    }
+
+    /// Counts special ink surrounded by filled cells in the columns of each row.
+    fn count_surrounded_special_ink_in_rows(
+        &self,
+        rows: impl Iterator<Item = (i32, u64)>,
+    ) -> (i32, i32) {
+        let all = self.all_columns();
+        // Cells out side of the board are walls.
+        let filled_row = |y: i32| {
+            if (0..self.height).contains(&y) {
+                self.get_filled_row(y as usize)
+            } else {
+                all
+            }
+        };
+        let right_edge = all ^ all >> 1;
+        let mut counts = [0; 2];
+        for (y, columns) in rows {
+            // Bit `x` is set if all cells around `(x, y)` are filled.
+            let mut surrounded = columns;
+            for around_y in [y - 1, y, y + 1] {
+                let row = filled_row(around_y);
+                surrounded &= (row << 1 | 1) & (row >> 1 | right_edge);
+                if around_y != y {
+                    surrounded &= row;
+                }
+            }
+            for player_id in [PlayerId::South, PlayerId::North] {
+                let index = player_id.to_index();
+                counts[index] += (self.special[index][y as usize] & surrounded).count_ones() as i32;
+            }
+        }
+        (counts[0], counts[1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use rand::seq::SliceRandom;
+    use rand_mt::Mt64;
+
+    use crate::{
+        state::tests::update_state_checked,
+        *,
+    };
+
+    fn assert_same_as_cells(board: &Board) {
+        let bits = board.get_bits().unwrap();
+        assert_eq!(
+            board.count_surrounded_special_ink_by_cells(),
+            bits.count_surrounded_special_ink(),
+            "{}",
+            board
+        );
+        let (width, height) = board.get_size();
+        let area = vec![u64::MAX >> (u64::BITS - width as u32); height as usize];
+        assert_eq!(
+            bits.count_surrounded_special_ink(),
+            bits.count_surrounded_special_ink_in(&area)
+        );
+        // Bits updated by `put_cell` are the same as ones made from scratch.
+        let lines = board.to_lines();
+        let refs: Vec<&str> = lines.iter().map(AsRef::as_ref).collect();
+        let loaded = load_board_from_lines(board.get_name().into(), &refs);
+        assert_eq!(loaded.get_bits(), Some(bits));
+    }
+
+    #[test]
+    fn test_count_surrounded_special_ink() {
+        #[rustfmt::skip]
+        let board = load_board_from_lines(String::from("test_board"), &[
+            "Pp.O",
+            "ppoo",
+        ]);
+        assert_eq!(
+            (1, 0),
+            board.get_bits().unwrap().count_surrounded_special_ink()
+        );
+        assert_same_as_cells(&board);
+
+        let data_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../data");
+        let all_cards = load_cards(data_dir.join("cards").to_str().unwrap()).unwrap();
+        let mut all_cards: Vec<Card> = all_cards.values().cloned().collect();
+        sort_by_id(&mut all_cards);
+
+        let mut rng = Mt64::new(42);
+        let mut surrounded_count = 0;
+        for board in load_boards(data_dir.join("boards").to_str().unwrap()) {
+            let mut state = State::new(board, 0, 0, 0, vec![], vec![]);
+            while !state.is_end() {
+                let hands: Vec<Card> = all_cards.choose_multiple(&mut rng, 4).cloned().collect();
+                let mut turn_actions = vec![];
+                for player_id in [PlayerId::South, PlayerId::North] {
+                    let mut actions = vec![];
+                    append_valid_actions(&state, &hands, player_id, &mut actions);
+                    turn_actions.push(actions.choose(&mut rng).unwrap().clone());
+                }
+                update_state_checked(&mut state, &turn_actions[0], &turn_actions[1]).unwrap();
+                assert_same_as_cells(&state.board);
+            }
+            let (south, north) = state.board.count_surrounded_special_ink();
+            surrounded_count += south + north;
+        }
+        assert!(surrounded_count > 0);
+    }
+}
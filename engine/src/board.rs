@@ -16,7 +16,10 @@ use std::{
 
 use log::*;
 
-use super::game::PlayerId;
+use super::{
+    bitboard::BitBoard,
+    game::PlayerId,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum BoardCell {
@@ -69,6 +72,12 @@ impl BoardCell {
     }
 }
 
+/// A position on a board.
+///
+/// The origin `(0, 0)` is the top-left cell of the board. `x` grows rightward and `y` grows
+/// downward. Boards are surrounded by walls, so cards can be put only on
+/// `1..width - 1` x `1..height - 1`. A card at `CardPosition { x, y, .. }` has the top-left
+/// corner of its (rotated) bounding box at `BoardPosition { x, y }`.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct BoardPosition {
     // We choose `i32` here so that we can describe the position of out side of the board.
@@ -76,6 +85,36 @@ pub struct BoardPosition {
     pub y: i32,
 }
 
+impl BoardPosition {
+    /// Offsets to the 8 surrounding cells.
+    #[rustfmt::skip]
+    pub const AROUND_DIFF: [(i32, i32); 8] = [
+        (-1, -1),  (0, -1),  (1, -1),
+        (-1,  0),/*(0,  0),*/(1,  0),
+        (-1,  1),  (0,  1),  (1,  1),
+    ];
+
+    /// Returns the position moved by `(dx, dy)` or None if it overflows.
+    pub fn offset(&self, dx: i32, dy: i32) -> Option<BoardPosition> {
+        Some(BoardPosition {
+            x: self.x.checked_add(dx)?,
+            y: self.y.checked_add(dy)?,
+        })
+    }
+
+    /// Whether the position is on a board of the size, including the walls at the border.
+    pub fn in_bounds(&self, width: i32, height: i32) -> bool {
+        (0..width).contains(&self.x) && (0..height).contains(&self.y)
+    }
+
+    /// The 8 surrounding positions. They can be out side of the board.
+    pub fn around(&self) -> impl Iterator<Item = BoardPosition> + '_ {
+        Self::AROUND_DIFF
+            .iter()
+            .filter_map(|(dx, dy)| self.offset(*dx, *dy))
+    }
+}
+
 impl Display for BoardPosition {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "[{},{}]", self.x, self.y)
@@ -86,6 +125,8 @@ impl Display for BoardPosition {
 pub struct Board {
     name: String,
     cells: Vec<Vec<BoardCell>>,
+    /// Same cells as `cells` for fast checks. None if the board is too wide.
+    bits: Option<BitBoard>,
 
     width: i32,
     height: i32,
@@ -100,6 +141,7 @@ impl Board {
         let height = cells.len() as i32;
         Self {
             name,
+            bits: BitBoard::new(&cells),
             cells,
             width,
             height,
@@ -113,36 +155,158 @@ impl Board {
     }
 
     pub fn get_scores(&self) -> (u32, u32) {
-        let mut player_cnt = 0;
-        let mut opponent_cnt = 0;
+        let breakdown = self.get_score_breakdown();
+        (
+            breakdown.get_controlled_cells(PlayerId::South),
+            breakdown.get_controlled_cells(PlayerId::North),
+        )
+    }
+
+    /// Counts cells of each kind in a single scan of the board.
+    pub fn get_score_breakdown(&self) -> ScoreBreakdown {
+        let mut breakdown = ScoreBreakdown::default();
+        for cell in self.cells.iter().flatten() {
+            match *cell {
+                BoardCell::None => breakdown.neutral_cells += 1,
+                BoardCell::Wall => breakdown.wall_cells += 1,
+                BoardCell::Ink(player_id) => breakdown.ink_cells[player_id.to_index()] += 1,
+                BoardCell::Special(player_id) => breakdown.special_cells[player_id.to_index()] += 1,
+            }
+        }
+        breakdown
+    }
+
+    /// A Zobrist-style hash of the cells: XOR of a random key for each inked cell.
+    /// Boards of a same layout with the same inked cells have the same hash regardless of the
+    /// order the cells were inked.
+    pub fn get_zobrist_hash(&self) -> u64 {
+        let mut hash = 0;
+        for (index, cell) in self.cells.iter().flatten().enumerate() {
+            let kind = match *cell {
+                BoardCell::None | BoardCell::Wall => continue,
+                BoardCell::Ink(player_id) => player_id.to_index(),
+                BoardCell::Special(player_id) => 2 + player_id.to_index(),
+            };
+            hash ^= zobrist_key((index * 4 + kind) as u64);
+        }
+        hash
+    }
+
+    /// Rows of the board in the format `load_board_from_lines` accepts.
+    pub fn to_lines(&self) -> Vec<String> {
+        self.cells
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.to_char()).collect())
+            .collect()
+    }
+
+    /// Counts cells which are not inked yet. Walls are not counted.
+    pub fn count_open_cells(&self) -> u32 {
+        self.cells
+            .iter()
+            .flatten()
+            .filter(|cell| cell.is_none())
+            .count() as u32
+    }
+
+    /// The ratio of inked cells to all non-wall cells, in `[0.0, 1.0]`.
+    pub fn get_fill_ratio(&self) -> f64 {
+        let (south, north) = self.get_scores();
+        let filled = south + north;
+        let total = filled + self.count_open_cells();
+        if total == 0 {
+            return 0.0;
+        }
+        filled as f64 / total as f64
+    }
+
+    /// Splits each player's inked cells into regions connected horizontally, vertically or
+    /// diagonally, and returns their sizes.
+    pub fn score_attribution(&self) -> ScoreAttribution {
         let (width, height) = self.get_size();
+        let mut visited = vec![vec![false; width as usize]; height as usize];
+        let mut regions: [Vec<u32>; 2] = [vec![], vec![]];
         for y in 0..height {
             for x in 0..width {
-                let position = BoardPosition {
-                    x: x as i32,
-                    y: y as i32,
+                if visited[y as usize][x as usize] {
+                    continue;
+                }
+                let player_id = match self.get_cell(BoardPosition {
+                    x,
+                    y,
+                }) {
+                    BoardCell::Ink(player_id) | BoardCell::Special(player_id) => player_id,
+                    _ => continue,
                 };
-                match self.get_cell(position) {
-                    BoardCell::Ink(PlayerId::South) | BoardCell::Special(PlayerId::South) => {
-                        player_cnt += 1;
-                    }
-                    BoardCell::Ink(PlayerId::North) | BoardCell::Special(PlayerId::North) => {
-                        opponent_cnt += 1;
+
+                // Flood fill cells of the same player.
+                visited[y as usize][x as usize] = true;
+                let mut size = 0;
+                let mut stack = vec![BoardPosition {
+                    x,
+                    y,
+                }];
+                while let Some(position) = stack.pop() {
+                    size += 1;
+                    for next in position.around() {
+                        let owner = match self.get_cell(next) {
+                            BoardCell::Ink(p) | BoardCell::Special(p) => p,
+                            _ => continue,
+                        };
+                        if owner != player_id || visited[next.y as usize][next.x as usize] {
+                            continue;
+                        }
+                        visited[next.y as usize][next.x as usize] = true;
+                        stack.push(next);
                     }
-                    _ => {}
                 }
+                regions[player_id.to_index()].push(size);
             }
         }
-        (player_cnt, opponent_cnt)
+        regions.iter_mut().for_each(|r| r.sort_by(|a, b| b.cmp(a)));
+        ScoreAttribution {
+            regions,
+        }
+    }
+
+    /// Lists cells which differ on `after`.
+    pub fn diff(&self, after: &Board) -> BoardDelta {
+        assert_eq!(self.get_size(), after.get_size());
+        let (width, height) = self.get_size();
+        let mut changes = vec![];
+        for y in 0..height {
+            for x in 0..width {
+                let position = BoardPosition {
+                    x,
+                    y,
+                };
+                let cell = after.get_cell(position);
+                if self.get_cell(position) != cell {
+                    changes.push((position, cell));
+                }
+            }
+        }
+        BoardDelta {
+            changes,
+        }
+    }
+
+    pub fn apply_delta(&mut self, delta: &BoardDelta) {
+        for (position, cell) in &delta.changes {
+            self.put_cell(*position, *cell);
+        }
     }
 
+    /// Returns `BoardCell::Wall` for positions out side of the board.
     pub fn get_cell(&self, position: BoardPosition) -> BoardCell {
-        let x = position.x;
-        let y = position.y;
-        if x < 0 || y < 0 || y >= self.height || x >= self.width {
+        if !self.contains(position) {
             return BoardCell::Wall;
         }
-        self.cells[y as usize][x as usize]
+        self.cells[position.y as usize][position.x as usize]
+    }
+
+    pub fn contains(&self, position: BoardPosition) -> bool {
+        position.in_bounds(self.width, self.height)
     }
 
     pub fn get_size(&self) -> (i32, i32) {
@@ -157,7 +321,19 @@ impl Board {
         &self.y_range
     }
 
+    /// The cells as bit masks. None if the board is too wide for them.
+    pub fn get_bits(&self) -> Option<&BitBoard> {
+        self.bits.as_ref()
+    }
+
     pub fn count_surrounded_special_ink(&self) -> (i32, i32) {
+        match &self.bits {
+            Some(bits) => bits.count_surrounded_special_ink(),
+            None => self.count_surrounded_special_ink_by_cells(),
+        }
+    }
+
+    pub(crate) fn count_surrounded_special_ink_by_cells(&self) -> (i32, i32) {
         let mut player_cnt = 0;
         let mut opponent_cnt = 0;
         let (width, height) = self.get_size();
@@ -182,45 +358,109 @@ impl Board {
     }
 
     fn is_surrounded(&self, center_position: &BoardPosition) -> bool {
-        #[rustfmt::skip]
-        const AROUND_DIFF: [(i32, i32); 8] = [
-            (-1, -1),  (0, -1),  (1, -1),
-            (-1,  0),/*(0,  0),*/(1,  0),
-            (-1,  1),  (0,  1),  (1,  1),
-        ];
-        for diff in AROUND_DIFF {
-            let around_pos = BoardPosition {
-                x: center_position.x + diff.0,
-                y: center_position.y + diff.1,
-            };
-            if self.get_cell(around_pos).is_none() {
-                return false;
-            }
-        }
-        true
+        center_position
+            .around()
+            .all(|around_pos| !self.get_cell(around_pos).is_none())
     }
 
     pub fn put_cell(&mut self, position: BoardPosition, cell: BoardCell) {
-        let x = position.x;
-        let y = position.y;
         assert!(
-            y >= 0 || x >= 0 || y < self.height || x < self.width,
-            "Cannot update a cell at out side of the board"
+            self.contains(position),
+            "Cannot update a cell at out side of the board: {}",
+            position
         );
-        self.cells[y as usize][x as usize] = cell;
+        self.cells[position.y as usize][position.x as usize] = cell;
+        if let Some(bits) = &mut self.bits {
+            bits.put_cell(position, cell);
+        }
+    }
+}
+
+/// Cells changed between two boards of the same size.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BoardDelta {
+    /// New cells at each changed position, in row-major order.
+    pub changes: Vec<(BoardPosition, BoardCell)>,
+}
+
+/// Numbers of cells on a board by their kinds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ScoreBreakdown {
+    /// Cells of normal ink, indexed by `PlayerId::to_index`.
+    pub ink_cells: [u32; 2],
+
+    /// Cells of special ink, indexed by `PlayerId::to_index`.
+    pub special_cells: [u32; 2],
+
+    /// Cells which are not inked yet.
+    pub neutral_cells: u32,
+
+    pub wall_cells: u32,
+}
+
+impl ScoreBreakdown {
+    /// Cells inked by the player, which is the player's score.
+    pub fn get_controlled_cells(&self, player_id: PlayerId) -> u32 {
+        let index = player_id.to_index();
+        self.ink_cells[index] + self.special_cells[index]
+    }
+
+    /// How many more cells the player controls than the opponent.
+    pub fn get_margin(&self, player_id: PlayerId) -> i32 {
+        self.get_controlled_cells(player_id) as i32
+            - self.get_controlled_cells(player_id.another()) as i32
     }
 }
 
+/// Sizes of connected regions of inked cells for each player, in descending order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScoreAttribution {
+    regions: [Vec<u32>; 2],
+}
+
+impl ScoreAttribution {
+    pub fn get_regions(&self, player_id: PlayerId) -> &[u32] {
+        &self.regions[player_id.to_index()]
+    }
+
+    pub fn get_score(&self, player_id: PlayerId) -> u32 {
+        self.get_regions(player_id).iter().sum()
+    }
+}
+
+impl Display for ScoreAttribution {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for player_id in [PlayerId::South, PlayerId::North] {
+            let regions: Vec<String> = self
+                .get_regions(player_id)
+                .iter()
+                .map(|size| size.to_string())
+                .collect();
+            writeln!(
+                f,
+                "{}: {} (regions: {})",
+                player_id,
+                self.get_score(player_id),
+                regions.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// `{:#}` shows how many cells each region contributed to the scores.
 impl std::fmt::Display for Board {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         writeln!(f, "{}", self.name)?;
-        self.cells.iter().for_each(|v| {
-            v.iter()
-                .for_each(|cell| write!(f, "{}", cell.to_char()).unwrap());
-            writeln!(f).unwrap();
-        });
-        let scores = self.get_scores();
-        writeln!(f, "Score: {}, {}", scores.0, scores.1)?;
+        for line in self.to_lines() {
+            writeln!(f, "{}", line)?;
+        }
+        if f.alternate() {
+            write!(f, "{}", self.score_attribution())?;
+        } else {
+            let scores = self.get_scores();
+            writeln!(f, "Score: {}, {}", scores.0, scores.1)?;
+        }
         Ok(())
     }
 }
@@ -254,6 +494,25 @@ pub fn load_board(board_path: &PathBuf) -> Board {
     load_board_from_lines(name, &refs)
 }
 
+/// Writes the board in the format `load_board` reads: its name followed by its rows.
+pub fn save_board(board: &Board, board_path: &PathBuf) -> Result<(), String> {
+    let mut text = format!("{}\n", board.get_name());
+    for line in board.to_lines() {
+        text.push_str(&line);
+        text.push('\n');
+    }
+    fs::write(board_path, text).map_err(|e| format!("Failed to write {:?}: {}", board_path, e))
+}
+
+/// A pseudo random key for `index` used by Zobrist hashing (SplitMix64).
+/// Computed on the fly rather than looked up from a table since boards have various sizes.
+pub(crate) fn zobrist_key(index: u64) -> u64 {
+    let mut z = index.wrapping_add(1).wrapping_mul(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
 pub fn load_board_from_lines(name: String, lines: &[&str]) -> Board {
     let cells = read_cells(lines);
     Board::new(name, cells)
@@ -279,3 +538,163 @@ fn read_cells(lines: &[&str]) -> Vec<Vec<BoardCell>> {
 
     cells
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_board_position_helpers() {
+        #[rustfmt::skip]
+        let board = load_board_from_lines(
+            String::from("test_board"),
+            &[
+            "####",
+            "#P.#",
+            "#.o#",
+            "####",
+            ]);
+        let (width, height) = board.get_size();
+
+        let corner = BoardPosition {
+            x: 0,
+            y: 0,
+        };
+        assert!(corner.in_bounds(width, height));
+        assert!(!corner.offset(-1, 0).unwrap().in_bounds(width, height));
+        assert!(!corner.offset(0, -1).unwrap().in_bounds(width, height));
+        assert_eq!(
+            BoardCell::Wall,
+            board.get_cell(corner.offset(-1, -1).unwrap())
+        );
+
+        let bottom_right = corner.offset(width - 1, height - 1).unwrap();
+        assert!(board.contains(bottom_right));
+        assert!(!board.contains(bottom_right.offset(1, 0).unwrap()));
+        assert!(!board.contains(bottom_right.offset(0, 1).unwrap()));
+
+        // Only 3 of 8 cells around the corner are on the board.
+        assert_eq!(8, corner.around().count());
+        assert_eq!(3, corner.around().filter(|p| board.contains(*p)).count());
+
+        // The top-left inner cell is surrounded by walls and 3 inner cells.
+        let top_left = corner.offset(1, 1).unwrap();
+        assert_eq!(
+            BoardCell::Special(PlayerId::South),
+            board.get_cell(top_left)
+        );
+        assert_eq!(
+            5,
+            top_left
+                .around()
+                .filter(|p| board.get_cell(*p).is_wall())
+                .count()
+        );
+
+        let far = BoardPosition {
+            x: i32::MAX,
+            y: 0,
+        };
+        assert_eq!(None, far.offset(1, 0));
+        assert_eq!(5, far.around().count());
+    }
+
+    #[test]
+    fn test_to_lines_round_trip() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../data/boards/massugu_street");
+        let mut board = load_board(&path);
+        let lines = board.to_lines();
+        let refs: Vec<&str> = lines.iter().map(AsRef::as_ref).collect();
+        assert_eq!(board, load_board_from_lines(board.get_name().into(), &refs));
+
+        // A board in the middle of a game is saved and loaded back.
+        board.put_cell(
+            BoardPosition {
+                x: 3,
+                y: 4,
+            },
+            BoardCell::Ink(PlayerId::South),
+        );
+        board.put_cell(
+            BoardPosition {
+                x: 4,
+                y: 4,
+            },
+            BoardCell::Ink(PlayerId::North),
+        );
+        let lines = board.to_lines();
+        assert!(lines.iter().any(|line| line.contains("po")), "{:?}", lines);
+        let path = std::env::temp_dir().join(format!("takoyaki-board-{}", std::process::id()));
+        save_board(&board, &path).unwrap();
+        let loaded = load_board(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(board, loaded);
+        assert!(loaded
+            .to_string()
+            .starts_with(&format!("Massugu Street\n{}\n", lines[0])));
+    }
+
+    #[test]
+    fn test_score_breakdown() {
+        #[rustfmt::skip]
+        let board = load_board_from_lines(
+            String::from("test_board"),
+            &[
+            "#######",
+            "#pP.o.#",
+            "#.pOO##",
+            "#######",
+            ]);
+
+        let breakdown = board.get_score_breakdown();
+        assert_eq!([2, 1], breakdown.ink_cells);
+        assert_eq!([1, 2], breakdown.special_cells);
+        assert_eq!(3, breakdown.neutral_cells);
+        assert_eq!(19, breakdown.wall_cells);
+        assert_eq!(3, breakdown.get_controlled_cells(PlayerId::South));
+        assert_eq!(0, breakdown.get_margin(PlayerId::North));
+        assert_eq!((3, 3), board.get_scores());
+    }
+
+    #[test]
+    fn test_score_attribution() {
+        #[rustfmt::skip]
+        let board = load_board_from_lines(
+            String::from("test_board"),
+            &[
+            "#########",
+            "#pp..o..#",
+            "#Pp..O#p#",
+            "#.....pP#",
+            "#########",
+            ]);
+
+        let attribution = board.score_attribution();
+        assert_eq!(&[4, 3], attribution.get_regions(PlayerId::South));
+        assert_eq!(&[2], attribution.get_regions(PlayerId::North));
+        assert_eq!(board.get_scores().0, attribution.get_score(PlayerId::South));
+        assert_eq!(board.get_scores().1, attribution.get_score(PlayerId::North));
+        assert_eq!(
+            "South: 7 (regions: 4, 3)\nNorth: 2 (regions: 2)\n",
+            attribution.to_string()
+        );
+    }
+
+    #[test]
+    fn test_score_attribution_diagonal() {
+        #[rustfmt::skip]
+        let board = load_board_from_lines(
+            String::from("test_board"),
+            &[
+            "#####",
+            "#p..#",
+            "#.P.#",
+            "#..o#",
+            "#####",
+            ]);
+
+        let attribution = board.score_attribution();
+        assert_eq!(&[2], attribution.get_regions(PlayerId::South));
+        assert_eq!(&[1], attribution.get_regions(PlayerId::North));
+    }
+}
@@ -10,6 +10,7 @@ use std::{
         self,
         File,
     },
+    hash::Hash,
     io::{
         BufRead,
         BufReader,
@@ -23,6 +24,7 @@ use std::{
 
 use super::{
     board::{
+        Board,
         BoardCell,
         BoardPosition,
     },
@@ -34,8 +36,12 @@ use super::{
 
 use log::*;
 use more_asserts::assert_ge;
+use serde::{
+    Deserialize,
+    Serialize,
+};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct CardCellPosition {
     pub x: i32,
     pub y: i32,
@@ -47,7 +53,7 @@ impl Display for CardCellPosition {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct CardCell {
     pub position: CardCellPosition,
     pub cell_type: CardCellType,
@@ -74,7 +80,7 @@ impl CardCell {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum CardCellType {
     None,
     Ink,
@@ -112,9 +118,37 @@ pub struct CardImpl {
     cell_count: i32,
     special_cost: i32,
     cells: HashMap<Rotation, HashMap<CardCellPosition, CardCell>>,
+    footprints: HashMap<Rotation, Vec<u64>>,
+    surroundings: HashMap<Rotation, Vec<u64>>,
 }
 
 impl CardImpl {
+    fn new(
+        id: u32,
+        name: String,
+        cell_count: i32,
+        special_cost: i32,
+        cells: HashMap<Rotation, HashMap<CardCellPosition, CardCell>>,
+    ) -> Self {
+        let footprints = cells
+            .iter()
+            .map(|(rotation, cells)| (*rotation, calc_footprint(cells)))
+            .collect::<HashMap<_, _>>();
+        let surroundings = footprints
+            .iter()
+            .map(|(rotation, footprint)| (*rotation, calc_surroundings(footprint)))
+            .collect();
+        CardImpl {
+            id,
+            name,
+            cell_count,
+            special_cost,
+            cells,
+            footprints,
+            surroundings,
+        }
+    }
+
     pub fn get_id(&self) -> u32 {
         self.id
     }
@@ -123,6 +157,11 @@ impl CardImpl {
         &self.name
     }
 
+    /// The number of cells the card has.
+    pub fn get_cell_count(&self) -> i32 {
+        self.cell_count
+    }
+
     pub fn get_special_cost(&self) -> i32 {
         self.special_cost
     }
@@ -136,18 +175,31 @@ impl CardImpl {
         card_position: &CardPosition,
     ) -> impl Iterator<Item = (BoardPosition, CardCell)> + 'a {
         let cells = self.get_cells(card_position.rotation);
-        let cx = card_position.x;
-        let cy = card_position.y;
+        let origin = BoardPosition {
+            x: card_position.x,
+            y: card_position.y,
+        };
         cells.values().map(move |cell| {
-            let cell_position = cell.position;
-            let board_pos = BoardPosition {
-                x: cx + cell_position.x,
-                y: cy + cell_position.y,
-            };
+            let board_pos = origin
+                .offset(cell.position.x, cell.position.y)
+                .unwrap_or_else(|| panic!("Card position overflowed: {}", origin));
             (board_pos, *cell)
         })
     }
 
+    /// Cells of the card as bit masks of each row. Bit `x` of the `y`-th row is set if the card
+    /// has a cell at `(x, y)`.
+    pub fn get_footprint(&self, rotation: Rotation) -> &[u64] {
+        self.footprints.get(&rotation).unwrap()
+    }
+
+    /// Cells of the card and the 8 cells around each of them as bit masks of each row, shifted
+    /// by one cell rightward and downward. i.e. the `y`-th row covers the `y - 1`-th row of
+    /// the card and bit `x` covers the `x - 1`-th column.
+    pub fn get_surroundings(&self, rotation: Rotation) -> &[u64] {
+        self.surroundings.get(&rotation).unwrap()
+    }
+
     pub fn calculate_width(&self, rotation: Rotation) -> i32 {
         self.get_cells(rotation).keys().map(|p| p.x).max().unwrap() + 1
     }
@@ -165,6 +217,80 @@ impl CardImpl {
         .unwrap();
         output
     }
+
+    /// Same as `Display` but shapes of all rotations are shown side by side under their names.
+    pub fn fmt_all_rotations(&self) -> String {
+        let mut output = format!(
+            "{}: {}\ncnt: {} cost: {}\n",
+            self.id, self.name, self.cell_count, self.special_cost
+        );
+        let columns: Vec<(Rotation, usize)> = Rotation::VALUES
+            .iter()
+            .map(|&rotation| {
+                let width = self.calculate_width(rotation) as usize;
+                (rotation, width.max(rotation.to_string().len()))
+            })
+            .collect();
+        let height = Rotation::VALUES
+            .iter()
+            .map(|&rotation| self.calculate_height(rotation))
+            .max()
+            .unwrap();
+
+        let mut lines = vec![columns
+            .iter()
+            .map(|(rotation, width)| format!("{:width$}", rotation.to_string(), width = width))
+            .collect::<Vec<String>>()
+            .join("  ")];
+        for y in 0..height {
+            lines.push(
+                columns
+                    .iter()
+                    .map(|(rotation, width)| {
+                        let row: String = (0..self.calculate_width(*rotation))
+                            .map(|x| {
+                                match self.get_cells(*rotation).get(&CardCellPosition {
+                                    x,
+                                    y,
+                                }) {
+                                    Some(cell) => cell.cell_type.to_char(),
+                                    None => ' ',
+                                }
+                            })
+                            .collect();
+                        format!("{:width$}", row, width = width)
+                    })
+                    .collect::<Vec<String>>()
+                    .join("  "),
+            );
+        }
+        for line in lines {
+            output += line.trim_end();
+            output += "\n";
+        }
+        output
+    }
+}
+
+fn calc_footprint(cells: &HashMap<CardCellPosition, CardCell>) -> Vec<u64> {
+    let height = cells.keys().map(|p| p.y).max().unwrap() + 1;
+    let mut rows = vec![0; height as usize];
+    for p in cells.keys() {
+        rows[p.y as usize] |= 1 << p.x;
+    }
+    rows
+}
+
+fn calc_surroundings(footprint: &[u64]) -> Vec<u64> {
+    let mut rows = vec![0; footprint.len() + 2];
+    for (y, row) in footprint.iter().enumerate() {
+        let row = row << 1;
+        let row = row | row << 1 | row >> 1;
+        for surrounding in &mut rows[y..y + 3] {
+            *surrounding |= row;
+        }
+    }
+    rows
 }
 
 pub fn sort_by_id(cards: &mut [Card]) {
@@ -236,19 +362,140 @@ pub fn to_ids(cards: &[Card]) -> Vec<u32> {
     cards.iter().map(|card| card.get_id()).collect()
 }
 
-pub fn load_cards(cards_dir: &str) -> HashMap<u32, Card> {
+/// Loads all card files in `cards_dir`.
+/// Returns an error if the directory doesn't exist or has no card files.
+pub fn load_cards(cards_dir: &str) -> Result<HashMap<u32, Card>, String> {
     debug!("Start loading card data from: {}", cards_dir);
 
     let mut cards: HashMap<u32, Card> = HashMap::new();
-    for entry in fs::read_dir(cards_dir).expect("Couldn't open the card dir") {
-        let dir = entry.unwrap();
-        let path = dir.path();
-        let path = path.to_str().unwrap();
-        let card = load_card(path);
+    for path in list_card_files(cards_dir)? {
+        let card = load_card(path.to_str().unwrap());
         trace!("{}", card);
         cards.insert(card.get_id(), card);
     }
-    cards
+    Ok(cards)
+}
+
+/// Lists files in `cards_dir` in the order of their names.
+fn list_card_files(cards_dir: &str) -> Result<Vec<PathBuf>, String> {
+    let entries = fs::read_dir(cards_dir)
+        .map_err(|e| format!("Couldn't open the card dir {}: {}", cards_dir, e))?;
+    let mut paths = vec![];
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    if paths.is_empty() {
+        return Err(format!("No card files found in {}", cards_dir));
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Same as `load_cards` but reuses the parsed cards stored in `cache_path` if the
+/// content of `cards_dir` hasn't changed since the cache was written.
+/// The cache is rewritten when it is missing or stale.
+pub fn load_cards_cached(cards_dir: &str, cache_path: &Path) -> Result<HashMap<u32, Card>, String> {
+    let source_hash = hash_cards_dir(cards_dir)?;
+    match read_card_cache(cache_path) {
+        Ok(cache) if cache.source_hash == source_hash => {
+            debug!("Loaded cards from the cache: {:?}", cache_path);
+            return Ok(cache
+                .cards
+                .into_iter()
+                .map(|card| (card.id, Card::new(card.into())))
+                .collect());
+        }
+        Ok(_) => info!("The card cache is stale: {:?}", cache_path),
+        Err(e) => info!("Couldn't read the card cache {:?}: {}", cache_path, e),
+    }
+
+    let cards = load_cards(cards_dir)?;
+    let cache = CardCache {
+        source_hash,
+        cards: cards.values().map(|card| card.as_ref().into()).collect(),
+    };
+    if let Err(e) = write_card_cache(cache_path, &cache) {
+        warn!("Failed to write the card cache {:?}: {}", cache_path, e);
+    }
+    Ok(cards)
+}
+
+/// Hashes names and contents of all files in `cards_dir`.
+/// The hash is stored in the cache, so it must not change between builds unlike `DefaultHasher`.
+fn hash_cards_dir(cards_dir: &str) -> Result<u64, String> {
+    let mut hash = 0;
+    for path in list_card_files(cards_dir)? {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        hash = wyhash::wyhash(name.as_bytes(), hash);
+        let content = fs::read(&path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+        hash = wyhash::wyhash(&content, hash);
+    }
+    Ok(hash)
+}
+
+fn read_card_cache(cache_path: &Path) -> Result<CardCache, String> {
+    let bytes = fs::read(cache_path).map_err(|e| e.to_string())?;
+    flexbuffers::from_slice(&bytes).map_err(|e| e.to_string())
+}
+
+fn write_card_cache(cache_path: &Path, cache: &CardCache) -> Result<(), String> {
+    let bytes = flexbuffers::to_vec(cache).map_err(|e| e.to_string())?;
+    fs::write(cache_path, bytes).map_err(|e| e.to_string())
+}
+
+#[derive(Serialize, Deserialize)]
+struct CardCache {
+    source_hash: u64,
+    cards: Vec<CachedCard>,
+}
+
+/// Serializable form of `CardImpl` which keeps the precomputed rotations.
+#[derive(Serialize, Deserialize)]
+struct CachedCard {
+    id: u32,
+    name: String,
+    cell_count: i32,
+    special_cost: i32,
+    cells: Vec<(Rotation, Vec<CardCell>)>,
+}
+
+impl From<&CardImpl> for CachedCard {
+    fn from(card: &CardImpl) -> Self {
+        let cells = Rotation::VALUES
+            .iter()
+            .map(|rotation| {
+                let mut cells: Vec<CardCell> =
+                    card.get_cells(*rotation).values().copied().collect();
+                cells.sort();
+                (*rotation, cells)
+            })
+            .collect();
+        CachedCard {
+            id: card.id,
+            name: card.name.clone(),
+            cell_count: card.cell_count,
+            special_cost: card.special_cost,
+            cells,
+        }
+    }
+}
+
+impl From<CachedCard> for CardImpl {
+    fn from(card: CachedCard) -> Self {
+        CardImpl::new(
+            card.id,
+            card.name,
+            card.cell_count,
+            card.special_cost,
+            card.cells
+                .into_iter()
+                .map(|(rotation, cells)| (rotation, convert_to_cell_map(cells)))
+                .collect(),
+        )
+    }
 }
 
 pub fn load_card(card_path: &str) -> Card {
@@ -325,14 +572,64 @@ pub fn load_card_from_lines(
         cells_variations.insert(*rot, convert_to_cell_map(rot_cells));
     }
     assert_eq!(4, cells_variations.len());
+    if cfg!(debug_assertions) {
+        if let Err(e) = validate_rotations(cell_count, &cells_variations, width, height) {
+            panic!("Card {} ({}) has broken rotations: {}", id, name, e);
+        }
+    }
 
-    Card::new(CardImpl {
+    Card::new(CardImpl::new(
         id,
         name,
         cell_count,
         special_cost,
-        cells: cells_variations,
-    })
+        cells_variations,
+    ))
+}
+
+/// Checks that every rotation keeps all cells and rotating `Up` to the right 4 times
+/// reproduces `Up`, passing through `Right`, `Down` and `Left` in order.
+fn validate_rotations(
+    cell_count: i32,
+    cells_variations: &HashMap<Rotation, HashMap<CardCellPosition, CardCell>>,
+    width: i32,
+    height: i32,
+) -> Result<(), String> {
+    for rotation in Rotation::VALUES {
+        let count = cells_variations
+            .get(&rotation)
+            .map_or(0, |cells| cells.len());
+        if count != cell_count as usize {
+            return Err(format!(
+                "{} has {} cells but expected {}",
+                rotation, count, cell_count
+            ));
+        }
+    }
+
+    let up = &cells_variations[&Rotation::Up];
+    let mut cells: Vec<CardCell> = up.values().copied().collect();
+    let (mut width, mut height) = (width, height);
+    for (n, expected) in [
+        Rotation::Right,
+        Rotation::Down,
+        Rotation::Left,
+        Rotation::Up,
+    ]
+    .iter()
+    .enumerate()
+    {
+        cells = rotate_card_cells(Rotation::Right, &cells, width, height);
+        (width, height) = (height, width);
+        if convert_to_cell_map(cells.clone()) != cells_variations[expected] {
+            return Err(format!(
+                "Rotating Up to the right {} time(s) doesn't match {}",
+                n + 1,
+                expected
+            ));
+        }
+    }
+    Ok(())
 }
 
 fn convert_to_cell_map(cells: Vec<CardCell>) -> HashMap<CardCellPosition, CardCell> {
@@ -424,6 +721,47 @@ pub struct CardPosition {
     pub rotation: Rotation,
 }
 
+impl CardPosition {
+    /// Makes a position which puts a card rotated by `rotation` with the top-left corner of its
+    /// (rotated) bounding box at `(x, y)` in board coordinates. See `BoardPosition` for the
+    /// coordinate convention.
+    ///
+    /// Whether the card is put as a special attack is chosen by `Action::Special`, not by the
+    /// position.
+    pub fn new(x: i32, y: i32, rotation: Rotation) -> Self {
+        CardPosition {
+            x,
+            y,
+            rotation,
+        }
+    }
+
+    /// Returns the position itself if all cells of `card` at the position are inside of `board`
+    /// excluding the walls at the border.
+    /// Note that it doesn't check whether the card can be put there in the game.
+    pub fn try_on_board(self, board: &Board, card: &Card) -> Result<Self, String> {
+        let (width, height) = board.get_size();
+        let origin = BoardPosition {
+            x: self.x,
+            y: self.y,
+        };
+        for cell in card.get_cells(self.rotation).values() {
+            let in_bounds = origin
+                .offset(cell.position.x, cell.position.y)
+                .is_some_and(|p| (1..width - 1).contains(&p.x) && (1..height - 1).contains(&p.y));
+            if !in_bounds {
+                return Err(format!(
+                    "{} at {} is out of the board: {}",
+                    card.get_name(),
+                    self,
+                    board.get_name()
+                ));
+            }
+        }
+        Ok(self)
+    }
+}
+
 impl Display for CardPosition {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "[p: [{},{}], r: {}]", self.x, self.y, self.rotation)
@@ -434,15 +772,272 @@ pub fn load_deck(deck_path: &PathBuf) -> Vec<u32> {
     let file = File::open(deck_path).unwrap_or_else(|_| panic!("Failed to open: {:?}", deck_path));
     let reader = BufReader::new(file);
     let lines: Vec<String> = reader.lines().collect::<Result<_, _>>().unwrap();
-    lines
+    load_deck_from_lines(&lines)
+}
+
+/// Writes `deck` in the format `load_deck` reads, one card per line followed by `comments`
+/// prefixed with `#`.
+pub fn save_deck(deck: &[Card], deck_path: &Path, comments: &[String]) -> Result<(), String> {
+    let mut text = String::new();
+    for card in deck {
+        text.push_str(&format!("{} {}\n", card.get_id(), card.get_name()));
+    }
+    for comment in comments {
+        text.push_str(&format!("# {}\n", comment));
+    }
+    fs::write(deck_path, text).map_err(|e| format!("Failed to write {:?}: {}", deck_path, e))
+}
+
+/// Parses lines of a deck file such as `6 splashooter`.
+/// A count suffix after the card id (e.g. `42 x3 tentatek`) puts the card in the deck that many
+/// times. Whether it's allowed depends on `RuleSet::max_card_copies`.
+/// Empty lines and lines starting with `#` are ignored.
+pub fn load_deck_from_lines(lines: &[String]) -> Vec<u32> {
+    let mut ids = vec![];
+    for line in lines {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let mut tokens = line.split_whitespace();
+        let id = tokens.next().unwrap().parse::<u32>().unwrap();
+        // A name may follow the id instead of a count.
+        let count = tokens
+            .next()
+            .and_then(|token| token.strip_prefix('x'))
+            .and_then(|count| count.parse::<usize>().ok())
+            .unwrap_or(1);
+        ids.extend(std::iter::repeat_n(id, count));
+    }
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("takoyaki-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("cards")).unwrap();
+        dir
+    }
+
+    fn assert_same_card(expected: &CardImpl, actual: &CardImpl) {
+        assert_eq!(expected.id, actual.id);
+        assert_eq!(expected.name, actual.name);
+        assert_eq!(expected.cell_count, actual.cell_count);
+        assert_eq!(expected.special_cost, actual.special_cost);
+        assert_eq!(expected.cells, actual.cells);
+        assert_eq!(expected.footprints, actual.footprints);
+        assert_eq!(expected.surroundings, actual.surroundings);
+    }
+
+    #[test]
+    fn test_rotations_of_asymmetric_card() {
+        #[rustfmt::skip]
+        let lines: Vec<String> = [
+            "=* ",
+            "=  ",
+            "===",
+            "  =",
+        ].iter().map(|s| s.to_string()).collect();
+        let card = load_card_from_lines(1, String::from("asymmetric"), 7, 3, &lines);
+        let mut cells_variations: HashMap<Rotation, HashMap<CardCellPosition, CardCell>> =
+            Rotation::VALUES
+                .iter()
+                .map(|r| (*r, card.get_cells(*r).clone()))
+                .collect();
+        assert_eq!(Ok(()), validate_rotations(7, &cells_variations, 3, 4));
+        assert_eq!(4, card.calculate_width(Rotation::Right));
+        assert_eq!(3, card.calculate_height(Rotation::Right));
+        assert_eq!(
+            &[0b011, 0b001, 0b111, 0b100],
+            card.get_footprint(Rotation::Up)
+        );
+        assert_eq!(
+            &[0b01111, 0b01111, 0b11111, 0b11111, 0b11111, 0b11100],
+            card.get_surroundings(Rotation::Up)
+        );
+
+        // Swapping rotations breaks the invariant.
+        let right = cells_variations.remove(&Rotation::Right).unwrap();
+        let left = cells_variations.remove(&Rotation::Left).unwrap();
+        cells_variations.insert(Rotation::Right, left);
+        cells_variations.insert(Rotation::Left, right.clone());
+        assert!(validate_rotations(7, &cells_variations, 3, 4).is_err());
+
+        // So does losing a cell.
+        let mut broken = right;
+        let key = *broken.keys().next().unwrap();
+        broken.remove(&key);
+        cells_variations.insert(Rotation::Right, broken);
+        assert!(validate_rotations(7, &cells_variations, 3, 4).is_err());
+    }
+
+    #[test]
+    fn test_fmt_all_rotations() {
+        #[rustfmt::skip]
+        let lines: Vec<String> = [
+            "=* ",
+            "=  ",
+            "===",
+            "  =",
+        ].iter().map(|s| s.to_string()).collect();
+        let card = load_card_from_lines(1, String::from("asymmetric"), 7, 3, &lines);
+        #[rustfmt::skip]
+        let expected = [
+            "1: asymmetric",
+            "cnt: 7 cost: 3",
+            "Up   Right  Down  Left",
+            "=*    ===   =       ==",
+            "=     = *   ===   * =",
+            "===  ==       =   ===",
+            "  =          *=",
+        ];
+        assert_eq!(
+            expected.map(|line| format!("{}\n", line)).concat(),
+            card.fmt_all_rotations()
+        );
+    }
+
+    #[test]
+    fn test_try_on_board() {
+        #[rustfmt::skip]
+        let board = crate::load_board_from_lines(String::from("test board"), &[
+            "######",
+            "#....#",
+            "#....#",
+            "#....#",
+            "######",
+        ]);
+        let lines: Vec<String> = ["==="].iter().map(|s| s.to_string()).collect();
+        let card = load_card_from_lines(1, String::from("bar"), 3, 1, &lines);
+
+        let pos = CardPosition::new(1, 3, Rotation::Up);
+        assert_eq!(Ok(pos), pos.try_on_board(&board, &card));
+        let pos = CardPosition::new(2, 1, Rotation::Up);
+        assert_eq!(Ok(pos), pos.try_on_board(&board, &card));
+        // Rotated cards grow downward.
+        let pos = CardPosition::new(4, 1, Rotation::Right);
+        assert_eq!(Ok(pos), pos.try_on_board(&board, &card));
+
+        // Overlapping the walls.
+        assert!(CardPosition::new(0, 1, Rotation::Up)
+            .try_on_board(&board, &card)
+            .is_err());
+        assert!(CardPosition::new(3, 1, Rotation::Up)
+            .try_on_board(&board, &card)
+            .is_err());
+        assert!(CardPosition::new(1, 2, Rotation::Right)
+            .try_on_board(&board, &card)
+            .is_err());
+        // Out side of the board entirely, including positions which would overflow.
+        assert!(CardPosition::new(-5, -5, Rotation::Up)
+            .try_on_board(&board, &card)
+            .is_err());
+        assert!(CardPosition::new(i32::MAX, 1, Rotation::Up)
+            .try_on_board(&board, &card)
+            .is_err());
+    }
+
+    #[test]
+    fn test_load_deck_from_lines() {
+        let lines: Vec<String> = [
+            "# a comment",
+            "6 splashooter",
+            "42 x3 tentatek",
+            "",
+            "13 x1",
+            "22 xtra",
+        ]
         .iter()
-        .map(|line| {
-            line.trim()
-                .split(' ')
-                .next()
-                .unwrap()
-                .parse::<u32>()
-                .unwrap()
-        })
-        .collect()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(vec![6, 42, 42, 42, 13, 22], load_deck_from_lines(&lines));
+    }
+
+    #[test]
+    fn test_save_deck() {
+        let dir = new_temp_dir("save-deck");
+        let path = dir.join("deck");
+        let lines = ["=".to_string()];
+        let deck = vec![
+            load_card_from_lines(42, "Tentatek Splattershot".into(), 1, 1, &lines),
+            load_card_from_lines(13, "Splattershot Jr.".into(), 1, 1, &lines),
+            load_card_from_lines(42, "Tentatek Splattershot".into(), 1, 1, &lines),
+        ];
+        save_deck(&deck, &path, &["win rate: 0.5".to_string()]).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("42 Tentatek Splattershot\n"), "{}", text);
+        assert!(text.ends_with("\n# win rate: 0.5\n"), "{}", text);
+        assert_eq!(to_ids(&deck), load_deck(&path));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_cards_cached() {
+        let dir = new_temp_dir("card-cache");
+        let cards_dir = dir.join("cards");
+        let cards_dir = cards_dir.to_str().unwrap();
+        let cache_path = dir.join("cards.cache");
+        fs::write(dir.join("cards/1"), "one\n3\n2\n==*\n").unwrap();
+        fs::write(dir.join("cards/2"), "two\n2\n1\n=\n=\n").unwrap();
+
+        let fresh = load_cards(cards_dir).unwrap();
+        let first = load_cards_cached(cards_dir, &cache_path).unwrap();
+        assert!(cache_path.exists());
+        let cached = load_cards_cached(cards_dir, &cache_path).unwrap();
+        assert_eq!(fresh.len(), cached.len());
+        for (id, card) in fresh.iter() {
+            assert_same_card(card, &first[id]);
+            assert_same_card(card, &cached[id]);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_cards_cached_stale() {
+        let dir = new_temp_dir("card-cache-stale");
+        let cards_dir = dir.join("cards");
+        let cards_dir = cards_dir.to_str().unwrap();
+        let cache_path = dir.join("cards.cache");
+        fs::write(dir.join("cards/1"), "one\n3\n2\n==*\n").unwrap();
+        let cards = load_cards_cached(cards_dir, &cache_path).unwrap();
+        assert_eq!("one", cards[&1].get_name());
+
+        // Changing the source must invalidate the cache.
+        fs::write(dir.join("cards/1"), "uno\n2\n5\n=*\n").unwrap();
+        let cards = load_cards_cached(cards_dir, &cache_path).unwrap();
+        assert_same_card(&load_cards(cards_dir).unwrap()[&1], &cards[&1]);
+        assert_eq!("uno", cards[&1].get_name());
+        assert_eq!(5, cards[&1].get_special_cost());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn test_load_cards_empty_dir() {
+        let dir = new_temp_dir("empty-cards");
+        let cards_dir = dir.join("cards");
+        let cards_dir = cards_dir.to_str().unwrap();
+
+        let err = load_cards(cards_dir).unwrap_err();
+        assert!(err.contains("No card files found"), "{}", err);
+        assert!(load_cards_cached(cards_dir, &dir.join("cards.cache")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_cards_missing_dir() {
+        let dir = new_temp_dir("missing-cards");
+        let cards_dir = dir.join("no_such_dir");
+        let cards_dir = cards_dir.to_str().unwrap();
+
+        let err = load_cards(cards_dir).unwrap_err();
+        assert!(err.contains("Couldn't open the card dir"), "{}", err);
+        assert!(load_cards_cached(cards_dir, &dir.join("cards.cache")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,237 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use clap::{
+    Args,
+    ValueHint,
+};
+use log::*;
+
+use super::{
+    board::{
+        self,
+        Board,
+        BoardCell,
+        BoardPosition,
+    },
+    card::{
+        self,
+        Card,
+    },
+    game::{
+        Context,
+        PlayerId,
+        RuleSet,
+    },
+};
+
+// The `--data-dir` option of the binaries. Not a doc comment as clap would take it as the about
+// text of the binaries flattening this.
+#[derive(Args, Clone, Debug, Default)]
+pub struct DataDirArgs {
+    /// a directory which has `cards/`, `boards/` and `decks/` like `data/`.
+    /// if specified, `--card-dir` is ignored and boards/decks can be specified by their names
+    /// (e.g. `massugu_street`, `starter`).
+    #[clap(long, value_parser, value_hint=ValueHint::DirPath)]
+    pub data_dir: Option<PathBuf>,
+}
+
+impl DataDirArgs {
+    /// Loads the game data if `--data-dir` is specified. Exits the process on failure.
+    pub fn load_or_exit(&self) -> Option<GameData> {
+        self.data_dir.as_deref().map(GameData::load_or_exit)
+    }
+}
+
+/// All data under a data directory such as `data/` in this repository.
+///
+/// The directory is expected to have `cards/`, `boards/` and `decks/`.
+/// Boards and decks are registered by their file names (e.g. "massugu_street", "starter").
+#[derive(Clone, Debug)]
+pub struct GameData {
+    pub context: Context,
+    pub boards: HashMap<String, Board>,
+    pub decks: HashMap<String, Vec<u32>>,
+}
+
+impl GameData {
+    /// Loads all data under `root` and checks that they are consistent with each other.
+    pub fn load(root: &Path) -> Result<GameData, String> {
+        info!("Start loading game data from: {:?}", root);
+
+        let cards_dir = Self::get_sub_dir(root, "cards")?;
+        let boards_dir = Self::get_sub_dir(root, "boards")?;
+        let decks_dir = Self::get_sub_dir(root, "decks")?;
+
+        let context = Context {
+            all_cards: card::load_cards(cards_dir.to_str().unwrap())?,
+            enabled_step_execution: false,
+            rules: RuleSet::default(),
+        };
+
+        let mut boards = HashMap::new();
+        for (name, path) in Self::list_files(&boards_dir)? {
+            let board = board::load_board(&path);
+            Self::validate_board(&name, &board)?;
+            boards.insert(name, board);
+        }
+
+        let mut decks = HashMap::new();
+        for (name, path) in Self::list_files(&decks_dir)? {
+            let deck = card::load_deck(&path);
+            if let Some(unknown) = deck.iter().find(|id| !context.all_cards.contains_key(id)) {
+                return Err(format!(
+                    "Deck '{}' contains an unknown card ID: {}",
+                    name, unknown
+                ));
+            }
+            decks.insert(name, deck);
+        }
+
+        Ok(GameData {
+            context,
+            boards,
+            decks,
+        })
+    }
+
+    /// Same as `load` but exits the process with an error log on failure.
+    pub fn load_or_exit(root: &Path) -> GameData {
+        Self::load(root).unwrap_or_else(|e| {
+            error!("Failed to load the game data: {}", e);
+            std::process::exit(1);
+        })
+    }
+
+    pub fn get_board(&self, name: &str) -> Option<&Board> {
+        self.boards.get(name)
+    }
+
+    pub fn get_deck(&self, name: &str) -> Option<Vec<Card>> {
+        self.decks.get(name).map(|ids| self.context.get_cards(ids))
+    }
+
+    /// Returns the registered board if `name_or_path` is a name of a board in this bundle.
+    /// Otherwise, loads a board from the path.
+    pub fn resolve_board(&self, name_or_path: &PathBuf) -> Board {
+        match Self::as_name(name_or_path).and_then(|name| self.get_board(name)) {
+            Some(board) => board.clone(),
+            None => board::load_board(name_or_path),
+        }
+    }
+
+    /// Returns card IDs of the registered deck if `name_or_path` is a name of a deck in this
+    /// bundle. Otherwise, loads a deck from the path.
+    pub fn resolve_deck(&self, name_or_path: &PathBuf) -> Vec<u32> {
+        match Self::as_name(name_or_path).and_then(|name| self.decks.get(name)) {
+            Some(deck) => deck.clone(),
+            None => card::load_deck(name_or_path),
+        }
+    }
+
+    /// A path which consists of a single component can be a name of a registered entry.
+    fn as_name(path: &Path) -> Option<&str> {
+        if path.components().count() == 1 {
+            path.to_str()
+        } else {
+            None
+        }
+    }
+
+    fn get_sub_dir(root: &Path, name: &str) -> Result<PathBuf, String> {
+        let dir = root.join(name);
+        if dir.is_dir() {
+            Ok(dir)
+        } else {
+            Err(format!("Couldn't find the directory: {:?}", dir))
+        }
+    }
+
+    fn list_files(dir: &Path) -> Result<Vec<(String, PathBuf)>, String> {
+        let entries =
+            fs::read_dir(dir).map_err(|e| format!("Couldn't open the dir {:?}: {}", dir, e))?;
+        let mut files = vec![];
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if !path.is_file() {
+                continue;
+            }
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| format!("Invalid file name: {:?}", path))?
+                .to_string();
+            files.push((name, path));
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Checks that both players have a special ink to start from.
+    fn validate_board(name: &str, board: &Board) -> Result<(), String> {
+        let (width, height) = board.get_size();
+        for player_id in [PlayerId::South, PlayerId::North] {
+            let has_special = (0..height).any(|y| {
+                (0..width).any(|x| {
+                    board.get_cell(BoardPosition {
+                        x,
+                        y,
+                    }) == BoardCell::Special(player_id)
+                })
+            });
+            if !has_special {
+                return Err(format!(
+                    "Board '{}' doesn't have a special ink for {}",
+                    name, player_id
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_dir() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../data")
+    }
+
+    #[test]
+    fn test_load_repo_data() {
+        let data = GameData::load(&data_dir()).unwrap();
+
+        assert!(data.get_board("massugu_street").is_some());
+        let starter = data.get_deck("starter").unwrap();
+        assert_eq!(crate::DECK_SIZE, starter.len());
+        assert_eq!(
+            card::load_deck(&data_dir().join("decks/starter")),
+            card::to_ids(&starter)
+        );
+    }
+
+    #[test]
+    fn test_load_missing_dir() {
+        assert!(GameData::load(&data_dir().join("cards")).is_err());
+    }
+
+    #[test]
+    fn test_validate_board() {
+        #[rustfmt::skip]
+        let board = board::load_board_from_lines(
+            String::from("test_board"),
+            &[
+            "#####",
+            "#..P#",
+            "#####",
+            ]);
+        assert!(GameData::validate_board("test_board", &board).is_err());
+    }
+}
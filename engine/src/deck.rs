@@ -0,0 +1,366 @@
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    fmt::Display,
+};
+
+use rand::{
+    seq::{
+        IteratorRandom,
+        SliceRandom,
+    },
+    Rng,
+};
+
+use super::{
+    card::{
+        Card,
+        CardCellType,
+    },
+    game::{
+        self,
+        Rotation,
+        RuleSet,
+    },
+};
+
+/// A change made by `repair_deck`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeckRepair {
+    /// The card appeared more than `RuleSet::max_card_copies` times.
+    RemovedDuplicate(u32),
+
+    /// The card isn't in the inventory.
+    RemovedUnknown(u32),
+
+    /// The card exceeded `DECK_SIZE`.
+    Trimmed(u32),
+
+    /// The card was picked from the inventory to fill the deck.
+    Added(u32),
+}
+
+impl Display for DeckRepair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeckRepair::RemovedDuplicate(id) => write!(f, "Removed a duplicated card: {}", id),
+            DeckRepair::RemovedUnknown(id) => write!(f, "Removed a card not in inventory: {}", id),
+            DeckRepair::Trimmed(id) => write!(f, "Trimmed an extra card: {}", id),
+            DeckRepair::Added(id) => write!(f, "Added a card: {}", id),
+        }
+    }
+}
+
+/// Makes a deck of `DECK_SIZE` cards in `inventory` out of `ids`, e.g. a hand-edited deck, with
+/// no more copies of a card than `rules` allow.
+///
+/// Cards are kept in the listed order. Copies beyond `RuleSet::max_card_copies` and cards not in
+/// `inventory` are removed, cards beyond `DECK_SIZE` are trimmed, and a short deck is filled with
+/// cards randomly picked from `inventory`. The result only depends on the arguments including the
+/// state of `rng`. Returns the repaired deck and the repairs made, or an error if `inventory`
+/// doesn't have enough cards.
+pub fn repair_deck(
+    ids: &[u32],
+    inventory: &[u32],
+    rules: &RuleSet,
+    rng: &mut impl Rng,
+) -> Result<(Vec<u32>, Vec<DeckRepair>), String> {
+    let mut inventory: Vec<u32> = inventory.to_vec();
+    inventory.sort();
+    inventory.dedup();
+    let max_copies = rules.max_card_copies;
+    if inventory.len() * (max_copies as usize) < game::DECK_SIZE {
+        return Err(format!(
+            "The inventory has only {} cards of up to {} copies but a deck needs {}",
+            inventory.len(),
+            max_copies,
+            game::DECK_SIZE
+        ));
+    }
+
+    let mut deck = vec![];
+    let mut repairs = vec![];
+    let mut copies: HashMap<u32, u32> = HashMap::new();
+    for &id in ids {
+        if inventory.binary_search(&id).is_err() {
+            repairs.push(DeckRepair::RemovedUnknown(id));
+        } else if copies.get(&id).copied().unwrap_or(0) == max_copies {
+            repairs.push(DeckRepair::RemovedDuplicate(id));
+        } else if deck.len() == game::DECK_SIZE {
+            repairs.push(DeckRepair::Trimmed(id));
+        } else {
+            *copies.entry(id).or_insert(0) += 1;
+            deck.push(id);
+        }
+    }
+
+    let missing = game::DECK_SIZE - deck.len();
+    // Candidates are sorted so that the picked cards only depend on the rng.
+    let mut added = inventory
+        .into_iter()
+        .flat_map(|id| {
+            let left = max_copies - copies.get(&id).copied().unwrap_or(0);
+            std::iter::repeat_n(id, left as usize)
+        })
+        .choose_multiple(rng, missing);
+    added.sort();
+    repairs.extend(added.iter().map(|id| DeckRepair::Added(*id)));
+    deck.extend(added);
+
+    Ok((deck, repairs))
+}
+
+/// Shuffles `deck` and deals its first `hand_size` cards. Returns the hands and the rest of
+/// the deck.
+///
+/// Every dealer (the local runner, the server, and determinizations of players) deals with it
+/// so that a same rng deals same hands everywhere, whether cards are given as `Card`s or ids.
+pub fn deal<T: Clone>(deck: &[T], hand_size: usize, rng: &mut impl Rng) -> (Vec<T>, Vec<T>) {
+    let mut deck = deck.to_vec();
+    deck.shuffle(rng);
+    let rest = deck.split_off(hand_size);
+    (deck, rest)
+}
+
+/// Puts `hands` back on top of `rest` and deals the same number of cards again, for a player
+/// who asked to redeal the initial hands.
+pub fn redeal<T: Clone>(hands: Vec<T>, rest: Vec<T>, rng: &mut impl Rng) -> (Vec<T>, Vec<T>) {
+    let hand_size = hands.len();
+    let mut deck = hands;
+    deck.extend(rest);
+    deal(&deck, hand_size, rng)
+}
+
+/// Aggregate properties of a deck returned by `analyze_deck`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeckStats {
+    /// The sum of the cell counts of the cards.
+    pub total_cell_count: i32,
+
+    /// The sum of the special costs of the cards.
+    pub total_special_cost: i32,
+
+    /// The number of cards of each cell count.
+    pub size_distribution: BTreeMap<i32, usize>,
+
+    /// The number of cards which have at least one special cell.
+    pub special_card_count: usize,
+}
+
+/// Summarizes `cards`. It works for any set of cards, not only for decks of `DECK_SIZE`.
+pub fn analyze_deck(cards: &[Card]) -> DeckStats {
+    let mut stats = DeckStats::default();
+    for card in cards {
+        stats.total_cell_count += card.get_cell_count();
+        stats.total_special_cost += card.get_special_cost();
+        *stats
+            .size_distribution
+            .entry(card.get_cell_count())
+            .or_default() += 1;
+        let has_special = card
+            .get_cells(Rotation::Up)
+            .values()
+            .any(|cell| cell.cell_type == CardCellType::Special);
+        if has_special {
+            stats.special_card_count += 1;
+        }
+    }
+    stats
+}
+
+/// The mean cell count of hands which `evaluate_hand` regards as fully covering the board.
+const HAND_COVERAGE_CELLS: f64 = 10.0;
+
+/// How much `evaluate_hand` weighs the spread of special costs against the coverage.
+const HAND_SPREAD_WEIGHT: f64 = 0.3;
+
+/// Rates `hands` from 0.0 to 1.0 without searching, e.g. to decide whether to redeal them.
+///
+/// Hands of larger cards score higher since they ink more cells, and so do hands with both
+/// cheap and expensive cards, which give choices between charging specials and using them.
+pub fn evaluate_hand(hands: &[Card]) -> f64 {
+    if hands.is_empty() {
+        return 0.0;
+    }
+    let mean_cells =
+        hands.iter().map(|card| card.get_cell_count()).sum::<i32>() as f64 / hands.len() as f64;
+    let coverage = (mean_cells / HAND_COVERAGE_CELLS).min(1.0);
+
+    let costs = hands.iter().map(|card| card.get_special_cost());
+    let (min_cost, max_cost) = (costs.clone().min().unwrap(), costs.max().unwrap());
+    // Special costs are from 1 to 6. A spread of 4, e.g. from 1 to 5, is enough.
+    let spread = ((max_cost - min_cost) as f64 / 4.0).min(1.0);
+
+    (1.0 - HAND_SPREAD_WEIGHT) * coverage + HAND_SPREAD_WEIGHT * spread
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use rand_mt::Mt64;
+
+    use super::*;
+    use crate::{
+        load_cards,
+        load_deck,
+    };
+
+    fn inventory() -> Vec<u32> {
+        (1..=30).collect()
+    }
+
+    fn data_dir() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../data")
+    }
+
+    fn load_test_cards(ids: &[u32]) -> Vec<Card> {
+        let all_cards = load_cards(data_dir().join("cards").to_str().unwrap()).unwrap();
+        ids.iter().map(|id| all_cards[id].clone()).collect()
+    }
+
+    #[test]
+    fn test_deal() {
+        let deck: Vec<u32> = (1..=15).collect();
+        let (hands, rest) = deal(&deck, game::HAND_SIZE, &mut Mt64::new(42));
+        assert_eq!(game::HAND_SIZE, hands.len());
+        assert_eq!(deck.len() - game::HAND_SIZE, rest.len());
+        let mut dealt = [hands.clone(), rest.clone()].concat();
+        dealt.sort();
+        assert_eq!(deck, dealt);
+
+        // Same as shuffling the deck again in the dealt order.
+        let mut rng = Mt64::new(42);
+        let (redealt_hands, redealt_rest) = redeal(hands.clone(), rest.clone(), &mut rng);
+        let mut shuffled = [hands, rest].concat();
+        shuffled.shuffle(&mut Mt64::new(42));
+        assert_eq!(shuffled[..game::HAND_SIZE], redealt_hands);
+        assert_eq!(shuffled[game::HAND_SIZE..], redealt_rest);
+    }
+
+    #[test]
+    fn test_repair_overfull_deck() {
+        let ids: Vec<u32> = (1..=17).collect();
+        let (deck, repairs) =
+            repair_deck(&ids, &inventory(), &RuleSet::default(), &mut Mt64::new(42)).unwrap();
+
+        assert_eq!((1..=15).collect::<Vec<u32>>(), deck);
+        assert_eq!(
+            vec![DeckRepair::Trimmed(16), DeckRepair::Trimmed(17)],
+            repairs
+        );
+    }
+
+    #[test]
+    fn test_repair_short_deck() {
+        let ids: Vec<u32> = (1..=12).collect();
+        let (deck, repairs) =
+            repair_deck(&ids, &inventory(), &RuleSet::default(), &mut Mt64::new(42)).unwrap();
+
+        assert_eq!(game::DECK_SIZE, deck.len());
+        assert_eq!(&ids[..], &deck[0..12]);
+        assert_eq!(3, repairs.len());
+        for (id, repair) in deck[12..].iter().zip(repairs.iter()) {
+            assert!(!ids.contains(id));
+            assert_eq!(DeckRepair::Added(*id), *repair);
+        }
+        // Deterministic given the rng.
+        assert_eq!(
+            (deck, repairs),
+            repair_deck(&ids, &inventory(), &RuleSet::default(), &mut Mt64::new(42)).unwrap()
+        );
+
+        assert!(repair_deck(&ids, &ids, &RuleSet::default(), &mut Mt64::new(42)).is_err());
+    }
+
+    #[test]
+    fn test_analyze_deck() {
+        let starter = load_test_cards(&load_deck(&data_dir().join("decks/starter")));
+        assert_eq!(
+            DeckStats {
+                total_cell_count: 131,
+                total_special_cost: 51,
+                size_distribution: BTreeMap::from([
+                    (3, 1),
+                    (5, 2),
+                    (6, 1),
+                    (8, 4),
+                    (9, 2),
+                    (10, 1),
+                    (11, 1),
+                    (12, 1),
+                    (14, 1),
+                    (15, 1),
+                ]),
+                special_card_count: 15,
+            },
+            analyze_deck(&starter)
+        );
+
+        // Card 70 has no special cell.
+        let stats = analyze_deck(&load_test_cards(&[70, 6]));
+        assert_eq!(2, stats.size_distribution.values().sum::<usize>());
+        assert_eq!(1, stats.special_card_count);
+        assert_eq!(DeckStats::default(), analyze_deck(&[]));
+    }
+
+    #[test]
+    fn test_evaluate_hand() {
+        // Cards of 1 to 3 cells.
+        let tiny = evaluate_hand(&load_test_cards(&[58, 113, 127, 56]));
+        // Splash bomb, bucket slosher, splashooter and mother.
+        let balanced = evaluate_hand(&load_test_cards(&[56, 34, 6, 92]));
+        assert!(tiny < balanced, "tiny: {}, balanced: {}", tiny, balanced);
+        for score in [tiny, balanced] {
+            assert!((0.0..=1.0).contains(&score), "{}", score);
+        }
+        assert_eq!(0.0, evaluate_hand(&[]));
+    }
+
+    #[test]
+    fn test_repair_duplicates() {
+        let mut ids: Vec<u32> = (1..=15).collect();
+        ids.insert(3, 2);
+        ids.push(5);
+        ids.push(99);
+        let (deck, repairs) =
+            repair_deck(&ids, &inventory(), &RuleSet::default(), &mut Mt64::new(42)).unwrap();
+
+        assert_eq!((1..=15).collect::<Vec<u32>>(), deck);
+        assert_eq!(
+            vec![
+                DeckRepair::RemovedDuplicate(2),
+                DeckRepair::RemovedDuplicate(5),
+                DeckRepair::RemovedUnknown(99)
+            ],
+            repairs
+        );
+    }
+
+    #[test]
+    fn test_repair_copies() {
+        let rules = RuleSet {
+            max_card_copies: 2,
+            ..Default::default()
+        };
+        // Copies within the limit are legal.
+        let mut ids: Vec<u32> = (1..=13).collect();
+        ids.insert(1, 1);
+        ids.insert(3, 2);
+        ids.push(2);
+        let (deck, repairs) = repair_deck(&ids, &inventory(), &rules, &mut Mt64::new(42)).unwrap();
+        assert_eq!(&ids[..game::DECK_SIZE], &deck[..]);
+        assert_eq!(vec![DeckRepair::RemovedDuplicate(2)], repairs);
+
+        // A short deck can be filled with copies of the cards in it.
+        let small_inventory: Vec<u32> = (1..=8).collect();
+        let (deck, repairs) =
+            repair_deck(&[1, 1], &small_inventory, &rules, &mut Mt64::new(42)).unwrap();
+        assert_eq!(game::DECK_SIZE, deck.len());
+        assert_eq!(game::DECK_SIZE - 2, repairs.len());
+        assert!(rules.check_card_copies(&deck).is_ok());
+        assert!(repair_deck(&[], &small_inventory[..7], &rules, &mut Mt64::new(42)).is_err());
+    }
+}
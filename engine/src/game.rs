@@ -1,11 +1,26 @@
 use std::{
     collections::HashMap,
     fmt::Display,
+    path::Path,
+    str::FromStr,
 };
 
-use super::card::{
-    Card,
-    CardPosition,
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use super::{
+    board::Board,
+    card::{
+        self,
+        Card,
+        CardPosition,
+    },
+    state::{
+        self,
+        State,
+    },
 };
 
 pub const HAND_SIZE: usize = 4;
@@ -13,13 +28,71 @@ pub const DECK_SIZE: usize = 15;
 
 pub const TURN_COUNT: i32 = 12;
 
+/// Optional rules of variants of the game. The default is the rules of the real game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RuleSet {
+    /// The game ends right after a turn where both players pass.
+    pub end_on_double_pass: bool,
+
+    /// Special points which each player has at the beginning of the game, indexed by
+    /// `PlayerId::to_index`. A handicap for boards which favor one of the players.
+    pub starting_special_points: [i32; 2],
+
+    /// Added to the scores of each player, indexed by `PlayerId::to_index`.
+    /// A negative offset puts the player behind. Scores never go below 0.
+    pub score_offsets: [i32; 2],
+
+    /// The maximum number of copies of a card in a deck. The real game allows only one.
+    pub max_card_copies: u32,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        RuleSet {
+            end_on_double_pass: false,
+            starting_special_points: [0, 0],
+            score_offsets: [0, 0],
+            max_card_copies: 1,
+        }
+    }
+}
+
+impl RuleSet {
+    /// Checks that no card in the deck (or an inventory) exceeds `max_card_copies`.
+    pub fn check_card_copies(&self, ids: &[u32]) -> Result<(), String> {
+        let mut exceeded: Vec<(u32, u32)> = state::count_cards(ids)
+            .into_iter()
+            .filter(|(_, count)| *count > self.max_card_copies)
+            .collect();
+        exceeded.sort();
+        match exceeded.first() {
+            Some((id, count)) => Err(format!(
+                "The card {} has {} copies but at most {} are allowed",
+                id, count, self.max_card_copies
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Context {
     pub all_cards: HashMap<u32, Card>,
     pub enabled_step_execution: bool,
+    pub rules: RuleSet,
 }
 
 impl Context {
+    /// Loads all cards in `card_dir`, reusing the parsed cards in `cache_path` if they
+    /// are still fresh. See `load_cards_cached`.
+    pub fn load_cached(card_dir: &str, cache_path: &Path) -> Result<Context, String> {
+        Ok(Context {
+            all_cards: card::load_cards_cached(card_dir, cache_path)?,
+            enabled_step_execution: false,
+            rules: RuleSet::default(),
+        })
+    }
+
     pub fn get_card(&self, card_id: u32) -> Card {
         self.all_cards
             .get(&card_id)
@@ -32,9 +105,70 @@ impl Context {
     pub fn get_cards(&self, ids: &[u32]) -> Vec<Card> {
         ids.iter().map(|id| self.get_card(*id)).collect()
     }
+
+    /// Checks that `ids` is a deck which can be played under the rules: it has `DECK_SIZE`
+    /// cards, all of them are known, and no card exceeds `RuleSet::max_card_copies`.
+    pub fn validate_deck(&self, ids: &[u32]) -> Result<(), String> {
+        if ids.len() != DECK_SIZE {
+            return Err(format!(
+                "The deck has {} cards but it must have {}",
+                ids.len(),
+                DECK_SIZE
+            ));
+        }
+        if let Some(id) = ids.iter().find(|id| !self.all_cards.contains_key(id)) {
+            return Err(format!("Unknown card ID: {}", id));
+        }
+        self.rules.check_card_copies(ids)
+    }
+
+    /// Checks that each player can put at least one of all cards in their first turn.
+    /// Catches boards which are unplayable with the card set before starting games.
+    pub fn validate_board(&self, board: &Board) -> Result<(), BoardCardError> {
+        let state = State::new(board.clone(), 0, 0, 0, vec![], vec![]);
+        let cards: Vec<Card> = self.all_cards.values().cloned().collect();
+        for player_id in [PlayerId::South, PlayerId::North] {
+            let mut actions = vec![];
+            state::append_valid_actions(&state, &cards, player_id, &mut actions);
+            if actions.iter().all(|action| action.is_pass()) {
+                return Err(BoardCardError::NoOpeningMove {
+                    board_name: board.get_name().to_string(),
+                    player_id,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reasons why a board can't be played with the card set of a `Context`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BoardCardError {
+    /// No card can be put in the first turn of the player.
+    NoOpeningMove {
+        board_name: String,
+        player_id: PlayerId,
+    },
+}
+
+impl Display for BoardCardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BoardCardError::NoOpeningMove {
+                board_name,
+                player_id,
+            } => write!(
+                f,
+                "{} can't put any card in the first turn on the board {:?}",
+                player_id, board_name
+            ),
+        }
+    }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// `South` is the first player (the "player" of the local runner, index 0) and `North` is the
+/// second one. See `proto::player_id` for the conversion from/to the wire format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PlayerId {
     South,
     North,
@@ -56,6 +190,18 @@ impl PlayerId {
     }
 }
 
+impl FromStr for PlayerId {
+    type Err = String;
+
+    /// Accepts `south` or `north` case-insensitively.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [PlayerId::South, PlayerId::North]
+            .into_iter()
+            .find(|player_id| player_id.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("Unknown player: {:?}", s))
+    }
+}
+
 impl Display for PlayerId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let s = match self {
@@ -67,7 +213,7 @@ impl Display for PlayerId {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Rotation {
     Up,
     Right,
@@ -79,6 +225,18 @@ impl Rotation {
     pub const VALUES: [Self; 4] = [Self::Up, Self::Right, Self::Down, Self::Left];
 }
 
+impl FromStr for Rotation {
+    type Err = String;
+
+    /// Accepts the names case-insensitively (e.g. `up`, `Right`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::VALUES
+            .into_iter()
+            .find(|rotation| rotation.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("Unknown rotation: {:?}", s))
+    }
+}
+
 impl Display for Rotation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self)
@@ -112,6 +270,74 @@ impl Action {
     pub fn is_pass(&self) -> bool {
         matches!(self, Action::Pass(_))
     }
+
+    /// Parses an action written in the human notation:
+    ///
+    /// - `pass <card_id>`
+    /// - `put <card_id> <x> <y> <rotation>`
+    /// - `special <card_id> <x> <y> <rotation>`
+    ///
+    /// e.g. `put 42 3 20 left`. Keywords and rotations are case-insensitive.
+    /// `to_notation` formats an action in the same notation.
+    pub fn parse(context: &Context, text: &str) -> Result<Action, String> {
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+        let (kind, args) = match tokens.split_first() {
+            Some((kind, args)) => (kind.to_ascii_lowercase(), args),
+            None => return Err("Empty action".into()),
+        };
+        let expected_len = if kind == "pass" { 1 } else { 4 };
+        if args.len() != expected_len {
+            return Err(format!(
+                "{:?} needs {} argument(s) but got {}: {:?}",
+                kind,
+                expected_len,
+                args.len(),
+                text
+            ));
+        }
+
+        let card_id: u32 = args[0]
+            .parse()
+            .map_err(|e| format!("Invalid card ID {:?}: {}", args[0], e))?;
+        let card = match context.all_cards.get(&card_id) {
+            Some(card) => card.clone(),
+            None => return Err(format!("Unknown card ID: {}", card_id)),
+        };
+        if kind == "pass" {
+            return Ok(Action::Pass(card));
+        }
+
+        let parse_coord = |s: &str| -> Result<i32, String> {
+            s.parse()
+                .map_err(|e| format!("Invalid coordinate {:?}: {}", s, e))
+        };
+        let position = CardPosition {
+            x: parse_coord(args[1])?,
+            y: parse_coord(args[2])?,
+            rotation: args[3].parse()?,
+        };
+        match kind.as_str() {
+            "put" => Ok(Action::Put(card, position)),
+            "special" => Ok(Action::Special(card, position)),
+            _ => Err(format!("Unknown action: {:?}", kind)),
+        }
+    }
+
+    pub fn to_notation(&self) -> String {
+        let (kind, card, position) = match self {
+            Action::Pass(card) => return format!("pass {}", card.get_id()),
+            Action::Put(card, position) => ("put", card, position),
+            Action::Special(card, position) => ("special", card, position),
+        };
+        format!(
+            "{} {} {} {} {}",
+            kind,
+            card.get_id(),
+            position.x,
+            position.y,
+            position.rotation.to_string().to_ascii_lowercase()
+        )
+    }
 }
 
 impl Display for Action {
@@ -142,3 +368,155 @@ impl Display for Action {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        load_board_from_lines,
+        load_card_from_lines,
+    };
+
+    fn new_test_context() -> Context {
+        let card = load_card_from_lines(42, String::from("test card"), 2, 1, &["==".to_string()]);
+        Context {
+            all_cards: HashMap::from([(42, card)]),
+            enabled_step_execution: false,
+            rules: RuleSet::default(),
+        }
+    }
+
+    #[test]
+    fn test_check_card_copies() {
+        let rules = RuleSet::default();
+        assert_eq!(Ok(()), rules.check_card_copies(&[1, 2, 3]));
+        assert_eq!(
+            Err("The card 2 has 2 copies but at most 1 are allowed".to_string()),
+            rules.check_card_copies(&[2, 1, 3, 2])
+        );
+
+        let rules = RuleSet {
+            max_card_copies: 2,
+            ..Default::default()
+        };
+        assert_eq!(Ok(()), rules.check_card_copies(&[2, 1, 3, 2]));
+        assert!(rules.check_card_copies(&[2, 2, 2]).is_err());
+    }
+
+    #[test]
+    fn test_validate_deck() {
+        let mut context = new_test_context();
+        for id in 1..=DECK_SIZE as u32 {
+            let card = load_card_from_lines(id, format!("card {}", id), 2, 1, &["==".into()]);
+            context.all_cards.insert(id, card);
+        }
+        let deck: Vec<u32> = (1..=DECK_SIZE as u32).collect();
+        assert_eq!(Ok(()), context.validate_deck(&deck));
+
+        assert_eq!(
+            Err(format!(
+                "The deck has 14 cards but it must have {}",
+                DECK_SIZE
+            )),
+            context.validate_deck(&deck[1..])
+        );
+        let mut long = deck.clone();
+        long.push(42);
+        assert!(context.validate_deck(&long).is_err());
+
+        let mut unknown = deck.clone();
+        unknown[3] = 99;
+        assert_eq!(
+            Err("Unknown card ID: 99".to_string()),
+            context.validate_deck(&unknown)
+        );
+
+        let mut duplicated = deck.clone();
+        duplicated[0] = 42;
+        duplicated[1] = 42;
+        assert_eq!(
+            Err("The card 42 has 2 copies but at most 1 are allowed".to_string()),
+            context.validate_deck(&duplicated)
+        );
+        context.rules.max_card_copies = 2;
+        assert_eq!(Ok(()), context.validate_deck(&duplicated));
+    }
+
+    #[test]
+    fn test_parse_action() {
+        let context = new_test_context();
+        let card = context.get_card(42);
+
+        assert_eq!(
+            Action::Pass(card.clone()),
+            Action::parse(&context, "pass 42").unwrap()
+        );
+        assert_eq!(
+            Action::Put(
+                card.clone(),
+                CardPosition {
+                    x: 3,
+                    y: 20,
+                    rotation: Rotation::Left
+                }
+            ),
+            Action::parse(&context, "put 42 3 20 left").unwrap()
+        );
+        assert_eq!(
+            Action::Special(
+                card,
+                CardPosition {
+                    x: 0,
+                    y: -1,
+                    rotation: Rotation::Up
+                }
+            ),
+            Action::parse(&context, "  Special 42 0 -1 UP ").unwrap()
+        );
+
+        for text in ["pass 42", "put 42 3 20 left", "special 42 0 -1 up"] {
+            let action = Action::parse(&context, text).unwrap();
+            assert_eq!(text, action.to_notation());
+        }
+
+        assert!(Action::parse(&context, "").is_err());
+        assert!(Action::parse(&context, "pass 1").is_err());
+        assert!(Action::parse(&context, "put 42 3 20").is_err());
+        assert!(Action::parse(&context, "put 42 3 20 sideways").is_err());
+        assert!(Action::parse(&context, "jump 42 3 20 up").is_err());
+    }
+
+    #[test]
+    fn test_validate_board() {
+        let context = new_test_context();
+
+        #[rustfmt::skip]
+        let board = load_board_from_lines(String::from("roomy"), &[
+            "#####",
+            "#.O.#",
+            "#...#",
+            "#.P.#",
+            "#####",
+        ]);
+        assert_eq!(Ok(()), context.validate_board(&board));
+
+        // The card needs two cells in a row but there is no room next to the North's ink.
+        #[rustfmt::skip]
+        let board = load_board_from_lines(String::from("tiny"), &[
+            "###",
+            "#O#",
+            "###",
+            "#.#",
+            "#.#",
+            "#P#",
+            "###",
+        ]);
+        assert_eq!(
+            Err(BoardCardError::NoOpeningMove {
+                board_name: String::from("tiny"),
+                player_id: PlayerId::North
+            }),
+            context.validate_board(&board)
+        );
+    }
+}
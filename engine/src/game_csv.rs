@@ -0,0 +1,158 @@
+use std::io::{
+    self,
+    Write,
+};
+
+use super::{
+    game::PlayerId,
+    outcome::GameOutcome,
+};
+
+/// Columns written by `GameCsvWriter`.
+pub const GAME_CSV_HEADER: [&str; 14] = [
+    "game",
+    "seed",
+    "board",
+    "south_deck",
+    "north_deck",
+    "south_player",
+    "north_player",
+    "south_score",
+    "north_score",
+    "winner",
+    "turns",
+    "south_specials",
+    "north_specials",
+    "forfeit",
+];
+
+/// A game written as a row of `GameCsvWriter`.
+#[derive(Clone, Debug)]
+pub struct GameCsvRow<'a> {
+    /// The index of the game in the run.
+    pub game: u32,
+
+    /// The seed to reproduce the game: the seed of the game if games are seeded one by one,
+    /// otherwise the seed of the run.
+    pub seed: u64,
+    pub board: &'a str,
+
+    /// Following fields are indexed by `PlayerId::to_index`.
+    pub decks: [&'a [u32]; 2],
+
+    /// How the players are configured e.g. `mcts-100`.
+    pub players: [&'a str; 2],
+
+    /// The number of special attacks taken by each player.
+    pub specials_used: [u32; 2],
+
+    pub outcome: &'a GameOutcome,
+}
+
+/// Writes a CSV row for each game. Each row is flushed once it's written so rows of finished
+/// games survive even if the run is interrupted.
+pub struct GameCsvWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> GameCsvWriter<W> {
+    /// Writes the header.
+    pub fn new(mut out: W) -> io::Result<Self> {
+        writeln!(out, "{}", GAME_CSV_HEADER.join(","))?;
+        out.flush()?;
+        Ok(GameCsvWriter {
+            out,
+        })
+    }
+
+    /// Appends rows to `out` which already has the header, e.g. a file of a resumed run.
+    pub fn without_header(out: W) -> Self {
+        GameCsvWriter {
+            out,
+        }
+    }
+
+    pub fn write_row(&mut self, row: &GameCsvRow) -> io::Result<()> {
+        let deck = |ids: &[u32]| {
+            ids.iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<String>>()
+                .join(" ")
+        };
+        let player_id = |player_id: Option<PlayerId>| {
+            player_id.map_or(String::new(), |p| p.to_string().to_lowercase())
+        };
+        let outcome = row.outcome;
+        let fields = [
+            row.game.to_string(),
+            row.seed.to_string(),
+            row.board.to_string(),
+            deck(row.decks[0]),
+            deck(row.decks[1]),
+            row.players[0].to_string(),
+            row.players[1].to_string(),
+            outcome.south.score.to_string(),
+            outcome.north.score.to_string(),
+            player_id(outcome.get_winner()),
+            outcome.turns_played.to_string(),
+            row.specials_used[0].to_string(),
+            row.specials_used[1].to_string(),
+            player_id(outcome.forfeit),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| escape_csv_field(field)).collect();
+        writeln!(self.out, "{}", fields.join(","))?;
+        self.out.flush()
+    }
+
+    pub fn get_output(&self) -> &W {
+        &self.out
+    }
+}
+
+/// Quotes a field if it has characters which have meanings in CSV.
+pub fn escape_csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn test_write_rows() {
+        let mut writer = GameCsvWriter::new(vec![]).unwrap();
+        let won = GameOutcome::new(NamedScore::new("p", 30), NamedScore::new("o", 25), 12);
+        let forfeited = won.clone().with_forfeit(PlayerId::South);
+        for (game, outcome) in [won, forfeited].iter().enumerate() {
+            writer
+                .write_row(&GameCsvRow {
+                    game: game as u32,
+                    seed: 66,
+                    board: "Board, \"Quoted\"",
+                    decks: [&[6, 13], &[22]],
+                    players: ["mcts-100", "random"],
+                    specials_used: [2, 0],
+                    outcome,
+                })
+                .unwrap();
+        }
+
+        let output = String::from_utf8(writer.get_output().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(3, lines.len());
+        assert_eq!(GAME_CSV_HEADER.join(","), lines[0]);
+        assert_eq!(
+            "0,66,\"Board, \"\"Quoted\"\"\",6 13,22,mcts-100,random,30,25,south,12,2,0,",
+            lines[1]
+        );
+        assert_eq!(
+            "1,66,\"Board, \"\"Quoted\"\"\",6 13,22,mcts-100,random,30,25,north,12,2,0,south",
+            lines[2]
+        );
+    }
+}
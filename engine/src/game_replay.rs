@@ -0,0 +1,242 @@
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use super::{
+    board::{
+        self,
+        Board,
+    },
+    game::{
+        Action,
+        Context,
+        PlayerId,
+        RuleSet,
+    },
+    replay::Replay,
+    state::{
+        self,
+        State,
+    },
+};
+
+/// A recorded game with the state after every turn, which can be saved as JSON.
+///
+/// Unlike `Replay`, it has everything needed to reconstruct the game: the initial board, the
+/// rules and the decks. States are kept so that a viewer can show any turn without the engine,
+/// and `reconstruct` checks them against the engine.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameReplay {
+    pub board_name: String,
+
+    /// The board at the beginning of the game formatted by `Board::to_lines`.
+    pub board: Vec<String>,
+
+    pub rules: RuleSet,
+
+    /// Decks indexed by `PlayerId::to_index`.
+    pub decks: [Vec<u32>; 2],
+
+    pub turns: Vec<ReplayTurn>,
+
+    /// The player who took an illegal action. The action isn't in `turns`.
+    pub forfeit: Option<PlayerId>,
+}
+
+/// Actions of a turn and the state after it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayTurn {
+    /// Actions in the notation of `Action::parse`, indexed by `PlayerId::to_index`.
+    pub actions: [String; 2],
+
+    /// The board after the turn formatted by `Board::to_lines`.
+    pub board: Vec<String>,
+
+    /// Special points after the turn, indexed by `PlayerId::to_index`.
+    pub special_points: [i32; 2],
+
+    /// (South's score, North's score) after the turn.
+    pub scores: (u32, u32),
+}
+
+impl ReplayTurn {
+    fn new(south_action: &Action, north_action: &Action, state: &State) -> Self {
+        ReplayTurn {
+            actions: [south_action.to_notation(), north_action.to_notation()],
+            board: state.board.to_lines(),
+            special_points: [state.player_special_count, state.opponent_special_count],
+            scores: state.get_scores(),
+        }
+    }
+}
+
+impl GameReplay {
+    /// Records a game played from the beginning with `replay`'s actions under `rules`.
+    pub fn record(
+        rules: RuleSet,
+        replay: &Replay,
+        decks: [Vec<u32>; 2],
+        forfeit: Option<PlayerId>,
+    ) -> Result<GameReplay, String> {
+        let board = replay.get_board();
+        let mut state = State::new_game(board.clone(), rules);
+        let mut turns = vec![];
+        for (turn, (south, north)) in replay.get_turns().iter().enumerate() {
+            state::update_state(&mut state, south, north)
+                .map_err(|e| format!("Turn {}: {}", turn + 1, e))?;
+            turns.push(ReplayTurn::new(south, north, &state));
+        }
+        Ok(GameReplay {
+            board_name: board.get_name().to_string(),
+            board: board.to_lines(),
+            rules,
+            decks,
+            turns,
+            forfeit,
+        })
+    }
+
+    pub fn from_json(json: &str) -> Result<GameReplay, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid replay: {}", e))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("A replay is always serializable")
+    }
+
+    pub fn get_initial_board(&self) -> Board {
+        let lines: Vec<&str> = self.board.iter().map(|line| line.as_str()).collect();
+        board::load_board_from_lines(self.board_name.clone(), &lines)
+    }
+
+    /// Scores at the end of the game.
+    pub fn get_final_scores(&self) -> (u32, u32) {
+        match self.turns.last() {
+            Some(turn) => turn.scores,
+            None => State::new_game(self.get_initial_board(), self.rules).get_scores(),
+        }
+    }
+
+    /// Actions parsed with the cards of `context`.
+    pub fn to_replay(&self, context: &Context) -> Result<Replay, String> {
+        let mut replay = Replay::new(self.get_initial_board());
+        for (turn, record) in self.turns.iter().enumerate() {
+            let [south, north] = &record.actions;
+            let parse = |action: &str| {
+                Action::parse(context, action).map_err(|e| format!("Turn {}: {}", turn + 1, e))
+            };
+            replay.push(parse(south)?, parse(north)?);
+        }
+        Ok(replay)
+    }
+
+    /// Plays the recorded actions again and returns the state after each turn.
+    /// A game forfeited before the first turn has only the initial state with the forfeit.
+    /// Returns an error if the engine doesn't reproduce a recorded state.
+    pub fn reconstruct(&self, context: &Context) -> Result<Vec<State>, String> {
+        let replay = self.to_replay(context)?;
+        let mut state = State::new_game(replay.get_board().clone(), self.rules);
+        let mut states = vec![];
+        let turns = replay.get_turns().iter().zip(self.turns.iter());
+        for (turn, ((south, north), recorded)) in turns.enumerate() {
+            state::update_state(&mut state, south, north)
+                .map_err(|e| format!("Turn {}: {}", turn + 1, e))?;
+            let actual = ReplayTurn::new(south, north, &state);
+            if actual != *recorded {
+                return Err(format!(
+                    "Turn {}: the recorded state differs from the engine's\nrecorded: {:?}\nactual: {:?}",
+                    turn + 1,
+                    recorded,
+                    actual
+                ));
+            }
+            states.push(state.clone());
+        }
+        if let Some(player_id) = self.forfeit {
+            match states.last_mut() {
+                Some(last) => last.forfeit(player_id),
+                None => {
+                    state.forfeit(player_id);
+                    states.push(state);
+                }
+            }
+        }
+        Ok(states)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_support::new_test_context,
+        *,
+    };
+
+    #[test]
+    fn test_reconstruct_tampered_replay() {
+        let context = new_test_context();
+        #[rustfmt::skip]
+        let board = load_board_from_lines(String::from("test board"), &[
+            "#######",
+            "#..O..#",
+            "#.....#",
+            "#.....#",
+            "#..P..#",
+            "#######",
+        ]);
+        let replay = Replay::parse(
+            &context,
+            board,
+            "1 south put 1 3 3 up\n1 north put 2 3 2 up\n2 south pass 2\n2 north pass 1\n",
+        )
+        .unwrap();
+        let recorded =
+            GameReplay::record(RuleSet::default(), &replay, [vec![1, 2], vec![1, 2]], None)
+                .unwrap();
+        assert_eq!((2, 3), recorded.get_final_scores());
+
+        let loaded = GameReplay::from_json(&recorded.to_json()).unwrap();
+        assert_eq!(recorded, loaded);
+        assert_eq!(replay, loaded.to_replay(&context).unwrap());
+        let states = loaded.reconstruct(&context).unwrap();
+        assert_eq!(2, states.len());
+        assert_eq!((2, 3), states[1].get_scores());
+
+        let mut tampered = loaded;
+        tampered.turns[0].scores = (5, 5);
+        let err = tampered.reconstruct(&context).unwrap_err();
+        assert!(
+            err.starts_with("Turn 1: the recorded state differs"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_reconstruct_forfeit_before_first_turn() {
+        let context = new_test_context();
+        #[rustfmt::skip]
+        let board = load_board_from_lines(String::from("test board"), &[
+            "#####",
+            "#.O.#",
+            "#.P.#",
+            "#####",
+        ]);
+        let replay = Replay::new(board);
+        let recorded = GameReplay::record(
+            RuleSet::default(),
+            &replay,
+            [vec![1, 2], vec![]],
+            Some(PlayerId::North),
+        )
+        .unwrap();
+
+        let states = recorded.reconstruct(&context).unwrap();
+        assert_eq!(1, states.len());
+        assert_eq!(0, states[0].get_turn());
+        assert!(states[0].is_end());
+        assert_eq!(Some(PlayerId::North), states[0].get_forfeited_player());
+    }
+}
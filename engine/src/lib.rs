@@ -1,9 +1,35 @@
+mod action_index;
+mod bitboard;
 mod board;
 mod card;
+mod data;
+mod deck;
 mod game;
+mod game_csv;
+mod game_replay;
+mod match_log;
+mod outcome;
+mod replay;
+mod run_config;
 mod state;
+mod symmetry;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+mod threat;
 
+pub use action_index::*;
+pub use bitboard::*;
 pub use board::*;
 pub use card::*;
+pub use data::*;
+pub use deck::*;
 pub use game::*;
+pub use game_csv::*;
+pub use game_replay::*;
+pub use match_log::*;
+pub use outcome::*;
+pub use replay::*;
+pub use run_config::*;
 pub use state::*;
+pub use symmetry::*;
+pub use threat::*;
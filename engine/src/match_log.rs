@@ -0,0 +1,351 @@
+use std::fmt::Display;
+
+use super::{
+    board::Board,
+    game::{
+        Action,
+        Context,
+        PlayerId,
+        RuleSet,
+    },
+    replay::Replay,
+    state::{
+        self,
+        State,
+    },
+};
+
+/// A game recorded by the server: decks, actions and scores of every turn.
+///
+/// A match log is a sequence of records formatted like:
+///
+/// ```text
+/// # game: 00000000000004d2
+/// # board: Massugu Street
+/// south deck: 6 13 22 ...
+/// north deck: 6 13 22 ...
+/// 1 south put 6 4 20 up
+/// 1 north pass 13
+/// 1 scores 4 1
+/// ...
+/// ```
+///
+/// Actions are written as `Replay` does and `<turn> scores <south> <north>` are the scores
+/// after the turn.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MatchRecord {
+    pub game_uid: u64,
+
+    /// Decks indexed by `PlayerId::to_index`.
+    pub decks: [Vec<u32>; 2],
+
+    pub replay: Replay,
+
+    /// (South's score, North's score) after each turn.
+    pub turn_scores: Vec<(u32, u32)>,
+}
+
+impl MatchRecord {
+    pub fn new(game_uid: u64, board: Board, south_deck: Vec<u32>, north_deck: Vec<u32>) -> Self {
+        MatchRecord {
+            game_uid,
+            decks: [south_deck, north_deck],
+            replay: Replay::new(board),
+            turn_scores: vec![],
+        }
+    }
+
+    pub fn push_turn(&mut self, south_action: Action, north_action: Action, scores: (u32, u32)) {
+        self.replay.push(south_action, north_action);
+        self.turn_scores.push(scores);
+    }
+
+    /// Replays the actions with the engine under `rules` and checks that they were legal and that
+    /// the engine computes the same scores as recorded at every turn.
+    pub fn verify(&self, rules: RuleSet) -> Result<(), String> {
+        let mut state = State::new_game(self.replay.get_board().clone(), rules);
+        let turns = self.replay.get_turns().iter().zip(self.turn_scores.iter());
+        for (turn, ((south, north), recorded)) in turns.enumerate() {
+            let turn = turn + 1;
+            for (player_id, action) in [(PlayerId::South, south), (PlayerId::North, north)] {
+                let card_id = action.get_consumed_card().get_id();
+                let copies = self.decks[player_id.to_index()]
+                    .iter()
+                    .filter(|id| **id == card_id)
+                    .count() as u32;
+                if copies == 0 {
+                    return Err(format!(
+                        "Turn {}: {} used a card not in the deck: {}",
+                        turn, player_id, action
+                    ));
+                }
+                if state.count_consumed(player_id, card_id) >= copies {
+                    return Err(format!(
+                        "Turn {}: {} used a card twice: {}",
+                        turn, player_id, action
+                    ));
+                }
+            }
+            state::update_state(&mut state, south, north)
+                .map_err(|e| format!("Turn {}: {}", turn, e))?;
+
+            let recomputed = state.get_scores();
+            if recomputed != *recorded {
+                return Err(format!(
+                    "Turn {}: recorded scores are {:?} but the engine computes {:?}",
+                    turn, recorded, recomputed
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for MatchRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "# game: {:016x}", self.game_uid)?;
+        writeln!(f, "# board: {}", self.replay.get_board().get_name())?;
+        for player_id in [PlayerId::South, PlayerId::North] {
+            let deck: Vec<String> = self.decks[player_id.to_index()]
+                .iter()
+                .map(|id| id.to_string())
+                .collect();
+            writeln!(
+                f,
+                "{} deck: {}",
+                player_id.to_string().to_lowercase(),
+                deck.join(" ")
+            )?;
+        }
+        let turns = self.replay.get_turns().iter().zip(self.turn_scores.iter());
+        for (turn, ((south, north), (south_score, north_score))) in turns.enumerate() {
+            writeln!(f, "{} south {}", turn + 1, south.to_notation())?;
+            writeln!(f, "{} north {}", turn + 1, north.to_notation())?;
+            writeln!(f, "{} scores {} {}", turn + 1, south_score, north_score)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses records written by `MatchRecord`'s `Display`.
+/// Boards are looked up from `boards` by their names.
+pub fn parse_match_log(
+    context: &Context,
+    boards: &[Board],
+    text: &str,
+) -> Result<Vec<MatchRecord>, String> {
+    let mut records = vec![];
+    let mut lines = text.lines().enumerate().peekable();
+    while let Some((line_index, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let game_uid = line
+            .strip_prefix("# game:")
+            .ok_or_else(|| format!("Line {}: expected `# game: <uid>`", line_index + 1))?;
+        let game_uid = u64::from_str_radix(game_uid.trim(), 16)
+            .map_err(|e| format!("Line {}: invalid game uid: {}", line_index + 1, e))?;
+
+        let mut block = vec![];
+        while let Some((_, line)) = lines.peek() {
+            if line.trim().starts_with("# game:") {
+                break;
+            }
+            block.push(lines.next().unwrap());
+        }
+        let record = parse_record(context, boards, game_uid, &block)
+            .map_err(|e| format!("Game {:016x}: {}", game_uid, e))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+fn parse_record(
+    context: &Context,
+    boards: &[Board],
+    game_uid: u64,
+    lines: &[(usize, &str)],
+) -> Result<MatchRecord, String> {
+    let mut board = None;
+    let mut decks: [Option<Vec<u32>>; 2] = [None, None];
+    let mut actions = String::new();
+    let mut action_lines = 0;
+    let mut turn_scores = vec![];
+    for (line_index, line) in lines {
+        let line = line.trim();
+        let error = |msg: String| format!("Line {} ({:?}): {}", line_index + 1, line, msg);
+        if let Some(name) = line.strip_prefix("# board:") {
+            let name = name.trim();
+            board = Some(
+                boards
+                    .iter()
+                    .find(|board| board.get_name() == name)
+                    .ok_or_else(|| error(format!("Unknown board: {}", name)))?,
+            );
+        } else if let Some((player_id, deck)) = line.split_once(" deck:") {
+            let player_id: PlayerId = player_id.parse().map_err(error)?;
+            let deck = deck
+                .split_whitespace()
+                .map(|id| id.parse::<u32>())
+                .collect::<Result<Vec<u32>, _>>()
+                .map_err(|e| error(format!("Invalid card id: {}", e)))?;
+            decks[player_id.to_index()] = Some(deck);
+        } else if let Some((turn, scores)) = line.split_once(" scores ") {
+            let turn: usize = turn.parse().map_err(|e| error(format!("{}", e)))?;
+            if turn != turn_scores.len() + 1 {
+                return Err(error(format!(
+                    "Expected scores of turn {}",
+                    turn_scores.len() + 1
+                )));
+            }
+            let scores = scores
+                .split_whitespace()
+                .map(|score| score.parse::<u32>())
+                .collect::<Result<Vec<u32>, _>>()
+                .map_err(|e| error(format!("Invalid score: {}", e)))?;
+            match scores[..] {
+                [south, north] => turn_scores.push((south, north)),
+                _ => return Err(error("Expected `<turn> scores <south> <north>`".into())),
+            }
+        } else {
+            // Keep line numbers of errors reported by `Replay::parse`.
+            while action_lines < *line_index {
+                actions.push('\n');
+                action_lines += 1;
+            }
+            actions.push_str(line);
+            actions.push('\n');
+            action_lines += 1;
+        }
+    }
+
+    let board = board.ok_or("The board is missing")?;
+    let replay = Replay::parse(context, board.clone(), &actions)?;
+    if replay.get_turns().len() != turn_scores.len() {
+        return Err(format!(
+            "{} turns have actions but {} turns have scores",
+            replay.get_turns().len(),
+            turn_scores.len()
+        ));
+    }
+    let [south_deck, north_deck] = decks;
+    Ok(MatchRecord {
+        game_uid,
+        decks: [
+            south_deck.ok_or("The deck of south is missing")?,
+            north_deck.ok_or("The deck of north is missing")?,
+        ],
+        replay,
+        turn_scores,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_support::{
+            new_test_board,
+            new_test_context,
+        },
+        *,
+    };
+
+    const LOG: &str = "\
+# game: 00000000000004d2
+# board: test board
+south deck: 1 2
+north deck: 1 2
+1 south put 1 3 4 up
+1 north put 1 3 2 up
+1 scores 2 2
+2 south put 2 2 3 up
+2 north pass 2
+2 scores 4 2
+# game: 00000000000004d3
+# board: test board
+south deck: 1 2
+north deck: 1 2
+1 south pass 1
+1 north pass 1
+1 scores 1 1
+";
+
+    #[test]
+    fn test_verify_log() {
+        let context = new_test_context();
+        let boards = [new_test_board()];
+        let records = parse_match_log(&context, &boards, LOG).unwrap();
+
+        assert_eq!(2, records.len());
+        assert_eq!(0x4d2, records[0].game_uid);
+        assert_eq!(vec![(2, 2), (4, 2)], records[0].turn_scores);
+        for record in records.iter() {
+            assert_eq!(Ok(()), record.verify(context.rules));
+        }
+        // Formatted records are parsed back.
+        let text: String = records.iter().map(|record| record.to_string()).collect();
+        assert_eq!(records, parse_match_log(&context, &boards, &text).unwrap());
+    }
+
+    #[test]
+    fn test_verify_tampered_log() {
+        let context = new_test_context();
+        let boards = [new_test_board()];
+
+        // The recorded scores don't match.
+        let tampered = LOG.replace("2 scores 4 2", "2 scores 5 2");
+        let records = parse_match_log(&context, &boards, &tampered).unwrap();
+        let err = records[0].verify(context.rules).unwrap_err();
+        assert!(err.starts_with("Turn 2: recorded scores"), "{}", err);
+
+        // The scores are recorded under other rules.
+        let records = parse_match_log(&context, &boards, LOG).unwrap();
+        let rules = RuleSet {
+            score_offsets: [1, 0],
+            ..context.rules
+        };
+        let err = records[0].verify(rules).unwrap_err();
+        assert!(err.starts_with("Turn 1: recorded scores"), "{}", err);
+
+        // The action is replaced with an illegal one, which is away from the player's ink.
+        let tampered = LOG.replace("2 south put 2 2 3 up", "2 south put 2 1 1 up");
+        let records = parse_match_log(&context, &boards, &tampered).unwrap();
+        let err = records[0].verify(context.rules).unwrap_err();
+        assert!(
+            err.starts_with("Turn 2: South took an illegal action (the card doesn't touch"),
+            "{}",
+            err
+        );
+
+        // North uses a card which isn't in the deck.
+        let tampered = LOG.replace("north deck: 1 2\n1 south put", "north deck: 2\n1 south put");
+        let records = parse_match_log(&context, &boards, &tampered).unwrap();
+        let err = records[0].verify(context.rules).unwrap_err();
+        assert!(
+            err.starts_with("Turn 1: North used a card not in the deck"),
+            "{}",
+            err
+        );
+
+        // South uses the only copy of a card twice.
+        let tampered = LOG.replace("2 south put 2 2 3 up", "2 south put 1 2 3 up");
+        let records = parse_match_log(&context, &boards, &tampered).unwrap();
+        let err = records[0].verify(context.rules).unwrap_err();
+        assert!(
+            err.starts_with("Turn 2: South used a card twice"),
+            "{}",
+            err
+        );
+        // It's fine if the deck has two copies of the card.
+        let tampered = tampered
+            .replacen("south deck: 1 2", "south deck: 1 1 2", 1)
+            .replace("2 scores 4 2", "2 scores 3 2");
+        let records = parse_match_log(&context, &boards, &tampered).unwrap();
+        assert_eq!(Ok(()), records[0].verify(context.rules));
+
+        // The second game is still fine.
+        assert_eq!(Ok(()), records[1].verify(context.rules));
+    }
+}
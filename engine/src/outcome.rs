@@ -0,0 +1,219 @@
+use std::{
+    fmt::Display,
+    time::Duration,
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use super::{
+    game::PlayerId,
+    state::State,
+};
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
+pub struct NamedScore {
+    pub name: String,
+    pub score: u32,
+}
+
+impl NamedScore {
+    pub fn new(name: &str, score: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            score,
+        }
+    }
+}
+
+impl Display for NamedScore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: ({})", self.name, self.score)
+    }
+}
+
+/// How a game ended. The local runner, the server and the client report games with it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameOutcome {
+    pub south: NamedScore,
+    pub north: NamedScore,
+    pub turns_played: u32,
+
+    /// The player who gave up the game. The player loses regardless of the scores.
+    pub forfeit: Option<PlayerId>,
+
+    /// Time spent from the first turn to the end of the game, if measured.
+    pub duration: Option<Duration>,
+}
+
+impl GameOutcome {
+    pub fn new(south: NamedScore, north: NamedScore, turns_played: u32) -> Self {
+        GameOutcome {
+            south,
+            north,
+            turns_played,
+            forfeit: None,
+            duration: None,
+        }
+    }
+
+    /// Makes an outcome of a game which ended at `state`.
+    pub fn from_state(south_name: &str, north_name: &str, state: &State) -> Self {
+        let (south, north) = state.get_scores();
+        let outcome = Self::new(
+            NamedScore::new(south_name, south),
+            NamedScore::new(north_name, north),
+            state.get_turn() as u32,
+        );
+        match state.get_forfeited_player() {
+            Some(player_id) => outcome.with_forfeit(player_id),
+            None => outcome,
+        }
+    }
+
+    pub fn with_forfeit(mut self, player_id: PlayerId) -> Self {
+        self.forfeit = Some(player_id);
+        self
+    }
+
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    pub fn get_score(&self, player_id: PlayerId) -> &NamedScore {
+        match player_id {
+            PlayerId::South => &self.south,
+            PlayerId::North => &self.north,
+        }
+    }
+
+    /// Returns `None` for a draw.
+    pub fn get_winner(&self) -> Option<PlayerId> {
+        if let Some(player_id) = self.forfeit {
+            return Some(player_id.another());
+        }
+        match self.south.score.cmp(&self.north.score) {
+            std::cmp::Ordering::Less => Some(PlayerId::North),
+            std::cmp::Ordering::Equal => None,
+            std::cmp::Ordering::Greater => Some(PlayerId::South),
+        }
+    }
+
+    /// The outcome seen from `player_id`.
+    pub fn view(&self, player_id: PlayerId) -> OutcomeView<'_> {
+        OutcomeView {
+            outcome: self,
+            player_id,
+        }
+    }
+}
+
+impl Display for GameOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} v.s. {}", self.south, self.north)?;
+        if let Some(player_id) = self.forfeit {
+            write!(f, " ({} forfeited)", player_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// A `GameOutcome` seen from one of the players.
+#[derive(Clone, Copy, Debug)]
+pub struct OutcomeView<'o> {
+    outcome: &'o GameOutcome,
+    player_id: PlayerId,
+}
+
+impl<'o> OutcomeView<'o> {
+    pub fn get_my_score(&self) -> u32 {
+        self.outcome.get_score(self.player_id).score
+    }
+
+    pub fn get_opponent_score(&self) -> u32 {
+        self.outcome.get_score(self.player_id.another()).score
+    }
+
+    pub fn is_win(&self) -> bool {
+        self.outcome.get_winner() == Some(self.player_id)
+    }
+
+    pub fn is_draw(&self) -> bool {
+        self.outcome.get_winner().is_none()
+    }
+
+    pub fn is_lose(&self) -> bool {
+        self.outcome.get_winner() == Some(self.player_id.another())
+    }
+}
+
+impl<'o> Display for OutcomeView<'o> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let result = if self.is_win() {
+            "WIN"
+        } else if self.is_lose() {
+            "LOSE"
+        } else {
+            "DRAW"
+        };
+        write!(
+            f,
+            "GameResult[{} ({}, {})]",
+            result,
+            self.get_my_score(),
+            self.get_opponent_score()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn test_views() {
+        let outcome = GameOutcome::new(NamedScore::new("s", 10), NamedScore::new("n", 8), 12);
+
+        assert_eq!(Some(PlayerId::South), outcome.get_winner());
+        let south = outcome.view(PlayerId::South);
+        assert_eq!((10, 8), (south.get_my_score(), south.get_opponent_score()));
+        assert!(south.is_win());
+        assert_eq!("GameResult[WIN (10, 8)]", south.to_string());
+        let north = outcome.view(PlayerId::North);
+        assert_eq!((8, 10), (north.get_my_score(), north.get_opponent_score()));
+        assert!(north.is_lose());
+
+        // Forfeiting loses regardless of the scores.
+        let forfeited = outcome.with_forfeit(PlayerId::South);
+        assert_eq!(Some(PlayerId::North), forfeited.get_winner());
+        assert!(forfeited.view(PlayerId::North).is_win());
+        assert_eq!(
+            "s: (10) v.s. n: (8) (South forfeited)",
+            forfeited.to_string()
+        );
+
+        let draw = GameOutcome::new(NamedScore::new("s", 3), NamedScore::new("n", 3), 12);
+        assert!(draw.view(PlayerId::South).is_draw());
+        assert!(draw.view(PlayerId::North).is_draw());
+    }
+
+    #[test]
+    fn test_from_state() {
+        let board = load_board_from_lines(
+            String::from("test board"),
+            &["#####", "#pP.#", "#.Oo#", "#..o#", "#####"],
+        );
+        let state = State::new(board, 5, 0, 0, vec![], vec![]);
+
+        let outcome = GameOutcome::from_state("s", "n", &state);
+        assert_eq!(NamedScore::new("s", 2), outcome.south);
+        assert_eq!(NamedScore::new("n", 3), outcome.north);
+        assert_eq!(5, outcome.turns_played);
+        assert_eq!(None, outcome.forfeit);
+        assert_eq!(None, outcome.duration);
+    }
+}
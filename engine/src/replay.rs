@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+};
+
+use super::{
+    board::Board,
+    game::{
+        self,
+        Action,
+        Context,
+        PlayerId,
+    },
+    state::{
+        self,
+        State,
+    },
+};
+
+/// Parses a line of a turn-indexed action list: `<turn> <south|north> <action>` where `turn`
+/// starts from 1 and `action` is written in the notation of `Action::parse`.
+pub fn parse_turn_action(context: &Context, line: &str) -> Result<(i32, PlayerId, Action), String> {
+    let mut tokens = line.trim().splitn(3, char::is_whitespace);
+    let (turn, player_id, action) = match (tokens.next(), tokens.next(), tokens.next()) {
+        (Some(turn), Some(player_id), Some(action)) => (turn, player_id, action),
+        _ => return Err("Expected `<turn> <south|north> <action>`".into()),
+    };
+    let turn: i32 = turn.parse().map_err(|e| format!("Invalid turn: {}", e))?;
+    if !(1..=game::TURN_COUNT).contains(&turn) {
+        return Err(format!(
+            "Turn must be in 1..={} but got {}",
+            game::TURN_COUNT,
+            turn
+        ));
+    }
+    Ok((turn, player_id.parse()?, Action::parse(context, action)?))
+}
+
+/// Actions taken by both players in a game.
+///
+/// Formatted as turn-indexed action lists (see `parse_turn_action`) so a replay can be passed
+/// to the local runner as a script to reproduce the game.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Replay {
+    board: Board,
+
+    /// (South's action, North's action) indexed by turn.
+    turns: Vec<(Action, Action)>,
+}
+
+impl Replay {
+    pub fn new(board: Board) -> Self {
+        Replay {
+            board,
+            turns: vec![],
+        }
+    }
+
+    /// Parses a replay of a game played on `board`.
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn parse(context: &Context, board: Board, text: &str) -> Result<Replay, String> {
+        let mut actions: HashMap<(i32, PlayerId), Action> = HashMap::new();
+        for (line_index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (turn, player_id, action) = parse_turn_action(context, line)
+                .map_err(|e| format!("Line {} ({:?}): {}", line_index + 1, line, e))?;
+            if actions.insert((turn, player_id), action).is_some() {
+                return Err(format!(
+                    "Line {}: turn {} of {} appears twice",
+                    line_index + 1,
+                    turn,
+                    player_id
+                ));
+            }
+        }
+
+        let mut replay = Replay::new(board);
+        for turn in 1..=game::TURN_COUNT {
+            let south = actions.remove(&(turn, PlayerId::South));
+            let north = actions.remove(&(turn, PlayerId::North));
+            match (south, north) {
+                (Some(south), Some(north)) => replay.push(south, north),
+                (None, None) => break,
+                _ => return Err(format!("Turn {} has an action of only one player", turn)),
+            }
+        }
+        if let Some((turn, player_id)) = actions.keys().min_by_key(|(turn, _)| *turn) {
+            return Err(format!(
+                "Turn {} of {} follows a missing turn",
+                turn, player_id
+            ));
+        }
+        Ok(replay)
+    }
+
+    pub fn push(&mut self, south_action: Action, north_action: Action) {
+        self.turns.push((south_action, north_action));
+    }
+
+    pub fn get_board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn get_turns(&self) -> &[(Action, Action)] {
+        &self.turns
+    }
+
+    /// The number of special attacks taken by `player_id`.
+    pub fn count_specials(&self, player_id: PlayerId) -> u32 {
+        self.turns
+            .iter()
+            .map(|(south, north)| match player_id {
+                PlayerId::South => south,
+                PlayerId::North => north,
+            })
+            .filter(|action| matches!(action, Action::Special(..)))
+            .count() as u32
+    }
+
+    /// Returns the first turn index where actions of the replays differ.
+    /// If one replay is a prefix of the other, the length of the shorter one is returned.
+    pub fn first_divergence(&self, other: &Replay) -> Option<usize> {
+        let differs = self
+            .turns
+            .iter()
+            .zip(other.turns.iter())
+            .position(|(a, b)| a != b);
+        match differs {
+            Some(turn) => Some(turn),
+            None if self.turns.len() != other.turns.len() => {
+                Some(self.turns.len().min(other.turns.len()))
+            }
+            None => None,
+        }
+    }
+
+    /// Reconstructs the state after the first `turn_count` turns are played.
+    pub fn get_state_after(&self, turn_count: usize) -> Result<State, String> {
+        let mut state = State::new(self.board.clone(), 0, 0, 0, vec![], vec![]);
+        for (turn, (south, north)) in self.turns.iter().take(turn_count).enumerate() {
+            state::update_state(&mut state, south, north)
+                .map_err(|e| format!("Turn {}: {}", turn + 1, e))?;
+        }
+        Ok(state)
+    }
+}
+
+impl Display for Replay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "# board: {}", self.board.get_name())?;
+        for (turn, (south, north)) in self.turns.iter().enumerate() {
+            writeln!(f, "{} south {}", turn + 1, south.to_notation())?;
+            writeln!(f, "{} north {}", turn + 1, north.to_notation())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_support::{
+            new_test_board,
+            new_test_context,
+        },
+        *,
+    };
+
+    #[test]
+    fn test_first_divergence() {
+        let context = new_test_context();
+        let common = "1 south put 1 3 4 up\n1 north put 1 3 2 up\n";
+        let a = Replay::parse(
+            &context,
+            new_test_board(),
+            &format!("{}2 south put 2 2 3 up\n2 north pass 2\n", common),
+        )
+        .unwrap();
+        let b = Replay::parse(
+            &context,
+            new_test_board(),
+            &format!("{}2 south put 2 3 3 up\n2 north pass 2\n", common),
+        )
+        .unwrap();
+
+        assert_eq!(None, a.first_divergence(&a));
+        assert_eq!(Some(1), a.first_divergence(&b));
+        assert_eq!(Some(1), b.first_divergence(&a));
+
+        let prefix = Replay::parse(&context, new_test_board(), common).unwrap();
+        assert_eq!(Some(1), prefix.first_divergence(&a));
+
+        // Both diverged boards are reconstructed.
+        let state_a = a.get_state_after(2).unwrap();
+        let state_b = b.get_state_after(2).unwrap();
+        assert_eq!(
+            BoardCell::Ink(PlayerId::South),
+            state_a.board.get_cell(BoardPosition {
+                x: 2,
+                y: 3
+            })
+        );
+        assert_eq!(
+            BoardCell::None,
+            state_b.board.get_cell(BoardPosition {
+                x: 2,
+                y: 3
+            })
+        );
+        assert_eq!(a.get_state_after(1), b.get_state_after(1));
+    }
+
+    #[test]
+    fn test_format_and_parse() {
+        let context = new_test_context();
+        let mut replay = Replay::new(new_test_board());
+        replay.push(
+            Action::Put(
+                context.get_card(1),
+                CardPosition {
+                    x: 3,
+                    y: 4,
+                    rotation: Rotation::Up,
+                },
+            ),
+            Action::Pass(context.get_card(2)),
+        );
+
+        let text = replay.to_string();
+        assert_eq!(
+            replay,
+            Replay::parse(&context, new_test_board(), &text).unwrap()
+        );
+
+        assert!(Replay::parse(&context, new_test_board(), "1 south pass 1").is_err());
+        assert!(
+            Replay::parse(&context, new_test_board(), "2 south pass 1\n2 north pass 1").is_err()
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+};
+
+use serde::Serialize;
+
+/// Effective parameters of a run which are needed to reproduce it.
+/// Binaries log it once at startup.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RunConfig {
+    pub binary: String,
+    pub seed: u64,
+    pub board: Option<String>,
+    pub decks: Vec<String>,
+    pub players: Vec<String>,
+    pub params: BTreeMap<String, String>,
+}
+
+impl RunConfig {
+    pub fn new(binary: &str, seed: u64) -> Self {
+        RunConfig {
+            binary: binary.to_string(),
+            seed,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_board(mut self, board: &str) -> Self {
+        self.board = Some(board.to_string());
+        self
+    }
+
+    pub fn with_deck(mut self, deck: impl Display) -> Self {
+        self.decks.push(deck.to_string());
+        self
+    }
+
+    pub fn with_player(mut self, player: impl Display) -> Self {
+        self.players.push(player.to_string());
+        self
+    }
+
+    pub fn with_param(mut self, name: &str, value: impl Display) -> Self {
+        self.params.insert(name.to_string(), value.to_string());
+        self
+    }
+}
+
+/// Formats the config as a single line of JSON.
+impl Display for RunConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let json = serde_json::to_string(self).map_err(|_| std::fmt::Error)?;
+        write!(f, "{}", json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_line_json() {
+        let config = RunConfig::new("local", 0x42)
+            .with_board("massugu_street")
+            .with_deck("data/decks/starter")
+            .with_player("random")
+            .with_param("play_cnt", 10);
+
+        let line = config.to_string();
+        assert!(!line.contains('\n'));
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(66, value["seed"]);
+        assert_eq!("massugu_street", value["board"]);
+        assert_eq!("10", value["params"]["play_cnt"]);
+    }
+}
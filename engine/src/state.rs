@@ -5,14 +5,18 @@ use std::{
     hash::Hash,
 };
 
+use log::*;
 use more_asserts::*;
+use once_cell::sync::OnceCell;
 
 use crate::card;
 
 use super::{
     board::{
+        self,
         Board,
         BoardCell,
+        BoardDelta,
         BoardPosition,
     },
     card::{
@@ -24,16 +28,35 @@ use super::{
     game::{
         Action,
         PlayerId,
+        Rotation,
+        RuleSet,
     },
 };
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Hash, PartialEq, Eq)]
 pub struct PlayerCardState {
     player_id: PlayerId,
     hands: Vec<Card>,
     deck: Vec<Card>,
 }
 
+impl Clone for PlayerCardState {
+    fn clone(&self) -> Self {
+        PlayerCardState {
+            player_id: self.player_id,
+            hands: self.hands.clone(),
+            deck: self.deck.clone(),
+        }
+    }
+
+    /// Reuses the buffers of `self`.
+    fn clone_from(&mut self, source: &Self) {
+        self.player_id = source.player_id;
+        self.hands.clone_from(&source.hands);
+        self.deck.clone_from(&source.deck);
+    }
+}
+
 impl PlayerCardState {
     pub fn new(player_id: PlayerId, mut hands: Vec<Card>, deck: Vec<Card>) -> PlayerCardState {
         hands.sort();
@@ -117,15 +140,29 @@ impl Display for PlayerCardState {
 }
 
 /// Observable information about the current state of the game.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug)]
 pub struct State {
     pub board: Board,
     pub turn: i32,
     pub player_special_count: i32,
     pub opponent_special_count: i32,
 
+    /// Consumed cards in the order of turns.
     player_consumed_cards: Vec<u32>,
     opponent_consumed_cards: Vec<u32>,
+
+    /// The number of consumed copies of each card in `*_consumed_cards`, indexed by
+    /// `PlayerId::to_index` for fast lookups. It's built by the first lookup and isn't cloned,
+    /// so that states cloned for playouts, which never look it up, don't allocate maps.
+    consumed_card_counts: OnceCell<[HashMap<u32, u32>; 2]>,
+
+    rules: RuleSet,
+
+    /// True if the game has ended before `TURN_COUNT` turns by `rules`.
+    ended_early: bool,
+
+    /// The player who lost the game by forfeit e.g. by taking an illegal action.
+    forfeited_by: Option<PlayerId>,
 }
 
 impl State {
@@ -144,15 +181,69 @@ impl State {
             opponent_special_count,
             player_consumed_cards,
             opponent_consumed_cards,
+            consumed_card_counts: OnceCell::new(),
+            rules: RuleSet::default(),
+            ended_early: false,
+            forfeited_by: None,
         }
     }
 
+    /// The state at the beginning of a game under `rules`.
+    pub fn new_game(board: Board, rules: RuleSet) -> Self {
+        let [south_special, north_special] = rules.starting_special_points;
+        Self::new(board, 0, south_special, north_special, vec![], vec![]).with_rules(rules)
+    }
+
+    /// Plays the game under `rules` instead of the default ones.
+    pub fn with_rules(mut self, rules: RuleSet) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub fn get_rules(&self) -> RuleSet {
+        self.rules
+    }
+
     pub fn get_turn(&self) -> i32 {
         self.turn
     }
 
     pub fn is_end(&self) -> bool {
-        self.turn == game::TURN_COUNT
+        self.turn == game::TURN_COUNT || self.ended_early || self.forfeited_by.is_some()
+    }
+
+    /// Ends the game with `player_id`'s loss regardless of the scores.
+    pub fn forfeit(&mut self, player_id: PlayerId) {
+        self.forfeited_by = Some(player_id);
+    }
+
+    pub fn get_forfeited_player(&self) -> Option<PlayerId> {
+        self.forfeited_by
+    }
+
+    /// Scores of (South, North) including `RuleSet::score_offsets`.
+    pub fn get_scores(&self) -> (u32, u32) {
+        let (south, north) = self.board.get_scores();
+        let [south_offset, north_offset] = self.rules.score_offsets;
+        (
+            south.saturating_add_signed(south_offset),
+            north.saturating_add_signed(north_offset),
+        )
+    }
+
+    /// A Zobrist-style hash of the board cells, the turn and the special points.
+    /// States reached by different orders of actions have the same hash if they match in those.
+    /// Consumed cards aren't hashed.
+    pub fn get_zobrist_hash(&self) -> u64 {
+        // Keys of the turn and the special points are taken from a range which board cells
+        // never use.
+        const TURN_BASE: u64 = 1 << 48;
+        const SPECIAL_BASE: u64 = 1 << 49;
+        const SPECIAL_STRIDE: u64 = 1 << 32;
+        self.board.get_zobrist_hash()
+            ^ board::zobrist_key(TURN_BASE + self.turn as u64)
+            ^ board::zobrist_key(SPECIAL_BASE + self.player_special_count as u64)
+            ^ board::zobrist_key(SPECIAL_BASE + SPECIAL_STRIDE + self.opponent_special_count as u64)
     }
 
     pub fn get_consumed_cards(&self, player_id: PlayerId) -> &[u32] {
@@ -161,21 +252,143 @@ impl State {
             PlayerId::North => &self.opponent_consumed_cards,
         }
     }
+
+    /// Same as `get_consumed_cards(player_id).contains(&card_id)` but takes O(1).
+    pub fn is_consumed(&self, player_id: PlayerId, card_id: u32) -> bool {
+        self.count_consumed(player_id, card_id) > 0
+    }
+
+    /// The number of copies of the card consumed by `player_id`, which can be more than one
+    /// under `RuleSet::max_card_copies`.
+    pub fn count_consumed(&self, player_id: PlayerId, card_id: u32) -> u32 {
+        self.get_consumed_card_counts()[player_id.to_index()]
+            .get(&card_id)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn get_consumed_card_counts(&self) -> &[HashMap<u32, u32>; 2] {
+        self.consumed_card_counts.get_or_init(|| {
+            [
+                count_cards(&self.player_consumed_cards),
+                count_cards(&self.opponent_consumed_cards),
+            ]
+        })
+    }
+
+    fn consume_card(&mut self, player_id: PlayerId, card_id: u32) {
+        match player_id {
+            PlayerId::South => self.player_consumed_cards.push(card_id),
+            PlayerId::North => self.opponent_consumed_cards.push(card_id),
+        }
+        if let Some(counts) = self.consumed_card_counts.get_mut() {
+            *counts[player_id.to_index()].entry(card_id).or_insert(0) += 1;
+        }
+    }
+}
+
+impl Clone for State {
+    fn clone(&self) -> Self {
+        Self {
+            board: self.board.clone(),
+            turn: self.turn,
+            player_special_count: self.player_special_count,
+            opponent_special_count: self.opponent_special_count,
+            player_consumed_cards: self.player_consumed_cards.clone(),
+            opponent_consumed_cards: self.opponent_consumed_cards.clone(),
+            consumed_card_counts: OnceCell::new(),
+            rules: self.rules,
+            ended_early: self.ended_early,
+            forfeited_by: self.forfeited_by,
+        }
+    }
+}
+
+// The counts are skipped as they are derived from the vecs.
+impl PartialEq for State {
+    fn eq(&self, other: &Self) -> bool {
+        self.board == other.board
+            && self.turn == other.turn
+            && self.player_special_count == other.player_special_count
+            && self.opponent_special_count == other.opponent_special_count
+            && self.player_consumed_cards == other.player_consumed_cards
+            && self.opponent_consumed_cards == other.opponent_consumed_cards
+            && self.rules == other.rules
+            && self.ended_early == other.ended_early
+            && self.forfeited_by == other.forfeited_by
+    }
+}
+
+impl Eq for State {}
+
+/// The number of copies of each card in `ids`.
+pub(crate) fn count_cards(ids: &[u32]) -> HashMap<u32, u32> {
+    let mut counts = HashMap::new();
+    for id in ids {
+        *counts.entry(*id).or_insert(0) += 1;
+    }
+    counts
+}
+
+// `HashMap` isn't `Hash`. The counts can be skipped since they have the same cards as the vecs.
+impl Hash for State {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.board.hash(state);
+        self.turn.hash(state);
+        self.player_special_count.hash(state);
+        self.opponent_special_count.hash(state);
+        self.player_consumed_cards.hash(state);
+        self.opponent_consumed_cards.hash(state);
+        self.rules.hash(state);
+        self.ended_early.hash(state);
+        self.forfeited_by.hash(state);
+    }
 }
 
 impl Display for State {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         writeln!(f, "Turn: {}", self.turn + 1)?;
-        write!(f, "{}", self.board)?;
+        if f.alternate() {
+            write!(f, "{:#}", self.board)?;
+        } else {
+            write!(f, "{}", self.board)?;
+        }
         writeln!(
             f,
             "Special: {}, {}",
             self.player_special_count, self.opponent_special_count
         )?;
+        writeln!(
+            f,
+            "Filled: {:.1}% (open cells: {})",
+            self.board.get_fill_ratio() * 100.0,
+            self.board.count_open_cells()
+        )?;
         Ok(())
     }
 }
 
+/// Checks that the card of `action` is in the hands of the player, which `update_player_state`
+/// requires. `update_state` can't check it since `State` doesn't know the hands.
+pub fn check_card_in_hands(
+    player_state: &PlayerCardState,
+    action: &Action,
+) -> Result<(), RuleViolation> {
+    let card_id = action.get_consumed_card().get_id();
+    if player_state
+        .get_hands()
+        .iter()
+        .any(|c| c.get_id() == card_id)
+    {
+        return Ok(());
+    }
+    Err(RuleViolation {
+        player_id: player_state.get_player_id(),
+        action: action.clone(),
+        reason: ViolationReason::CardNotInHand,
+    })
+}
+
 pub fn update_player_state(state: &State, player_state: &mut PlayerCardState, action: &Action) {
     player_state.consume_card(action.get_consumed_card());
     if !state.is_end() {
@@ -183,62 +396,182 @@ pub fn update_player_state(state: &State, player_state: &mut PlayerCardState, ac
     }
 }
 
-pub fn update_state(state: &mut State, player_action: &Action, opponent_action: &Action) {
-    assert_lt!(state.turn, game::TURN_COUNT);
-    if !is_valid_action(state, PlayerId::South, player_action) {
-        todo!(
-            "Invalid action. Player should lose/nstate: {}/naction: {}",
-            state,
-            player_action
-        );
+/// Why an action can't be taken.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViolationReason {
+    /// The card overlaps walls, special ink or, unless it's a special attack, ink.
+    Conflict,
+
+    /// The card isn't next to the player's ink, or special ink for a special attack.
+    NoTouchingPoint,
+
+    InsufficientSpecialPoints,
+
+    /// The card isn't in the player's hands.
+    CardNotInHand,
+}
+
+impl Display for ViolationReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ViolationReason::Conflict => "the card overlaps other cells",
+            ViolationReason::NoTouchingPoint => "the card doesn't touch the player's ink",
+            ViolationReason::InsufficientSpecialPoints => "not enough special points",
+            ViolationReason::CardNotInHand => "the card isn't in the player's hands",
+        };
+        write!(f, "{}", s)
     }
-    if !is_valid_action(state, PlayerId::North, opponent_action) {
-        todo!(
-            "Opponent should lose/nstate: {}/naction: {}",
-            state,
-            opponent_action
-        );
+}
+
+/// An illegal action taken by a player. The player should lose the game.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuleViolation {
+    pub player_id: PlayerId,
+    pub action: Action,
+    pub reason: ViolationReason,
+}
+
+impl Display for RuleViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} took an illegal action ({}): {}",
+            self.player_id, self.reason, self.action
+        )
     }
+}
 
-    // Activated special ink count
-    let activated_cell_cnts = state.board.count_surrounded_special_ink();
+/// Same as `update_state` but also returns cells changed by the actions.
+pub fn update_state_with_delta(
+    state: &mut State,
+    player_action: &Action,
+    opponent_action: &Action,
+) -> Result<BoardDelta, RuleViolation> {
+    let before = state.board.clone();
+    update_state(state, player_action, opponent_action)?;
+    Ok(before.diff(&state.board))
+}
 
-    fill_cells(state, player_action, opponent_action);
+/// Plays a turn with the actions of South (`player_action`) and North (`opponent_action`).
+/// Returns an error without changing `state` if either action is illegal. South's action is
+/// reported if both are.
+pub fn update_state(
+    state: &mut State,
+    player_action: &Action,
+    opponent_action: &Action,
+) -> Result<(), RuleViolation> {
+    assert!(!state.is_end(), "The game has already ended: {}", state);
+    for (player_id, action) in [
+        (PlayerId::South, player_action),
+        (PlayerId::North, opponent_action),
+    ] {
+        check_action(state, player_id, action).map_err(|reason| RuleViolation {
+            player_id,
+            action: action.clone(),
+            reason,
+        })?;
+    }
 
-    let activated_cell_cnts_later = state.board.count_surrounded_special_ink();
+    // Activated special ink count. Only the cells filled by the actions and the cells around them
+    // can get activated, so the rest of the board isn't counted if possible.
+    let area = get_affected_area(&state.board, [player_action, opponent_action]);
+    let count_activated = |board: &Board| match (board.get_bits(), &area) {
+        (Some(bits), Some(area)) => bits.count_surrounded_special_ink_in(area),
+        _ => board.count_surrounded_special_ink(),
+    };
+    let activated_cell_cnts = count_activated(&state.board);
+    fill_cells(state, player_action, opponent_action);
+    let activated_cell_cnts_later = count_activated(&state.board);
 
     assert_le!(activated_cell_cnts.0, activated_cell_cnts_later.0);
     assert_le!(activated_cell_cnts.1, activated_cell_cnts_later.1);
 
     // consume cards
-    state
-        .player_consumed_cards
-        .push(player_action.get_consumed_card().get_id());
-    state
-        .opponent_consumed_cards
-        .push(opponent_action.get_consumed_card().get_id());
+    state.consume_card(PlayerId::South, player_action.get_consumed_card().get_id());
+    state.consume_card(
+        PlayerId::North,
+        opponent_action.get_consumed_card().get_id(),
+    );
 
     // consume special points
-    maybe_consume_special_points(&mut state.player_special_count, player_action);
-    state.player_special_count += activated_cell_cnts_later.0 - activated_cell_cnts.0;
-    if player_action.is_pass() {
-        state.player_special_count += 1;
+    update_special_points(
+        &mut state.player_special_count,
+        player_action,
+        activated_cell_cnts_later.0 - activated_cell_cnts.0,
+    );
+    update_special_points(
+        &mut state.opponent_special_count,
+        opponent_action,
+        activated_cell_cnts_later.1 - activated_cell_cnts.1,
+    );
+    state.turn += 1;
+    if state.rules.end_on_double_pass && player_action.is_pass() && opponent_action.is_pass() {
+        state.ended_early = true;
     }
+    Ok(())
+}
 
-    maybe_consume_special_points(&mut state.opponent_special_count, opponent_action);
-    state.opponent_special_count += activated_cell_cnts_later.1 - activated_cell_cnts.1;
-    if opponent_action.is_pass() {
-        state.opponent_special_count += 1;
+/// Cells covered by `actions` and the cells around them as bit masks of each row, in the same
+/// layout as `BitBoard`. Returns None if the board is too wide for the masks or the cells around
+/// a card stick out of the board, where the whole board should be counted instead.
+fn get_affected_area<'a>(
+    board: &Board,
+    actions: impl IntoIterator<Item = &'a Action>,
+) -> Option<Vec<u64>> {
+    let bits = board.get_bits()?;
+    let (_, height) = board.get_size();
+    let mut area = vec![0; height as usize];
+    for action in actions {
+        if action.is_pass() {
+            continue;
+        }
+        let (card, position) = action.get_card_and_position();
+        if !bits.fits(card.get_footprint(position.rotation), position.x, position.y) {
+            return None;
+        }
+        // The first row/column of the surroundings is one cell above/left of the card.
+        let surroundings = card.get_surroundings(position.rotation);
+        for (dy, around_row) in surroundings.iter().enumerate() {
+            area[(position.y - 1) as usize + dy] |= around_row << (position.x - 1);
+        }
     }
-    state.turn += 1
+    Some(area)
 }
 
-fn maybe_consume_special_points(special_points: &mut i32, action: &Action) {
+/// Consumes/earns special points for the action.
+/// `is_valid_action` should have rejected unaffordable special attacks already, so the count
+/// going negative means we have a bug in the rules.
+fn update_special_points(special_points: &mut i32, action: &Action, activated_cnt: i32) {
     if let Action::Special(card, _) = action {
-        *special_points -= card.get_special_cost();
+        *special_points = special_points
+            .checked_sub(card.get_special_cost())
+            .unwrap_or_else(|| {
+                panic!(
+                    "Special points overflowed: points: {}, action: {}",
+                    special_points, action
+                )
+            });
+    }
+    *special_points = special_points.saturating_add(activated_cnt);
+    if action.is_pass() {
+        *special_points = special_points.saturating_add(1);
     }
+    debug_assert!(
+        *special_points >= 0,
+        "Special points became negative: points: {}, action: {}",
+        special_points,
+        action
+    );
 }
 
+/// Fills cells covered by both actions.
+///
+/// When both players fill the same cell at the same turn, including the first turn, the cell with
+/// lower `CardCell::priority` wins:
+///   - Special ink always beats normal ink, regardless of the sizes of the cards.
+///   - Between the same type of ink, the card with fewer cells wins.
+///   - Otherwise (same type of ink from cards of the same size) the cell becomes a wall. This
+///     also applies to special ink vs special ink, so neither player gets the special cell.
 fn fill_cells(state: &mut State, player_action: &Action, opponent_action: &Action) {
     let mut priorities: HashMap<BoardPosition, i32> = HashMap::new();
 
@@ -275,44 +608,138 @@ fn fill_cells(state: &mut State, player_action: &Action, opponent_action: &Actio
     }
 }
 
+/// Appends all valid actions (including passes) which `player_id` can take with `cards`.
+///
+/// A pass is always legal, so an `Action::Pass` is appended for every card even if no
+/// card can be put on the board. Callers can rely on getting at least one action as long
+/// as `cards` isn't empty.
+pub fn append_valid_actions(
+    state: &State,
+    cards: &[Card],
+    player_id: PlayerId,
+    actions: &mut Vec<Action>,
+) {
+    let (width, height) = state.board.get_size();
+    let blocked_rows = blocked_row_masks(&state.board);
+    for card in cards {
+        actions.push(Action::Pass(card.clone()));
+        for rotation in Rotation::VALUES {
+            // Keep the card's bounding box inside of the walls. The last row/column inside of
+            // the walls is `height - 2`/`width - 2`.
+            let card_width = card.calculate_width(rotation);
+            let card_height = card.calculate_height(rotation);
+            for y in 1..=height - 1 - card_height {
+                for x in 1..=width - 1 - card_width {
+                    if let Some(blocked_rows) = &blocked_rows {
+                        // Neither a normal put nor a special attack can overlap the cells.
+                        let footprint = card.get_footprint(rotation);
+                        if overlaps(blocked_rows, footprint, x, y) {
+                            continue;
+                        }
+                    }
+                    let pos = CardPosition {
+                        x,
+                        y,
+                        rotation,
+                    };
+
+                    // Normal
+                    let action = Action::Put(card.clone(), pos);
+                    if is_valid_action(state, player_id, &action) {
+                        actions.push(action);
+                    }
+
+                    // Special
+                    let action = Action::Special(card.clone(), pos);
+                    if is_valid_action(state, player_id, &action) {
+                        actions.push(action);
+                    }
+                }
+            }
+        }
+    }
+    debug!("Found {} valid actions", actions.len());
+    trace!("Found actions:\n{:?}", actions);
+}
+
+/// Walls and special ink on `board` as bit masks of each row, in the same layout as
+/// `CardImpl::get_footprint`. Returns None if the board is too wide for the masks.
+pub(crate) fn blocked_row_masks(board: &Board) -> Option<Vec<u64>> {
+    board.get_bits().map(|bits| bits.get_blocked_rows())
+}
+
+/// Whether `footprint` put at `(x, y)` overlaps cells set in `rows`.
+pub(crate) fn overlaps(rows: &[u64], footprint: &[u64], x: i32, y: i32) -> bool {
+    footprint
+        .iter()
+        .zip(&rows[y as usize..])
+        .any(|(card_row, board_row)| card_row << x & board_row != 0)
+}
+
 pub fn is_valid_action(state: &State, player_id: PlayerId, action: &Action) -> bool {
+    check_action(state, player_id, action).is_ok()
+}
+
+/// Same as `is_valid_action` but tells why the action can't be taken.
+pub fn check_action(
+    state: &State,
+    player_id: PlayerId,
+    action: &Action,
+) -> Result<(), ViolationReason> {
     match action {
-        Action::Pass(_) => true,
-        Action::Put(card, pos) => is_valid_action_put(state, player_id, card, pos, false),
-        Action::Special(card, pos) => is_valid_action_put(state, player_id, card, pos, true),
+        Action::Pass(_) => Ok(()),
+        Action::Put(card, pos) => check_action_put(state, player_id, card, pos, false),
+        Action::Special(card, pos) => check_action_put(state, player_id, card, pos, true),
     }
 }
 
-fn is_valid_action_put(
+fn check_action_put(
     state: &State,
     player_id: PlayerId,
     card: &Card,
     position: &CardPosition,
     special: bool,
-) -> bool {
+) -> Result<(), ViolationReason> {
     if special {
-        match player_id {
-            PlayerId::South => {
-                if state.player_special_count < card.get_special_cost() {
-                    return false;
-                }
-            }
-            PlayerId::North => {
-                if state.opponent_special_count < card.get_special_cost() {
-                    return false;
-                }
-            }
+        let special_count = match player_id {
+            PlayerId::South => state.player_special_count,
+            PlayerId::North => state.opponent_special_count,
+        };
+        if special_count < card.get_special_cost() {
+            return Err(ViolationReason::InsufficientSpecialPoints);
         }
     }
 
-    if has_conflict(&state.board, card, position, special) {
-        return false;
+    // Cards sticking out of the board are checked cell by cell, as well as boards too wide for
+    // the bit masks.
+    let footprint = card.get_footprint(position.rotation);
+    let bits = state
+        .board
+        .get_bits()
+        .filter(|bits| bits.fits(footprint, position.x, position.y));
+
+    let conflict = match bits {
+        Some(bits) => bits.has_conflict(footprint, position.x, position.y, special),
+        None => has_conflict(&state.board, card, position, special),
+    };
+    if conflict {
+        return Err(ViolationReason::Conflict);
     }
 
-    if !has_touching_point(&state.board, player_id, card, position, special) {
-        return false;
+    let touching = match bits {
+        Some(bits) => bits.has_touching_point(
+            player_id,
+            card.get_surroundings(position.rotation),
+            position.x,
+            position.y,
+            special,
+        ),
+        None => has_touching_point(&state.board, player_id, card, position, special),
+    };
+    if !touching {
+        return Err(ViolationReason::NoTouchingPoint);
     }
-    true
+    Ok(())
 }
 
 fn has_conflict(board: &Board, card: &Card, card_position: &CardPosition, special: bool) -> bool {
@@ -339,18 +766,8 @@ fn has_touching_point(
     card_position: &CardPosition,
     special: bool,
 ) -> bool {
-    #[rustfmt::skip]
-    const AROUND_DIFF: [(i32, i32); 8] = [
-        (-1, -1),  (0, -1),  (1, -1),
-        (-1,  0),/*(0,  0),*/(1,  0),
-        (-1,  1),  (0,  1),  (1,  1),
-    ];
     for (board_pos, _cell) in card.get_cells_on_board_coord(card_position) {
-        for diff in AROUND_DIFF {
-            let board_pos = BoardPosition {
-                x: board_pos.x + diff.0,
-                y: board_pos.y + diff.1,
-            };
+        for board_pos in board_pos.around() {
             let board_cell = board.get_cell(board_pos);
             let touching = match (board_cell, special) {
                 (BoardCell::Ink(pid), false) => player_id == pid,
@@ -369,6 +786,8 @@ fn has_touching_point(
 // tests can use them without making the `tests` mod public.
 #[cfg(test)]
 pub mod tests {
+    use std::collections::HashSet;
+
     use crate::*;
 
     fn init() {
@@ -427,6 +846,29 @@ pub mod tests {
         )
     }
 
+    /// Same as `update_state` but also checks that the special points earned by the turn match
+    /// the special ink activated on the whole board, which `update_state` only counts around
+    /// the filled cells.
+    pub fn update_state_checked(
+        state: &mut State,
+        player_action: &Action,
+        opponent_action: &Action,
+    ) -> Result<(), RuleViolation> {
+        let before = state.board.count_surrounded_special_ink();
+        let mut expected = (state.player_special_count, state.opponent_special_count);
+        update_state(state, player_action, opponent_action)?;
+        let after = state.board.count_surrounded_special_ink();
+        super::update_special_points(&mut expected.0, player_action, after.0 - before.0);
+        super::update_special_points(&mut expected.1, opponent_action, after.1 - before.1);
+        assert_eq!(
+            expected,
+            (state.player_special_count, state.opponent_special_count),
+            "The incremental count differs from the full scan:\n{}",
+            state.board
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_conflict() {
         init();
@@ -846,7 +1288,7 @@ pub mod tests {
             "="
         ]);
 
-        update_state(
+        update_state_checked(
             &mut state,
             &Action::Put(
                 card.clone(),
@@ -864,7 +1306,8 @@ pub mod tests {
                     rotation: Rotation::Up,
                 },
             ),
-        );
+        )
+        .unwrap();
 
         #[rustfmt::skip]
         let expected = new_test_state(
@@ -885,6 +1328,144 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_get_affected_area() {
+        init();
+
+        #[rustfmt::skip]
+        let state = new_test_state(
+            &[
+            "#########",
+            "#..O....#",
+            "#.......#",
+            "#..P....#",
+            "#.....pp#",
+            "#.....pP#",
+            "#########"],
+            0,
+            0,
+            0, vec![], vec![]
+        );
+        #[rustfmt::skip]
+        let card = new_test_card(&[
+            "=",
+            "*",
+            "="
+        ]);
+        let put = Action::Put(
+            card.clone(),
+            CardPosition {
+                x: 2,
+                y: 1,
+                rotation: Rotation::Up,
+            },
+        );
+        let pass = Action::Pass(card);
+
+        // The card covers x = 2, y = 1..=3 and the area spreads one more cell around it.
+        let area = super::get_affected_area(&state.board, [&put, &pass]).unwrap();
+        assert_eq!(vec![0b1110, 0b1110, 0b1110, 0b1110, 0b1110, 0, 0], area);
+        assert_eq!(
+            Some(vec![0; 7]),
+            super::get_affected_area(&state.board, [&pass, &pass])
+        );
+
+        // The surrounded special ink at the bottom-right corner is out of the area.
+        let bits = state.board.get_bits().unwrap();
+        assert_eq!((1, 0), bits.count_surrounded_special_ink());
+        assert_eq!((0, 0), bits.count_surrounded_special_ink_in(&area));
+    }
+
+    #[test]
+    fn test_update_state_without_border_walls() {
+        init();
+
+        // The cells around a card on the edge stick out of the board.
+        #[rustfmt::skip]
+        let mut state = new_test_state(
+            &[
+            "P...",
+            "....",
+            "...O"],
+            0,
+            0,
+            0, vec![], vec![]
+        );
+        let card = new_test_card(&["="]);
+        let put = Action::Put(
+            card.clone(),
+            CardPosition {
+                x: 1,
+                y: 0,
+                rotation: Rotation::Up,
+            },
+        );
+        assert!(is_valid_action(&state, PlayerId::South, &put));
+        assert_eq!(
+            None,
+            super::get_affected_area(&state.board, [&put, &Action::Pass(card.clone())])
+        );
+        update_state_checked(&mut state, &put, &Action::Pass(card)).unwrap();
+        assert_eq!((2, 1), state.get_scores());
+    }
+
+    #[test]
+    fn test_update_state_rule_violation() {
+        init();
+
+        #[rustfmt::skip]
+        let mut state = new_test_state(
+            &[
+            "#######",
+            "#..O..#",
+            "#.....#",
+            "#.....#",
+            "#..P..#",
+            "#######"],
+            0,
+            0,
+            0, vec![], vec![]
+        );
+        let before = state.clone();
+        let card = new_test_card(&["="]);
+        let legal = Action::Put(card.clone(), CardPosition::new(3, 3, Rotation::Up));
+        // Away from North's ink.
+        let illegal = Action::Put(card.clone(), CardPosition::new(1, 3, Rotation::Up));
+
+        let violation = update_state(&mut state, &legal, &illegal).unwrap_err();
+        assert_eq!(PlayerId::North, violation.player_id);
+        assert_eq!(illegal, violation.action);
+        assert_eq!(ViolationReason::NoTouchingPoint, violation.reason);
+        // The state is left unchanged.
+        assert_eq!(before, state);
+
+        // South is checked first when both are illegal.
+        let special = Action::Special(card, CardPosition::new(3, 3, Rotation::Up));
+        let violation = update_state(&mut state, &special, &illegal).unwrap_err();
+        assert_eq!(PlayerId::South, violation.player_id);
+        assert_eq!(ViolationReason::InsufficientSpecialPoints, violation.reason);
+        assert_eq!(before, state);
+    }
+
+    #[test]
+    fn test_check_card_in_hands() {
+        let in_hand = new_test_card_impl(&["="], 1, 42);
+        let in_deck = new_test_card_impl(&["="], 2, 42);
+        let player_state = PlayerCardState::new(
+            PlayerId::North,
+            vec![in_hand.clone()],
+            vec![in_deck.clone()],
+        );
+
+        assert_eq!(
+            Ok(()),
+            check_card_in_hands(&player_state, &Action::Pass(in_hand))
+        );
+        let violation = check_card_in_hands(&player_state, &Action::Pass(in_deck)).unwrap_err();
+        assert_eq!(PlayerId::North, violation.player_id);
+        assert_eq!(ViolationReason::CardNotInHand, violation.reason);
+    }
+
     #[test]
     fn test_update_state_conflict() {
         init();
@@ -906,7 +1487,7 @@ pub mod tests {
             "=*=",
         ]);
 
-        update_state(
+        update_state_checked(
             &mut state,
             &Action::Put(
                 card.clone(),
@@ -924,7 +1505,8 @@ pub mod tests {
                     rotation: Rotation::Up,
                 },
             ),
-        );
+        )
+        .unwrap();
 
         #[rustfmt::skip]
         let expected = new_test_state(
@@ -971,7 +1553,7 @@ pub mod tests {
             "  =",
         ]);
 
-        update_state(
+        update_state_checked(
             &mut state,
             &Action::Put(
                 card,
@@ -989,7 +1571,8 @@ pub mod tests {
                     rotation: Rotation::Up,
                 },
             ),
-        );
+        )
+        .unwrap();
 
         // smaller card should be prioritized
         #[rustfmt::skip]
@@ -1037,7 +1620,7 @@ pub mod tests {
             "  =",
         ]);
 
-        update_state(
+        update_state_checked(
             &mut state,
             &Action::Put(
                 card,
@@ -1055,7 +1638,8 @@ pub mod tests {
                     rotation: Rotation::Up,
                 },
             ),
-        );
+        )
+        .unwrap();
 
         // smaller card should be prioritized
         #[rustfmt::skip]
@@ -1098,7 +1682,7 @@ pub mod tests {
             "=*=",
         ], 1);
 
-        update_state(
+        update_state_checked(
             &mut state,
             &Action::Put(
                 card.clone(),
@@ -1116,7 +1700,8 @@ pub mod tests {
                     rotation: Rotation::Up,
                 },
             ),
-        );
+        )
+        .unwrap();
 
         // Opponent used special attack.
         // The conflicted cell should become a wall.
@@ -1139,6 +1724,220 @@ pub mod tests {
         );
     }
 
+    /// Puts `south_card` at (`south_x`, 2) and `north_card` at (`north_x`, 2) on the first turn
+    /// and checks the resulting row between both players' special cells.
+    fn assert_first_turn_overlap(
+        south_card: Card,
+        south_x: i32,
+        north_card: Card,
+        north_x: i32,
+        expected_row: &str,
+    ) {
+        #[rustfmt::skip]
+        let mut state = new_test_state(
+            &[
+            "#######",
+            "#..O..#",
+            "#.....#",
+            "#..P..#",
+            "#######"],
+            0,
+            0,
+            0, vec![], vec![]
+        );
+        let put = |card, x| {
+            Action::Put(
+                card,
+                CardPosition {
+                    x,
+                    y: 2,
+                    rotation: Rotation::Up,
+                },
+            )
+        };
+        update_state_checked(
+            &mut state,
+            &put(south_card, south_x),
+            &put(north_card, north_x),
+        )
+        .unwrap();
+
+        let expected = new_test_state(
+            &["#######", "#..O..#", expected_row, "#..P..#", "#######"],
+            1,
+            0,
+            0,
+            vec![42],
+            vec![42],
+        );
+        assert_eq!(
+            state, expected,
+            "\nActual:\n{}\nExpected:\n{}",
+            state, expected
+        );
+    }
+
+    #[test]
+    fn test_first_turn_overlap_ink_vs_ink() {
+        init();
+
+        // Same size: a wall.
+        assert_first_turn_overlap(
+            new_test_card(&["==="]),
+            1,
+            new_test_card(&["==="]),
+            3,
+            "#pp#oo#",
+        );
+        // The smaller card wins.
+        assert_first_turn_overlap(
+            new_test_card(&["=="]),
+            2,
+            new_test_card(&["==="]),
+            3,
+            "#.ppoo#",
+        );
+    }
+
+    #[test]
+    fn test_first_turn_overlap_special_vs_special() {
+        init();
+
+        // Same size: a wall, so neither player gets the special cell.
+        assert_first_turn_overlap(
+            new_test_card(&["=*"]),
+            2,
+            new_test_card(&["*="]),
+            3,
+            "#.p#o.#",
+        );
+        // The smaller card wins.
+        assert_first_turn_overlap(
+            new_test_card(&["=*"]),
+            2,
+            new_test_card(&["*=="]),
+            3,
+            "#.pPoo#",
+        );
+    }
+
+    #[test]
+    fn test_first_turn_overlap_ink_vs_special() {
+        init();
+
+        // Special ink wins even if its card is larger.
+        assert_first_turn_overlap(
+            new_test_card(&["="]),
+            3,
+            new_test_card(&["*=="]),
+            3,
+            "#..Ooo#",
+        );
+        assert_first_turn_overlap(
+            new_test_card(&["==*"]),
+            1,
+            new_test_card(&["="]),
+            3,
+            "#ppP..#",
+        );
+    }
+
+    #[test]
+    fn test_update_state_double_pass() {
+        init();
+
+        let card = new_test_card(&["="]);
+        let pass = Action::Pass(card.clone());
+        let put = Action::Put(
+            card,
+            CardPosition {
+                x: 3,
+                y: 2,
+                rotation: Rotation::Up,
+            },
+        );
+        #[rustfmt::skip]
+        let new_state = || new_test_state(
+            &[
+            "#######",
+            "#..O..#",
+            "#.....#",
+            "#..P..#",
+            "#######"],
+            0,
+            0,
+            0, vec![], vec![]
+        );
+        let rules = RuleSet {
+            end_on_double_pass: true,
+            ..Default::default()
+        };
+
+        // The game continues by default.
+        let mut state = new_state();
+        update_state(&mut state, &pass, &pass).unwrap();
+        assert!(!state.is_end());
+
+        let mut state = new_state().with_rules(rules);
+        update_state(&mut state, &pass, &pass).unwrap();
+        assert!(state.is_end());
+        assert_eq!(1, state.get_turn());
+        // Passes still earn special points.
+        assert_eq!(
+            (1, 1),
+            (state.player_special_count, state.opponent_special_count)
+        );
+
+        // Only one player passes.
+        let mut state = new_state().with_rules(rules);
+        update_state(&mut state, &put, &pass).unwrap();
+        assert!(!state.is_end());
+        assert_eq!((2, 1), state.board.get_scores());
+    }
+
+    #[test]
+    fn test_handicap() {
+        init();
+
+        #[rustfmt::skip]
+        let board = new_test_board(&[
+            "#######",
+            "#..O..#",
+            "#.....#",
+            "#.....#",
+            "#..P..#",
+            "#######",
+        ]);
+        let card = new_test_card_impl(&["="], 1, 10);
+        let pass = Action::Pass(card);
+        let rules = RuleSet {
+            starting_special_points: [0, 3],
+            score_offsets: [-2, 5],
+            ..Default::default()
+        };
+
+        let mut state = State::new_game(board, rules);
+        assert_eq!(0, state.get_turn());
+        assert_eq!(
+            (0, 3),
+            (state.player_special_count, state.opponent_special_count)
+        );
+        // South's score can't go below 0.
+        assert_eq!((0, 6), state.get_scores());
+
+        while !state.is_end() {
+            update_state(&mut state, &pass, &pass).unwrap();
+        }
+        assert_eq!(
+            (12, 15),
+            (state.player_special_count, state.opponent_special_count)
+        );
+        assert_eq!((1, 1), state.board.get_scores());
+        assert_eq!((0, 6), state.get_scores());
+        let outcome = GameOutcome::from_state("s", "n", &state);
+        assert_eq!((0, 6), (outcome.south.score, outcome.north.score));
+    }
+
     #[test]
     fn test_update_state_pass() {
         init();
@@ -1160,7 +1959,7 @@ pub mod tests {
             "=*=",
         ]);
 
-        update_state(
+        update_state_checked(
             &mut state,
             &Action::Put(
                 card.clone(),
@@ -1171,7 +1970,8 @@ pub mod tests {
                 },
             ),
             &Action::Pass(card),
-        );
+        )
+        .unwrap();
 
         // Opponent used special attack.
         // The conflicted cell should become a wall.
@@ -1218,7 +2018,7 @@ pub mod tests {
             "= =",
         ]);
 
-        update_state(
+        update_state_checked(
             &mut state,
             &Action::Put(
                 card.clone(),
@@ -1229,7 +2029,8 @@ pub mod tests {
                 },
             ),
             &Action::Pass(card),
-        );
+        )
+        .unwrap();
 
         // Opponent used special attack.
         // The conflicted cell should become a wall.
@@ -1281,7 +2082,7 @@ pub mod tests {
             "==",
         ]);
 
-        update_state(
+        update_state_checked(
             &mut state,
             &Action::Put(
                 card,
@@ -1299,7 +2100,8 @@ pub mod tests {
                     rotation: Rotation::Up,
                 },
             ),
-        );
+        )
+        .unwrap();
 
         // Opponent used special attack.
         // The conflicted cell should become a wall.
@@ -1321,4 +2123,376 @@ pub mod tests {
             state, expected
         );
     }
+
+    #[test]
+    fn test_update_state_special_exact_cost() {
+        init();
+
+        #[rustfmt::skip]
+        let mut state = new_test_state(
+            &[
+            "#####",
+            "#...#",
+            "#...#",
+            "#.P.#",
+            "#####"],
+            0,
+            2, // Exactly same as the special cost.
+            0, vec![], vec![]
+        );
+        #[rustfmt::skip]
+        let card = new_test_card_with_special_cost(&[
+            "===",
+        ], 2);
+
+        update_state_checked(
+            &mut state,
+            &Action::Special(
+                card.clone(),
+                CardPosition {
+                    x: 1,
+                    y: 1,
+                    rotation: Rotation::Right,
+                },
+            ),
+            &Action::Pass(card),
+        )
+        .unwrap();
+
+        assert_eq!(0, state.player_special_count);
+        assert_eq!(1, state.opponent_special_count);
+    }
+
+    #[test]
+    fn test_update_state_with_delta() {
+        init();
+
+        #[rustfmt::skip]
+        let mut state = new_test_state(
+            &[
+            "#####",
+            "#..O#",
+            "#...#",
+            "#.P.#",
+            "#####"],
+            0,
+            0, 0, vec![], vec![]
+        );
+        #[rustfmt::skip]
+        let card = new_test_card(&[
+            "===",
+        ]);
+        let before = state.board.clone();
+
+        let delta = update_state_with_delta(
+            &mut state,
+            &Action::Put(
+                card.clone(),
+                CardPosition {
+                    x: 1,
+                    y: 1,
+                    rotation: Rotation::Right,
+                },
+            ),
+            &Action::Pass(card),
+        )
+        .unwrap();
+
+        assert_eq!(3, delta.changes.len());
+        let mut applied = before;
+        applied.apply_delta(&delta);
+        assert_eq!(state.board, applied);
+    }
+
+    #[test]
+    fn test_append_valid_actions_edges_and_corners() {
+        init();
+
+        #[rustfmt::skip]
+        let state = new_test_state(
+            &[
+            "######",
+            "#P...#",
+            "#....#",
+            "#....#",
+            "#...P#",
+            "######"],
+            0,
+            0, 0, vec![], vec![]
+        );
+        #[rustfmt::skip]
+        let card = new_test_card(&[
+            "==",
+        ]);
+        let mut actions = vec![];
+        append_valid_actions(&state, &[card], PlayerId::South, &mut actions);
+
+        let (width, height) = state.board.get_size();
+        let mut positions = vec![];
+        for action in actions.iter().filter(|a| !a.is_pass()) {
+            let (card, position) = action.get_card_and_position();
+            for (board_pos, _cell) in card.get_cells_on_board_coord(position) {
+                // Never on the walls.
+                assert!(board_pos.in_bounds(width, height));
+                assert!(board_pos.offset(-1, -1).unwrap().in_bounds(width, height));
+                assert!(board_pos.offset(1, 1).unwrap().in_bounds(width, height));
+            }
+            positions.push((position.x, position.y, position.rotation));
+        }
+        // Next to the special ink at the top-left and the bottom-right corners.
+        assert!(positions.contains(&(2, 1, Rotation::Up)));
+        assert!(positions.contains(&(1, 2, Rotation::Right)));
+        assert!(positions.contains(&(2, 4, Rotation::Up)));
+        assert!(positions.contains(&(4, 2, Rotation::Right)));
+        assert!(!positions.contains(&(4, 4, Rotation::Up)));
+    }
+
+    #[test]
+    fn test_append_valid_actions_walled_in() {
+        init();
+
+        #[rustfmt::skip]
+        let state = new_test_state(
+            &[
+            "#####",
+            "#P#O#",
+            "#####"],
+            0,
+            // Enough special points to pay for any special attack.
+            42, 42, vec![], vec![]
+        );
+        let cards = vec![
+            new_test_card_impl(&["="], 1, 1),
+            new_test_card_impl(&["=="], 2, 1),
+        ];
+        for player_id in [PlayerId::South, PlayerId::North] {
+            let mut actions = vec![];
+            append_valid_actions(&state, &cards, player_id, &mut actions);
+            assert_eq!(
+                vec![Action::Pass(cards[0].clone()), Action::Pass(cards[1].clone())],
+                actions
+            );
+        }
+    }
+
+    #[test]
+    fn test_append_valid_actions_same_as_brute_force() {
+        init();
+
+        #[rustfmt::skip]
+        let state = new_test_state(
+            &[
+            "#########",
+            "#P..o..##",
+            "#.#.p...#",
+            "#..O.o..#",
+            "#.p..#..#",
+            "##..P.O.#",
+            "#########"],
+            0,
+            10, 10, vec![], vec![]
+        );
+        let cards = [
+            new_test_card_impl(&["=="], 1, 1),
+            new_test_card_impl(&["=*", " ="], 2, 2),
+            new_test_card_impl(&["===", "  ="], 3, 3),
+            new_test_card_impl(&[" = ", "=*=", " = "], 4, 4),
+        ];
+
+        let (width, height) = state.board.get_size();
+        for player_id in [PlayerId::South, PlayerId::North] {
+            let mut actions = vec![];
+            append_valid_actions(&state, &cards, player_id, &mut actions);
+
+            let mut expected = vec![];
+            for card in cards.iter() {
+                expected.push(Action::Pass(card.clone()));
+                for rotation in Rotation::VALUES {
+                    for y in -1..=height {
+                        for x in -1..=width {
+                            let pos = CardPosition::new(x, y, rotation);
+                            if pos.try_on_board(&state.board, card).is_err() {
+                                continue;
+                            }
+                            for action in [
+                                Action::Put(card.clone(), pos),
+                                Action::Special(card.clone(), pos),
+                            ] {
+                                if is_valid_action(&state, player_id, &action) {
+                                    expected.push(action);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            assert!(actions.iter().any(|a| matches!(a, Action::Special(..))));
+            assert_eq!(expected.len(), actions.len());
+            assert_eq!(
+                expected.into_iter().collect::<HashSet<Action>>(),
+                actions.into_iter().collect::<HashSet<Action>>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_consumed_card_set_in_sync() {
+        init();
+
+        #[rustfmt::skip]
+        let mut state = new_test_state(
+            &[
+            "#######",
+            "#..O..#",
+            "#.....#",
+            "#.....#",
+            "#.....#",
+            "#..P..#",
+            "#######"],
+            0,
+            0, 0, vec![], vec![]
+        );
+        let cards: Vec<Card> = (1..=game::TURN_COUNT as u32)
+            .map(|id| new_test_card_impl(&["="], id, 10))
+            .collect();
+
+        while !state.is_end() {
+            let card = &cards[state.get_turn() as usize];
+            let mut actions = vec![];
+            for player_id in [PlayerId::South, PlayerId::North] {
+                let mut valid_actions = vec![];
+                append_valid_actions(
+                    &state,
+                    std::slice::from_ref(card),
+                    player_id,
+                    &mut valid_actions,
+                );
+                // Puts the card if possible.
+                actions.push(valid_actions.pop().unwrap());
+            }
+            update_state(&mut state, &actions[0], &actions[1]).unwrap();
+
+            for player_id in [PlayerId::South, PlayerId::North] {
+                let consumed = state.get_consumed_cards(player_id);
+                assert_eq!(state.get_turn() as usize, consumed.len());
+                for card in cards.iter() {
+                    assert_eq!(
+                        consumed.contains(&card.get_id()),
+                        state.is_consumed(player_id, card.get_id())
+                    );
+                }
+            }
+        }
+        let expected: Vec<u32> = cards.iter().map(|card| card.get_id()).collect();
+        assert_eq!(expected, state.get_consumed_cards(PlayerId::South));
+
+        // A state made from consumed cards has the same counts.
+        let restored = State::new(
+            state.board.clone(),
+            state.turn,
+            state.player_special_count,
+            state.opponent_special_count,
+            state.get_consumed_cards(PlayerId::South).to_vec(),
+            state.get_consumed_cards(PlayerId::North).to_vec(),
+        );
+        assert_eq!(
+            state.get_consumed_card_counts(),
+            restored.get_consumed_card_counts()
+        );
+    }
+
+    #[test]
+    fn test_zobrist_hash_transposition() {
+        #[rustfmt::skip]
+        let new_state = || new_test_state(&[
+            "#######",
+            "#..O..#",
+            "#.....#",
+            "#.....#",
+            "#..P..#",
+            "#######"],
+            0,
+            0, 0, vec![], vec![]
+        );
+        let a = new_test_card_impl(&["="], 1, 10);
+        let b = new_test_card_impl(&["="], 2, 10);
+        let put = |card: &Card, x: i32, y: i32| {
+            Action::Put(
+                card.clone(),
+                CardPosition {
+                    x,
+                    y,
+                    rotation: Rotation::Up,
+                },
+            )
+        };
+        let play = |turns: &[(Action, Action)]| {
+            let mut state = new_state();
+            for (south, north) in turns {
+                update_state(&mut state, south, north).unwrap();
+            }
+            state
+        };
+
+        // South inks the same cells in different orders with different cards.
+        let x = play(&[
+            (put(&a, 2, 3), put(&a, 3, 2)),
+            (put(&b, 4, 3), put(&b, 4, 2)),
+        ]);
+        let y = play(&[
+            (put(&b, 4, 3), put(&a, 3, 2)),
+            (put(&a, 2, 3), put(&b, 4, 2)),
+        ]);
+        assert_ne!(x, y);
+        assert_eq!(x.board, y.board);
+        assert_eq!(x.get_zobrist_hash(), y.get_zobrist_hash());
+
+        // The turn and special points are hashed too.
+        let z = play(&[(put(&a, 2, 3), put(&a, 3, 2))]);
+        assert_ne!(x.get_zobrist_hash(), z.get_zobrist_hash());
+        let mut w = x.clone();
+        w.player_special_count += 1;
+        assert_ne!(x.get_zobrist_hash(), w.get_zobrist_hash());
+    }
+
+    #[test]
+    fn test_count_consumed_duplicates() {
+        let board = load_board_from_lines(String::from("b"), &["###", "#.#", "###"]);
+        let state = State::new(board, 3, 0, 0, vec![7, 3, 7], vec![3]);
+
+        assert_eq!(2, state.count_consumed(PlayerId::South, 7));
+        assert_eq!(1, state.count_consumed(PlayerId::South, 3));
+        assert_eq!(0, state.count_consumed(PlayerId::North, 7));
+        assert!(state.is_consumed(PlayerId::North, 3));
+        assert!(!state.is_consumed(PlayerId::North, 7));
+    }
+
+    #[test]
+    fn test_display_fill() {
+        #[rustfmt::skip]
+        let state = new_test_state(
+            &[
+            "#####",
+            "#pPo#",
+            "#o#O#",
+            "#####"],
+            0,
+            0, 0, vec![], vec![]
+        );
+        assert_eq!(0, state.board.count_open_cells());
+        assert_eq!(1.0, state.board.get_fill_ratio());
+        assert!(state.to_string().contains("Filled: 100.0% (open cells: 0)"));
+
+        #[rustfmt::skip]
+        let state = new_test_state(
+            &[
+            "#####",
+            "#p..#",
+            "#..O#",
+            "#####"],
+            0,
+            0, 0, vec![], vec![]
+        );
+        assert!(state.to_string().contains("Filled: 33.3% (open cells: 4)"));
+    }
 } // mod tests
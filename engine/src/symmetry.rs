@@ -0,0 +1,264 @@
+use std::collections::HashSet;
+
+use super::{
+    board::{
+        Board,
+        BoardPosition,
+    },
+    card::{
+        CardCellType,
+        CardPosition,
+    },
+    game::{
+        Action,
+        Rotation,
+    },
+};
+
+/// One of the 8 symmetries of a rectangle (the dihedral group of a square) in board
+/// coordinates. Rotations are clockwise as `Rotation` is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Transform {
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Mirrors left and right.
+    FlipHorizontal,
+    /// Mirrors top and bottom.
+    FlipVertical,
+    /// Mirrors along the diagonal from the top-left corner.
+    Transpose,
+    /// Mirrors along the diagonal from the top-right corner.
+    AntiTranspose,
+}
+
+impl Transform {
+    pub const VALUES: [Self; 8] = [
+        Self::Identity,
+        Self::Rotate90,
+        Self::Rotate180,
+        Self::Rotate270,
+        Self::FlipHorizontal,
+        Self::FlipVertical,
+        Self::Transpose,
+        Self::AntiTranspose,
+    ];
+
+    /// Whether the transform swaps the width and the height.
+    pub fn swaps_axes(self) -> bool {
+        matches!(
+            self,
+            Self::Rotate90 | Self::Rotate270 | Self::Transpose | Self::AntiTranspose
+        )
+    }
+
+    /// Moves `position` on a board of `width` x `height`.
+    pub fn apply(self, position: BoardPosition, width: i32, height: i32) -> BoardPosition {
+        let BoardPosition {
+            x,
+            y,
+        } = position;
+        let (x, y) = match self {
+            Self::Identity => (x, y),
+            Self::Rotate90 => (height - 1 - y, x),
+            Self::Rotate180 => (width - 1 - x, height - 1 - y),
+            Self::Rotate270 => (y, width - 1 - x),
+            Self::FlipHorizontal => (width - 1 - x, y),
+            Self::FlipVertical => (x, height - 1 - y),
+            Self::Transpose => (y, x),
+            Self::AntiTranspose => (height - 1 - y, width - 1 - x),
+        };
+        BoardPosition {
+            x,
+            y,
+        }
+    }
+
+    /// Moves `action` on `board`. Returns None if the card can't be put in the transformed
+    /// shape with any rotation, e.g. an asymmetric card mirrored.
+    pub fn apply_to_action(self, board: &Board, action: &Action) -> Option<Action> {
+        let (card, position) = match action {
+            Action::Pass(_) => return Some(action.clone()),
+            Action::Put(card, position) | Action::Special(card, position) => (card, position),
+        };
+        let (width, height) = board.get_size();
+        let cells: HashSet<(BoardPosition, CardCellType)> = card
+            .get_cells_on_board_coord(position)
+            .map(|(pos, cell)| (self.apply(pos, width, height), cell.cell_type))
+            .collect();
+        let min_x = cells.iter().map(|(pos, _)| pos.x).min()?;
+        let min_y = cells.iter().map(|(pos, _)| pos.y).min()?;
+
+        Rotation::VALUES.into_iter().find_map(|rotation| {
+            let card_cells = card.get_cells(rotation);
+            let x = min_x - card_cells.keys().map(|p| p.x).min()?;
+            let y = min_y - card_cells.keys().map(|p| p.y).min()?;
+            let moved = CardPosition::new(x, y, rotation);
+            let matched = card
+                .get_cells_on_board_coord(&moved)
+                .all(|(pos, cell)| cells.contains(&(pos, cell.cell_type)));
+            matched.then(|| match action {
+                Action::Special(..) => Action::Special(card.clone(), moved),
+                _ => Action::Put(card.clone(), moved),
+            })
+        })
+    }
+}
+
+impl Board {
+    /// Transforms which map the board onto itself, including the walls and ink of each
+    /// player. Always contains `Transform::Identity`.
+    pub fn symmetries(&self) -> Vec<Transform> {
+        let (width, height) = self.get_size();
+        Transform::VALUES
+            .into_iter()
+            .filter(|transform| !transform.swaps_axes() || width == height)
+            .filter(|transform| {
+                (0..height).all(|y| {
+                    (0..width).all(|x| {
+                        let position = BoardPosition {
+                            x,
+                            y,
+                        };
+                        self.get_cell(position)
+                            == self.get_cell(transform.apply(position, width, height))
+                    })
+                })
+            })
+            .collect()
+    }
+}
+
+/// The representative of the actions equivalent to `action` under `symmetries` of `board`,
+/// so that equivalent actions can be merged. It's the one with the smallest position among
+/// the transformed actions which the card can make.
+pub fn canonicalize_action(board: &Board, symmetries: &[Transform], action: &Action) -> Action {
+    symmetries
+        .iter()
+        .filter_map(|transform| transform.apply_to_action(board, action))
+        .min_by_key(|action| match action {
+            Action::Pass(_) => None,
+            Action::Put(_, position) | Action::Special(_, position) => Some(*position),
+        })
+        .unwrap_or_else(|| action.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{
+        board::{
+            load_board,
+            load_board_from_lines,
+        },
+        state::tests::new_test_card,
+    };
+
+    #[test]
+    fn test_symmetries() {
+        #[rustfmt::skip]
+        let board = load_board_from_lines(
+            String::from("empty"),
+            &[
+            "######",
+            "#....#",
+            "#....#",
+            "#....#",
+            "#....#",
+            "######",
+            ]);
+        assert_eq!(Transform::VALUES.to_vec(), board.symmetries());
+
+        // The players swap by any transform but the anti-diagonal mirror.
+        #[rustfmt::skip]
+        let board = load_board_from_lines(
+            String::from("diagonal"),
+            &[
+            "######",
+            "#...O#",
+            "#....#",
+            "#....#",
+            "#P...#",
+            "######",
+            ]);
+        assert_eq!(
+            vec![Transform::Identity, Transform::AntiTranspose],
+            board.symmetries()
+        );
+
+        #[rustfmt::skip]
+        let board = load_board_from_lines(
+            String::from("asymmetric"),
+            &[
+            "######",
+            "#..#O#",
+            "#....#",
+            "#P...#",
+            "######",
+            ]);
+        assert_eq!(vec![Transform::Identity], board.symmetries());
+
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../data/boards/massugu_street");
+        assert_eq!(
+            vec![Transform::Identity, Transform::FlipHorizontal],
+            load_board(&path).symmetries()
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_action() {
+        #[rustfmt::skip]
+        let board = load_board_from_lines(
+            String::from("diagonal"),
+            &[
+            "######",
+            "#...O#",
+            "#....#",
+            "#....#",
+            "#P...#",
+            "######",
+            ]);
+        let symmetries = board.symmetries();
+        let card = new_test_card(&["=="]);
+
+        let top_left = Action::Put(card.clone(), CardPosition::new(1, 1, Rotation::Up));
+        let bottom_right = Action::Put(card.clone(), CardPosition::new(4, 3, Rotation::Right));
+        assert_eq!(top_left, canonicalize_action(&board, &symmetries, &bottom_right));
+        assert_eq!(top_left, canonicalize_action(&board, &symmetries, &top_left));
+
+        // Already the smallest one of the equivalent actions.
+        let center = Action::Special(card.clone(), CardPosition::new(2, 2, Rotation::Up));
+        assert_eq!(center, canonicalize_action(&board, &symmetries, &center));
+
+        let pass = Action::Pass(card);
+        assert_eq!(pass, canonicalize_action(&board, &symmetries, &pass));
+    }
+
+    #[test]
+    fn test_mirrored_asymmetric_card() {
+        #[rustfmt::skip]
+        let board = load_board_from_lines(
+            String::from("empty"),
+            &[
+            "#####",
+            "#...#",
+            "#...#",
+            "#####",
+            ]);
+        #[rustfmt::skip]
+        let card = new_test_card(&[
+            "==",
+            " ==",
+        ]);
+        let action = Action::Put(card, CardPosition::new(1, 1, Rotation::Up));
+        // The mirrored S shape is a Z shape which no rotation of the card makes.
+        assert_eq!(None, Transform::FlipHorizontal.apply_to_action(&board, &action));
+        assert_eq!(
+            Some(action.clone()),
+            Transform::Rotate180.apply_to_action(&board, &action)
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! Helpers for tests which reproduce reported situations from files or need small cards.
+//! Available for tests of other crates with the `test-support` feature.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+};
+
+use super::{
+    board::{
+        self,
+        Board,
+    },
+    card::{
+        self,
+        Card,
+    },
+    game::{
+        Context,
+        PlayerId,
+        RuleSet,
+    },
+    state::{
+        PlayerCardState,
+        State,
+    },
+};
+
+/// A card named after `id` whose cells are `=` in `lines`, e.g. `&["==", "= "]`.
+/// Its special cost is 3.
+pub fn new_test_card(id: u32, lines: &[&str]) -> Card {
+    let lines: Vec<String> = lines.iter().map(|s| String::from(*s)).collect();
+    let cell_cnt = lines
+        .iter()
+        .map(|line| line.matches('=').count() as i32)
+        .sum();
+    card::load_card_from_lines(id, format!("card {}", id), cell_cnt, 3, &lines)
+}
+
+/// A context with the default rules and two cards: 1 "small" (`=`) and 2 "pair" (`==`).
+pub fn new_test_context() -> Context {
+    let small = card::load_card_from_lines(1, String::from("small"), 1, 1, &["=".to_string()]);
+    let pair = card::load_card_from_lines(2, String::from("pair"), 2, 1, &["==".to_string()]);
+    Context {
+        all_cards: HashMap::from([(1, small), (2, pair)]),
+        enabled_step_execution: false,
+        rules: RuleSet::default(),
+    }
+}
+
+/// A 7x7 board named "test board" with a 5x5 empty area and a special cell on each side.
+pub fn new_test_board() -> Board {
+    #[rustfmt::skip]
+    let board = board::load_board_from_lines(String::from("test board"), &[
+        "#######",
+        "#..O..#",
+        "#.....#",
+        "#.....#",
+        "#.....#",
+        "#..P..#",
+        "#######",
+    ]);
+    board
+}
+
+/// A snapshot of a game in the middle, loaded from a scenario file like:
+///
+/// ```text
+/// # comments start with `#` before the board.
+/// turn: 11
+/// south special: 4
+/// north special: 1
+/// south hands: 6 13 22 34
+/// north hands: 6 28 40 45
+/// south consumed: 52 55
+/// north deck: 61
+/// board:
+/// #######
+/// #..O..#
+/// #..P..#
+/// #######
+/// ```
+///
+/// `turn` is the number of turns already played. Consumed cards and decks are optional and
+/// empty by default. All lines after `board:` are the board in the format of board files.
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    pub board: Board,
+    pub turn: i32,
+
+    /// Following fields are indexed by `PlayerId::to_index`.
+    pub special_counts: [i32; 2],
+    pub hands: [Vec<u32>; 2],
+    pub decks: [Vec<u32>; 2],
+    pub consumed_cards: [Vec<u32>; 2],
+}
+
+impl Scenario {
+    pub fn get_state(&self) -> State {
+        let [south_special, north_special] = self.special_counts;
+        let [south_consumed, north_consumed] = self.consumed_cards.clone();
+        State::new(
+            self.board.clone(),
+            self.turn,
+            south_special,
+            north_special,
+            south_consumed,
+            north_consumed,
+        )
+    }
+
+    /// Panics if a card isn't in `context`.
+    pub fn get_player_state(&self, context: &Context, player_id: PlayerId) -> PlayerCardState {
+        let index = player_id.to_index();
+        PlayerCardState::new(
+            player_id,
+            context.get_cards(&self.hands[index]),
+            context.get_cards(&self.decks[index]),
+        )
+    }
+}
+
+/// Loads a scenario file. The board is named after the file.
+pub fn load_scenario(path: &Path) -> Result<Scenario, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{:?}: {}", path, e))?;
+    let name = path
+        .file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().to_string());
+    parse_scenario(name, &text).map_err(|e| format!("{:?}: {}", path, e))
+}
+
+pub fn parse_scenario(name: String, text: &str) -> Result<Scenario, String> {
+    let mut turn = None;
+    let mut special_counts = [0; 2];
+    let mut hands: [Option<Vec<u32>>; 2] = [None, None];
+    let mut decks: [Vec<u32>; 2] = [vec![], vec![]];
+    let mut consumed_cards: [Vec<u32>; 2] = [vec![], vec![]];
+
+    let mut lines = text.lines().enumerate();
+    for (line_index, line) in lines.by_ref() {
+        let line = line.trim();
+        let error = |msg: String| format!("Line {} ({:?}): {}", line_index + 1, line, msg);
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line == "board:" {
+            break;
+        }
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| error("Expected `<key>: <value>`".into()))?;
+        let value = value.trim();
+        if key == "turn" {
+            turn = Some(value.parse().map_err(|e| error(format!("{}", e)))?);
+            continue;
+        }
+        let (player_id, key) = key
+            .split_once(' ')
+            .ok_or_else(|| error(format!("Unknown key: {}", key)))?;
+        let index = player_id.parse::<PlayerId>().map_err(error)?.to_index();
+        let ids = || {
+            value
+                .split_whitespace()
+                .map(|id| id.parse::<u32>())
+                .collect::<Result<Vec<u32>, _>>()
+                .map_err(|e| error(format!("Invalid card id: {}", e)))
+        };
+        match key {
+            "special" => {
+                special_counts[index] = value.parse().map_err(|e| error(format!("{}", e)))?
+            }
+            "hands" => hands[index] = Some(ids()?),
+            "deck" => decks[index] = ids()?,
+            "consumed" => consumed_cards[index] = ids()?,
+            _ => return Err(error(format!("Unknown key: {}", key))),
+        }
+    }
+
+    let board_lines: Vec<&str> = lines
+        .map(|(_, line)| line.trim_end())
+        .filter(|line| !line.is_empty())
+        .collect();
+    if board_lines.is_empty() {
+        return Err("The board is missing".into());
+    }
+    let [south_hands, north_hands] = hands;
+    Ok(Scenario {
+        board: board::load_board_from_lines(name, &board_lines),
+        turn: turn.ok_or("The turn is missing")?,
+        special_counts,
+        hands: [
+            south_hands.ok_or("The hands of south are missing")?,
+            north_hands.ok_or("The hands of north are missing")?,
+        ],
+        decks,
+        consumed_cards,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::*;
+
+    fn data_path(relative: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../data")
+            .join(relative)
+    }
+
+    #[test]
+    fn test_load_scenario() {
+        let context = Context {
+            all_cards: load_cards(data_path("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: RuleSet::default(),
+        };
+        let scenario = load_scenario(&data_path("scenarios/special_next_to_wall")).unwrap();
+
+        let state = scenario.get_state();
+        #[rustfmt::skip]
+        let expected = load_board_from_lines(String::from("special_next_to_wall"), &[
+            "#########",
+            "#...oO..#",
+            "#..oo...#",
+            "#pppp...#",
+            "#.P.....#",
+            "#########",
+        ]);
+        assert_eq!(expected, state.board);
+        assert_eq!(11, state.get_turn());
+        assert_eq!(4, state.player_special_count);
+        assert_eq!(1, state.opponent_special_count);
+        assert_eq!(10, state.get_consumed_cards(PlayerId::North).len());
+
+        let south = scenario.get_player_state(&context, PlayerId::South);
+        assert_eq!(vec![6, 13, 22, 34], to_ids(south.get_hands()));
+        assert!(south.get_deck().is_empty());
+        let north = scenario.get_player_state(&context, PlayerId::North);
+        assert_eq!(vec![6, 28, 40, 45], to_ids(north.get_hands()));
+    }
+
+    #[test]
+    fn test_parse_invalid_scenario() {
+        let err = parse_scenario("s".into(), "turn: 1\nsouth hands: 6\nboard:\n###").unwrap_err();
+        assert_eq!("The hands of north are missing", err);
+        let err = parse_scenario("s".into(), "turn: 1\neast hands: 6\n").unwrap_err();
+        assert!(err.starts_with("Line 2"), "{}", err);
+    }
+}
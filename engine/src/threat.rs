@@ -0,0 +1,97 @@
+use super::{
+    board::BoardCell,
+    card::Card,
+    game::{
+        Action,
+        PlayerId,
+    },
+    state::{
+        self,
+        State,
+    },
+};
+
+/// Lists placements the opponent of `player_id` can make with `opponent_possible_cards`,
+/// paired with the number of cells each placement would newly ink for the opponent.
+///
+/// Results are sorted by the gained cells in descending order. Placements with the same gain
+/// keep the order of `append_valid_actions` so that the result is deterministic.
+/// Passes are not listed since they never gain any cell.
+pub fn opponent_threats(
+    state: &State,
+    opponent_possible_cards: &[Card],
+    player_id: PlayerId,
+) -> Vec<(Action, u32)> {
+    let opponent_id = player_id.another();
+    let mut actions = vec![];
+    state::append_valid_actions(state, opponent_possible_cards, opponent_id, &mut actions);
+
+    let mut threats: Vec<(Action, u32)> = actions
+        .into_iter()
+        .filter(|action| !action.is_pass())
+        .map(|action| {
+            let gain = count_gained_cells(state, &action, opponent_id);
+            (action, gain)
+        })
+        .collect();
+    threats.sort_by_key(|(_, gain)| std::cmp::Reverse(*gain));
+    threats
+}
+
+/// Cells which are not inked by `player_id` yet. Special attacks may overwrite the other's ink.
+fn count_gained_cells(state: &State, action: &Action, player_id: PlayerId) -> u32 {
+    let (card, position) = action.get_card_and_position();
+    card.get_cells_on_board_coord(position)
+        .filter(
+            |(board_pos, _cell)| match state.board.get_cell(*board_pos) {
+                BoardCell::Ink(pid) | BoardCell::Special(pid) => pid != player_id,
+                _ => true,
+            },
+        )
+        .count() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn test_dominant_threat_ranks_first() {
+        #[rustfmt::skip]
+        let board = load_board_from_lines(String::from("test board"), &[
+            "#########",
+            "#.......#",
+            "#...O...#",
+            "#.......#",
+            "#.......#",
+            "#.......#",
+            "#...P...#",
+            "#########",
+        ]);
+        let state = State::new(board, 0, 0, 0, vec![], vec![]);
+        let small = load_card_from_lines(1, String::from("small"), 1, 1, &["=".to_string()]);
+        let large = load_card_from_lines(
+            2,
+            String::from("large"),
+            9,
+            5,
+            &["===".to_string(), "===".to_string(), "===".to_string()],
+        );
+
+        let threats = opponent_threats(&state, &[small, large], PlayerId::South);
+
+        assert!(!threats.is_empty());
+        let (top_action, top_gain) = &threats[0];
+        assert_eq!(2, top_action.get_consumed_card().get_id());
+        assert!(!matches!(top_action, Action::Special(_, _)));
+        assert_eq!(9, *top_gain);
+        assert!(threats.iter().all(|(action, _)| !action.is_pass()));
+        assert!(threats.windows(2).all(|w| w[0].1 >= w[1].1));
+        // Every placement of the small card gains only one cell.
+        assert!(threats
+            .iter()
+            .filter(|(action, _)| action.get_consumed_card().get_id() == 1)
+            .all(|(_, gain)| *gain == 1));
+    }
+}
@@ -0,0 +1,110 @@
+use std::path::PathBuf;
+
+use clap::{
+    self,
+    Parser,
+    ValueHint,
+};
+use log::*;
+
+use engine::{
+    Context,
+    Replay,
+};
+
+/// Prints the first turn where two replays of games on a same board differ.
+/// Useful for checking how a change of a player affects its play against a same opponent.
+#[derive(Parser)]
+pub struct ReplayDiffArgs {
+    /// a directory path where holds all card data. no need to specify for many cases.
+    #[clap(long, value_parser, default_value_t = String::from("data/cards"))]
+    card_dir: String,
+
+    /// a file path to the board file where the games were played.
+    #[clap(
+        long,
+        short,
+        value_parser,
+        default_value = "data/boards/massugu_street"
+    )]
+    board_path: PathBuf,
+
+    #[clap(value_parser, value_hint=ValueHint::FilePath)]
+    replay_a: PathBuf,
+
+    #[clap(value_parser, value_hint=ValueHint::FilePath)]
+    replay_b: PathBuf,
+}
+
+fn load_replay(context: &Context, board: &engine::Board, path: &PathBuf) -> Replay {
+    std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| Replay::parse(context, board.clone(), &text))
+        .unwrap_or_else(|e| {
+            error!("Failed to load the replay {:?}: {}", path, e);
+            std::process::exit(1);
+        })
+}
+
+/// Joins lines of `left` and `right` with padding so that they are shown side by side.
+fn format_side_by_side(left: &str, right: &str) -> String {
+    let width = left.lines().map(|l| l.chars().count()).max().unwrap_or(0);
+    let left_lines: Vec<&str> = left.lines().collect();
+    let right_lines: Vec<&str> = right.lines().collect();
+    (0..left_lines.len().max(right_lines.len()))
+        .map(|i| {
+            format!(
+                "{:width$}    {}\n",
+                left_lines.get(i).unwrap_or(&""),
+                right_lines.get(i).unwrap_or(&""),
+                width = width
+            )
+        })
+        .collect()
+}
+
+fn main() {
+    env_logger::init_from_env(
+        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
+    );
+
+    let args = ReplayDiffArgs::parse();
+    let context = Context {
+        all_cards: engine::load_cards(&args.card_dir).unwrap_or_else(|e| {
+            error!("Failed to load cards: {}", e);
+            std::process::exit(1);
+        }),
+        enabled_step_execution: false,
+        rules: engine::RuleSet::default(),
+    };
+    let board = engine::load_board(&args.board_path);
+    let a = load_replay(&context, &board, &args.replay_a);
+    let b = load_replay(&context, &board, &args.replay_b);
+
+    let turn = match a.first_divergence(&b) {
+        Some(turn) => turn,
+        None => {
+            println!("The replays are identical.");
+            return;
+        }
+    };
+
+    println!("Diverged at turn {}", turn + 1);
+    let mut boards = vec![];
+    for (path, replay) in [(&args.replay_a, &a), (&args.replay_b, &b)] {
+        println!("{:?}:", path);
+        match replay.get_turns().get(turn) {
+            Some((south, north)) => {
+                println!("    South: {}", south);
+                println!("    North: {}", north);
+            }
+            None => println!("    (ended)"),
+        }
+        let state = replay.get_state_after(turn + 1).unwrap_or_else(|e| {
+            error!("Failed to replay {:?}: {}", path, e);
+            std::process::exit(1);
+        });
+        boards.push(state.board.to_string());
+    }
+    print!("{}", format_side_by_side(&boards[0], &boards[1]));
+}
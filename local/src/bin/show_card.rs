@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+use clap::{
+    self,
+    Parser,
+    ValueHint,
+};
+use log::*;
+
+/// Prints shapes of cards in all rotations side by side.
+/// Useful for checking card data files.
+#[derive(Parser)]
+pub struct ShowCardArgs {
+    /// a directory path where holds all card data. no need to specify for many cases.
+    #[clap(long, value_parser, default_value_t = String::from("data/cards"))]
+    card_dir: String,
+
+    /// a file path to a deck whose cards are shown in addition to `card_ids`.
+    #[clap(long, short, value_parser, value_hint=ValueHint::FilePath)]
+    deck_path: Option<PathBuf>,
+
+    /// IDs of cards to show.
+    #[clap(value_parser)]
+    card_ids: Vec<u32>,
+}
+
+fn main() {
+    env_logger::init_from_env(
+        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
+    );
+
+    let args = ShowCardArgs::parse();
+    let all_cards = engine::load_cards(&args.card_dir).unwrap_or_else(|e| {
+        error!("Failed to load cards: {}", e);
+        std::process::exit(1);
+    });
+
+    let mut card_ids = args.card_ids;
+    if let Some(deck_path) = &args.deck_path {
+        card_ids.extend(engine::load_deck(deck_path));
+    }
+    if card_ids.is_empty() {
+        error!("No card is specified. Pass card IDs or --deck-path.");
+        std::process::exit(1);
+    }
+    for id in card_ids {
+        match all_cards.get(&id) {
+            Some(card) => println!("{}", card.fmt_all_rotations()),
+            None => {
+                error!("Unknown card ID: {}", id);
+                std::process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+
+use clap::{
+    self,
+    Parser,
+    ValueHint,
+};
+use log::*;
+
+use engine::Context;
+
+/// Replays games in a match log written by the server's `--match-log` and checks that the
+/// current engine computes the same scores as recorded.
+/// Useful for catching changes of the engine's behavior.
+#[derive(Parser)]
+pub struct VerifyLogArgs {
+    /// a directory path where holds all card data. no need to specify for many cases.
+    #[clap(long, value_parser, default_value_t = String::from("data/cards"))]
+    card_dir: String,
+
+    /// a directory which has boards where the games were played.
+    #[clap(long, value_parser, default_value_t = String::from("data/boards"))]
+    board_dir: String,
+
+    #[clap(value_parser, value_hint=ValueHint::FilePath)]
+    match_log: PathBuf,
+}
+
+fn main() {
+    env_logger::init_from_env(
+        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
+    );
+
+    let args = VerifyLogArgs::parse();
+    let context = Context {
+        all_cards: engine::load_cards(&args.card_dir).unwrap_or_else(|e| {
+            error!("Failed to load cards: {}", e);
+            std::process::exit(1);
+        }),
+        enabled_step_execution: false,
+        rules: engine::RuleSet::default(),
+    };
+    let boards = engine::load_boards(&args.board_dir);
+    let records = std::fs::read_to_string(&args.match_log)
+        .map_err(|e| e.to_string())
+        .and_then(|text| engine::parse_match_log(&context, &boards, &text))
+        .unwrap_or_else(|e| {
+            error!("Failed to load the match log {:?}: {}", args.match_log, e);
+            std::process::exit(1);
+        });
+
+    let mut failed_cnt = 0;
+    for record in records.iter() {
+        if let Err(e) = record.verify(context.rules) {
+            error!("Game {:016x}: {}", record.game_uid, e);
+            failed_cnt += 1;
+        }
+    }
+    info!("Verified {} games: {} failed", records.len(), failed_cnt);
+    if failed_cnt > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::Path,
+};
+
+use rand::Rng;
+use rand_mt::Mt64;
+
+use engine::{
+    Board,
+    Context,
+};
+
+use crate::Contestant;
+
+/// Picks a board randomly for each battle so that players are evaluated on various boards.
+pub struct BoardSelector {
+    /// Sorted by their names so that the selection doesn't depend on the order of loading.
+    boards: Vec<Board>,
+    rng: Mt64,
+}
+
+impl BoardSelector {
+    pub fn new(mut boards: Vec<Board>, seed: u64) -> Self {
+        assert!(!boards.is_empty(), "No board to select");
+        boards.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+        BoardSelector {
+            boards,
+            rng: Mt64::new(seed),
+        }
+    }
+
+    /// Loads all boards in `dir` and checks that each of them is playable with the cards.
+    pub fn load(context: &Context, dir: &Path, seed: u64) -> Result<Self, String> {
+        if !dir.is_dir() {
+            return Err(format!("Not a directory: {:?}", dir));
+        }
+        let boards = engine::load_boards(dir.to_str().unwrap());
+        if boards.is_empty() {
+            return Err(format!("No board is found in {:?}", dir));
+        }
+        for board in boards.iter() {
+            context.validate_board(board).map_err(|e| e.to_string())?;
+        }
+        Ok(Self::new(boards, seed))
+    }
+
+    /// A selector which picks from the same boards with another seed.
+    pub fn with_seed(&self, seed: u64) -> Self {
+        BoardSelector {
+            boards: self.boards.clone(),
+            rng: Mt64::new(seed),
+        }
+    }
+
+    pub fn get_boards(&self) -> &[Board] {
+        &self.boards
+    }
+
+    pub fn next_board(&mut self) -> &Board {
+        let index = self.rng.gen_range(0..self.boards.len());
+        &self.boards[index]
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct BoardCount {
+    win: u32,
+    lose: u32,
+    draw: u32,
+}
+
+/// Results of battles for each board.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BoardBreakdown {
+    counts: BTreeMap<String, BoardCount>,
+}
+
+impl BoardBreakdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a battle on `board` won by `winner`, or drawn if it's `None`.
+    /// The winner isn't always the one who scored more since a player may forfeit.
+    pub fn push(&mut self, board: &Board, winner: Option<Contestant>) {
+        let count = self.counts.entry(board.get_name().to_string()).or_default();
+        match winner {
+            Some(Contestant::Opponent) => count.lose += 1,
+            None => count.draw += 1,
+            Some(Contestant::Player) => count.win += 1,
+        }
+    }
+}
+
+/// Formats a line for each board.
+impl Display for BoardBreakdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, count) in self.counts.iter() {
+            let total = count.win + count.lose + count.draw;
+            writeln!(
+                f,
+                "{}: battles: {}, win: {}, lose: {}, draw: {} (win rate: {:.3})",
+                name,
+                total,
+                count.win,
+                count.lose,
+                count.draw,
+                count.win as f64 / total as f64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn data_path(relative: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../data")
+            .join(relative)
+    }
+
+    fn pick_names(selector: &mut BoardSelector, n: usize) -> Vec<String> {
+        (0..n)
+            .map(|_| selector.next_board().get_name().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_reproducible_board_sequence() {
+        let context = Context {
+            all_cards: engine::load_cards(data_path("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        };
+        let mut selector = BoardSelector::load(&context, &data_path("boards"), 42).unwrap();
+        let names = pick_names(&mut selector, 30);
+
+        let mut same_seed = BoardSelector::load(&context, &data_path("boards"), 42).unwrap();
+        assert_eq!(names, pick_names(&mut same_seed, 30));
+
+        // The order of loaded boards doesn't matter.
+        let mut reversed_boards = selector.get_boards().to_vec();
+        reversed_boards.reverse();
+        let mut reversed = BoardSelector::new(reversed_boards, 42);
+        assert_eq!(names, pick_names(&mut reversed, 30));
+
+        // Various boards are picked.
+        let mut unique_names = names.clone();
+        unique_names.sort();
+        unique_names.dedup();
+        assert!(unique_names.len() > 1);
+
+        let mut other_seed = BoardSelector::load(&context, &data_path("boards"), 43).unwrap();
+        assert_ne!(names, pick_names(&mut other_seed, 30));
+    }
+
+    #[test]
+    fn test_breakdown() {
+        let a = engine::load_board_from_lines(String::from("a"), &["###", "#P#", "###"]);
+        let b = engine::load_board_from_lines(String::from("b"), &["###", "#P#", "###"]);
+        let mut breakdown = BoardBreakdown::new();
+        breakdown.push(&b, Some(Contestant::Opponent));
+        breakdown.push(&a, Some(Contestant::Player));
+        breakdown.push(&a, None);
+
+        assert_eq!(
+            "a: battles: 2, win: 1, lose: 0, draw: 1 (win rate: 0.500)\n\
+             b: battles: 1, win: 0, lose: 1, draw: 0 (win rate: 0.000)\n",
+            breakdown.to_string()
+        );
+    }
+}
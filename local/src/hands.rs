@@ -0,0 +1,141 @@
+use std::{
+    io::Write,
+    sync::Arc,
+    time::Duration,
+};
+
+use engine::{
+    Action,
+    Board,
+    Card,
+    Context,
+    PlayerId,
+    State,
+};
+use players::{
+    Player,
+    SearchInfo,
+};
+
+/// A player which writes its hands to `out` whenever it's asked for an action.
+/// Note that turns taken by a script aren't written since the player isn't asked for them.
+pub struct HandPrinter<'p, W: Write> {
+    player: &'p mut dyn Player,
+    player_id: PlayerId,
+    out: W,
+}
+
+impl<'p, W: Write> HandPrinter<'p, W> {
+    pub fn new(player: &'p mut dyn Player, out: W) -> Self {
+        HandPrinter {
+            player,
+            player_id: PlayerId::South,
+            out,
+        }
+    }
+
+    pub fn get_output(&self) -> &W {
+        &self.out
+    }
+}
+
+/// Formats hands of `player_id` at the beginning of the turn in a line.
+pub fn format_hands(state: &State, player_id: PlayerId, hands: &[Card]) -> String {
+    format!(
+        "Turn {}: {}'s hands ({} cards): {}",
+        state.get_turn() + 1,
+        player_id,
+        hands.len(),
+        engine::format_cards(hands)
+    )
+}
+
+impl<'p, W: Write + Send> Player for HandPrinter<'p, W> {
+    fn get_name(&self) -> &str {
+        self.player.get_name()
+    }
+
+    fn init_game(
+        &mut self,
+        player_id: PlayerId,
+        context: &Arc<Context>,
+        board: &Board,
+        deck: Vec<Card>,
+    ) {
+        self.player_id = player_id;
+        self.player.init_game(player_id, context, board, deck);
+    }
+
+    fn need_redeal_hands(&mut self, dealed_cards: &[Card], time_limit: &Duration) -> bool {
+        self.player.need_redeal_hands(dealed_cards, time_limit)
+    }
+
+    fn get_action(&mut self, state: &State, hands: &[Card], time_limit: &Duration) -> Action {
+        writeln!(self.out, "{}", format_hands(state, self.player_id, hands))
+            .expect("Failed to write hands");
+        self.player.get_action(state, hands, time_limit)
+    }
+
+    fn get_last_search_info(&self) -> Option<SearchInfo> {
+        self.player.get_last_search_info()
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.player.reseed(seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use players::random::RandomPlayer;
+    use rand_mt::Mt64;
+
+    use super::*;
+    use crate::DealPolicy;
+
+    fn data_path(relative: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../data")
+            .join(relative)
+    }
+
+    #[test]
+    fn test_print_hands() {
+        let context = Arc::new(Context {
+            all_cards: engine::load_cards(data_path("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        });
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        let mut random = RandomPlayer::new("p".into(), 1);
+        let mut player = HandPrinter::new(&mut random, vec![]);
+        let mut opponent = RandomPlayer::new("o".into(), 2);
+
+        crate::run(
+            &context,
+            &board,
+            &deck,
+            &deck,
+            &mut player,
+            &mut opponent,
+            &mut Mt64::new(42),
+            [DealPolicy::Shuffle; 2],
+        );
+
+        let output = String::from_utf8(player.get_output().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(engine::TURN_COUNT as usize, lines.len());
+        // Hands are refilled after each turn until the deck runs out.
+        let full_hands = format!("({} cards)", engine::HAND_SIZE);
+        assert!(
+            lines[0].starts_with("Turn 1: South's hands"),
+            "{}",
+            lines[0]
+        );
+        assert!(lines[0].contains(&full_hands), "{}", lines[0]);
+        assert!(lines[1].contains(&full_hands), "{}", lines[1]);
+    }
+}
@@ -1,5 +1,16 @@
+pub mod board_selector;
+pub mod hands;
+pub mod replay_viewer;
+pub mod script;
+pub mod synergy;
+
 use std::{
-    io::stdin,
+    io::{
+        self,
+        stdin,
+        Write,
+    },
+    sync::Arc,
     time::Duration,
 };
 
@@ -11,20 +22,77 @@ use engine::{
     Board,
     Card,
     Context,
+    GameCsvRow,
+    GameCsvWriter,
+    GameOutcome,
+    GameReplay,
     PlayerCardState,
     PlayerId,
+    Replay,
     State,
 };
 
 use players::*;
 
+use crate::script::Script;
+
+/// How the dealer orders a deck before dealing hands.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DealPolicy {
+    /// Shuffles the deck, as in the real game.
+    #[default]
+    Shuffle,
+
+    /// Uses the deck as it is listed so the first `HAND_SIZE` cards are dealt.
+    /// Redeal requests don't change the hands.
+    /// Useful for testing specific scenarios.
+    AsListed,
+
+    /// Same as `AsListed` but the last `HAND_SIZE` cards are dealt.
+    Bottom,
+
+    /// Deals cards at the indices of the listed deck. The rest of the cards are left in the deck
+    /// in the listed order. Redeal requests don't change the hands.
+    Indices([usize; engine::HAND_SIZE]),
+}
+
+impl DealPolicy {
+    /// Splits `deck` into hands and the rest of the deck as listed. `Shuffle` deals with
+    /// `engine::deal` instead.
+    fn split(&self, deck: &[Card]) -> (Vec<Card>, Vec<Card>) {
+        let hand_indices: Vec<usize> = match self {
+            DealPolicy::Shuffle | DealPolicy::AsListed => (0..engine::HAND_SIZE).collect(),
+            DealPolicy::Bottom => (deck.len() - engine::HAND_SIZE..deck.len()).collect(),
+            DealPolicy::Indices(indices) => {
+                for (i, index) in indices.iter().enumerate() {
+                    assert!(*index < deck.len(), "Index out of the deck: {}", index);
+                    assert!(
+                        !indices[..i].contains(index),
+                        "Index is dealt twice: {}",
+                        index
+                    );
+                }
+                indices.to_vec()
+            }
+        };
+        let hands = hand_indices.iter().map(|i| deck[*i].clone()).collect();
+        let rest = deck
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !hand_indices.contains(i))
+            .map(|(_, card)| card.clone())
+            .collect();
+        (hands, rest)
+    }
+}
+
 pub fn deal_hands(
     rng: &mut Mt64,
     deck: &[Card],
     player_id: PlayerId,
     player: &mut dyn Player,
+    deal_policy: DealPolicy,
 ) -> PlayerCardState {
-    let mut deck = deck.to_vec();
     debug!(
         "Deck: {:#?}",
         deck.iter()
@@ -32,45 +100,340 @@ pub fn deal_hands(
             .collect::<Vec<&str>>()
     );
 
-    deck.shuffle(rng);
+    let (mut hands, mut rest) = if deal_policy == DealPolicy::Shuffle {
+        engine::deal(deck, engine::HAND_SIZE, rng)
+    } else {
+        deal_policy.split(deck)
+    };
+    if player.need_redeal_hands(&hands, &Duration::from_secs(5))
+        && deal_policy == DealPolicy::Shuffle
+    {
+        (hands, rest) = engine::redeal(hands, rest, rng);
+    }
+
+    PlayerCardState::new(player_id, hands, rest)
+}
+
+/// The seed of the `battle_index`-th battle of a series started with `base_seed`.
+/// Everything random in the battle prepared by `prepare_battle` derives from it.
+pub fn battle_seed(base_seed: u64, battle_index: u32) -> u64 {
+    Mt64::new(base_seed).next_u64() ^ battle_index as u64
+}
+
+/// Prepares the `battle_index`-th battle of a series started with `base_seed`.
+///
+/// Both players are re-seeded and decks are picked from the inventories (indexed by
+/// `PlayerId::to_index`) so that the battle plays identically regardless of battles played
+/// before it. Returns the rng for the battle and the decks.
+pub fn prepare_battle(
+    base_seed: u64,
+    battle_index: u32,
+    inventories: [&[Card]; 2],
+    player: &mut dyn Player,
+    opponent: &mut dyn Player,
+) -> (Mt64, [Vec<Card>; 2]) {
+    let mut rng = Mt64::new(battle_seed(base_seed, battle_index));
+    player.reseed(rng.next_u64());
+    opponent.reseed(rng.next_u64());
+    let decks = inventories.map(|inventory| {
+        inventory
+            .choose_multiple(&mut rng, engine::DECK_SIZE)
+            .cloned()
+            .collect()
+    });
+    (rng, decks)
+}
+
+/// Either side of a match, regardless of the side of the board they play in a game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Contestant {
+    Player,
+    Opponent,
+}
+
+/// A game played in `run_match`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MatchGame {
+    /// The side the player played. The opponent played the other one.
+    pub player_side: PlayerId,
+
+    /// (the player's score, the opponent's score).
+    pub scores: (u32, u32),
+
+    /// `None` for a draw.
+    pub winner: Option<Contestant>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MatchResult {
+    /// `None` if neither won more games than the other.
+    pub winner: Option<Contestant>,
+
+    /// Games in the order they were played.
+    pub games: Vec<MatchGame>,
+}
+
+/// A battle between the player and the opponent, which can be written as a row of
+/// `GameCsvWriter`.
+#[derive(Clone, Debug)]
+pub struct PlayedBattle {
+    pub board: String,
 
-    if player.need_redeal_hands(&deck[0..engine::HAND_SIZE], &Duration::from_secs(5)) {
-        deck.shuffle(rng);
+    /// The side the player played. The opponent played the other one.
+    pub player_side: PlayerId,
+
+    /// Indexed by `PlayerId::to_index`.
+    pub specials_used: [u32; 2],
+
+    pub outcome: GameOutcome,
+}
+
+impl PlayedBattle {
+    pub fn new(replay: &Replay, player_side: PlayerId, outcome: GameOutcome) -> Self {
+        PlayedBattle {
+            board: replay.get_board().get_name().to_string(),
+            player_side,
+            specials_used: [
+                replay.count_specials(PlayerId::South),
+                replay.count_specials(PlayerId::North),
+            ],
+            outcome,
+        }
     }
 
-    PlayerCardState::new(
-        player_id,
-        deck[0..engine::HAND_SIZE].to_vec(),
-        deck[engine::HAND_SIZE..].to_vec(),
-    )
+    /// Writes the battle as the `game`-th row. `decks` and `players` (e.g. `mcts-100`) are
+    /// ordered as (the player, the opponent).
+    pub fn write_csv_row<W: Write>(
+        &self,
+        csv: &mut GameCsvWriter<W>,
+        game: u32,
+        seed: u64,
+        decks: [&[Card]; 2],
+        mut players: [&str; 2],
+    ) -> io::Result<()> {
+        let mut decks = decks.map(engine::to_ids);
+        if self.player_side == PlayerId::North {
+            decks.reverse();
+            players.reverse();
+        }
+        csv.write_row(&GameCsvRow {
+            game,
+            seed,
+            board: &self.board,
+            decks: [&decks[0], &decks[1]],
+            players,
+            specials_used: self.specials_used,
+            outcome: &self.outcome,
+        })
+    }
 }
 
+impl Contestant {
+    /// The one who played `side` in a game where the player played `player_side`.
+    pub fn on_side(side: PlayerId, player_side: PlayerId) -> Contestant {
+        if side == player_side {
+            Contestant::Player
+        } else {
+            Contestant::Opponent
+        }
+    }
+}
+
+/// The side which the player plays in the `battle_index`-th battle. It alternates every battle
+/// so that the advantage of either side cancels out over an even number of battles.
+pub fn player_side(battle_index: u32) -> PlayerId {
+    match battle_index % 2 {
+        0 => PlayerId::South,
+        _ => PlayerId::North,
+    }
+}
+
+impl MatchResult {
+    pub fn count_wins(&self, contestant: Contestant) -> u32 {
+        self.games
+            .iter()
+            .filter(|game| game.winner == Some(contestant))
+            .count() as u32
+    }
+}
+
+/// Plays a best-of-`best_of` match between `player` and `opponent`. The first one who wins more
+/// than half of `best_of` games wins the match, and the rest of the games aren't played.
+/// If nobody does because of draws, the one who won more games wins.
+///
+/// The player plays the side given by `player_side` so that neither gets the advantage of a
+/// side. `inventories` are indexed by `PlayerId::to_index` as if the
+/// player were always South, and each game is prepared by `prepare_battle`.
+#[allow(clippy::too_many_arguments)]
+pub fn run_match(
+    context: &Arc<Context>,
+    board: &Board,
+    inventories: [&[Card]; 2],
+    player: &mut dyn Player,
+    opponent: &mut dyn Player,
+    base_seed: u64,
+    best_of: u32,
+) -> MatchResult {
+    let wins_needed = best_of / 2 + 1;
+    let mut result = MatchResult {
+        winner: None,
+        games: vec![],
+    };
+    for n in 0..best_of {
+        let (mut rng, [player_deck, opponent_deck]) =
+            prepare_battle(base_seed, n, inventories, player, opponent);
+        let player_side = player_side(n);
+        let (outcome, _) = run_with_script_on_side(
+            context,
+            board,
+            &player_deck,
+            &opponent_deck,
+            player,
+            opponent,
+            &mut rng,
+            player_side,
+            [DealPolicy::Shuffle; 2],
+            &Script::default(),
+            None,
+        )
+        .expect("An empty script never fails");
+        let view = outcome.view(player_side);
+        // The winner isn't decided by the scores if a player forfeited.
+        let winner = outcome
+            .get_winner()
+            .map(|side| Contestant::on_side(side, player_side));
+        result.games.push(MatchGame {
+            player_side,
+            scores: (view.get_my_score(), view.get_opponent_score()),
+            winner,
+        });
+
+        if let Some(winner) = winner {
+            if result.count_wins(winner) == wins_needed {
+                result.winner = Some(winner);
+                return result;
+            }
+        }
+    }
+    let (player_wins, opponent_wins) = (
+        result.count_wins(Contestant::Player),
+        result.count_wins(Contestant::Opponent),
+    );
+    result.winner = match player_wins.cmp(&opponent_wins) {
+        std::cmp::Ordering::Greater => Some(Contestant::Player),
+        std::cmp::Ordering::Less => Some(Contestant::Opponent),
+        std::cmp::Ordering::Equal => None,
+    };
+    result
+}
+
+/// Runs a game between `player` (South) and `opponent` (North).
+/// `deal_policies` is indexed by `PlayerId::to_index`.
+/// The returned outcome is named after the players' `get_name`.
+#[allow(clippy::too_many_arguments)]
 pub fn run(
-    context: &Context,
+    context: &Arc<Context>,
     board: &Board,
     player_deck: &[Card],
     opponent_deck: &[Card],
     player: &mut dyn Player,
     opponent: &mut dyn Player,
     rng: &mut Mt64,
-) -> (u32, u32) {
+    deal_policies: [DealPolicy; 2],
+) -> (GameOutcome, State) {
+    run_with_script(
+        context,
+        board,
+        player_deck,
+        opponent_deck,
+        player,
+        opponent,
+        rng,
+        deal_policies,
+        &Script::default(),
+        None,
+    )
+    .expect("An empty script never fails")
+}
+
+/// Same as `run` but also records the game as a `GameReplay`, e.g. to save it as JSON.
+#[allow(clippy::too_many_arguments)]
+pub fn run_with_replay(
+    context: &Arc<Context>,
+    board: &Board,
+    player_deck: &[Card],
+    opponent_deck: &[Card],
+    player: &mut dyn Player,
+    opponent: &mut dyn Player,
+    rng: &mut Mt64,
+    deal_policies: [DealPolicy; 2],
+) -> (GameReplay, State) {
+    let mut replay = Replay::new(board.clone());
+    let (_, state) = run_with_script(
+        context,
+        board,
+        player_deck,
+        opponent_deck,
+        player,
+        opponent,
+        rng,
+        deal_policies,
+        &Script::default(),
+        Some(&mut replay),
+    )
+    .expect("An empty script never fails");
+    let decks = [engine::to_ids(player_deck), engine::to_ids(opponent_deck)];
+    let game_replay =
+        GameReplay::record(context.rules, &replay, decks, state.get_forfeited_player())
+            .expect("Actions taken in the game are legal");
+    (game_replay, state)
+}
+
+/// Same as `run` but actions in `script` are taken instead of asking the players.
+/// Returns an error if a scripted action can't be taken.
+/// A player who takes an illegal action loses the game by forfeit immediately.
+/// Actions of both players are appended to `replay` if given.
+#[allow(clippy::too_many_arguments)]
+pub fn run_with_script(
+    context: &Arc<Context>,
+    board: &Board,
+    player_deck: &[Card],
+    opponent_deck: &[Card],
+    player: &mut dyn Player,
+    opponent: &mut dyn Player,
+    rng: &mut Mt64,
+    deal_policies: [DealPolicy; 2],
+    script: &Script,
+    mut replay: Option<&mut Replay>,
+) -> Result<(GameOutcome, State), String> {
     assert_eq!(engine::DECK_SIZE, player_deck.len());
     assert_eq!(engine::DECK_SIZE, opponent_deck.len());
 
     player.init_game(PlayerId::South, context, board, player_deck.to_vec());
     opponent.init_game(PlayerId::North, context, board, opponent_deck.to_vec());
 
-    let mut player_state = deal_hands(rng, player_deck, PlayerId::South, player);
-    let mut opponent_state = deal_hands(rng, opponent_deck, PlayerId::North, opponent);
+    let mut player_state = deal_hands(
+        rng,
+        player_deck,
+        PlayerId::South,
+        player,
+        deal_policies[PlayerId::South.to_index()],
+    );
+    let mut opponent_state = deal_hands(
+        rng,
+        opponent_deck,
+        PlayerId::North,
+        opponent,
+        deal_policies[PlayerId::North.to_index()],
+    );
 
     debug!("Player states initialized");
     debug!("player: {}\nopponent: {}", player_state, opponent_state);
-    let mut state = State::new(board.clone(), 0, 0, 0, vec![], vec![]);
+    let mut state = State::new_game(board.clone(), context.rules);
     for turn in 0..engine::TURN_COUNT {
         debug!("Starting Turn {}", turn + 1);
-        let player_action = player.get_action(&state, player_state.get_hands(), &Duration::MAX);
-        let opponent_action =
-            opponent.get_action(&state, opponent_state.get_hands(), &Duration::MAX);
+        let player_action = get_action(&state, &player_state, player, script)?;
+        let opponent_action = get_action(&state, &opponent_state, opponent, script)?;
 
         debug!("Original State: {}", state);
         debug!("Player state: {}", player_state);
@@ -84,9 +447,16 @@ pub fn run(
             println!("{}", opponent_action.get_consumed_card());
         }
 
-        engine::update_state(&mut state, &player_action, &opponent_action);
+        if let Err(violation) = engine::update_state(&mut state, &player_action, &opponent_action) {
+            warn!("Turn {}: {}", turn + 1, violation);
+            state.forfeit(violation.player_id);
+            break;
+        }
         engine::update_player_state(&state, &mut player_state, &player_action);
         engine::update_player_state(&state, &mut opponent_state, &opponent_action);
+        if let Some(replay) = replay.as_mut() {
+            replay.push(player_action, opponent_action);
+        }
 
         debug!("State is updated ->: {}", state);
         debug!("Player state: {}", player_state);
@@ -98,7 +468,649 @@ pub fn run(
             });
             stdin().read_line(&mut String::new()).unwrap();
         }
+        if state.is_end() {
+            break;
+        }
+    }
+
+    let outcome = GameOutcome::from_state(player.get_name(), opponent.get_name(), &state);
+    Ok((outcome, state))
+}
+
+/// Same as `run_with_script` but `player` plays `player_side`. The decks and the players are
+/// swapped if it's North, so the outcome is still by sides: use `GameOutcome::view` with
+/// `player_side` to see it from the player. `deal_policies` and `script` are for the sides, not
+/// for the players.
+#[allow(clippy::too_many_arguments)]
+pub fn run_with_script_on_side(
+    context: &Arc<Context>,
+    board: &Board,
+    player_deck: &[Card],
+    opponent_deck: &[Card],
+    player: &mut dyn Player,
+    opponent: &mut dyn Player,
+    rng: &mut Mt64,
+    player_side: PlayerId,
+    deal_policies: [DealPolicy; 2],
+    script: &Script,
+    replay: Option<&mut Replay>,
+) -> Result<(GameOutcome, State), String> {
+    match player_side {
+        PlayerId::South => run_with_script(
+            context,
+            board,
+            player_deck,
+            opponent_deck,
+            player,
+            opponent,
+            rng,
+            deal_policies,
+            script,
+            replay,
+        ),
+        PlayerId::North => run_with_script(
+            context,
+            board,
+            opponent_deck,
+            player_deck,
+            opponent,
+            player,
+            rng,
+            deal_policies,
+            script,
+            replay,
+        ),
+    }
+}
+
+fn get_action(
+    state: &State,
+    player_state: &PlayerCardState,
+    player: &mut dyn Player,
+    script: &Script,
+) -> Result<engine::Action, String> {
+    let player_id = player_state.get_player_id();
+    match script.get_action(state.get_turn() + 1, player_id) {
+        Some(action) => {
+            Script::check_action(state, player_state.get_hands(), player_id, action)?;
+            Ok(action.clone())
+        }
+        None => Ok(player.get_action(state, player_state.get_hands(), &Duration::MAX)),
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
 
-    state.board.get_scores()
+    use engine::Action;
+    use players::{
+        greedy::GreedyPlayer,
+        random::RandomPlayer,
+    };
+
+    use super::*;
+
+    /// Remembers turns where the wrapped player was asked for an action.
+    struct TurnRecorder {
+        player: RandomPlayer,
+        turns: Vec<i32>,
+    }
+
+    impl Player for TurnRecorder {
+        fn get_name(&self) -> &str {
+            self.player.get_name()
+        }
+
+        fn init_game(
+            &mut self,
+            player_id: PlayerId,
+            context: &Arc<Context>,
+            board: &Board,
+            deck: Vec<Card>,
+        ) {
+            self.player.init_game(player_id, context, board, deck);
+        }
+
+        fn need_redeal_hands(&mut self, dealed_cards: &[Card], time_limit: &Duration) -> bool {
+            self.player.need_redeal_hands(dealed_cards, time_limit)
+        }
+
+        fn get_action(&mut self, state: &State, hands: &[Card], time_limit: &Duration) -> Action {
+            self.turns.push(state.get_turn());
+            self.player.get_action(state, hands, time_limit)
+        }
+    }
+
+    /// Passes every turn and always (or never) asks for a redeal.
+    struct RedealingPlayer {
+        redeal: bool,
+    }
+
+    impl Player for RedealingPlayer {
+        fn get_name(&self) -> &str {
+            "redealing"
+        }
+
+        fn init_game(&mut self, _: PlayerId, _: &Arc<Context>, _: &Board, _: Vec<Card>) {}
+
+        fn need_redeal_hands(&mut self, _: &[Card], _: &Duration) -> bool {
+            self.redeal
+        }
+
+        fn get_action(&mut self, _: &State, hands: &[Card], _: &Duration) -> Action {
+            Action::Pass(hands[0].clone())
+        }
+    }
+
+    /// Passes every turn and remembers the sides it played.
+    struct SideRecorder {
+        sides: Vec<PlayerId>,
+    }
+
+    impl Player for SideRecorder {
+        fn get_name(&self) -> &str {
+            "side recorder"
+        }
+
+        fn init_game(&mut self, player_id: PlayerId, _: &Arc<Context>, _: &Board, _: Vec<Card>) {
+            self.sides.push(player_id);
+        }
+
+        fn need_redeal_hands(&mut self, _: &[Card], _: &Duration) -> bool {
+            false
+        }
+
+        fn get_action(&mut self, _: &State, hands: &[Card], _: &Duration) -> Action {
+            Action::Pass(hands[0].clone())
+        }
+    }
+
+    /// Always uses a special attack without special points, which is illegal.
+    struct IllegalPlayer {
+        player_id: PlayerId,
+    }
+
+    impl Player for IllegalPlayer {
+        fn get_name(&self) -> &str {
+            "illegal"
+        }
+
+        fn init_game(&mut self, player_id: PlayerId, _: &Arc<Context>, _: &Board, _: Vec<Card>) {
+            self.player_id = player_id;
+        }
+
+        fn need_redeal_hands(&mut self, _: &[Card], _: &Duration) -> bool {
+            false
+        }
+
+        fn get_action(&mut self, state: &State, hands: &[Card], _: &Duration) -> Action {
+            let mut actions = vec![];
+            engine::append_valid_actions(state, hands, self.player_id, &mut actions);
+            actions
+                .into_iter()
+                .find_map(|action| match action {
+                    Action::Put(card, position) => Some(Action::Special(card, position)),
+                    _ => None,
+                })
+                .expect("No cards can be put")
+        }
+    }
+
+    fn data_path(relative: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../data")
+            .join(relative)
+    }
+
+    fn new_test_context() -> Arc<Context> {
+        Arc::new(Context {
+            all_cards: engine::load_cards(data_path("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        })
+    }
+
+    #[test]
+    fn test_run_returns_final_state() {
+        let context = new_test_context();
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        let mut player = RandomPlayer::new("p".into(), 1);
+        let mut opponent = RandomPlayer::new("o".into(), 2);
+        let mut rng = Mt64::new(42);
+
+        let (outcome, state) = run(
+            &context,
+            &board,
+            &deck,
+            &deck,
+            &mut player,
+            &mut opponent,
+            &mut rng,
+            [DealPolicy::Shuffle; 2],
+        );
+
+        assert!(state.is_end());
+        assert_eq!(
+            state.board.get_scores(),
+            (outcome.south.score, outcome.north.score)
+        );
+        assert_eq!(("p", "o"), (&*outcome.south.name, &*outcome.north.name));
+        assert_eq!(engine::TURN_COUNT as u32, outcome.turns_played);
+    }
+
+    #[test]
+    fn test_run_with_replay_reproduces_scores() {
+        let context = new_test_context();
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        let new_players = || {
+            (
+                RandomPlayer::new("p".into(), 1),
+                RandomPlayer::new("o".into(), 2),
+            )
+        };
+
+        let (mut player, mut opponent) = new_players();
+        let (outcome, _) = run(
+            &context,
+            &board,
+            &deck,
+            &deck,
+            &mut player,
+            &mut opponent,
+            &mut Mt64::new(42),
+            [DealPolicy::Shuffle; 2],
+        );
+        let scores = (outcome.south.score, outcome.north.score);
+        // Recording doesn't change the game.
+        let (mut player, mut opponent) = new_players();
+        let (replay, state) = run_with_replay(
+            &context,
+            &board,
+            &deck,
+            &deck,
+            &mut player,
+            &mut opponent,
+            &mut Mt64::new(42),
+            [DealPolicy::Shuffle; 2],
+        );
+        assert_eq!(scores, state.get_scores());
+
+        let replay = GameReplay::from_json(&replay.to_json()).unwrap();
+        assert_eq!(engine::TURN_COUNT as usize, replay.turns.len());
+        assert_eq!(scores, replay.get_final_scores());
+        let states = replay.reconstruct(&context).unwrap();
+        assert_eq!(scores, states.last().unwrap().get_scores());
+        for (state, turn) in states.iter().zip(replay.turns.iter()) {
+            assert_eq!(turn.board, state.board.to_lines());
+        }
+    }
+
+    #[test]
+    fn test_run_forfeits_illegal_action() {
+        let context = new_test_context();
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        let mut player = RandomPlayer::new("p".into(), 1);
+        let mut opponent = IllegalPlayer {
+            player_id: PlayerId::South,
+        };
+
+        let (outcome, state) = run(
+            &context,
+            &board,
+            &deck,
+            &deck,
+            &mut player,
+            &mut opponent,
+            &mut Mt64::new(42),
+            [DealPolicy::Shuffle; 2],
+        );
+
+        // The game ends at the first turn without applying the actions.
+        assert!(state.is_end());
+        assert_eq!(0, state.get_turn());
+        assert_eq!(
+            board.get_scores(),
+            (outcome.south.score, outcome.north.score)
+        );
+        assert_eq!(Some(PlayerId::North), outcome.forfeit);
+        assert_eq!(Some(PlayerId::South), outcome.get_winner());
+    }
+
+    #[test]
+    fn test_battle_independent_of_prior_battles() {
+        let context = new_test_context();
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let inventory = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        let play_battle = |n: u32, player: &mut RandomPlayer, opponent: &mut RandomPlayer| {
+            let (mut rng, [player_deck, opponent_deck]) =
+                prepare_battle(42, n, [&inventory, &inventory], player, opponent);
+            let (_, state) = run(
+                &context,
+                &board,
+                &player_deck,
+                &opponent_deck,
+                player,
+                opponent,
+                &mut rng,
+                [DealPolicy::Shuffle; 2],
+            );
+            state
+        };
+
+        // Players are created with different seeds and play different numbers of battles
+        // before the 3rd battle.
+        let mut player = RandomPlayer::new("p".into(), 1);
+        let mut opponent = RandomPlayer::new("o".into(), 2);
+        for n in 0..3 {
+            play_battle(n, &mut player, &mut opponent);
+        }
+        let after_three = play_battle(3, &mut player, &mut opponent);
+
+        let mut player = RandomPlayer::new("p".into(), 3);
+        let mut opponent = RandomPlayer::new("o".into(), 4);
+        let alone = play_battle(3, &mut player, &mut opponent);
+
+        assert_eq!(after_three.board, alone.board);
+        assert_eq!(
+            after_three.get_consumed_cards(PlayerId::South),
+            alone.get_consumed_cards(PlayerId::South)
+        );
+        // Another battle is different.
+        assert_ne!(
+            after_three.board,
+            play_battle(4, &mut player, &mut opponent).board
+        );
+    }
+
+    #[test]
+    fn test_alternate_player_side() {
+        let context = new_test_context();
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        let mut player = SideRecorder {
+            sides: vec![],
+        };
+        let mut opponent = SideRecorder {
+            sides: vec![],
+        };
+        for n in 0..6 {
+            run_with_script_on_side(
+                &context,
+                &board,
+                &deck,
+                &deck,
+                &mut player,
+                &mut opponent,
+                &mut Mt64::new(n as u64),
+                player_side(n),
+                [DealPolicy::Shuffle; 2],
+                &Script::default(),
+                None,
+            )
+            .unwrap();
+        }
+
+        let count = |sides: &[PlayerId], side| sides.iter().filter(|s| **s == side).count();
+        for sides in [&player.sides, &opponent.sides] {
+            assert_eq!(3, count(sides, PlayerId::South));
+            assert_eq!(3, count(sides, PlayerId::North));
+        }
+        for (p, o) in player.sides.iter().zip(opponent.sides.iter()) {
+            assert_eq!(p.another(), *o);
+        }
+    }
+
+    #[test]
+    fn test_run_match_ends_early() {
+        let context = new_test_context();
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        let mut greedy = GreedyPlayer::new("greedy".into());
+        let mut passing = RedealingPlayer {
+            redeal: false,
+        };
+
+        // Best of 5: the greedy player, who wins every game, clinches after 3 games.
+        let result = run_match(
+            &context,
+            &board,
+            [&deck, &deck],
+            &mut greedy,
+            &mut passing,
+            42,
+            5,
+        );
+        assert_eq!(Some(Contestant::Player), result.winner);
+        assert_eq!(
+            vec![PlayerId::South, PlayerId::North, PlayerId::South],
+            result
+                .games
+                .iter()
+                .map(|game| game.player_side)
+                .collect::<Vec<_>>()
+        );
+        for game in result.games.iter() {
+            assert_eq!(Some(Contestant::Player), game.winner);
+            assert!(game.scores.0 > game.scores.1, "{:?}", game);
+        }
+
+        let result = run_match(
+            &context,
+            &board,
+            [&deck, &deck],
+            &mut passing,
+            &mut greedy,
+            42,
+            5,
+        );
+        assert_eq!(Some(Contestant::Opponent), result.winner);
+        assert_eq!(3, result.count_wins(Contestant::Opponent));
+        assert_eq!(3, result.games.len());
+    }
+
+    #[test]
+    fn test_deal_hands_as_listed() {
+        let context = new_test_context();
+        let deck_ids = engine::load_deck(&data_path("decks/starter"));
+        let deck = context.get_cards(&deck_ids);
+        let mut player = RandomPlayer::new("p".into(), 1);
+        let mut rng = Mt64::new(42);
+
+        // The random player may ask for a redeal, which must not change the hands.
+        for _ in 0..10 {
+            let cards = deal_hands(
+                &mut rng,
+                &deck,
+                PlayerId::South,
+                &mut player,
+                DealPolicy::AsListed,
+            );
+
+            let mut expected_hands = deck[0..engine::HAND_SIZE].to_vec();
+            engine::sort_by_id(&mut expected_hands);
+            let mut hands = cards.get_hands().to_vec();
+            engine::sort_by_id(&mut hands);
+            assert_eq!(engine::to_ids(&expected_hands), engine::to_ids(&hands));
+            assert_eq!(
+                engine::to_ids(&deck[engine::HAND_SIZE..]),
+                engine::to_ids(cards.get_deck())
+            );
+        }
+    }
+
+    #[test]
+    fn test_deal_hands_by_indices() {
+        let context = new_test_context();
+        let deck_ids = engine::load_deck(&data_path("decks/starter"));
+        let deck = context.get_cards(&deck_ids);
+        let mut player = RandomPlayer::new("p".into(), 1);
+        let mut rng = Mt64::new(42);
+
+        let cards = deal_hands(
+            &mut rng,
+            &deck,
+            PlayerId::South,
+            &mut player,
+            DealPolicy::Indices([14, 0, 7, 3]),
+        );
+        let mut hands = engine::to_ids(cards.get_hands());
+        hands.sort();
+        let mut expected_hands = vec![deck_ids[14], deck_ids[0], deck_ids[7], deck_ids[3]];
+        expected_hands.sort();
+        assert_eq!(expected_hands, hands);
+        let expected_deck: Vec<u32> = deck_ids
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| ![14, 0, 7, 3].contains(i))
+            .map(|(_, id)| *id)
+            .collect();
+        assert_eq!(expected_deck, engine::to_ids(cards.get_deck()));
+
+        let cards = deal_hands(
+            &mut rng,
+            &deck,
+            PlayerId::South,
+            &mut player,
+            DealPolicy::Bottom,
+        );
+        let mut hands = engine::to_ids(cards.get_hands());
+        hands.sort();
+        let mut expected_hands = deck_ids[deck_ids.len() - engine::HAND_SIZE..].to_vec();
+        expected_hands.sort();
+        assert_eq!(expected_hands, hands);
+        assert_eq!(
+            &deck_ids[..deck_ids.len() - engine::HAND_SIZE],
+            engine::to_ids(cards.get_deck())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Index is dealt twice: 3")]
+    fn test_deal_hands_by_duplicated_indices() {
+        let context = new_test_context();
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        deal_hands(
+            &mut Mt64::new(42),
+            &deck,
+            PlayerId::South,
+            &mut RandomPlayer::new("p".into(), 1),
+            DealPolicy::Indices([3, 1, 3, 2]),
+        );
+    }
+
+    #[test]
+    fn test_run_with_script() {
+        let context = new_test_context();
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        let script = Script::parse(&context, "1 south put 6 4 20 up\n2 south pass 13\n").unwrap();
+        let mut player = TurnRecorder {
+            player: RandomPlayer::new("p".into(), 1),
+            turns: vec![],
+        };
+        let mut opponent = RandomPlayer::new("o".into(), 2);
+        let mut rng = Mt64::new(42);
+        let mut replay = Replay::new(board.clone());
+
+        let (_, state) = run_with_script(
+            &context,
+            &board,
+            &deck,
+            &deck,
+            &mut player,
+            &mut opponent,
+            &mut rng,
+            [DealPolicy::AsListed, DealPolicy::Shuffle],
+            &script,
+            Some(&mut replay),
+        )
+        .unwrap();
+
+        assert!(state.is_end());
+        assert_eq!(&[6, 13], &state.get_consumed_cards(PlayerId::South)[0..2]);
+        assert_eq!((2..engine::TURN_COUNT).collect::<Vec<i32>>(), player.turns);
+        assert_eq!(
+            state.board,
+            replay
+                .get_state_after(engine::TURN_COUNT as usize)
+                .unwrap()
+                .board
+        );
+    }
+
+    #[test]
+    fn test_run_with_invalid_script() {
+        let context = new_test_context();
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        let mut player = RandomPlayer::new("p".into(), 1);
+        let mut opponent = RandomPlayer::new("o".into(), 2);
+
+        for (text, expected) in [
+            // The card isn't dealt.
+            ("1 south pass 6\n2 south pass 34", "Turn 2:"),
+            // Too far from the player's ink.
+            ("1 south put 6 4 2 up", "Turn 1:"),
+        ] {
+            let script = Script::parse(&context, text).unwrap();
+            let err = run_with_script(
+                &context,
+                &board,
+                &deck,
+                &deck,
+                &mut player,
+                &mut opponent,
+                &mut Mt64::new(42),
+                [DealPolicy::AsListed; 2],
+                &script,
+                None,
+            )
+            .unwrap_err();
+            assert!(err.starts_with(expected), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_played_battle_csv_row() {
+        let context = new_test_context();
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        let (player_deck, opponent_deck) = (&deck[0..1], &deck[1..2]);
+        let mut csv = GameCsvWriter::new(vec![]).unwrap();
+        for player_side in [PlayerId::South, PlayerId::North] {
+            let outcome = GameOutcome::new(
+                engine::NamedScore::new("s", 1),
+                engine::NamedScore::new("n", 2),
+                12,
+            );
+            PlayedBattle::new(&Replay::new(board.clone()), player_side, outcome)
+                .write_csv_row(
+                    &mut csv,
+                    0,
+                    battle_seed(42, 0),
+                    [player_deck, opponent_deck],
+                    ["p", "o"],
+                )
+                .unwrap();
+        }
+
+        let output = String::from_utf8(csv.get_output().clone()).unwrap();
+        let rows: Vec<Vec<&str>> = output
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').collect())
+            .collect();
+        let (player_id, opponent_id) = (
+            player_deck[0].get_id().to_string(),
+            opponent_deck[0].get_id().to_string(),
+        );
+        // Decks and players are written in the order of the sides.
+        assert_eq!([player_id.as_str(), &opponent_id, "p", "o"], rows[0][3..7]);
+        assert_eq!([opponent_id.as_str(), &player_id, "o", "p"], rows[1][3..7]);
+        assert_eq!(battle_seed(42, 0).to_string(), rows[0][1]);
+    }
 }
@@ -1,8 +1,16 @@
-use std::path::PathBuf;
+use std::{
+    fmt::Display,
+    fs::File,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+};
 
 use clap::{
     self,
+    Args,
     Parser,
+    Subcommand,
     ValueHint,
 };
 use log::*;
@@ -12,20 +20,51 @@ use engine::{
     Board,
     Card,
     Context,
+    DataDirArgs,
+    GameCsvWriter,
+    GameData,
+    Replay,
+    RunConfig,
+};
+use local::{
+    board_selector::{
+        BoardBreakdown,
+        BoardSelector,
+    },
+    hands::HandPrinter,
+    replay_viewer,
+    script::Script,
+    synergy::{
+        ActionRecorder,
+        SynergyMatrix,
+    },
+    Contestant,
+    DealPolicy,
+    PlayedBattle,
 };
 use players::{
+    human::HumanPlayer,
     Player,
     PlayerType,
 };
-use rand::seq::SliceRandom;
 use rand_mt::Mt64;
 
+// Use fixed seed for reproducible results.
+const SEED: u64 = 0x42;
+
 #[derive(Parser)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct AppArgs {
+    #[clap(subcommand)]
+    command: Option<Commands>,
+
     /// a directory path where holds all card data. no need to specify for many cases.
     #[clap(long, value_parser, default_value_t = String::from("data/cards"))]
     card_dir: String,
 
+    #[clap(flatten)]
+    data: DataDirArgs,
+
     /// a file path to a board file. the selected board is used for games/training.
     #[clap(
         long,
@@ -38,23 +77,35 @@ pub struct AppArgs {
     #[clap(long, short, value_parser, default_value_t = false)]
     step_execution: bool,
 
+    /// `human` to type actions by hand, or one of the players the other tools take
+    /// (e.g. `random`, `mcts-100`, or `mcts-100:1.2` for MCTS with the UCT constant 1.2).
     #[clap(long, value_parser, default_value = "random")]
-    player: PlayerType,
+    player: LocalPlayerType,
 
+    /// Same as `--player`.
     #[clap(long, value_parser, default_value = "random")]
-    opponent: PlayerType,
+    opponent: LocalPlayerType,
 
+    /// The number of battles. The player plays South in even-numbered battles and North in
+    /// odd-numbered ones, so use an even number to cancel out the advantage of a side.
     #[clap(long, short = 'c', value_parser, default_value_t = 1)]
     play_cnt: u32,
 
+    /// Play a best-of-N match instead of `--play-cnt` battles. The match ends as soon as either
+    /// player wins more than half of N games. The player and the opponent swap their sides
+    /// every game.
+    #[clap(long, value_parser)]
+    best_of: Option<u32>,
+
     /// List of cards which the player can choose for their deck. See data/decks/starter for an example.
     #[clap(
         short,
         long,
         value_parser,
         value_hint=ValueHint::FilePath,
+        required = true
     )]
-    player_deck_path: PathBuf,
+    player_deck_path: Option<PathBuf>,
 
     /// List of cards which the opponnt can choose for their deck. See data/decks/starter for an example.
     #[clap(
@@ -62,8 +113,135 @@ pub struct AppArgs {
         long,
         value_parser,
         value_hint=ValueHint::FilePath,
+        required = true
     )]
-    opponent_deck_path: PathBuf,
+    opponent_deck_path: Option<PathBuf>,
+
+    /// Print the final boards of the first N battles (all battles if N is omitted).
+    #[clap(long, value_parser, min_values = 0, require_equals = true)]
+    dump_final_boards: Option<Option<u32>>,
+
+    /// Show top N pairs of the player's cards which are played together in won games.
+    #[clap(long, value_parser)]
+    synergy_pairs: Option<usize>,
+
+    /// a file listing actions forced at specific turns of every battle, e.g. `1 south put 6 4 20 up`.
+    /// players choose their own actions once the script is exhausted. See `local::script::Script`.
+    #[clap(long, value_parser, value_hint=ValueHint::FilePath)]
+    script: Option<PathBuf>,
+
+    /// a directory where replays of battles are saved as `battle_<N>.txt`.
+    /// use `replay_diff` to compare replays of different players.
+    #[clap(long, value_parser, value_hint=ValueHint::DirPath)]
+    replay_dir: Option<PathBuf>,
+
+    /// a directory of boards. a board is randomly picked from them for each battle and
+    /// results are reported for each board. `--board-path` is ignored if specified.
+    #[clap(long, value_parser, value_hint=ValueHint::DirPath)]
+    random_board_dir: Option<PathBuf>,
+
+    /// Print hands of both players at the beginning of every turn.
+    #[clap(long, value_parser, default_value_t = false)]
+    show_hands: bool,
+
+    /// End a game right after a turn where both players pass.
+    #[clap(long, value_parser, default_value_t = false)]
+    end_on_double_pass: bool,
+
+    /// Special points which South and North have at the beginning of games. A handicap for boards
+    /// which favor one of the sides. They're given to the sides, not to the player and the
+    /// opponent, who swap their sides every battle.
+    #[clap(long, value_parser, number_of_values = 2, value_names = &["SOUTH", "NORTH"])]
+    starting_special_points: Option<Vec<i32>>,
+
+    /// Offsets added to the scores of South and North. Like `--starting-special-points`, they're
+    /// given to the sides rather than the player and the opponent.
+    #[clap(
+        long,
+        value_parser,
+        number_of_values = 2,
+        value_names = &["SOUTH", "NORTH"],
+        allow_hyphen_values = true
+    )]
+    score_offsets: Option<Vec<i32>>,
+
+    /// The maximum number of copies of a card in a deck. Deck files may list a card with a count
+    /// like `42 x3`.
+    #[clap(long, value_parser, default_value_t = 1)]
+    max_card_copies: u32,
+
+    /// a file path where a CSV row is written for each battle.
+    #[clap(long, value_parser, value_hint=ValueHint::FilePath)]
+    csv: Option<PathBuf>,
+}
+
+impl AppArgs {
+    // Deck paths are required unless a subcommand is given.
+    fn get_player_deck_path(&self) -> &PathBuf {
+        self.player_deck_path.as_ref().expect("--player-deck-path is required")
+    }
+
+    fn get_opponent_deck_path(&self) -> &PathBuf {
+        self.opponent_deck_path.as_ref().expect("--opponent-deck-path is required")
+    }
+}
+
+/// Players the local runner can play with. Only the local runner offers a human player since the
+/// other tools play too many games for a human to type their actions.
+#[derive(Clone, Debug)]
+enum LocalPlayerType {
+    /// Reads actions from stdin.
+    Human,
+    Ai(PlayerType),
+}
+
+impl LocalPlayerType {
+    fn create_player(&self, context: &Context, seed: u64) -> Box<dyn Player> {
+        match self {
+            LocalPlayerType::Human => Box::new(HumanPlayer::new("human".into())),
+            LocalPlayerType::Ai(player_type) => player_type.create_player(context, seed),
+        }
+    }
+}
+
+impl FromStr for LocalPlayerType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(LocalPlayerType::Human),
+            _ => s.parse().map(LocalPlayerType::Ai),
+        }
+    }
+}
+
+impl Display for LocalPlayerType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LocalPlayerType::Human => write!(f, "human"),
+            LocalPlayerType::Ai(player_type) => player_type.fmt(f),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Step through a game saved as a `GameReplay` JSON file instead of playing battles.
+    Replay(ReplayArgs),
+}
+
+#[derive(Args)]
+struct ReplayArgs {
+    /// a directory path where holds all card data. no need to specify for many cases.
+    #[clap(long, value_parser, default_value_t = String::from("data/cards"))]
+    card_dir: String,
+
+    /// Wait for the enter key after each turn.
+    #[clap(long, short, value_parser, default_value_t = false)]
+    step_execution: bool,
+
+    #[clap(value_parser, value_hint=ValueHint::FilePath)]
+    replay: PathBuf,
 }
 
 fn main() {
@@ -73,75 +251,316 @@ fn main() {
     );
 
     let args = AppArgs::parse();
+    if let Some(Commands::Replay(replay_args)) = &args.command {
+        view_replay(replay_args);
+        return;
+    }
 
-    let all_cards = engine::load_cards(&args.card_dir);
-    let board = engine::load_board(&args.board_path);
-
-    let context = Context {
-        all_cards,
-        enabled_step_execution: args.step_execution,
+    let data: Option<GameData> = args.data.load_or_exit();
+    let (mut context, board) = match &data {
+        Some(data) => (data.context.clone(), data.resolve_board(&args.board_path)),
+        None => (
+            Context {
+                all_cards: engine::load_cards(&args.card_dir).unwrap_or_else(|e| {
+                    error!("Failed to load cards: {}", e);
+                    std::process::exit(1);
+                }),
+                enabled_step_execution: false,
+                rules: engine::RuleSet::default(),
+            },
+            engine::load_board(&args.board_path),
+        ),
     };
+    context.enabled_step_execution = args.step_execution;
+    context.rules.end_on_double_pass = args.end_on_double_pass;
+    if let Some(points) = &args.starting_special_points {
+        context.rules.starting_special_points = [points[0], points[1]];
+    }
+    if let Some(offsets) = &args.score_offsets {
+        context.rules.score_offsets = [offsets[0], offsets[1]];
+    }
+    context.rules.max_card_copies = args.max_card_copies;
+    if let Err(e) = context.validate_board(&board) {
+        error!("Invalid board: {}", e);
+        std::process::exit(1);
+    }
+    info!("Config: {}", run_config(&args, &board));
+    let context = Arc::new(context);
 
-    // Use fixed seed for reproducible results.
-    let mut rng = Mt64::new(0x42);
+    let mut rng = Mt64::new(SEED);
 
     let mut player = args.player.create_player(&context, rng.next_u64());
     let mut opponent = args.opponent.create_player(&context, rng.next_u64());
 
-    run_battles(&context, &board, &mut *player, &mut *opponent, args);
+    if let Some(best_of) = args.best_of {
+        run_match(
+            &context,
+            &board,
+            data.as_ref(),
+            &mut *player,
+            &mut *opponent,
+            &args,
+            best_of,
+        );
+        return;
+    }
+    run_battles(
+        &context,
+        &board,
+        data.as_ref(),
+        &mut *player,
+        &mut *opponent,
+        args,
+    );
+}
+
+fn view_replay(args: &ReplayArgs) {
+    let context = Context {
+        all_cards: engine::load_cards(&args.card_dir).unwrap_or_else(|e| {
+            error!("Failed to load cards: {}", e);
+            std::process::exit(1);
+        }),
+        enabled_step_execution: false,
+        rules: engine::RuleSet::default(),
+    };
+    let result = replay_viewer::load_game_replay(&args.replay).and_then(|replay| {
+        replay_viewer::print_replay(
+            &context,
+            &replay,
+            &mut std::io::stdout(),
+            args.step_execution,
+        )
+    });
+    if let Err(e) = result {
+        error!("Failed to replay {:?}: {}", args.replay, e);
+        std::process::exit(1);
+    }
+}
+
+fn run_config(args: &AppArgs, board: &Board) -> RunConfig {
+    let mut config = RunConfig::new("local", SEED)
+        .with_board(board.get_name())
+        .with_deck(args.get_player_deck_path().display())
+        .with_deck(args.get_opponent_deck_path().display())
+        .with_player(&args.player)
+        .with_player(&args.opponent)
+        .with_param("play_cnt", args.play_cnt)
+        .with_param("step_execution", args.step_execution)
+        .with_param("show_hands", args.show_hands)
+        .with_param("end_on_double_pass", args.end_on_double_pass)
+        .with_param("max_card_copies", args.max_card_copies);
+    if let Some(best_of) = args.best_of {
+        config = config.with_param("best_of", best_of);
+    }
+    if let Some(data_dir) = &args.data.data_dir {
+        config = config.with_param("data_dir", data_dir.display());
+    }
+    if let Some(script) = &args.script {
+        config = config.with_param("script", script.display());
+    }
+    if let Some(replay_dir) = &args.replay_dir {
+        config = config.with_param("replay_dir", replay_dir.display());
+    }
+    if let Some(random_board_dir) = &args.random_board_dir {
+        config = config.with_param("random_board_dir", random_board_dir.display());
+    }
+    if let Some(points) = &args.starting_special_points {
+        config = config.with_param("starting_special_points", format!("{:?}", points));
+    }
+    if let Some(offsets) = &args.score_offsets {
+        config = config.with_param("score_offsets", format!("{:?}", offsets));
+    }
+    if let Some(csv) = &args.csv {
+        config = config.with_param("csv", csv.display());
+    }
+    config
+}
+
+fn load_deck(context: &Context, data: Option<&GameData>, deck_path: &PathBuf) -> Vec<u32> {
+    let ids = match data {
+        Some(data) => data.resolve_deck(deck_path),
+        None => engine::load_deck(deck_path),
+    };
+    context.validate_deck(&ids).unwrap_or_else(|e| {
+        error!("Invalid deck {:?}: {}", deck_path, e);
+        std::process::exit(1);
+    });
+    ids
+}
+
+fn run_match(
+    context: &Arc<Context>,
+    board: &Board,
+    data: Option<&GameData>,
+    player: &mut dyn Player,
+    opponent: &mut dyn Player,
+    args: &AppArgs,
+    best_of: u32,
+) {
+    let player_inventory_cards: Vec<Card> =
+        context.get_cards(&load_deck(context, data, args.get_player_deck_path()));
+    let opponent_inventory_cards: Vec<Card> =
+        context.get_cards(&load_deck(context, data, args.get_opponent_deck_path()));
+
+    let result = local::run_match(
+        context,
+        board,
+        [&player_inventory_cards, &opponent_inventory_cards],
+        player,
+        opponent,
+        SEED,
+        best_of,
+    );
+    for (n, game) in result.games.iter().enumerate() {
+        info!(
+            "Game #{}: player ({}): {}, opponent: {}, winner: {:?}",
+            n, game.player_side, game.scores.0, game.scores.1, game.winner
+        );
+    }
+    info!(
+        "Match winner: {:?} ({} - {})",
+        result.winner,
+        result.count_wins(Contestant::Player),
+        result.count_wins(Contestant::Opponent)
+    );
 }
 
 pub fn run_battles(
-    context: &Context,
+    context: &Arc<Context>,
     board: &Board,
+    data: Option<&GameData>,
     player: &mut dyn Player,
     opponent: &mut dyn Player,
     args: AppArgs,
 ) {
     let play_cnt: u32 = args.play_cnt;
-    let player_deck_path: PathBuf = args.player_deck_path;
-    let opponent_deck_path: PathBuf = args.opponent_deck_path;
+    let player_deck_path: PathBuf = args.get_player_deck_path().clone();
+    let opponent_deck_path: PathBuf = args.get_opponent_deck_path().clone();
+    let dump_final_boards: Option<Option<u32>> = args.dump_final_boards;
+    let synergy_pairs: Option<usize> = args.synergy_pairs;
+    let replay_dir: Option<PathBuf> = args.replay_dir;
+    let script = match &args.script {
+        Some(path) => Script::load(context, path).unwrap_or_else(|e| {
+            error!("Failed to load the script: {}", e);
+            std::process::exit(1);
+        }),
+        None => Script::default(),
+    };
+    let mut csv = args.csv.as_ref().map(|path| {
+        File::create(path)
+            .and_then(GameCsvWriter::new)
+            .unwrap_or_else(|e| {
+                error!("Failed to create the CSV file {:?}: {}", path, e);
+                std::process::exit(1);
+            })
+    });
+    let mut board_selector = args.random_board_dir.as_ref().map(|dir| {
+        BoardSelector::load(context, dir, SEED).unwrap_or_else(|e| {
+            error!("Failed to load boards: {}", e);
+            std::process::exit(1);
+        })
+    });
 
-    // Use fixed seed for reproducible results.
-    let mut rng = Mt64::new(0x42);
+    let player_inventory_cards: Vec<Card> =
+        context.get_cards(&load_deck(context, data, &player_deck_path));
+    let opponent_inventory_cards: Vec<Card> =
+        context.get_cards(&load_deck(context, data, &opponent_deck_path));
 
-    let mut player_inventory_cards: Vec<Card> =
-        context.get_cards(&engine::load_deck(&player_deck_path));
-    let mut opponent_inventory_cards: Vec<Card> =
-        context.get_cards(&engine::load_deck(&opponent_deck_path));
+    let (mut player_printer, mut opponent_printer);
+    let (player, opponent): (&mut dyn Player, &mut dyn Player) = if args.show_hands {
+        player_printer = HandPrinter::new(player, std::io::stdout());
+        opponent_printer = HandPrinter::new(opponent, std::io::stdout());
+        (&mut player_printer, &mut opponent_printer)
+    } else {
+        (player, opponent)
+    };
 
     let mut player_won_cnt = 0;
     let mut opponent_won_cnt = 0;
     let mut draw_cnt = 0;
+    let mut player = ActionRecorder::new(player);
+    let mut synergy_matrix = SynergyMatrix::new();
+    let mut breakdown = BoardBreakdown::new();
     for n in 0..play_cnt {
-        let (player_deck, _) = player_inventory_cards.partial_shuffle(&mut rng, engine::DECK_SIZE);
-        let (opponent_deck, _) =
-            opponent_inventory_cards.partial_shuffle(&mut rng, engine::DECK_SIZE);
+        let board = match board_selector.as_mut() {
+            Some(selector) => selector.next_board(),
+            None => board,
+        };
+        // Each battle is seeded by its index so that it doesn't depend on the preceding ones.
+        let (mut rng, [player_deck, opponent_deck]) = local::prepare_battle(
+            SEED,
+            n,
+            [&player_inventory_cards, &opponent_inventory_cards],
+            &mut player,
+            opponent,
+        );
 
-        let (p, o) = local::run(
+        let player_side = local::player_side(n);
+        let mut replay = Replay::new(board.clone());
+        let (outcome, state) = local::run_with_script_on_side(
             context,
             board,
-            player_deck,
-            opponent_deck,
-            player,
+            &player_deck,
+            &opponent_deck,
+            &mut player,
             opponent,
             &mut rng,
-        );
-        match p.cmp(&o) {
-            std::cmp::Ordering::Less => {
+            player_side,
+            [DealPolicy::Shuffle; 2],
+            &script,
+            Some(&mut replay),
+        )
+        .unwrap_or_else(|e| {
+            error!("Battle #{} failed: {}", n, e);
+            std::process::exit(1);
+        });
+        if let Some(dir) = &replay_dir {
+            let path = dir.join(format!("battle_{}.txt", n));
+            if let Err(e) = std::fs::write(&path, replay.to_string()) {
+                error!("Failed to save the replay {:?}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        if let Some(limit) = dump_final_boards {
+            if limit.is_none_or(|limit| n < limit) {
+                info!("Final board of battle #{}:\n{:#}", n, state);
+            }
+        }
+        // The winner isn't decided by the scores if a player forfeited.
+        let winner = outcome
+            .get_winner()
+            .map(|side| Contestant::on_side(side, player_side));
+        match winner {
+            Some(Contestant::Opponent) => {
                 debug!("Opponent win!");
                 opponent_won_cnt += 1;
             }
-            std::cmp::Ordering::Equal => {
+            None => {
                 debug!("Draw");
                 draw_cnt += 1;
             }
-            std::cmp::Ordering::Greater => {
+            Some(Contestant::Player) => {
                 debug!("Player win!");
                 player_won_cnt += 1;
             }
         }
-        info!("Battle #{}. {} v.s. {} ", n, p, o);
+        synergy_matrix.push_game(player.get_played_cards(), winner == Some(Contestant::Player));
+        breakdown.push(board, winner);
+        info!("Battle #{} (player: {}). {}", n, player_side, outcome);
+        if let Some(csv) = csv.as_mut() {
+            let battle = PlayedBattle::new(&replay, player_side, outcome);
+            let result = battle.write_csv_row(
+                csv,
+                n,
+                local::battle_seed(SEED, n),
+                [&player_deck, &opponent_deck],
+                [&args.player.to_string(), &args.opponent.to_string()],
+            );
+            if let Err(e) = result {
+                error!("Failed to write a CSV row: {}", e);
+                std::process::exit(1);
+            }
+        }
         print_rate(player_won_cnt, opponent_won_cnt, draw_cnt);
     }
 
@@ -150,8 +569,25 @@ pub fn run_battles(
         "Used decks: p: {:?}, o: {:?}",
         &player_deck_path, &opponent_deck_path
     );
-    info!("Board: {}", board.get_name());
+    match &board_selector {
+        Some(_) => info!("Results for each board:\n{}", breakdown),
+        None => info!("Board: {}", board.get_name()),
+    }
     print_rate(player_won_cnt, opponent_won_cnt, draw_cnt);
+
+    if let Some(n) = synergy_pairs {
+        info!("Top {} synergistic pairs of the player's cards:", n);
+        for pair in synergy_matrix.top_pairs(n) {
+            info!(
+                "    {} + {}: played together: {:.3}, win rate: {:.3}, correlation: {:.3}",
+                context.get_card(pair.cards.0).fmt_short(),
+                context.get_card(pair.cards.1).fmt_short(),
+                pair.co_occurrence,
+                pair.win_rate,
+                pair.win_correlation
+            );
+        }
+    }
 }
 
 fn print_rate(p_cnt: usize, o_cnt: usize, draw_cnt: usize) {
@@ -162,3 +598,40 @@ fn print_rate(p_cnt: usize, o_cnt: usize, draw_cnt: usize) {
     info!("Opponent won cnt: {} ({:.3})", o_cnt, opponent_won_ratio);
     info!("Draw cnt: {}", draw_cnt);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_config() {
+        let args = AppArgs::parse_from([
+            "local",
+            "--player-deck-path",
+            "data/decks/starter",
+            "--opponent-deck-path",
+            "data/decks/starter",
+            "--play-cnt",
+            "3",
+        ]);
+        let board =
+            engine::load_board_from_lines(String::from("test_board"), &["###", "#P#", "###"]);
+
+        let line = run_config(&args, &board).to_string();
+        assert!(line.contains(&format!("\"seed\":{}", SEED)), "{}", line);
+        assert!(line.contains("\"board\":\"test_board\""), "{}", line);
+        assert!(line.contains("\"play_cnt\":\"3\""), "{}", line);
+    }
+
+    #[test]
+    fn test_replay_command() {
+        let args = AppArgs::parse_from(["local", "replay", "--step-execution", "game.json"]);
+        match args.command {
+            Some(Commands::Replay(replay_args)) => {
+                assert_eq!(PathBuf::from("game.json"), replay_args.replay);
+                assert!(replay_args.step_execution);
+            }
+            None => panic!("The replay command isn't parsed"),
+        }
+    }
+}
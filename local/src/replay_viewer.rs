@@ -0,0 +1,120 @@
+use std::{
+    io::{
+        stdin,
+        Write,
+    },
+    path::Path,
+};
+
+use engine::{
+    Context,
+    GameReplay,
+    State,
+};
+
+/// Loads a `GameReplay` saved as JSON, e.g. by `run_with_replay`.
+pub fn load_game_replay(path: &Path) -> Result<GameReplay, String> {
+    let json = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read the replay {:?}: {}", path, e))?;
+    GameReplay::from_json(&json)
+}
+
+/// Writes the actions of both players and the state after each turn of `replay` to `out`.
+///
+/// States are reconstructed by playing the recorded actions with the engine rather than read
+/// from the replay, and it fails if the engine doesn't reproduce a recorded state.
+/// Waits for the enter key after each turn if `step_execution` is set.
+/// Returns the state at the end of the replay.
+pub fn print_replay(
+    context: &Context,
+    replay: &GameReplay,
+    out: &mut impl Write,
+    step_execution: bool,
+) -> Result<State, String> {
+    let write_error = |e: std::io::Error| format!("Failed to write the replay: {}", e);
+    let states = replay.reconstruct(context)?;
+    let initial_state = State::new_game(replay.get_initial_board(), replay.rules);
+    writeln!(out, "{}", initial_state).map_err(write_error)?;
+
+    for (turn, (record, state)) in replay.turns.iter().zip(states.iter()).enumerate() {
+        let [south, north] = &record.actions;
+        writeln!(out, "Turn {}", turn + 1).map_err(write_error)?;
+        writeln!(out, "South action: {}", south).map_err(write_error)?;
+        writeln!(out, "North action: {}", north).map_err(write_error)?;
+        writeln!(out, "{}", state).map_err(write_error)?;
+        if step_execution {
+            println!(
+                "Turn {} has finished. Press enter key to continue",
+                turn + 1
+            );
+            stdin().read_line(&mut String::new()).map_err(write_error)?;
+        }
+    }
+    if let Some(player_id) = replay.forfeit {
+        writeln!(out, "{} forfeited the game", player_id).map_err(write_error)?;
+    }
+    let (south, north) = replay.get_final_scores();
+    writeln!(out, "Scores: South: {}, North: {}", south, north).map_err(write_error)?;
+    Ok(states.into_iter().last().unwrap_or(initial_state))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::Arc,
+    };
+
+    use players::random::RandomPlayer;
+    use rand_mt::Mt64;
+
+    use super::*;
+    use crate::DealPolicy;
+
+    fn data_path(relative: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../data")
+            .join(relative)
+    }
+
+    #[test]
+    fn test_print_replay() {
+        let context = Arc::new(Context {
+            all_cards: engine::load_cards(data_path("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        });
+        let board = engine::load_board(&data_path("boards/massugu_street"));
+        let deck = context.get_cards(&engine::load_deck(&data_path("decks/starter")));
+        let (recorded, state) = crate::run_with_replay(
+            &context,
+            &board,
+            &deck,
+            &deck,
+            &mut RandomPlayer::new("p".into(), 1),
+            &mut RandomPlayer::new("o".into(), 2),
+            &mut Mt64::new(42),
+            [DealPolicy::Shuffle; 2],
+        );
+        let path =
+            std::env::temp_dir().join(format!("takoyaki-replay-{}.json", std::process::id()));
+        std::fs::write(&path, recorded.to_json()).unwrap();
+
+        let replay = load_game_replay(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut out = vec![];
+        let last = print_replay(&context, &replay, &mut out, false).unwrap();
+        assert_eq!(state.get_scores(), last.get_scores());
+        assert_eq!(replay.get_final_scores(), last.get_scores());
+
+        let output = String::from_utf8(out).unwrap();
+        let turn_cnt = engine::TURN_COUNT as usize;
+        assert_eq!(turn_cnt, output.matches("South action: ").count());
+        assert_eq!(turn_cnt, output.matches("North action: ").count());
+
+        // A replay which the engine doesn't reproduce is rejected.
+        let mut tampered = replay;
+        tampered.turns[0].special_points[0] += 1;
+        assert!(print_replay(&context, &tampered, &mut vec![], false).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+};
+
+use engine::{
+    Action,
+    Card,
+    Context,
+    PlayerId,
+    State,
+};
+
+/// Actions forced on players at specific turns, e.g. to study a particular opening.
+///
+/// Each line of a script is parsed by `engine::parse_turn_action`. Empty lines and lines
+/// starting with `#` are ignored. For example:
+///
+/// ```text
+/// # Both players open with their first card.
+/// 1 south put 6 10 20 up
+/// 1 north put 6 10 4 down
+/// 2 south pass 13
+/// ```
+///
+/// A player is asked for its own action once the script has nothing for the turn.
+/// A saved `engine::Replay` is also a script which reproduces the game.
+#[derive(Clone, Debug, Default)]
+pub struct Script {
+    actions: HashMap<(i32, PlayerId), Action>,
+}
+
+impl Script {
+    pub fn load(context: &Context, path: &Path) -> Result<Script, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read the script {:?}: {}", path, e))?;
+        Script::parse(context, &text)
+    }
+
+    pub fn parse(context: &Context, text: &str) -> Result<Script, String> {
+        let mut actions = HashMap::new();
+        for (line_index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |msg: String| format!("Line {} ({:?}): {}", line_index + 1, line, msg);
+            let (turn, player_id, action) =
+                engine::parse_turn_action(context, line).map_err(error)?;
+            if actions.insert((turn, player_id), action).is_some() {
+                return Err(error(format!(
+                    "Turn {} of {} is scripted twice",
+                    turn, player_id
+                )));
+            }
+        }
+        Ok(Script {
+            actions,
+        })
+    }
+
+    /// Returns the scripted action of `player_id` at `turn`, which starts from 1.
+    pub fn get_action(&self, turn: i32, player_id: PlayerId) -> Option<&Action> {
+        self.actions.get(&(turn, player_id))
+    }
+
+    /// Checks that the scripted action can be taken at the state with the hands.
+    pub fn check_action(
+        state: &State,
+        hands: &[Card],
+        player_id: PlayerId,
+        action: &Action,
+    ) -> Result<(), String> {
+        let turn = state.get_turn() + 1;
+        let card_id = action.get_consumed_card().get_id();
+        if !hands.iter().any(|card| card.get_id() == card_id) {
+            return Err(format!(
+                "Turn {}: the scripted action of {} uses a card not in the hands: {}\nHands: {}",
+                turn,
+                player_id,
+                action,
+                engine::format_cards(hands)
+            ));
+        }
+        if !engine::is_valid_action(state, player_id, action) {
+            return Err(format!(
+                "Turn {}: the scripted action of {} is invalid: {}\nState: {}",
+                turn, player_id, action, state
+            ));
+        }
+        Ok(())
+    }
+}
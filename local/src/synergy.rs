@@ -0,0 +1,207 @@
+use std::{
+    collections::{
+        BTreeSet,
+        HashMap,
+    },
+    sync::Arc,
+    time::Duration,
+};
+
+use engine::{
+    Action,
+    Board,
+    Card,
+    Context,
+    PlayerId,
+    State,
+};
+use players::{
+    Player,
+    SearchInfo,
+};
+
+/// A player which remembers cards played (not passed) by the wrapped player in the current game.
+pub struct ActionRecorder<'p> {
+    player: &'p mut dyn Player,
+    played_cards: Vec<u32>,
+}
+
+impl<'p> ActionRecorder<'p> {
+    pub fn new(player: &'p mut dyn Player) -> Self {
+        ActionRecorder {
+            player,
+            played_cards: vec![],
+        }
+    }
+
+    pub fn get_played_cards(&self) -> &[u32] {
+        &self.played_cards
+    }
+}
+
+impl<'p> Player for ActionRecorder<'p> {
+    fn get_name(&self) -> &str {
+        self.player.get_name()
+    }
+
+    fn init_game(
+        &mut self,
+        player_id: PlayerId,
+        context: &Arc<Context>,
+        board: &Board,
+        deck: Vec<Card>,
+    ) {
+        self.played_cards.clear();
+        self.player.init_game(player_id, context, board, deck);
+    }
+
+    fn need_redeal_hands(&mut self, dealed_cards: &[Card], time_limit: &Duration) -> bool {
+        self.player.need_redeal_hands(dealed_cards, time_limit)
+    }
+
+    fn get_action(&mut self, state: &State, hands: &[Card], time_limit: &Duration) -> Action {
+        let action = self.player.get_action(state, hands, time_limit);
+        if !action.is_pass() {
+            self.played_cards.push(action.get_consumed_card().get_id());
+        }
+        action
+    }
+
+    fn get_last_search_info(&self) -> Option<SearchInfo> {
+        self.player.get_last_search_info()
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.player.reseed(seed)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct PairCount {
+    games: u32,
+    wins: u32,
+}
+
+/// How a pair of cards played in a same game relates to winning.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PairSynergy {
+    /// Card IDs of the pair. `cards.0 < cards.1`.
+    pub cards: (u32, u32),
+
+    /// Fraction of games where both cards were played.
+    pub co_occurrence: f64,
+
+    /// Win rate of games where both cards were played.
+    pub win_rate: f64,
+
+    /// Phi coefficient between "both cards were played" and "won".
+    /// Positive values mean that the pair is played more often in won games.
+    pub win_correlation: f64,
+}
+
+/// Accumulates which pairs of cards are played together in each game and the results.
+#[derive(Clone, Debug, Default)]
+pub struct SynergyMatrix {
+    games: u32,
+    wins: u32,
+    pairs: HashMap<(u32, u32), PairCount>,
+}
+
+impl SynergyMatrix {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_game(&mut self, played_cards: &[u32], won: bool) {
+        self.games += 1;
+        if won {
+            self.wins += 1;
+        }
+        let played: BTreeSet<u32> = played_cards.iter().copied().collect();
+        for (i, a) in played.iter().enumerate() {
+            for b in played.iter().skip(i + 1) {
+                let count = self.pairs.entry((*a, *b)).or_default();
+                count.games += 1;
+                if won {
+                    count.wins += 1;
+                }
+            }
+        }
+    }
+
+    pub fn get_synergy(&self, a: u32, b: u32) -> PairSynergy {
+        let cards = if a < b { (a, b) } else { (b, a) };
+        let count = self.pairs.get(&cards).copied().unwrap_or_default();
+        self.calc_synergy(cards, count)
+    }
+
+    /// Returns at most `n` pairs ordered by their win correlation.
+    pub fn top_pairs(&self, n: usize) -> Vec<PairSynergy> {
+        let mut synergies: Vec<PairSynergy> = self
+            .pairs
+            .iter()
+            .map(|(cards, count)| self.calc_synergy(*cards, *count))
+            .collect();
+        synergies.sort_by(|a, b| {
+            b.win_correlation
+                .total_cmp(&a.win_correlation)
+                .then(a.cards.cmp(&b.cards))
+        });
+        synergies.truncate(n);
+        synergies
+    }
+
+    fn calc_synergy(&self, cards: (u32, u32), count: PairCount) -> PairSynergy {
+        let ratio = |a: u32, b: u32| if b == 0 { 0.0 } else { a as f64 / b as f64 };
+
+        // 2x2 contingency table of (pair played, won).
+        let n = self.games as f64;
+        let n11 = count.wins as f64;
+        let pair_games = count.games as f64;
+        let wins = self.wins as f64;
+        let denominator = (pair_games * (n - pair_games) * wins * (n - wins)).sqrt();
+        let win_correlation = if denominator == 0.0 {
+            0.0
+        } else {
+            (n * n11 - pair_games * wins) / denominator
+        };
+
+        PairSynergy {
+            cards,
+            co_occurrence: ratio(count.games, self.games),
+            win_rate: ratio(count.wins, count.games),
+            win_correlation,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_always_played_pair() {
+        let mut matrix = SynergyMatrix::new();
+        matrix.push_game(&[1, 2, 3], true);
+        matrix.push_game(&[2, 1, 4], false);
+        matrix.push_game(&[1, 5, 2, 3], true);
+        matrix.push_game(&[4, 2, 1], false);
+
+        let pair = matrix.get_synergy(2, 1);
+        assert_eq!((1, 2), pair.cards);
+        assert_eq!(1.0, pair.co_occurrence);
+        assert_eq!(0.5, pair.win_rate);
+        // It doesn't tell anything about winning since it's always played.
+        assert_eq!(0.0, pair.win_correlation);
+        assert!(matrix
+            .top_pairs(usize::MAX)
+            .iter()
+            .all(|p| p.co_occurrence <= pair.co_occurrence));
+
+        // (1, 3) is played only in won games.
+        let top = matrix.top_pairs(1);
+        assert_eq!(1, top.len());
+        assert_eq!((1, 3), top[0].cards);
+        assert_eq!(1.0, top[0].win_correlation);
+    }
+}
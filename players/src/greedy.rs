@@ -0,0 +1,138 @@
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use engine::{
+    Action,
+    Board,
+    Card,
+    Context,
+    PlayerId,
+    State,
+};
+
+use crate::{
+    utils::append_valid_actions,
+    Player,
+};
+
+/// A player which takes the action inking the most cells for itself in the current turn,
+/// assuming the opponent passes. Ties are broken by the smallest card to save larger ones.
+pub struct GreedyPlayer {
+    player_id: PlayerId,
+    name: String,
+}
+
+impl GreedyPlayer {
+    pub fn new(name: String) -> Self {
+        GreedyPlayer {
+            player_id: PlayerId::South,
+            name,
+        }
+    }
+
+    /// Cells controlled by the player after taking `action` while the opponent passes.
+    fn evaluate(&self, state: &State, action: &Action, opponent_pass: &Action) -> u32 {
+        let mut state = state.clone();
+        let (south_action, north_action) = match self.player_id {
+            PlayerId::South => (action, opponent_pass),
+            PlayerId::North => (opponent_pass, action),
+        };
+        engine::update_state(&mut state, south_action, north_action)
+            .expect("Only valid actions are evaluated");
+        state
+            .board
+            .get_score_breakdown()
+            .get_controlled_cells(self.player_id)
+    }
+}
+
+impl Player for GreedyPlayer {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn init_game(
+        &mut self,
+        player_id: PlayerId,
+        _context: &Arc<Context>,
+        _board: &Board,
+        _deck: Vec<Card>,
+    ) {
+        self.player_id = player_id;
+    }
+
+    fn need_redeal_hands(&mut self, _dealed_cards: &[Card], _time_limit: &Duration) -> bool {
+        false
+    }
+
+    fn get_action(&mut self, state: &State, hands: &[Card], _time_limit: &Duration) -> Action {
+        let mut actions = vec![];
+        append_valid_actions(state, hands, self.player_id, &mut actions);
+        // Passing is always valid and doesn't change the board.
+        let opponent_pass = Action::Pass(hands[0].clone());
+        actions
+            .into_iter()
+            .max_by_key(|action| {
+                (
+                    self.evaluate(state, action, &opponent_pass),
+                    -action.get_consumed_card().get_cell_count(),
+                )
+            })
+            .expect("Passing is always valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use engine::test_support::new_test_card;
+
+    use super::*;
+
+    #[test]
+    fn test_prefers_larger_coverage() {
+        let small = new_test_card(1, &["="]);
+        let large = new_test_card(2, &["==="]);
+        let context = Arc::new(Context {
+            all_cards: HashMap::from([(1, small.clone()), (2, large.clone())]),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        });
+        #[rustfmt::skip]
+        let board = engine::load_board_from_lines(
+            String::from("test_board"),
+            &[
+            "#######",
+            "#O....#",
+            "#.....#",
+            "#....P#",
+            "#######",
+            ]);
+        let state = State::new(board.clone(), 0, 0, 0, vec![], vec![]);
+        let hands = [small.clone(), large.clone()];
+
+        let mut player = GreedyPlayer::new("greedy".into());
+        player.init_game(PlayerId::South, &context, &board, hands.to_vec());
+        let action = player.get_action(&state, &hands, &Duration::MAX);
+        assert!(
+            matches!(action, Action::Put(ref card, _) if *card == large),
+            "{}",
+            action
+        );
+
+        // Passes with the smallest card if no card can be put.
+        let state = State::new(
+            engine::load_board_from_lines(String::from("full"), &["###", "#P#", "###"]),
+            0,
+            0,
+            0,
+            vec![],
+            vec![],
+        );
+        let action = player.get_action(&state, &[large, small.clone()], &Duration::MAX);
+        assert_eq!(Action::Pass(small), action);
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    io::{
+        self,
+        BufRead,
+        Write,
+    },
+    sync::Arc,
+    time::Duration,
+};
+
+use log::*;
+
+use engine::{
+    Action,
+    Board,
+    Card,
+    CardPosition,
+    Context,
+    PlayerId,
+    State,
+};
+
+use crate::Player;
+
+const MOVE_USAGE: &str = "pass <card_id> | put <card_id> <x> <y> <up|right|down|left> [special]";
+
+/// A player which shows the board and its hands on stdout and reads moves from stdin.
+/// Useful for trying decks by hand.
+pub struct HumanPlayer {
+    player_id: PlayerId,
+    name: String,
+}
+
+impl HumanPlayer {
+    pub fn new(name: String) -> Self {
+        HumanPlayer {
+            player_id: PlayerId::South,
+            name,
+        }
+    }
+
+    /// Reads a line from stdin after showing `prompt`. Returns `None` if stdin is closed.
+    fn read_line(&self, prompt: &str) -> Option<String> {
+        print!("{} > ", prompt);
+        io::stdout().flush().expect("Failed to flush stdout");
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(line),
+            Err(e) => {
+                warn!("Failed to read stdin: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Parses a move typed by a human:
+///
+/// - `pass <card_id>`
+/// - `put <card_id> <x> <y> <rotation> [special]`
+///
+/// e.g. `put 42 3 20 left special`. Keywords and rotations are case-insensitive.
+/// The card must be in `hands`. Whether the action can be taken isn't checked.
+pub fn parse_move(hands: &[Card], text: &str) -> Result<Action, String> {
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let (kind, args) = match tokens.split_first() {
+        Some((kind, args)) => (kind.to_ascii_lowercase(), args),
+        None => return Err("Empty move".into()),
+    };
+    let find_card = |card_id: &str| -> Result<Card, String> {
+        let card_id: u32 = card_id
+            .parse()
+            .map_err(|e| format!("Invalid card ID {:?}: {}", card_id, e))?;
+        hands
+            .iter()
+            .find(|card| card.get_id() == card_id)
+            .cloned()
+            .ok_or_else(|| format!("The card {} isn't in the hands", card_id))
+    };
+    let parse_coord = |s: &str| -> Result<i32, String> {
+        s.parse()
+            .map_err(|e| format!("Invalid coordinate {:?}: {}", s, e))
+    };
+    match (kind.as_str(), args) {
+        ("pass", [card_id]) => Ok(Action::Pass(find_card(card_id)?)),
+        ("put", [card_id, x, y, rotation, rest @ ..]) => {
+            let special = match rest {
+                [] => false,
+                [flag] if flag.eq_ignore_ascii_case("special") => true,
+                _ => return Err(format!("Unexpected arguments: {:?}", rest.join(" "))),
+            };
+            let card = find_card(card_id)?;
+            let position = CardPosition {
+                x: parse_coord(x)?,
+                y: parse_coord(y)?,
+                rotation: rotation.parse()?,
+            };
+            if special {
+                Ok(Action::Special(card, position))
+            } else {
+                Ok(Action::Put(card, position))
+            }
+        }
+        _ => Err(format!(
+            "Expected `{}` but got {:?}",
+            MOVE_USAGE,
+            text.trim()
+        )),
+    }
+}
+
+impl Player for HumanPlayer {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn init_game(
+        &mut self,
+        player_id: PlayerId,
+        _context: &Arc<Context>,
+        board: &Board,
+        _deck: Vec<Card>,
+    ) {
+        self.player_id = player_id;
+        println!("You are {}.", player_id);
+        println!("{}", board);
+    }
+
+    fn need_redeal_hands(&mut self, dealed_cards: &[Card], _time_limit: &Duration) -> bool {
+        for card in dealed_cards {
+            println!("{}", card);
+        }
+        let answer = self.read_line("Redeal the hands? [y/N]");
+        answer.is_some_and(|answer| answer.trim().eq_ignore_ascii_case("y"))
+    }
+
+    fn get_action(&mut self, state: &State, hands: &[Card], _time_limit: &Duration) -> Action {
+        println!("{}", state);
+        for card in hands {
+            println!("{}", card);
+        }
+        println!("Enter a move: {}", MOVE_USAGE);
+        loop {
+            let line = match self.read_line(&self.player_id.to_string()) {
+                Some(line) => line,
+                None => {
+                    warn!("stdin is closed. Passing with the first card");
+                    return Action::Pass(hands[0].clone());
+                }
+            };
+            let action = match parse_move(hands, &line) {
+                Ok(action) => action,
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            };
+            match engine::check_action(state, self.player_id, &action) {
+                Ok(()) => return action,
+                Err(reason) => println!("The action can't be taken: {}", reason),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use engine::Rotation;
+
+    use super::*;
+
+    fn new_test_card(id: u32) -> Card {
+        engine::load_card_from_lines(id, format!("card {}", id), 2, 1, &["==".to_string()])
+    }
+
+    #[test]
+    fn test_parse_move() {
+        let hands = [new_test_card(6), new_test_card(13)];
+        let position = CardPosition {
+            x: 3,
+            y: -1,
+            rotation: Rotation::Left,
+        };
+
+        assert_eq!(
+            Ok(Action::Pass(hands[1].clone())),
+            parse_move(&hands, "pass 13\n")
+        );
+        assert_eq!(
+            Ok(Action::Put(hands[0].clone(), position)),
+            parse_move(&hands, "put 6 3 -1 left")
+        );
+        assert_eq!(
+            Ok(Action::Special(hands[0].clone(), position)),
+            parse_move(&hands, "  PUT 6 3 -1 Left SPECIAL ")
+        );
+
+        for (text, error) in [
+            ("", "Empty move"),
+            ("pass 22", "The card 22 isn't in the hands"),
+            ("pass x", "Invalid card ID"),
+            ("put 6 3 -1", "Expected `pass"),
+            ("put 6 3 y left", "Invalid coordinate"),
+            ("put 6 3 -1 sideways", "Unknown rotation"),
+            ("put 6 3 -1 left twice", "Unexpected arguments"),
+            ("special 6 3 -1 left", "Expected `pass"),
+        ] {
+            let actual = parse_move(&hands, text).unwrap_err();
+            assert!(actual.starts_with(error), "{:?}: {}", text, actual);
+        }
+    }
+}
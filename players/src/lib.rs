@@ -1,4 +1,7 @@
+pub mod greedy;
+pub mod human;
 pub mod mcts;
+pub mod minimax;
 pub mod random;
 pub mod utils;
 
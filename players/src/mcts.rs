@@ -7,11 +7,22 @@ use rand::{
     RngCore,
     SeedableRng,
 };
+use serde::Serialize;
 
 use std::{
     cmp::Ordering,
-    collections::HashMap,
+    collections::{
+        HashMap,
+        HashSet,
+    },
     fmt::Display,
+    sync::{
+        atomic::{
+            self,
+            AtomicUsize,
+        },
+        Arc,
+    },
     time::{
         Duration,
         Instant,
@@ -21,6 +32,7 @@ use wyhash::WyRng;
 
 use engine::{
     Action,
+    ActionIndex,
     Board,
     Card,
     Context,
@@ -29,40 +41,337 @@ use engine::{
     State,
 };
 
-use crate::utils::choose_random_action;
+use crate::utils::choose_random_action_with_index;
 
 use super::{
-    utils::append_valid_actions,
+    Candidate,
     Player,
+    SearchInfo,
 };
 
 // It looks good enough acording to random battles.
 pub const UCT_CONST_DEFAULT: f64 = 0.9;
 
+/// The maximum number of redealt hands considered when searching whether we should redeal
+/// the initial hands or not.
+pub const REDEAL_SAMPLES_DEFAULT: usize = 128;
+
+/// Progressive widening of the redeal chance node: a node visited `n` times deals one of
+/// `ceil(REDEAL_WIDENING_CONST * n^REDEAL_WIDENING_EXPONENT)` sampled hands, so that hands are
+/// sampled only as the node is visited enough to evaluate them.
+const REDEAL_WIDENING_CONST: f64 = 2.0;
+const REDEAL_WIDENING_EXPONENT: f64 = 0.5;
+
+/// What the search maximizes.
+#[derive(clap::ArgEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RewardKind {
+    /// The average score difference. A bigger win is better.
+    #[default]
+    Margin,
+
+    /// The average of +1/0/-1 for win/draw/lose. A narrow win is as good as a blowout.
+    WinLoss,
+}
+
+/// The number of tree nodes which searches sharing the budget can expand in total.
+///
+/// Clones share the counter so that trees searched in parallel stay under the cap together.
+/// A search returns its nodes to the budget when it finishes. Root nodes are not counted.
+#[derive(Clone, Debug)]
+pub struct NodeBudget {
+    cap: usize,
+    used: Arc<AtomicUsize>,
+}
+
+impl NodeBudget {
+    pub fn new(cap: usize) -> Self {
+        NodeBudget {
+            cap,
+            used: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn get_cap(&self) -> usize {
+        self.cap
+    }
+
+    /// The number of nodes currently held by all searches sharing the budget.
+    pub fn get_used(&self) -> usize {
+        self.used.load(atomic::Ordering::SeqCst)
+    }
+
+    /// Takes a node from the budget. Returns false if the budget is exhausted.
+    fn try_acquire(&self) -> bool {
+        self.used
+            .fetch_update(atomic::Ordering::SeqCst, atomic::Ordering::SeqCst, |used| {
+                (used < self.cap).then_some(used + 1)
+            })
+            .is_ok()
+    }
+
+    fn release(&self, count: usize) {
+        self.used.fetch_sub(count, atomic::Ordering::SeqCst);
+    }
+}
+
 pub struct MctsPlayer {
-    iterations: usize,
+    /// The number of determinizations sampled for each search.
+    determinizations: usize,
+    iterations_per_determinization: usize,
     uct_const: f64,
+    redeal_samples: usize,
+    reward_kind: RewardKind,
+    profile: bool,
+    export_tree: bool,
+    node_budget: Option<NodeBudget>,
+    reuse_tree: bool,
+    transpositions: bool,
+    threads: usize,
 
     name: String,
     player_id: PlayerId,
     traverser: Option<Traverser>,
+    /// Trees searched in parallel with `traverser` if `threads` is more than 1.
+    helper_traversers: Vec<Traverser>,
     board: Option<Board>,
     rng: WyRng,
 }
 
 impl MctsPlayer {
-    pub fn new(name: String, seed: u64, iterations: usize, uct_constant: f64) -> Self {
+    pub fn new(name: String, seed: u64, determinizations: usize, uct_constant: f64) -> Self {
         let rng = WyRng::seed_from_u64(seed);
         MctsPlayer {
             name,
-            iterations,
+            determinizations,
+            iterations_per_determinization: 1,
             uct_const: uct_constant,
+            redeal_samples: REDEAL_SAMPLES_DEFAULT,
+            reward_kind: RewardKind::default(),
+            profile: false,
+            export_tree: false,
+            node_budget: None,
+            reuse_tree: false,
+            transpositions: false,
+            threads: 1,
             player_id: PlayerId::South,
             traverser: None,
+            helper_traversers: vec![],
             board: None,
             rng,
         }
     }
+
+    pub fn with_redeal_samples(mut self, redeal_samples: usize) -> Self {
+        assert_gt!(redeal_samples, 0);
+        self.redeal_samples = redeal_samples;
+        self
+    }
+
+    /// Runs `iterations_per_determinization` iterations for each determinization.
+    /// The total number of iterations for each search becomes
+    /// `determinizations * iterations_per_determinization`.
+    pub fn with_iterations_per_determinization(
+        mut self,
+        iterations_per_determinization: usize,
+    ) -> Self {
+        assert_gt!(iterations_per_determinization, 0);
+        self.iterations_per_determinization = iterations_per_determinization;
+        self
+    }
+
+    pub fn with_reward_kind(mut self, reward_kind: RewardKind) -> Self {
+        self.reward_kind = reward_kind;
+        self
+    }
+
+    /// Stops expanding trees while `node_budget` is exhausted. Leaves are played out without
+    /// being expanded instead. Share a budget between players to cap their total memory usage.
+    pub fn with_node_budget(mut self, node_budget: NodeBudget) -> Self {
+        self.node_budget = Some(node_budget);
+        self
+    }
+
+    /// Keeps the tree of a search and starts the next search from its subtree reached by the
+    /// actions actually played, so that its statistics are reused.
+    /// Nodes of the kept tree stay taken from the node budget between searches.
+    pub fn with_tree_reuse(mut self, reuse_tree: bool) -> Self {
+        self.reuse_tree = reuse_tree;
+        self
+    }
+
+    /// Shares statistics between nodes which reach the same state by different orders of
+    /// actions, e.g. cards put in a different order. UCB1 values of such nodes are computed from
+    /// the merged statistics while their visit counts are kept separately.
+    pub fn with_transpositions(mut self, transpositions: bool) -> Self {
+        self.transpositions = transpositions;
+        self
+    }
+
+    /// Searches `threads` independent trees in parallel for each action, each with its own
+    /// determinizations, and chooses the action most visited in total (root parallelization).
+    /// Each tree runs as many iterations as a single tree would.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        assert_gt!(threads, 0);
+        self.threads = threads;
+        self
+    }
+
+    /// Measures time spent in each phase of searches and logs a summary for each search.
+    pub fn with_profile(mut self, profile: bool) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Keeps a copy of the tree of each search for debugging, see `get_last_tree`, and logs it
+    /// as JSON. Copying the whole tree slows down searches.
+    pub fn with_tree_export(mut self, export_tree: bool) -> Self {
+        self.export_tree = export_tree;
+        self
+    }
+
+    /// Returns the tree of the last `get_action` search if the tree export is enabled.
+    /// Only the tree of the first thread is kept when searching in parallel.
+    pub fn get_last_tree(&self) -> Option<&ExportedNode> {
+        self.traverser.as_ref()?.last_exported_tree.as_ref()
+    }
+
+    /// Returns the time spent in each phase of the last search if profiling is enabled.
+    pub fn get_last_profile(&self) -> Option<&PhaseProfile> {
+        self.traverser.as_ref()?.last_profile.as_ref()
+    }
+
+    /// Reports how widely the last `get_action` search visited the root's legal actions.
+    /// Returns `None` if no action has been searched in the current game yet.
+    pub fn get_search_coverage(&self, visit_threshold: i32) -> Option<SearchCoverage> {
+        let traverser = self.traverser.as_ref()?;
+        let (visits, legal_action_count) = traverser.last_root_visits.as_ref()?;
+        Some(SearchCoverage::new(
+            visits,
+            *legal_action_count,
+            visit_threshold,
+        ))
+    }
+}
+
+/// Time spent in each phase of a search.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PhaseProfile {
+    pub determinization: Duration,
+    pub selection: Duration,
+    pub expansion: Duration,
+    pub playout: Duration,
+    pub backpropagation: Duration,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Phase {
+    Determinization,
+    Selection,
+    Expansion,
+    Playout,
+    Backpropagation,
+}
+
+impl PhaseProfile {
+    pub fn total(&self) -> Duration {
+        self.determinization + self.selection + self.expansion + self.playout + self.backpropagation
+    }
+
+    fn get_mut(&mut self, phase: Phase) -> &mut Duration {
+        match phase {
+            Phase::Determinization => &mut self.determinization,
+            Phase::Selection => &mut self.selection,
+            Phase::Expansion => &mut self.expansion,
+            Phase::Playout => &mut self.playout,
+            Phase::Backpropagation => &mut self.backpropagation,
+        }
+    }
+}
+
+impl Display for PhaseProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total = self.total().as_secs_f64();
+        let ratio = |d: Duration| {
+            if total > 0.0 {
+                d.as_secs_f64() / total * 100.0
+            } else {
+                0.0
+            }
+        };
+        write!(
+            f,
+            "Profile(total: {:?}, determinization: {:?} ({:.1}%), selection: {:?} ({:.1}%), expansion: {:?} ({:.1}%), playout: {:?} ({:.1}%), backpropagation: {:?} ({:.1}%))",
+            self.total(),
+            self.determinization,
+            ratio(self.determinization),
+            self.selection,
+            ratio(self.selection),
+            self.expansion,
+            ratio(self.expansion),
+            self.playout,
+            ratio(self.playout),
+            self.backpropagation,
+            ratio(self.backpropagation),
+        )
+    }
+}
+
+/// Diagnostic about how the visits of a search are distributed over the root's legal actions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchCoverage {
+    pub legal_action_count: usize,
+
+    /// Fraction of the legal actions visited more than the threshold.
+    pub well_visited_fraction: f64,
+
+    /// Shannon entropy (in nats) of the visit distribution over the root's children.
+    pub visit_entropy: f64,
+
+    /// Number of legal actions which have never been expanded.
+    pub unexpanded_count: usize,
+}
+
+impl SearchCoverage {
+    /// `visits` contains visit counts of the expanded children of the root.
+    pub fn new(visits: &[i32], legal_action_count: usize, visit_threshold: i32) -> Self {
+        assert_le!(visits.len(), legal_action_count);
+        let well_visited = visits.iter().filter(|v| **v > visit_threshold).count();
+        let well_visited_fraction = if legal_action_count == 0 {
+            0.0
+        } else {
+            well_visited as f64 / legal_action_count as f64
+        };
+
+        let total: i32 = visits.iter().sum();
+        let visit_entropy = visits
+            .iter()
+            .filter(|v| **v > 0)
+            .map(|v| {
+                let p = *v as f64 / total as f64;
+                -p * p.ln()
+            })
+            .sum();
+
+        SearchCoverage {
+            legal_action_count,
+            well_visited_fraction,
+            visit_entropy,
+            unexpanded_count: legal_action_count - visits.len(),
+        }
+    }
+}
+
+impl Display for SearchCoverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Coverage(legal: {}, well visited: {:.1}%, entropy: {:.3}, unexpanded: {})",
+            self.legal_action_count,
+            self.well_visited_fraction * 100.0,
+            self.visit_entropy,
+            self.unexpanded_count
+        )
+    }
 }
 
 impl Player for MctsPlayer {
@@ -73,18 +382,39 @@ impl Player for MctsPlayer {
     fn init_game(
         &mut self,
         player_id: PlayerId,
-        context: &Context,
+        context: &Arc<Context>,
         board: &Board,
         deck: Vec<Card>,
     ) {
         self.player_id = player_id;
-        self.traverser = Some(Traverser::new(
-            context,
-            player_id,
-            deck,
-            self.uct_const,
-            self.rng.next_u64(),
-        ));
+        info!(
+            "{} starts a game with the UCT constant: {}",
+            self.name, self.uct_const
+        );
+        let action_index = ActionIndex::new(board, context.all_cards.values());
+        let mut traversers: Vec<Traverser> = (0..self.threads)
+            .map(|_| {
+                let mut traverser = Traverser::new(
+                    context.clone(),
+                    player_id,
+                    deck.clone(),
+                    self.uct_const,
+                    self.redeal_samples,
+                    self.rng.next_u64(),
+                );
+                traverser.enable_profile(self.profile);
+                traverser.export_tree = self.export_tree;
+                traverser.iterations_per_determinization = self.iterations_per_determinization;
+                traverser.reward_kind = self.reward_kind;
+                traverser.node_budget = self.node_budget.clone();
+                traverser.reuse_tree = self.reuse_tree;
+                traverser.transpositions = self.transpositions.then(HashMap::new);
+                traverser.action_index = action_index.clone();
+                traverser
+            })
+            .collect();
+        self.traverser = Some(traversers.remove(0));
+        self.helper_traversers = traversers;
         self.board = Some(board.clone());
     }
 
@@ -93,17 +423,91 @@ impl Player for MctsPlayer {
         self.traverser.as_mut().unwrap().search_need_redeal_hands(
             self.board.as_ref().unwrap(),
             dealed_cards,
-            self.iterations,
+            self.determinizations,
             time_limit,
         )
     }
 
     fn get_action(&mut self, state: &State, hands: &[Card], time_limit: &Duration) -> Action {
-        self.traverser
-            .as_mut()
-            .unwrap()
-            .search_action(state, hands, self.iterations, time_limit)
+        let traverser = self.traverser.as_mut().unwrap();
+        let determinizations = self.determinizations;
+        if self.helper_traversers.is_empty() {
+            return traverser.search_action(state, hands, determinizations, time_limit);
+        }
+        let action = std::thread::scope(|scope| {
+            for helper in self.helper_traversers.iter_mut() {
+                scope.spawn(move || {
+                    helper.search_action(state, hands, determinizations, time_limit)
+                });
+            }
+            traverser.search_action(state, hands, determinizations, time_limit)
+        });
+
+        let trees = std::iter::once(&*traverser)
+            .chain(self.helper_traversers.iter())
+            .filter_map(|t| t.last_search_info.as_ref())
+            .map(|info| info.candidates.as_slice());
+        let candidates = merge_candidates(trees);
+        let info = traverser.last_search_info.as_mut().unwrap();
+        match candidates.first() {
+            Some(best) => {
+                let action = best.action.clone();
+                // The principal variation is the one of the first tree.
+                info.candidates = candidates;
+                action
+            }
+            // No iteration has finished in any tree.
+            None => action,
+        }
+    }
+
+    fn get_last_search_info(&self) -> Option<SearchInfo> {
+        self.traverser.as_ref()?.last_search_info.clone()
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        // The traverser is seeded by `rng` in `init_game`.
+        self.rng = WyRng::seed_from_u64(seed);
+    }
+}
+
+/// Merges candidates of trees searched independently for a same state. Visits of a same action
+/// are summed up and its expected values are averaged weighted by the visits.
+/// The most visited candidate comes first.
+fn merge_candidates<'a>(trees: impl IntoIterator<Item = &'a [Candidate]>) -> Vec<Candidate> {
+    let mut merged: Vec<(Candidate, f64)> = vec![];
+    let mut indices: HashMap<Action, usize> = HashMap::new();
+    for candidate in trees.into_iter().flatten() {
+        let index = *indices.entry(candidate.action.clone()).or_insert_with(|| {
+            merged.push((
+                Candidate {
+                    action: candidate.action.clone(),
+                    visits: 0,
+                    expected_value: 0.0,
+                },
+                0.0,
+            ));
+            merged.len() - 1
+        });
+        let (merged_candidate, value_sum) = &mut merged[index];
+        merged_candidate.visits += candidate.visits;
+        *value_sum += candidate.expected_value * candidate.visits as f64;
     }
+    let mut candidates: Vec<Candidate> = merged
+        .into_iter()
+        .map(|(mut candidate, value_sum)| {
+            if candidate.visits > 0 {
+                candidate.expected_value = value_sum / candidate.visits as f64;
+            }
+            candidate
+        })
+        .collect();
+    candidates.sort_by(|a, b| {
+        b.visits
+            .cmp(&a.visits)
+            .then(b.expected_value.total_cmp(&a.expected_value))
+    });
+    candidates
 }
 
 #[derive(Clone, Default, Debug, PartialEq, Eq)]
@@ -128,9 +532,11 @@ impl Statistic {
         }
     }
 
-    fn get_expected_value(&self) -> f64 {
-        self.score_diff as f64 / self.total_cnt as f64
-        //(self.win_cnt - self.lose_cnt) as f64 / self.total_cnt as f64
+    fn get_expected_value(&self, reward_kind: RewardKind) -> f64 {
+        match reward_kind {
+            RewardKind::Margin => self.score_diff as f64 / self.total_cnt as f64,
+            RewardKind::WinLoss => (self.win_cnt - self.lose_cnt) as f64 / self.total_cnt as f64,
+        }
     }
 
     fn get_visit_count(&self) -> i32 {
@@ -149,6 +555,33 @@ impl Display for Statistic {
     }
 }
 
+/// A copy of a node of a search tree with its `Statistic`, for visualizing the tree externally.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ExportedNode {
+    pub action: String,
+    pub total_cnt: i32,
+    pub win_cnt: i32,
+    pub lose_cnt: i32,
+    pub draw_cnt: i32,
+    pub score_diff: i32,
+    pub children: Vec<ExportedNode>,
+}
+
+impl ExportedNode {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("The tree can always be serialized")
+    }
+
+    /// The number of nodes in the tree including the node itself.
+    pub fn count_nodes(&self) -> usize {
+        1 + self
+            .children
+            .iter()
+            .map(|child| child.count_nodes())
+            .sum::<usize>()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 enum NodeAction {
     TurnRoot,
@@ -186,11 +619,25 @@ impl Display for NodeAction {
 
 /// Game state which is visible from a player.
 /// It includes presumed information (e.g. opponent's hand/deck)
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq)]
 struct Determinization {
     player_cards: [PlayerCardState; 2],
 }
 
+impl Clone for Determinization {
+    fn clone(&self) -> Self {
+        Determinization {
+            player_cards: self.player_cards.clone(),
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        for (cards, source) in self.player_cards.iter_mut().zip(&source.player_cards) {
+            cards.clone_from(source);
+        }
+    }
+}
+
 impl Determinization {
     fn new(player_cards_a: PlayerCardState, player_cards_b: PlayerCardState) -> Self {
         if player_cards_a.get_player_id() == PlayerId::North {
@@ -211,12 +658,17 @@ impl Determinization {
     fn set_hands(&mut self, player_id: PlayerId, hands: &[Card], rng: &mut impl Rng) {
         let cards = self.get_cards_as_mut(player_id);
 
-        let mut new_deck: Vec<Card> = Vec::new();
-        for h in cards.get_hands().iter().filter(|c| !hands.contains(c)) {
-            new_deck.push(h.clone());
-        }
-        for d in cards.get_deck().iter().filter(|c| !hands.contains(c)) {
-            new_deck.push(d.clone());
+        let mut new_deck: Vec<Card> = cards
+            .get_hands()
+            .iter()
+            .chain(cards.get_deck())
+            .cloned()
+            .collect();
+        // Removes only one copy per card in the new hand.
+        for h in hands {
+            if let Some(i) = new_deck.iter().position(|c| c.get_id() == h.get_id()) {
+                new_deck.swap_remove(i);
+            }
         }
         new_deck.shuffle(rng);
 
@@ -233,23 +685,30 @@ impl Determinization {
             && self.is_consistent_for_player(state, PlayerId::North)
     }
 
+    /// A determinization is inconsistent if the player would have more copies of a card than
+    /// `RuleSet::max_card_copies` counting the consumed ones.
     fn is_consistent_for_player(&self, state: &State, player_id: PlayerId) -> bool {
         let cards = self.get_cards(player_id);
-        let consumed = state.get_consumed_cards(player_id);
-
-        for consumed_id in consumed {
-            for h in cards.get_hands() {
-                if h.get_id() == *consumed_id {
-                    return false;
+        let max_copies = state.get_rules().max_card_copies;
+        // Doesn't allocate till the first insertion, which never happens under the default rules
+        // since a held copy of a consumed card is already one too many.
+        let mut held_counts: HashMap<u32, u32> = HashMap::new();
+        cards
+            .get_hands()
+            .iter()
+            .chain(cards.get_deck())
+            .all(|card| {
+                let consumed = state.count_consumed(player_id, card.get_id());
+                if consumed == 0 {
+                    return true;
                 }
-            }
-            for d in cards.get_deck() {
-                if d.get_id() == *consumed_id {
+                if consumed >= max_copies {
                     return false;
                 }
-            }
-        }
-        true
+                let held = held_counts.entry(card.get_id()).or_insert(0);
+                *held += 1;
+                *held + consumed <= max_copies
+            })
     }
 }
 
@@ -318,10 +777,6 @@ impl SimultaneousState {
         &self.state
     }
 
-    fn get_turn(&self) -> i32 {
-        self.state.turn
-    }
-
     fn is_end(&self) -> bool {
         self.south_action.is_none() && self.north_action.is_none() && self.state.is_end()
     }
@@ -355,7 +810,8 @@ impl SimultaneousState {
         match (south_action, north_action) {
             (Some(sa), Some(na)) => {
                 // Both action is filled. Update the State itself.
-                engine::update_state(&mut state, &sa, &na);
+                engine::update_state(&mut state, &sa, &na)
+                    .expect("The tree only has valid actions");
                 Self {
                     south_action: None,
                     north_action: None,
@@ -394,11 +850,20 @@ struct Node {
 
     statistic: Statistic,
 
+    /// `State::get_zobrist_hash` of the state if this node shares statistics with transposed
+    /// nodes. Only set for nodes where both players' actions have just been applied.
+    transposition_key: Option<u64>,
+
     child_nodes: HashMap<NodeAction, Node>,
 
     /// Lists of legal actions based on the consumed hands.
     /// The entry may not exist if the traverser has never accessed the value.
     legal_actions: HashMap<Card, Vec<NodeAction>>,
+
+    /// Sampled hands which can be dealt after the player asked to redeal.
+    /// It's filled only for `AcceptInitialHands(false)` nodes and grows with the visit count
+    /// (see `redeal_widening_bound`).
+    redeal_hands: Vec<Vec<Card>>,
 }
 
 impl Node {
@@ -417,8 +882,10 @@ impl Node {
             simultaneous_state,
             action,
             statistic: Statistic::default(),
+            transposition_key: None,
             child_nodes: HashMap::new(),
             legal_actions: HashMap::new(),
+            redeal_hands: vec![],
         }
     }
 
@@ -426,12 +893,37 @@ impl Node {
         self.simultaneous_state.is_end()
     }
 
+    /// The number of nodes in the subtree including this node.
+    fn count_nodes(&self) -> usize {
+        1 + self
+            .child_nodes
+            .values()
+            .map(|child| child.count_nodes())
+            .sum::<usize>()
+    }
+
+    /// Copies the statistics of the node and its descendants. Children are sorted by their
+    /// visit counts in descending order.
+    fn export(&self) -> ExportedNode {
+        let mut children: Vec<ExportedNode> =
+            self.child_nodes.values().map(|child| child.export()).collect();
+        children.sort_by_key(|child| std::cmp::Reverse(child.total_cnt));
+        ExportedNode {
+            action: self.action.to_string(),
+            total_cnt: self.statistic.total_cnt,
+            win_cnt: self.statistic.win_cnt,
+            lose_cnt: self.statistic.lose_cnt,
+            draw_cnt: self.statistic.draw_cnt,
+            score_diff: self.statistic.score_diff,
+            children,
+        }
+    }
+
     fn is_pure_state(&self) -> bool {
         match self.action {
             NodeAction::TurnRoot => true,
             NodeAction::PlayerAction(pid, _) => {
-                pid != self.traverser_player_id
-                    && self.simultaneous_state.get_turn() == engine::TURN_COUNT
+                pid != self.traverser_player_id && self.simultaneous_state.state.is_end()
             }
             NodeAction::DealCard(_) => true,
 
@@ -453,28 +945,36 @@ impl Node {
         }
     }
 
-    fn get_legal_actions(&mut self, determinization: &Determinization) -> Vec<NodeAction> {
+    fn get_legal_actions(
+        &mut self,
+        determinization: &Determinization,
+        action_index: &ActionIndex,
+    ) -> Vec<NodeAction> {
         assert_lt!(
             self.simultaneous_state.state.get_turn(),
             engine::TURN_COUNT,
             "There shouldn't be any child ndoes since this node is a terminal node."
         );
         match self.action {
-            NodeAction::TurnRoot => {
-                self.get_legal_player_actions(self.traverser_player_id, determinization)
-            }
+            NodeAction::TurnRoot => self.get_legal_player_actions(
+                self.traverser_player_id,
+                determinization,
+                action_index,
+            ),
             NodeAction::PlayerAction(pid, _) => {
                 if pid == self.traverser_player_id {
                     // Opponent's action.
-                    self.get_legal_player_actions(pid.another(), determinization)
+                    self.get_legal_player_actions(pid.another(), determinization, action_index)
                 } else {
                     // Next node would be deal action
                     self.get_deal_action(pid.another(), determinization)
                 }
             }
-            NodeAction::DealCard(_) => {
-                self.get_legal_player_actions(self.traverser_player_id, determinization)
-            }
+            NodeAction::DealCard(_) => self.get_legal_player_actions(
+                self.traverser_player_id,
+                determinization,
+                action_index,
+            ),
             NodeAction::GameRoot => self.get_legal_accept_initial_hands_action(),
             NodeAction::AcceptInitialHands(accept) => {
                 self.get_legal_deal_accepted_hands_action(accept, determinization)
@@ -482,7 +982,11 @@ impl Node {
             NodeAction::DealAcceptedHands(_) => {
                 // Here is the end of dealing phase.
                 // Let players to play the game.
-                self.get_legal_player_actions(self.traverser_player_id, determinization)
+                self.get_legal_player_actions(
+                    self.traverser_player_id,
+                    determinization,
+                    action_index,
+                )
             }
         }
     }
@@ -491,12 +995,13 @@ impl Node {
         &mut self,
         player_id: PlayerId,
         determinization: &Determinization,
+        action_index: &ActionIndex,
     ) -> Vec<NodeAction> {
         let hands = determinization.get_cards(player_id).get_hands();
 
         let mut v = vec![];
         for c in hands {
-            let actions = self.get_legal_actions_for_card(player_id, c);
+            let actions = self.get_legal_actions_for_card(player_id, c, action_index);
             for act in actions {
                 v.push(act.clone());
             }
@@ -504,11 +1009,16 @@ impl Node {
         v
     }
 
-    fn get_legal_actions_for_card(&mut self, next_pid: PlayerId, card: &Card) -> &Vec<NodeAction> {
+    fn get_legal_actions_for_card(
+        &mut self,
+        next_pid: PlayerId,
+        card: &Card,
+        action_index: &ActionIndex,
+    ) -> &Vec<NodeAction> {
         assert!(!self.simultaneous_state.action_is_filled(next_pid));
         let entry = self.legal_actions.entry(card.clone()).or_insert_with(|| {
             let mut actions: Vec<Action> = vec![];
-            append_valid_actions(
+            action_index.append_valid_actions(
                 self.simultaneous_state.get_state(),
                 &[card.clone()],
                 next_pid,
@@ -552,21 +1062,76 @@ impl Node {
             return vec![NodeAction::DealAcceptedHands(dealed_hands.to_vec())];
         }
 
-        // Lists all possible hands.
-        let all_cards = determinization
-            .get_cards(self.traverser_player_id)
-            .get_all_cards();
-        debug!("# of all cards: {}", all_cards.len());
-        let hands: Vec<NodeAction> = all_cards
-            .into_iter()
-            .combinations(engine::HAND_SIZE)
+        assert!(
+            !self.redeal_hands.is_empty(),
+            "Redealt hands should be sampled when the node is created"
+        );
+        self.redeal_hands
+            .iter()
+            .cloned()
             .map(NodeAction::DealAcceptedHands)
+            .collect()
+    }
+}
+
+/// The number of hands a redeal node visited `visits` times can deal, which is at least one and
+/// at most `max_samples`.
+fn redeal_widening_bound(visits: i32, max_samples: usize) -> usize {
+    let bound = REDEAL_WIDENING_CONST * (visits.max(1) as f64).powf(REDEAL_WIDENING_EXPONENT);
+    (bound.ceil() as usize).clamp(1, max_samples)
+}
+
+/// Adds distinct hands which can be dealt from `cards` to `hands` until there are `target` of
+/// them. All possible hands are added if there are no more than `target` of them.
+fn sample_redeal_hands(
+    hands: &mut Vec<Vec<Card>>,
+    cards: &[Card],
+    target: usize,
+    rng: &mut impl Rng,
+) {
+    if hands.len() >= target {
+        return;
+    }
+    let possible_hands = count_distinct_hands(cards);
+    debug!("# of possible hands: {}", possible_hands);
+    let mut seen: HashSet<Vec<u32>> = hands.iter().map(|hand| engine::to_ids(hand)).collect();
+    if possible_hands <= target {
+        for mut hand in cards.iter().cloned().combinations(engine::HAND_SIZE) {
+            engine::sort_by_id(&mut hand);
+            if seen.insert(engine::to_ids(&hand)) {
+                hands.push(hand);
+            }
+        }
+        return;
+    }
+
+    while hands.len() < target {
+        let mut hand: Vec<Card> = cards
+            .choose_multiple(rng, engine::HAND_SIZE)
+            .cloned()
             .collect();
-        debug!("# of possible hands: {}", hands.len());
-        hands
+        engine::sort_by_id(&mut hand);
+        if seen.insert(engine::to_ids(&hand)) {
+            hands.push(hand);
+        }
     }
 }
 
+/// Counts hands which can be dealt from `cards`, regarding copies of a card as the same.
+fn count_distinct_hands(cards: &[Card]) -> usize {
+    // ways[k]: the number of distinct hands of k cards made of the cards counted so far.
+    let mut ways = [0usize; engine::HAND_SIZE + 1];
+    ways[0] = 1;
+    for copies in cards.iter().counts_by(|card| card.get_id()).into_values() {
+        for k in (1..=engine::HAND_SIZE).rev() {
+            for c in 1..=copies.min(k) {
+                ways[k] = ways[k].saturating_add(ways[k - c]);
+            }
+        }
+    }
+    ways[engine::HAND_SIZE]
+}
+
 impl Display for Node {
     fn fmt(&self, f: &mut __core::fmt::Formatter<'_>) -> __core::fmt::Result {
         writeln!(f, "Node: {}", self.action)?;
@@ -580,32 +1145,108 @@ impl Display for Node {
 }
 
 struct Traverser {
-    context: Context,
+    context: Arc<Context>,
     traverser_player_id: PlayerId,
     my_initial_deck: Vec<Card>,
 
     uct_const: f64,
+    redeal_samples: usize,
+    iterations_per_determinization: usize,
+    reward_kind: RewardKind,
+
+    node_budget: Option<NodeBudget>,
+    /// The number of nodes taken from `node_budget` by the current search and the kept tree.
+    acquired_nodes: usize,
+
+    /// Whether the tree of the last `search_action` is kept for the next one.
+    reuse_tree: bool,
+    last_tree: Option<Node>,
+
+    /// Statistics merged across nodes of a same `transposition_key`.
+    /// `None` if transpositions are disabled. Lives as long as the tree.
+    transpositions: Option<HashMap<u64, Statistic>>,
+
+    /// Visit counts of the root's children and the number of the root's legal actions
+    /// in the last `search_action`.
+    last_root_visits: Option<(Vec<i32>, usize)>,
+
+    /// Candidates and the principal variation found by the last `search_action`.
+    last_search_info: Option<SearchInfo>,
+
+    /// Time spent in each phase of the current search. `None` if profiling is disabled.
+    profile: Option<PhaseProfile>,
+    last_profile: Option<PhaseProfile>,
+
+    /// Whether a copy of the tree of each `search_action` is kept in `last_exported_tree`.
+    export_tree: bool,
+    last_exported_tree: Option<ExportedNode>,
+
+    /// Positions of the cards on the board of the game. Empty until `init_game`, in which case
+    /// legal actions are listed without it.
+    action_index: ActionIndex,
 
     rng: WyRng,
 }
 
 impl Traverser {
     fn new(
-        context: &Context,
+        context: Arc<Context>,
         traverser_player_id: PlayerId,
         player_initial_deck: Vec<Card>,
         uct_const: f64,
+        redeal_samples: usize,
         seed: u64,
     ) -> Self {
         Self {
-            context: context.clone(), // TODO: Stop cloning it.
+            context,
             traverser_player_id,
             my_initial_deck: player_initial_deck,
             uct_const,
+            redeal_samples,
+            iterations_per_determinization: 1,
+            reward_kind: RewardKind::default(),
+            node_budget: None,
+            acquired_nodes: 0,
+            reuse_tree: false,
+            last_tree: None,
+            transpositions: None,
+            last_root_visits: None,
+            last_search_info: None,
+            profile: None,
+            last_profile: None,
+            export_tree: false,
+            last_exported_tree: None,
+            action_index: ActionIndex::default(),
             rng: WyRng::seed_from_u64(seed),
         }
     }
 
+    fn enable_profile(&mut self, enabled: bool) {
+        self.profile = if enabled {
+            Some(PhaseProfile::default())
+        } else {
+            None
+        };
+    }
+
+    fn start_phase(&self) -> Option<Instant> {
+        self.profile.as_ref().map(|_| Instant::now())
+    }
+
+    fn end_phase(&mut self, phase: Phase, start: Option<Instant>) {
+        if let (Some(profile), Some(start)) = (self.profile.as_mut(), start) {
+            *profile.get_mut(phase) += start.elapsed();
+        }
+    }
+
+    /// Stores the profile of the finished search and starts a new one.
+    fn finish_profile(&mut self) {
+        if let Some(profile) = self.profile.replace(PhaseProfile::default()) {
+            info!("{}", profile);
+            self.last_profile = Some(profile);
+        }
+    }
+
     /// Descend the tree until leaf/terminal node is found.
     fn select_leaf<'a>(
         &mut self,
@@ -656,27 +1297,145 @@ impl Traverser {
         };
     }
 
-    fn search_action(
+    /// Runs `determinizations * iterations_per_determinization` iterations from `root_node`.
+    /// Iterations for a same determinization start from a same copy of it.
+    fn run_iterations(
         &mut self,
-        state: &State,
+        root_node: &mut Node,
         hands: &[Card],
-        iterations: usize,
+        determinizations: usize,
         time_limit: &Duration,
-    ) -> Action {
-        let mut root_node = self.create_turn_root_node(self.traverser_player_id, state.clone());
+    ) {
         let timer = Instant::now();
-        for n in 0..iterations {
+        let mut n = 0;
+        // Only needed if a determinization is shared by more than 1 iteration.
+        let mut scratch: Option<Determinization> = None;
+        'search: for _ in 0..determinizations {
+            let start = self.start_phase();
             let mut determinization = Determinization::new(
                 self.determinize_my_deck(root_node.simultaneous_state.get_state(), hands),
                 self.determinize_another_deck(root_node.simultaneous_state.get_state()),
             );
-            self.iterate(&mut root_node, &mut determinization);
+            self.end_phase(Phase::Determinization, start);
 
-            if timer.elapsed() > *time_limit {
-                info!("Time limit exceeded: Ran {} iterations", n + 1);
-                break;
+            for i in 0..self.iterations_per_determinization {
+                if timer.elapsed() >= *time_limit {
+                    info!("Time limit exceeded: Ran {} iterations", n);
+                    break 'search;
+                }
+
+                if i + 1 == self.iterations_per_determinization {
+                    // Nothing reads the determinization after the last iteration.
+                    self.iterate(root_node, &mut determinization);
+                } else {
+                    let scratch = match &mut scratch {
+                        Some(scratch) => {
+                            scratch.clone_from(&determinization);
+                            scratch
+                        }
+                        None => scratch.insert(determinization.clone()),
+                    };
+                    self.iterate(root_node, scratch);
+                }
+                n += 1;
+            }
+        }
+        self.finish_profile();
+    }
+
+    /// Takes a node from the budget for expanding a tree.
+    fn try_acquire_node(&mut self) -> bool {
+        match &self.node_budget {
+            Some(budget) if !budget.try_acquire() => false,
+            _ => {
+                self.acquired_nodes += 1;
+                true
+            }
+        }
+    }
+
+    /// Returns nodes of the finished search to the budget.
+    fn release_nodes(&mut self) {
+        if let Some(budget) = &self.node_budget {
+            budget.release(self.acquired_nodes);
+        }
+        self.acquired_nodes = 0;
+    }
+
+    fn search_action(
+        &mut self,
+        state: &State,
+        hands: &[Card],
+        determinizations: usize,
+        time_limit: &Duration,
+    ) -> Action {
+        let mut root_node = match self.take_subtree(state, hands) {
+            Some(node) => {
+                debug!(
+                    "Reusing a subtree with {} visits",
+                    node.statistic.get_visit_count()
+                );
+                node
             }
+            None => {
+                self.clear_transpositions();
+                self.create_turn_root_node(self.traverser_player_id, state.clone())
+            }
+        };
+        let action =
+            self.search_action_impl(&mut root_node, state, hands, determinizations, time_limit);
+        if self.export_tree {
+            let tree = root_node.export();
+            info!("Search tree: {}", tree.to_json());
+            self.last_exported_tree = Some(tree);
+        }
+        if self.reuse_tree {
+            self.last_tree = Some(root_node);
+        } else {
+            // The tree is dropped when it returns.
+            self.release_nodes();
         }
+        action
+    }
+
+    /// Takes the node of the last tree which is reached by the actions played since the last
+    /// search and the card dealt after them. The rest of the tree is dropped.
+    fn take_subtree(&mut self, state: &State, hands: &[Card]) -> Option<Node> {
+        let tree = self.last_tree.take()?;
+        let subtree = tree
+            .child_nodes
+            .into_values()
+            .flat_map(|mine| mine.child_nodes.into_values())
+            .filter(|opponents| opponents.simultaneous_state.get_state() == state)
+            .flat_map(|opponents| opponents.child_nodes.into_values())
+            // Dealt cards are the only ones in the hands which were in the deck.
+            .filter(
+                |deal| matches!(&deal.action, NodeAction::DealCard(card) if hands.contains(card)),
+            )
+            // Different actions can reach the same state e.g. rotations of a symmetric card.
+            .max_by_key(|deal| deal.statistic.get_visit_count())
+            .map(|mut node| {
+                node.action = NodeAction::TurnRoot;
+                node
+            });
+
+        let kept_nodes = subtree.as_ref().map_or(0, |node| node.count_nodes());
+        if let Some(budget) = &self.node_budget {
+            budget.release(self.acquired_nodes - kept_nodes);
+        }
+        self.acquired_nodes = kept_nodes;
+        subtree
+    }
+
+    fn search_action_impl(
+        &mut self,
+        root_node: &mut Node,
+        state: &State,
+        hands: &[Card],
+        determinizations: usize,
+        time_limit: &Duration,
+    ) -> Action {
+        self.run_iterations(root_node, hands, determinizations, time_limit);
 
         // Choose the best hand.
         if log::log_enabled!(Level::Debug) {
@@ -687,15 +1446,34 @@ impl Traverser {
                 .for_each(|c| debug!("    {}: {}", c.action, c.statistic));
         }
 
-        let most_visited = root_node
-            .child_nodes
-            .values()
-            .max_by(|a, b| {
-                a.statistic
-                    .get_visit_count()
-                    .cmp(&b.statistic.get_visit_count())
-            })
-            .unwrap();
+        let mut legal_actions = vec![];
+        self.action_index.append_valid_actions(
+            state,
+            hands,
+            self.traverser_player_id,
+            &mut legal_actions,
+        );
+        self.last_root_visits = Some((
+            root_node
+                .child_nodes
+                .values()
+                .map(|c| c.statistic.get_visit_count())
+                .collect(),
+            legal_actions.len(),
+        ));
+        self.last_search_info = Some(Self::collect_search_info(root_node, self.reward_kind));
+
+        let most_visited = match root_node.child_nodes.values().max_by(|a, b| {
+            a.statistic
+                .get_visit_count()
+                .cmp(&b.statistic.get_visit_count())
+        }) {
+            Some(node) => node,
+            None => {
+                warn!("No iteration has finished. Falling back to the first legal action.");
+                return Self::fallback_action(hands, legal_actions);
+            }
+        };
         if let NodeAction::PlayerAction(player_id, action) = &most_visited.action {
             assert_eq!(self.traverser_player_id, *player_id);
             action.clone()
@@ -707,29 +1485,76 @@ impl Traverser {
         }
     }
 
-    fn search_need_redeal_hands(
+    fn collect_search_info(root_node: &Node, reward_kind: RewardKind) -> SearchInfo {
+        let mut candidates: Vec<Candidate> = root_node
+            .child_nodes
+            .values()
+            .filter_map(|c| match &c.action {
+                NodeAction::PlayerAction(_, action) => Some(Candidate {
+                    action: action.clone(),
+                    visits: c.statistic.get_visit_count(),
+                    expected_value: c.statistic.get_expected_value(reward_kind),
+                }),
+                _ => None,
+            })
+            .collect();
+        candidates.sort_by(|a, b| {
+            b.visits
+                .cmp(&a.visits)
+                .then(b.expected_value.total_cmp(&a.expected_value))
+        });
+
+        let mut pv = vec![];
+        let mut node = root_node;
+        while let Some(child) = node
+            .child_nodes
+            .values()
+            .max_by_key(|c| c.statistic.get_visit_count())
+        {
+            if let NodeAction::PlayerAction(player_id, action) = &child.action {
+                pv.push((*player_id, action.clone()));
+            }
+            node = child;
+        }
+
+        SearchInfo {
+            candidates,
+            pv,
+        }
+    }
+
+    /// Picks the first placement from `legal_actions` or passes if there is no placement.
+    fn fallback_action(hands: &[Card], legal_actions: Vec<Action>) -> Action {
+        legal_actions
+            .into_iter()
+            .find(|action| !action.is_pass())
+            .unwrap_or_else(|| Action::Pass(hands[0].clone()))
+    }
+
+    fn search_need_redeal_hands(
         &mut self,
         board: &Board,
         hands: &[Card],
-        iterations: usize,
+        determinizations: usize,
+        time_limit: &Duration,
+    ) -> bool {
+        let accept = self.search_need_redeal_hands_impl(board, hands, determinizations, time_limit);
+        self.release_nodes();
+        accept
+    }
+
+    fn search_need_redeal_hands_impl(
+        &mut self,
+        board: &Board,
+        hands: &[Card],
+        determinizations: usize,
         time_limit: &Duration,
     ) -> bool {
         info!("Should we redeal hands? {}", engine::format_cards(hands));
-        let state = State::new(board.clone(), 0, 0, 0, vec![], vec![]);
+        let state = State::new_game(board.clone(), self.context.rules);
+        self.clear_transpositions();
         let mut root_node = self.create_game_root_node(self.traverser_player_id, state);
-        let timer = Instant::now();
-        for n in 0..iterations {
-            let mut determinization = Determinization::new(
-                self.determinize_my_deck(root_node.simultaneous_state.get_state(), hands),
-                self.determinize_another_deck(root_node.simultaneous_state.get_state()),
-            );
-            self.iterate(&mut root_node, &mut determinization);
-
-            if timer.elapsed() > *time_limit {
-                info!("Time limit exceeded: Ran {} iterations", n + 1);
-                break;
-            }
-        }
+        self.run_iterations(&mut root_node, hands, determinizations, time_limit);
 
         // Choose the best hand.
         info!("Legal actions");
@@ -738,15 +1563,17 @@ impl Traverser {
             .values()
             .for_each(|c| info!("    {}: {}", c.action, c.statistic));
 
-        let most_visited = root_node
-            .child_nodes
-            .values()
-            .max_by(|a, b| {
-                a.statistic
-                    .get_visit_count()
-                    .cmp(&b.statistic.get_visit_count())
-            })
-            .unwrap();
+        let most_visited = match root_node.child_nodes.values().max_by(|a, b| {
+            a.statistic
+                .get_visit_count()
+                .cmp(&b.statistic.get_visit_count())
+        }) {
+            Some(node) => node,
+            None => {
+                warn!("No iteration has finished. Keeping the initial hands.");
+                return false;
+            }
+        };
         if let NodeAction::AcceptInitialHands(accept) = most_visited.action {
             info!("Should we redeal hands? {}", accept);
             accept
@@ -761,23 +1588,30 @@ impl Traverser {
     fn iterate(&mut self, root_node: &mut Node, determinization: &mut Determinization) {
         // Selection
         debug!("Selection");
+        let start = self.start_phase();
         let (leaf, mut history) = self.select_leaf(root_node, determinization);
+        self.end_phase(Phase::Selection, start);
 
         // Expansion
         debug!("Expansion");
-        let leaf = if !leaf.is_terminal() {
+        let start = self.start_phase();
+        let leaf = if !leaf.is_terminal() && self.try_acquire_node() {
             let new_leaf = self.expand(leaf, determinization);
             history.push(new_leaf.action.clone());
             new_leaf
         } else {
             leaf
         };
+        self.end_phase(Phase::Expansion, start);
 
         // Simulation
+        let start = self.start_phase();
         let result = self.playout(leaf, determinization);
+        self.end_phase(Phase::Playout, start);
 
         // Backpropagation
         debug!("Backpropagation");
+        let start = self.start_phase();
         let mut node = root_node;
         node.statistic.update_with(result);
         for visited_node in history {
@@ -787,11 +1621,16 @@ impl Traverser {
                 .find(|c| c.action == visited_node)
                 .unwrap();
             node.statistic.update_with(result);
+            if let (Some(table), Some(key)) = (self.transpositions.as_mut(), node.transposition_key)
+            {
+                table.entry(key).or_default().update_with(result);
+            }
         }
+        self.end_phase(Phase::Backpropagation, start);
     }
 
     fn playout(&mut self, node: &mut Node, determinization: &mut Determinization) -> (u32, u32) {
-        debug_assert!(
+        assert!(
             determinization.is_consistent(node.simultaneous_state.get_state()),
             "Inconsistent state with the determination:\nConsumed cards:\nSouth: {:?}\nNorth: {:?}\nDeterminization: {}",
             &node.simultaneous_state.get_state().get_consumed_cards(PlayerId::South),
@@ -803,8 +1642,11 @@ impl Traverser {
 
         // Simulate the game till intermidiate state is solved.
         while !node.is_pure_state() {
-            let acts = node.get_legal_actions(determinization);
-            let rand_action = acts.choose(&mut self.rng).unwrap();
+            let acts = node.get_legal_actions(determinization, &self.action_index);
+            // Player nodes always have passes and chance nodes always have an outcome.
+            let rand_action = acts
+                .choose(&mut self.rng)
+                .expect("Every non-terminal node has at least one legal action");
             node = self.create_child_node(&node, rand_action);
             self.update_determinization_by_node_action(rand_action, determinization);
         }
@@ -824,12 +1666,13 @@ impl Traverser {
             let o_act =
                 self.choose_random_player_action(&state, PlayerId::North, o_state.get_hands());
 
-            engine::update_state(&mut state, &p_act, &o_act);
+            engine::update_state(&mut state, &p_act, &o_act)
+                .expect("Playouts only choose valid actions");
             engine::update_player_state(&state, &mut p_state, &p_act);
             engine::update_player_state(&state, &mut o_state, &o_act);
         }
         trace!("Playout result: {}", state);
-        state.board.get_scores()
+        state.get_scores()
     }
 
     fn choose_random_player_action(
@@ -838,7 +1681,7 @@ impl Traverser {
         player_id: PlayerId,
         hands: &[Card],
     ) -> Action {
-        choose_random_action(state, hands, player_id, &mut self.rng)
+        choose_random_action_with_index(state, hands, player_id, &self.action_index, &mut self.rng)
     }
 
     fn expand<'a>(
@@ -846,7 +1689,7 @@ impl Traverser {
         node: &'a mut Node,
         determinization: &mut Determinization,
     ) -> &'a mut Node {
-        let legal_actions = node.get_legal_actions(determinization);
+        let legal_actions = node.get_legal_actions(determinization, &self.action_index);
 
         debug!("# of legal actions: {}", legal_actions.len());
         // There can be other legal actions which have never selected.
@@ -869,13 +1712,16 @@ impl Traverser {
             panic!("{}", s);
         });
         self.update_determinization_by_node_action(&action_for_expanding, determinization);
-        let new_node = self.create_child_node(node, &action_for_expanding);
+        let mut new_node = self.create_child_node(node, &action_for_expanding);
+        if self.transpositions.is_some() {
+            new_node.transposition_key = Self::get_transposition_key(&new_node);
+        }
         node.child_nodes
             .insert(action_for_expanding.clone(), new_node);
         node.child_nodes.get_mut(&action_for_expanding).unwrap()
     }
 
-    fn create_child_node(&self, node: &Node, action: &NodeAction) -> Node {
+    fn create_child_node(&mut self, node: &Node, action: &NodeAction) -> Node {
         match action {
             NodeAction::TurnRoot => unimplemented!(),
             NodeAction::GameRoot => unimplemented!(),
@@ -888,6 +1734,15 @@ impl Traverser {
                     action.clone(),
                 )
             }
+            NodeAction::AcceptInitialHands(false) => {
+                let mut new_node = Node::new(
+                    node.traverser_player_id,
+                    node.simultaneous_state.clone(),
+                    action.clone(),
+                );
+                self.widen_redeal_hands(&mut new_node);
+                new_node
+            }
             _ => Node::new(
                 node.traverser_player_id,
                 node.simultaneous_state.clone(),
@@ -901,7 +1756,7 @@ impl Traverser {
         node: &'a mut Node,
         determinization: &Determinization,
     ) -> Vec<&'a mut Node> {
-        let legal_actions = node.get_legal_actions(determinization);
+        let legal_actions = node.get_legal_actions(determinization, &self.action_index);
         node.child_nodes
             .iter_mut()
             .filter(|(act, _child)| legal_actions.contains(act))
@@ -909,12 +1764,28 @@ impl Traverser {
             .collect()
     }
 
+    /// Samples more hands for a redeal node if it has been visited enough.
+    fn widen_redeal_hands(&mut self, node: &mut Node) {
+        if node.action != NodeAction::AcceptInitialHands(false) {
+            return;
+        }
+        let target = redeal_widening_bound(node.statistic.total_cnt, self.redeal_samples);
+        // No card has been consumed yet. Hands are dealt from the whole deck.
+        sample_redeal_hands(
+            &mut node.redeal_hands,
+            &self.my_initial_deck,
+            target,
+            &mut self.rng,
+        );
+    }
+
     fn is_leaf_node(&mut self, node: &mut Node, determinization: &Determinization) -> bool {
         if node.is_terminal() {
             return true;
         }
+        self.widen_redeal_hands(node);
 
-        let legal_actions = node.get_legal_actions(determinization);
+        let legal_actions = node.get_legal_actions(determinization, &self.action_index);
         for act in legal_actions {
             if !node.child_nodes.contains_key(&act) {
                 // This node doesn't have a child node for `act` yet.
@@ -943,7 +1814,14 @@ impl Traverser {
         for (i, child) in filtered_nodes.iter().enumerate() {
             assert_gt!(child.statistic.total_cnt, 0);
             debug!("   {}, {}:", child.action, child.statistic);
-            let ucb1 = Self::calc_ucb1(log_n_sum, self.uct_const, child);
+            let statistic = self.get_merged_statistic(child);
+            let ucb1 = Self::calc_ucb1(
+                log_n_sum,
+                self.uct_const,
+                self.reward_kind,
+                child,
+                statistic,
+            );
             if ucb1 > max_ucb1 {
                 max_ucb1 = ucb1;
                 max_index = i;
@@ -952,8 +1830,41 @@ impl Traverser {
         filtered_nodes.swap_remove(max_index)
     }
 
-    fn calc_ucb1(log_n_sum: f64, c: f64, child: &Node) -> f64 {
-        let mut value: f64 = child.statistic.get_expected_value();
+    /// Transposed nodes share a state where both players' actions have been applied.
+    /// The deal node after it has the same state but isn't keyed so that a visit isn't counted
+    /// twice.
+    fn get_transposition_key(node: &Node) -> Option<u64> {
+        let state = &node.simultaneous_state;
+        let applied = matches!(node.action, NodeAction::PlayerAction(..))
+            && !state.action_is_filled(PlayerId::South)
+            && !state.action_is_filled(PlayerId::North);
+        applied.then(|| state.get_state().get_zobrist_hash())
+    }
+
+    /// Returns the statistic shared by transposed nodes of `node` if any.
+    fn get_merged_statistic<'a>(&'a self, node: &'a Node) -> &'a Statistic {
+        match (&self.transpositions, node.transposition_key) {
+            (Some(table), Some(key)) => table.get(&key).unwrap_or(&node.statistic),
+            _ => &node.statistic,
+        }
+    }
+
+    fn clear_transpositions(&mut self) {
+        if let Some(table) = self.transpositions.as_mut() {
+            table.clear();
+        }
+    }
+
+    /// The value is taken from `statistic`, which can be merged with transposed nodes, and the
+    /// exploration term from the visits of `child` itself.
+    fn calc_ucb1(
+        log_n_sum: f64,
+        c: f64,
+        reward_kind: RewardKind,
+        child: &Node,
+        statistic: &Statistic,
+    ) -> f64 {
+        let mut value: f64 = statistic.get_expected_value(reward_kind);
 
         if child.get_prev_player_id() == PlayerId::North {
             value = -value;
@@ -986,13 +1897,19 @@ impl Traverser {
 
     fn determinize_another_deck(&mut self, state: &State) -> PlayerCardState {
         let another_player_id = self.traverser_player_id.another();
-        let mut all_cards = self.context.all_cards.values().cloned().collect_vec();
+        let copies = state.get_rules().max_card_copies as usize;
+        let mut all_cards = self
+            .context
+            .all_cards
+            .values()
+            .flat_map(|card| std::iter::repeat_n(card, copies))
+            .cloned()
+            .collect_vec();
         Self::filter_cards(&mut all_cards, state.get_consumed_cards(another_player_id));
 
-        all_cards.shuffle(&mut self.rng);
-        let (hands, deck) = all_cards.split_at(engine::HAND_SIZE);
+        let (hands, deck) = engine::deal(&all_cards, engine::HAND_SIZE, &mut self.rng);
 
-        PlayerCardState::new(another_player_id, hands.to_vec(), deck.to_vec())
+        PlayerCardState::new(another_player_id, hands, deck)
     }
 
     fn determinize_my_deck(&mut self, state: &State, hands: &[Card]) -> PlayerCardState {
@@ -1031,6 +1948,13 @@ impl Traverser {
     }
 }
 
+impl Drop for Traverser {
+    fn drop(&mut self) {
+        // Returns nodes of the kept tree.
+        self.release_nodes();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
@@ -1071,6 +1995,94 @@ mod tests {
         tmp
     }
 
+    struct TestSetup {
+        context: Arc<Context>,
+        deck: Vec<Card>,
+        board: Board,
+        traverser: Traverser,
+    }
+
+    // A deck of single cell cards and a board where they can be put in many ways.
+    fn new_test_setup() -> TestSetup {
+        let all_cards = new_test_all_cards(&[&["="] as &[&str]; engine::DECK_SIZE]);
+        let deck = all_cards.values().cloned().collect_vec();
+        let context = Arc::new(Context {
+            all_cards,
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        });
+        #[rustfmt::skip]
+        let board = engine::load_board_from_lines(
+            String::from("test_board"),
+            &[
+            "#######",
+            "#...O.#",
+            "#.....#",
+            "#.P...#",
+            "#######"
+            ]);
+        let traverser = Traverser::new(
+            context.clone(),
+            PlayerId::South,
+            deck.clone(),
+            UCT_CONST_DEFAULT,
+            REDEAL_SAMPLES_DEFAULT,
+            42,
+        );
+        TestSetup {
+            context,
+            deck,
+            board,
+            traverser,
+        }
+    }
+
+    #[test]
+    fn test_determinization_consistency() {
+        // The nested loops which `is_consistent_for_player` used to run.
+        fn is_consistent_brute_force(
+            determinization: &Determinization,
+            state: &State,
+            player_id: PlayerId,
+        ) -> bool {
+            let cards = determinization.get_cards(player_id);
+            state.get_consumed_cards(player_id).iter().all(|id| {
+                !cards
+                    .get_hands()
+                    .iter()
+                    .chain(cards.get_deck())
+                    .any(|card| card.get_id() == *id)
+            })
+        }
+
+        let all_cards = new_test_all_cards(&[&["="] as &[&str]; 8]);
+        let cards =
+            |ids: &[u32]| -> Vec<Card> { ids.iter().map(|id| all_cards[id].clone()).collect() };
+        let determinization = Determinization::new(
+            PlayerCardState::new(PlayerId::South, cards(&[0, 1]), cards(&[2, 3])),
+            PlayerCardState::new(PlayerId::North, cards(&[4, 5]), cards(&[6])),
+        );
+        let board = engine::load_board_from_lines(String::from("b"), &["###", "#.#", "###"]);
+        for (south_consumed, north_consumed) in [
+            (vec![], vec![]),
+            (vec![4, 5, 6, 7], vec![0, 1, 2, 3, 7]),
+            (vec![7, 3], vec![7]),
+            (vec![7], vec![5]),
+            (vec![0], vec![6]),
+        ] {
+            let state = State::new(board.clone(), 0, 0, 0, south_consumed, north_consumed);
+            for player_id in [PlayerId::South, PlayerId::North] {
+                assert_eq!(
+                    is_consistent_brute_force(&determinization, &state, player_id),
+                    determinization.is_consistent_for_player(&state, player_id),
+                    "{}: {:?}",
+                    player_id,
+                    state.get_consumed_cards(player_id)
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_initial_node() {
         #[rustfmt::skip]
@@ -1116,6 +2128,7 @@ mod tests {
         let context = Arc::new(Context {
             all_cards,
             enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
         });
         const SEED: u64 = 42;
         let sorted_cards = context
@@ -1132,10 +2145,11 @@ mod tests {
 
         let player_initial_deck = context.all_cards.values().cloned().collect_vec();
         let mut traverser = Traverser::new(
-            &context,
+            context.clone(),
             PlayerId::South,
             player_initial_deck,
             std::f64::consts::SQRT_2,
+            REDEAL_SAMPLES_DEFAULT,
             SEED,
         );
 
@@ -1173,4 +2187,628 @@ mod tests {
         traverser.iterate(&mut root_node, &mut determinization);
         assert_eq!(5, root_node.child_nodes.len());
     }
+
+    #[test]
+    fn test_sample_redeal_hands() {
+        let deck = new_test_all_cards(&[&["="] as &[&str]; engine::DECK_SIZE])
+            .into_values()
+            .collect_vec();
+        let mut rng = WyRng::seed_from_u64(42);
+
+        // C(15, 4) = 1365
+        for cap in [1365, 10000] {
+            let mut hands = vec![];
+            sample_redeal_hands(&mut hands, &deck, cap, &mut rng);
+            assert_eq!(1365, hands.len());
+        }
+
+        let mut hands = vec![];
+        for cap in [1, 10, 100, 1364, 1365] {
+            // Hands sampled so far are kept.
+            let sampled = hands.clone();
+            sample_redeal_hands(&mut hands, &deck, cap, &mut rng);
+            assert_eq!(sampled, hands[0..sampled.len()]);
+            assert_eq!(cap, hands.len());
+            let unique: HashSet<Vec<u32>> = hands.iter().map(|h| engine::to_ids(h)).collect();
+            assert_eq!(cap, unique.len());
+            assert!(hands.iter().all(|h| h.len() == engine::HAND_SIZE));
+        }
+
+        // Copies of a card make fewer distinct hands than C(15, 4).
+        let mut deck = deck;
+        for i in 0..7 {
+            deck[2 * i + 1] = deck[2 * i].clone();
+        }
+        let distinct = count_distinct_hands(&deck);
+        let mut hands = vec![];
+        sample_redeal_hands(&mut hands, &deck, 10000, &mut rng);
+        assert_eq!(distinct, hands.len());
+        let unique: HashSet<Vec<u32>> = hands.iter().map(|h| engine::to_ids(h)).collect();
+        assert_eq!(distinct, unique.len());
+        let mut hands = vec![];
+        sample_redeal_hands(&mut hands, &deck, distinct - 1, &mut rng);
+        assert_eq!(distinct - 1, hands.len());
+    }
+
+    #[test]
+    fn test_set_hands_keeps_copies() {
+        let TestSetup {
+            mut deck, ..
+        } = new_test_setup();
+        deck[1] = deck[0].clone();
+        let (hands, rest) = deck.split_at(engine::HAND_SIZE);
+        let mut determinization = Determinization::new(
+            PlayerCardState::new(PlayerId::South, hands.to_vec(), rest.to_vec()),
+            PlayerCardState::new(PlayerId::North, vec![], vec![]),
+        );
+
+        // Only one of the two copies goes to the new hand.
+        let mut new_hands = [&deck[0], &deck[2], &deck[3], &deck[4]].map(Clone::clone);
+        let mut rng = WyRng::seed_from_u64(42);
+        determinization.set_hands(PlayerId::South, &new_hands, &mut rng);
+        let cards = determinization.get_cards(PlayerId::South);
+        engine::sort_by_id(&mut new_hands);
+        assert_eq!(
+            engine::to_ids(&new_hands),
+            engine::to_ids(cards.get_hands())
+        );
+        assert_eq!(
+            engine::DECK_SIZE - engine::HAND_SIZE,
+            cards.get_deck().len()
+        );
+        let copies_in_deck = cards
+            .get_deck()
+            .iter()
+            .filter(|c| c.get_id() == deck[0].get_id())
+            .count();
+        assert_eq!(1, copies_in_deck);
+    }
+
+    #[test]
+    fn test_redeal_node_respects_cap() {
+        const CAP: usize = 7;
+        let TestSetup {
+            deck,
+            board,
+            mut traverser,
+            ..
+        } = new_test_setup();
+        traverser.redeal_samples = CAP;
+        let state = State::new(board, 0, 0, 0, vec![], vec![]);
+        let root_node = traverser.create_game_root_node(PlayerId::South, state);
+        let mut redeal_node =
+            traverser.create_child_node(&root_node, &NodeAction::AcceptInitialHands(false));
+        redeal_node.statistic.total_cnt = 10000;
+        traverser.widen_redeal_hands(&mut redeal_node);
+
+        let (hands, rest) = deck.split_at(engine::HAND_SIZE);
+        let determinization = Determinization::new(
+            PlayerCardState::new(PlayerId::South, hands.to_vec(), rest.to_vec()),
+            PlayerCardState::new(PlayerId::North, hands.to_vec(), rest.to_vec()),
+        );
+        assert_eq!(
+            CAP,
+            redeal_node
+                .get_legal_actions(&determinization, &traverser.action_index)
+                .len()
+        );
+    }
+
+    #[test]
+    fn test_redeal_progressive_widening() {
+        const ITERATIONS: usize = 200;
+        let TestSetup {
+            deck,
+            board,
+            mut traverser,
+            ..
+        } = new_test_setup();
+        let state = State::new(board, 0, 0, 0, vec![], vec![]);
+        let mut root_node = traverser.create_game_root_node(PlayerId::South, state);
+        traverser.run_iterations(
+            &mut root_node,
+            &deck[0..engine::HAND_SIZE],
+            ITERATIONS,
+            &Duration::MAX,
+        );
+
+        let redeal_node = &root_node.child_nodes[&NodeAction::AcceptInitialHands(false)];
+        let visits = redeal_node.statistic.total_cnt;
+        let expanded = redeal_node.child_nodes.len();
+        assert!(expanded > 1);
+        assert!(expanded <= redeal_widening_bound(visits, REDEAL_SAMPLES_DEFAULT));
+        assert!(expanded <= redeal_widening_bound(ITERATIONS as i32, REDEAL_SAMPLES_DEFAULT));
+        assert!(expanded <= redeal_node.redeal_hands.len());
+        assert!(redeal_node.redeal_hands.len() < REDEAL_SAMPLES_DEFAULT);
+    }
+
+    #[test]
+    fn test_search_coverage() {
+        let coverage = SearchCoverage::new(&[8, 4, 2, 2], 6, 3);
+
+        assert_eq!(6, coverage.legal_action_count);
+        assert_eq!(2.0 / 6.0, coverage.well_visited_fraction);
+        assert_eq!(2, coverage.unexpanded_count);
+        // -(1/2 ln 1/2 + 1/4 ln 1/4 + 2 * 1/8 ln 1/8) = 1.75 ln 2
+        assert!((coverage.visit_entropy - 1.75 * 2f64.ln()).abs() < 1e-9);
+
+        // No visits at all.
+        let coverage = SearchCoverage::new(&[], 3, 0);
+        assert_eq!(0.0, coverage.well_visited_fraction);
+        assert_eq!(0.0, coverage.visit_entropy);
+        assert_eq!(3, coverage.unexpanded_count);
+    }
+
+    #[test]
+    fn test_profile() {
+        let TestSetup {
+            deck,
+            board,
+            mut traverser,
+            ..
+        } = new_test_setup();
+        traverser.enable_profile(true);
+
+        let state = State::new(board, 0, 0, 0, vec![], vec![]);
+        let timer = Instant::now();
+        traverser.search_action(&state, &deck[0..engine::HAND_SIZE], 50, &Duration::MAX);
+        let elapsed = timer.elapsed();
+
+        let profile = traverser.last_profile.as_ref().unwrap();
+        assert_gt!(profile.total(), Duration::ZERO);
+        assert_le!(profile.total(), elapsed);
+    }
+
+    #[test]
+    fn test_iterations_per_determinization() {
+        let TestSetup {
+            deck,
+            board,
+            mut traverser,
+            ..
+        } = new_test_setup();
+        traverser.iterations_per_determinization = 7;
+
+        let state = State::new(board, 0, 0, 0, vec![], vec![]);
+        traverser.search_action(&state, &deck[0..engine::HAND_SIZE], 5, &Duration::MAX);
+
+        // Every iteration visits one of the root's children.
+        let (visits, _) = traverser.last_root_visits.as_ref().unwrap();
+        assert_eq!(5 * 7, visits.iter().sum::<i32>());
+    }
+
+    #[test]
+    fn test_zero_time_budget() {
+        let TestSetup {
+            deck,
+            board,
+            mut traverser,
+            ..
+        } = new_test_setup();
+
+        let state = State::new(board.clone(), 0, 0, 0, vec![], vec![]);
+        let hands = &deck[0..engine::HAND_SIZE];
+        let action = traverser.search_action(&state, hands, 100, &Duration::ZERO);
+
+        let (visits, _) = traverser.last_root_visits.as_ref().unwrap();
+        assert!(visits.is_empty());
+        assert!(!action.is_pass());
+        assert!(engine::is_valid_action(&state, PlayerId::South, &action));
+
+        // The initial hands are kept without searching.
+        assert!(!traverser.search_need_redeal_hands(&board, hands, 100, &Duration::ZERO));
+    }
+
+    #[test]
+    fn test_root_parallel() {
+        let TestSetup {
+            context,
+            deck,
+            board,
+            ..
+        } = new_test_setup();
+        let mut player = MctsPlayer::new("mcts".into(), 42, 20, UCT_CONST_DEFAULT).with_threads(3);
+        player.init_game(PlayerId::South, &context, &board, deck.clone());
+
+        let state = State::new(board, 0, 0, 0, vec![], vec![]);
+        let action = player.get_action(&state, &deck[0..engine::HAND_SIZE], &Duration::MAX);
+
+        // Every tree runs its own iterations.
+        let per_tree: Vec<i32> = std::iter::once(player.traverser.as_ref().unwrap())
+            .chain(player.helper_traversers.iter())
+            .map(|t| t.last_root_visits.as_ref().unwrap().0.iter().sum())
+            .collect();
+        assert_eq!(vec![20, 20, 20], per_tree);
+        let candidates = player.get_last_search_info().unwrap().candidates;
+        assert_eq!(60, candidates.iter().map(|c| c.visits).sum::<i32>());
+        let max_visits = candidates.iter().map(|c| c.visits).max().unwrap();
+        assert_eq!(max_visits, candidates[0].visits);
+        assert_eq!(candidates[0].action, action);
+
+        let candidate = |card: usize, visits: i32, expected_value: f64| Candidate {
+            action: Action::Pass(deck[card].clone()),
+            visits,
+            expected_value,
+        };
+        let tree_a = vec![candidate(0, 3, 1.0), candidate(1, 2, 0.0)];
+        let tree_b = vec![candidate(1, 4, 3.0)];
+        assert_eq!(
+            vec![candidate(1, 6, 2.0), candidate(0, 3, 1.0)],
+            merge_candidates([tree_a.as_slice(), tree_b.as_slice()])
+        );
+    }
+
+    #[test]
+    fn test_walled_in_board() {
+        let TestSetup {
+            context,
+            deck,
+            ..
+        } = new_test_setup();
+        // No card can be put anywhere, so both players can only pass till the end.
+        #[rustfmt::skip]
+        let board = engine::load_board_from_lines(
+            String::from("test_board"),
+            &[
+            "#####",
+            "#P#O#",
+            "#####"
+            ]);
+        let mut player = MctsPlayer::new("mcts".into(), 42, 20, UCT_CONST_DEFAULT);
+        player.init_game(PlayerId::South, &context, &board, deck.clone());
+
+        let state = State::new(board, 0, 0, 0, vec![], vec![]);
+        let action = player.get_action(&state, &deck[0..engine::HAND_SIZE], &Duration::MAX);
+        assert!(action.is_pass());
+    }
+
+    #[test]
+    fn test_tree_export() {
+        let TestSetup {
+            context,
+            deck,
+            board,
+            ..
+        } = new_test_setup();
+        // Keeps the tree to compare it with the exported one.
+        let mut player = MctsPlayer::new("mcts".into(), 42, 30, UCT_CONST_DEFAULT)
+            .with_tree_export(true)
+            .with_tree_reuse(true);
+        player.init_game(PlayerId::South, &context, &board, deck.clone());
+
+        let state = State::new(board, 0, 0, 0, vec![], vec![]);
+        player.get_action(&state, &deck[0..engine::HAND_SIZE], &Duration::MAX);
+
+        let tree = player.get_last_tree().unwrap();
+        let root = player
+            .traverser
+            .as_ref()
+            .unwrap()
+            .last_tree
+            .as_ref()
+            .unwrap();
+        assert_eq!(root.count_nodes(), tree.count_nodes());
+        assert_eq!("RootNode", tree.action);
+        assert_eq!(30, tree.total_cnt);
+        // Even the first iteration expands a child of the root, so every visit of the root
+        // goes through exactly one of its children.
+        assert_eq!(
+            tree.total_cnt,
+            tree.children.iter().map(|c| c.total_cnt).sum::<i32>()
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&tree.to_json()).unwrap();
+        assert_eq!(30, json["total_cnt"]);
+        assert_eq!(
+            tree.children.len(),
+            json["children"].as_array().unwrap().len()
+        );
+    }
+
+    #[test]
+    fn test_uct_const_changes_ordering() {
+        let all_cards = new_test_all_cards(&[&["="] as &[&str]; 2]);
+        let cards = all_cards.values().cloned().collect_vec();
+        #[rustfmt::skip]
+        let board = engine::load_board_from_lines(
+            String::from("test_board"),
+            &[
+            "#####",
+            "#.O.#",
+            "#.P.#",
+            "#####"
+            ]);
+        let state = State::new(board, 0, 0, 0, vec![], vec![]);
+
+        // A well explored good action and a rarely visited worse one.
+        let new_child = |card: usize, visits: i32, score_diff: i32| {
+            let mut node = Node::new(
+                PlayerId::South,
+                SimultaneousState::new(state.clone()),
+                NodeAction::PlayerAction(PlayerId::South, Action::Pass(cards[card].clone())),
+            );
+            node.statistic.total_cnt = visits;
+            node.statistic.score_diff = score_diff;
+            node
+        };
+        let explored = new_child(0, 90, 90);
+        let rare = new_child(1, 10, 0);
+        let log_n_sum = 100f64.ln();
+
+        let prefers_rare = |c: f64| {
+            let ucb1 = |child: &Node| {
+                Traverser::calc_ucb1(log_n_sum, c, RewardKind::Margin, child, &child.statistic)
+            };
+            ucb1(&rare) > ucb1(&explored)
+        };
+        assert!(!prefers_rare(0.0));
+        assert!(!prefers_rare(UCT_CONST_DEFAULT));
+        assert!(prefers_rare(5.0));
+    }
+
+    #[test]
+    fn test_context_shared_across_games() {
+        let TestSetup {
+            context,
+            deck,
+            board,
+            ..
+        } = new_test_setup();
+        let state = State::new(board.clone(), 0, 0, 0, vec![], vec![]);
+        let mut player = MctsPlayer::new("mcts".into(), 42, 10, UCT_CONST_DEFAULT).with_threads(2);
+        let mut play_game = |context: &Arc<Context>| {
+            player.init_game(PlayerId::South, context, &board, deck.clone());
+            player.get_action(&state, &deck[0..engine::HAND_SIZE], &Duration::MAX);
+            assert!(Arc::ptr_eq(
+                context,
+                &player.traverser.as_ref().unwrap().context
+            ));
+            assert!(Arc::ptr_eq(context, &player.helper_traversers[0].context));
+        };
+
+        play_game(&context);
+        // The caller's copy and the traversers.
+        assert_eq!(3, Arc::strong_count(&context));
+        play_game(&context);
+        assert_eq!(3, Arc::strong_count(&context));
+    }
+
+    #[test]
+    fn test_reward_kind() {
+        // Always wins by 1.
+        let mut narrow = Statistic::default();
+        (0..10).for_each(|_| narrow.update_with((31, 30)));
+        // Sometimes wins by a lot but loses as often.
+        let mut blowout = Statistic::default();
+        (0..5).for_each(|_| blowout.update_with((50, 20)));
+        (0..5).for_each(|_| blowout.update_with((20, 30)));
+
+        assert_gt!(
+            blowout.get_expected_value(RewardKind::Margin),
+            narrow.get_expected_value(RewardKind::Margin)
+        );
+        assert_gt!(
+            narrow.get_expected_value(RewardKind::WinLoss),
+            blowout.get_expected_value(RewardKind::WinLoss)
+        );
+        assert_eq!(1.0, narrow.get_expected_value(RewardKind::WinLoss));
+        assert_eq!(0.0, blowout.get_expected_value(RewardKind::WinLoss));
+    }
+
+    fn count_descendants(node: &Node) -> usize {
+        node.child_nodes
+            .values()
+            .map(|child| 1 + count_descendants(child))
+            .sum()
+    }
+
+    #[test]
+    fn test_shared_node_budget() {
+        let TestSetup {
+            context,
+            deck,
+            board,
+            ..
+        } = new_test_setup();
+        let state = State::new(board, 0, 0, 0, vec![], vec![]);
+        let hands = &deck[0..engine::HAND_SIZE];
+
+        const TREE_COUNT: usize = 4;
+        const CAP: usize = 50;
+        let budget = NodeBudget::new(CAP);
+        let mut traversers = (0..TREE_COUNT)
+            .map(|i| {
+                let mut traverser = Traverser::new(
+                    context.clone(),
+                    PlayerId::South,
+                    deck.clone(),
+                    UCT_CONST_DEFAULT,
+                    REDEAL_SAMPLES_DEFAULT,
+                    i as u64,
+                );
+                traverser.node_budget = Some(budget.clone());
+                traverser
+            })
+            .collect_vec();
+
+        // Trees are kept alive until all of them finish.
+        let node_counts = std::thread::scope(|scope| {
+            let handles = traversers
+                .iter_mut()
+                .map(|traverser| {
+                    let state = state.clone();
+                    scope.spawn(move || {
+                        let mut root = traverser.create_turn_root_node(PlayerId::South, state);
+                        traverser.run_iterations(&mut root, hands, 100, &Duration::MAX);
+                        (count_descendants(&root), traverser.acquired_nodes)
+                    })
+                })
+                .collect_vec();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect_vec()
+        });
+
+        let total: usize = node_counts.iter().map(|(count, _)| count).sum();
+        // 400 iterations would expand more nodes than the cap without the budget.
+        assert_eq!(CAP, total);
+        assert_eq!(CAP, budget.get_used());
+        for (count, acquired) in node_counts {
+            assert_eq!(count, acquired);
+        }
+
+        for traverser in traversers.iter_mut() {
+            traverser.release_nodes();
+        }
+        assert_eq!(0, budget.get_used());
+
+        // A search releases its nodes when it finishes.
+        traversers[0].search_action(&state, hands, 10, &Duration::MAX);
+        assert_eq!(0, budget.get_used());
+    }
+
+    #[test]
+    fn test_tree_reuse() {
+        let TestSetup {
+            deck,
+            mut traverser,
+            ..
+        } = new_test_setup();
+        #[rustfmt::skip]
+        let board = engine::load_board_from_lines(
+            String::from("test_board"),
+            &[
+            "#########",
+            "#P.....O#",
+            "#########",
+            ]);
+        let budget = NodeBudget::new(usize::MAX);
+        traverser.reuse_tree = true;
+        traverser.node_budget = Some(budget.clone());
+
+        let state = State::new(board, 0, 0, 0, vec![], vec![]);
+        let hands = deck[0..engine::HAND_SIZE].to_vec();
+        // Few actions can be taken on the narrow board so that the tree grows deep enough.
+        let action = traverser.search_action(&state, &hands, 1000, &Duration::MAX);
+        let tree = traverser.last_tree.clone().unwrap();
+        assert_eq!(tree.count_nodes() - 1, budget.get_used());
+
+        // Let the opponent take the action whose subtree is searched the most after ours.
+        let mine = &tree.child_nodes[&NodeAction::PlayerAction(PlayerId::South, action.clone())];
+        let (opponents, deal) = mine
+            .child_nodes
+            .values()
+            .flat_map(|opponents| {
+                opponents
+                    .child_nodes
+                    .values()
+                    .map(move |deal| (opponents, deal))
+            })
+            .max_by_key(|(_, deal)| deal.statistic.get_visit_count())
+            .unwrap();
+        let dealt_card = match &deal.action {
+            NodeAction::DealCard(card) => card.clone(),
+            action => panic!("Unexpected action: {}", action),
+        };
+        let next_state = opponents.simultaneous_state.get_state().clone();
+        // Other rotations of the symmetric cards reach the same state.
+        let deal_visits = tree
+            .child_nodes
+            .values()
+            .flat_map(|mine| mine.child_nodes.values())
+            .filter(|opponents| opponents.simultaneous_state.get_state() == &next_state)
+            .flat_map(|opponents| opponents.child_nodes.values())
+            .filter(|node| node.action == deal.action)
+            .map(|node| node.statistic.get_visit_count())
+            .max()
+            .unwrap();
+        let mut next_hands = hands.clone();
+        next_hands.retain(|card| card != action.get_consumed_card());
+        next_hands.push(dealt_card);
+
+        traverser.search_action(&next_state, &next_hands, 100, &Duration::MAX);
+        let tree = traverser.last_tree.as_ref().unwrap();
+        assert_eq!(&next_state, tree.simultaneous_state.get_state());
+        assert_eq!(NodeAction::TurnRoot, tree.action);
+        // Visits of the subtree are preserved.
+        assert_gt!(deal_visits, 0);
+        assert_eq!(deal_visits + 100, tree.statistic.get_visit_count());
+        assert_eq!(tree.count_nodes(), budget.get_used());
+
+        // The tree doesn't have the state. A new tree is created.
+        let unknown_state = State::new(next_state.board.clone(), 1, 0, 0, vec![], vec![]);
+        traverser.search_action(&unknown_state, &hands, 100, &Duration::MAX);
+        let tree = traverser.last_tree.as_ref().unwrap();
+        assert_eq!(100, tree.statistic.get_visit_count());
+        assert_eq!(tree.count_nodes() - 1, budget.get_used());
+
+        drop(traverser);
+        assert_eq!(0, budget.get_used());
+    }
+
+    #[test]
+    fn test_transpositions_share_visits() {
+        let TestSetup {
+            deck,
+            mut traverser,
+            ..
+        } = new_test_setup();
+        #[rustfmt::skip]
+        let board = engine::load_board_from_lines(
+            String::from("test_board"),
+            &[
+            "#########",
+            "#P.....O#",
+            "#########",
+            ]);
+        traverser.reuse_tree = true;
+        traverser.transpositions = Some(HashMap::new());
+
+        let state = State::new(board, 0, 0, 0, vec![], vec![]);
+        let hands = deck[0..engine::HAND_SIZE].to_vec();
+        traverser.search_action(&state, &hands, 1000, &Duration::MAX);
+        let tree = traverser.last_tree.as_ref().unwrap();
+
+        fn collect_keyed<'a>(
+            node: &'a Node,
+            path: &mut Vec<NodeAction>,
+            keyed: &mut HashMap<u64, Vec<(Vec<NodeAction>, &'a Node)>>,
+        ) {
+            if let Some(key) = node.transposition_key {
+                keyed.entry(key).or_default().push((path.clone(), node));
+            }
+            for (action, child) in node.child_nodes.iter() {
+                path.push(action.clone());
+                collect_keyed(child, path, keyed);
+                path.pop();
+            }
+        }
+        let mut keyed = HashMap::new();
+        collect_keyed(tree, &mut vec![], &mut keyed);
+
+        // Single cell cards put on the same cell in different orders or by different cards reach
+        // the same board.
+        let table = traverser.transpositions.as_ref().unwrap();
+        let transposed = keyed
+            .iter()
+            .filter(|(_, nodes)| nodes.len() > 1)
+            .collect_vec();
+        assert!(!transposed.is_empty());
+        for (key, nodes) in transposed {
+            let (first_path, first) = &nodes[0];
+            for (path, node) in &nodes[1..] {
+                assert_ne!(first_path, path);
+                assert_eq!(
+                    first.simultaneous_state.get_state().board,
+                    node.simultaneous_state.get_state().board
+                );
+            }
+            let visits: i32 = nodes
+                .iter()
+                .map(|(_, node)| node.statistic.get_visit_count())
+                .sum();
+            assert_eq!(visits, table[key].get_visit_count());
+        }
+    }
 }
@@ -0,0 +1,212 @@
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use more_asserts::assert_gt;
+
+use engine::{
+    Action,
+    Board,
+    Card,
+    Context,
+    PlayerId,
+    State,
+};
+
+use crate::{
+    utils::append_valid_actions,
+    Player,
+};
+
+/// A player which searches `depth` turns ahead and takes the action with the best worst case.
+///
+/// Both players act simultaneously, so the opponent is assumed to respond to each action with
+/// the one which is the worst for the player (maximin). The opponent may have any card it hasn't
+/// consumed yet. Cards dealt after the current turn are unknown, so only the rest of the current
+/// hands are searched in later turns. At the horizon, states are evaluated by the margin of
+/// controlled cells.
+///
+/// The search grows quickly with the depth and the number of cards. Intended for small boards
+/// and late game positions.
+pub struct MinimaxPlayer {
+    player_id: PlayerId,
+    name: String,
+    depth: usize,
+
+    /// All cards sorted by id. The opponent may have any of them.
+    all_cards: Vec<Card>,
+}
+
+impl MinimaxPlayer {
+    pub fn new(name: String, depth: usize) -> Self {
+        assert_gt!(depth, 0);
+        MinimaxPlayer {
+            player_id: PlayerId::South,
+            name,
+            depth,
+            all_cards: vec![],
+        }
+    }
+
+    /// Actions the opponent can take at `state` with the cards it hasn't consumed.
+    fn get_opponent_actions(&self, state: &State) -> Vec<Action> {
+        let opponent_id = self.player_id.another();
+        let max_copies = state.get_rules().max_card_copies;
+        let cards: Vec<Card> = self
+            .all_cards
+            .iter()
+            .filter(|card| state.count_consumed(opponent_id, card.get_id()) < max_copies)
+            .cloned()
+            .collect();
+        let mut actions = vec![];
+        append_valid_actions(state, &cards, opponent_id, &mut actions);
+        actions
+    }
+
+    fn evaluate(&self, state: &State) -> i32 {
+        state.board.get_score_breakdown().get_margin(self.player_id)
+    }
+
+    /// The value of `state` for the player who has `hands`, searched `depth` turns ahead.
+    fn search(&self, state: &State, hands: &[Card], depth: usize) -> i32 {
+        if depth == 0 || hands.is_empty() || state.is_end() {
+            return self.evaluate(state);
+        }
+        let mut actions = vec![];
+        append_valid_actions(state, hands, self.player_id, &mut actions);
+        let opponent_actions = self.get_opponent_actions(state);
+
+        let mut best = i32::MIN;
+        for action in actions.iter() {
+            let value = self.evaluate_action(state, hands, action, &opponent_actions, depth, best);
+            best = best.max(value);
+        }
+        best
+    }
+
+    /// The value of taking `action` against the opponent's worst response.
+    /// Stops early once the value gets below `alpha` since such an action is never chosen.
+    fn evaluate_action(
+        &self,
+        state: &State,
+        hands: &[Card],
+        action: &Action,
+        opponent_actions: &[Action],
+        depth: usize,
+        alpha: i32,
+    ) -> i32 {
+        let mut rest_hands = hands.to_vec();
+        let used = rest_hands
+            .iter()
+            .position(|card| card == action.get_consumed_card())
+            .expect("The action uses a card in the hands");
+        rest_hands.remove(used);
+
+        let mut worst = i32::MAX;
+        for response in opponent_actions {
+            let (south_action, north_action) = match self.player_id {
+                PlayerId::South => (action, response),
+                PlayerId::North => (response, action),
+            };
+            let mut next_state = state.clone();
+            engine::update_state(&mut next_state, south_action, north_action)
+                .expect("Only valid actions are searched");
+            worst = worst.min(self.search(&next_state, &rest_hands, depth - 1));
+            if worst < alpha {
+                break;
+            }
+        }
+        worst
+    }
+}
+
+impl Player for MinimaxPlayer {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn init_game(
+        &mut self,
+        player_id: PlayerId,
+        context: &Arc<Context>,
+        _board: &Board,
+        _deck: Vec<Card>,
+    ) {
+        self.player_id = player_id;
+        self.all_cards = context.all_cards.values().cloned().collect();
+        engine::sort_by_id(&mut self.all_cards);
+    }
+
+    fn need_redeal_hands(&mut self, _dealed_cards: &[Card], _time_limit: &Duration) -> bool {
+        false
+    }
+
+    fn get_action(&mut self, state: &State, hands: &[Card], _time_limit: &Duration) -> Action {
+        let mut actions = vec![];
+        append_valid_actions(state, hands, self.player_id, &mut actions);
+        let opponent_actions = self.get_opponent_actions(state);
+
+        // Ties are broken by the smallest card to save larger ones, as `GreedyPlayer` does.
+        let mut best: Option<((i32, i32), Action)> = None;
+        for action in actions {
+            let alpha = best.as_ref().map_or(i32::MIN, |((value, _), _)| *value);
+            let value =
+                self.evaluate_action(state, hands, &action, &opponent_actions, self.depth, alpha);
+            let key = (value, -action.get_consumed_card().get_cell_count());
+            if best.as_ref().is_none_or(|(best_key, _)| key >= *best_key) {
+                best = Some((key, action));
+            }
+        }
+        best.expect("Passing is always valid").1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use engine::test_support::new_test_card;
+
+    use super::*;
+    use crate::greedy::GreedyPlayer;
+
+    #[test]
+    fn test_depth_one_matches_greedy() {
+        let cards = [
+            new_test_card(1, &["="]),
+            new_test_card(2, &["==="]),
+            new_test_card(3, &["==", "= "]),
+            new_test_card(4, &["=="]),
+        ];
+        let context = Arc::new(Context {
+            all_cards: cards
+                .iter()
+                .map(|card| (card.get_id(), card.clone()))
+                .collect(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        });
+        // The opponent is walled in so that it can only pass, as the greedy player assumes.
+        #[rustfmt::skip]
+        let board = engine::load_board_from_lines(
+            String::from("test_board"),
+            &[
+            "#########",
+            "#O#.....#",
+            "###.....#",
+            "#.......#",
+            "#......P#",
+            "#########",
+            ]);
+        let state = State::new(board.clone(), 0, 0, 0, vec![], vec![]);
+
+        let mut greedy = GreedyPlayer::new("greedy".into());
+        let mut minimax = MinimaxPlayer::new("minimax".into(), 1);
+        greedy.init_game(PlayerId::South, &context, &board, cards.to_vec());
+        minimax.init_game(PlayerId::South, &context, &board, cards.to_vec());
+        for hands in [&cards[..], &cards[0..1], &cards[2..4]] {
+            let expected = greedy.get_action(&state, hands, &Duration::MAX);
+            let actual = minimax.get_action(&state, hands, &Duration::MAX);
+            assert_eq!(expected, actual, "{}", engine::format_cards(hands));
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use std::time::Duration;
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 use engine::{
     Action,
@@ -10,67 +14,137 @@ use engine::{
 };
 
 use super::{
+    greedy,
     mcts,
+    minimax,
     random,
 };
 
+/// Hands scored below it by `engine::evaluate_hand` are redealt by default.
+pub const REDEAL_THRESHOLD: f64 = 0.4;
+
 /// The base class for all player implementations.
-pub trait Player {
+/// Players are `Send` so that network clients can play in their own tasks.
+pub trait Player: Send {
     fn get_name(&self) -> &str;
-    fn init_game(&mut self, player_id: PlayerId, context: &Context, board: &Board, deck: Vec<Card>);
+    fn init_game(
+        &mut self,
+        player_id: PlayerId,
+        context: &Arc<Context>,
+        board: &Board,
+        deck: Vec<Card>,
+    );
 
     /// It will be called once before the first action.
-    fn need_redeal_hands(&mut self, dealed_cards: &[Card], time_limit: &Duration) -> bool;
+    /// By default, hands are redealt if `engine::evaluate_hand` scores them below
+    /// `REDEAL_THRESHOLD`, which costs nothing compared to a search.
+    fn need_redeal_hands(&mut self, dealed_cards: &[Card], _time_limit: &Duration) -> bool {
+        engine::evaluate_hand(dealed_cards) < REDEAL_THRESHOLD
+    }
 
     fn get_action(&mut self, state: &State, hands: &[Card], time_limit: &Duration) -> Action;
+
+    /// Diagnostics of the last `get_action` call, if the player searches actions.
+    fn get_last_search_info(&self) -> Option<SearchInfo> {
+        None
+    }
+
+    /// Resets the player's random number generator so that its following games depend only on
+    /// `seed`. Deterministic players don't need to implement it.
+    fn reseed(&mut self, _seed: u64) {}
+}
+
+/// A candidate action considered by a search.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub action: Action,
+    pub visits: i32,
+    pub expected_value: f64,
+}
+
+/// What a player thought about when choosing an action.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchInfo {
+    /// Candidate actions at the root, the most promising first.
+    pub candidates: Vec<Candidate>,
+
+    /// The principal variation i.e. the sequence of actions the search expects from the root.
+    pub pv: Vec<(PlayerId, Action)>,
+}
+
+impl<P: Player + ?Sized> Player for Box<P> {
+    fn get_name(&self) -> &str {
+        (**self).get_name()
+    }
+
+    fn init_game(
+        &mut self,
+        player_id: PlayerId,
+        context: &Arc<Context>,
+        board: &Board,
+        deck: Vec<Card>,
+    ) {
+        (**self).init_game(player_id, context, board, deck)
+    }
+
+    fn need_redeal_hands(&mut self, dealed_cards: &[Card], time_limit: &Duration) -> bool {
+        (**self).need_redeal_hands(dealed_cards, time_limit)
+    }
+
+    fn get_action(&mut self, state: &State, hands: &[Card], time_limit: &Duration) -> Action {
+        (**self).get_action(state, hands, time_limit)
+    }
+
+    fn get_last_search_info(&self) -> Option<SearchInfo> {
+        (**self).get_last_search_info()
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        (**self).reseed(seed)
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum PlayerType {
-    // Manual
     Random,
-    Mcts { iterations: usize },
-}
-
-const PLAYER_TYPE_VARIANTS: [PlayerType; 5] = [
-    PlayerType::Random,
-    PlayerType::Mcts {
-        iterations: 10,
-    },
-    PlayerType::Mcts {
-        iterations: 100,
+    Greedy,
+    Mcts {
+        iterations: usize,
+        /// The exploration constant. See `MctsPlayer::new`.
+        uct: f64,
     },
-    PlayerType::Mcts {
-        iterations: 300,
+    Minimax {
+        depth: usize,
     },
-    PlayerType::Mcts {
-        iterations: 1000,
-    },
-];
+}
 
-impl clap::ArgEnum for PlayerType {
-    fn value_variants<'a>() -> &'a [Self] {
-        &PLAYER_TYPE_VARIANTS
-    }
+/// Parses the names shown by `Display`: `random`, `greedy`, `minimax-<depth>` and
+/// `mcts-<iterations>`, optionally with the exploration constant like `mcts-100:1.2`.
+impl FromStr for PlayerType {
+    type Err = String;
 
-    fn to_possible_value<'a>(&self) -> Option<clap::PossibleValue<'a>> {
-        let name = match self {
-            PlayerType::Random => "random",
-            PlayerType::Mcts {
-                iterations: 10,
-            } => "mcts-10",
-            PlayerType::Mcts {
-                iterations: 100,
-            } => "mcts-100",
-            PlayerType::Mcts {
-                iterations: 300,
-            } => "mcts-300",
-            PlayerType::Mcts {
-                iterations: 1000,
-            } => "mcts-1000",
-            _ => panic!(),
-        };
-        Some(clap::PossibleValue::new(name))
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |e: &dyn std::fmt::Display| format!("Invalid player type {:?}: {}", s, e);
+        if let Some(depth) = s.strip_prefix("minimax-") {
+            return Ok(PlayerType::Minimax {
+                depth: depth.parse().map_err(|e| invalid(&e))?,
+            });
+        }
+        if let Some(params) = s.strip_prefix("mcts-") {
+            let (iterations, uct) = match params.split_once(':') {
+                Some((iterations, uct)) => (iterations, uct.parse().map_err(|e| invalid(&e))?),
+                None => (params, mcts::UCT_CONST_DEFAULT),
+            };
+            return Ok(PlayerType::Mcts {
+                iterations: iterations.parse().map_err(|e| invalid(&e))?,
+                uct,
+            });
+        }
+        match s {
+            "random" => Ok(PlayerType::Random),
+            "greedy" => Ok(PlayerType::Greedy),
+            _ => Err(format!("Unknown player type: {:?}", s)),
+        }
     }
 }
 
@@ -78,14 +152,42 @@ impl PlayerType {
     pub fn create_player(&self, _context: &Context, seed: u64) -> Box<dyn Player> {
         match self {
             PlayerType::Random => Box::new(random::RandomPlayer::new("rand".into(), seed)),
+            PlayerType::Greedy => Box::new(greedy::GreedyPlayer::new("greedy".into())),
             PlayerType::Mcts {
                 iterations,
+                uct,
             } => Box::new(mcts::MctsPlayer::new(
-                format!("mcts-{}", iterations),
+                self.to_string(),
                 seed,
                 *iterations,
-                mcts::UCT_CONST_DEFAULT,
+                *uct,
+            )),
+            PlayerType::Minimax {
+                depth,
+            } => Box::new(minimax::MinimaxPlayer::new(
+                format!("minimax-{}", depth),
+                *depth,
             )),
         }
     }
 }
+
+impl std::fmt::Display for PlayerType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlayerType::Random => write!(f, "random"),
+            PlayerType::Greedy => write!(f, "greedy"),
+            PlayerType::Mcts {
+                iterations,
+                uct,
+            } if *uct == mcts::UCT_CONST_DEFAULT => write!(f, "mcts-{}", iterations),
+            PlayerType::Mcts {
+                iterations,
+                uct,
+            } => write!(f, "mcts-{}:{}", iterations, uct),
+            PlayerType::Minimax {
+                depth,
+            } => write!(f, "minimax-{}", depth),
+        }
+    }
+}
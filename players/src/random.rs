@@ -1,10 +1,10 @@
-use std::time::Duration;
-
-use rand::{
-    Rng,
-    SeedableRng,
+use std::{
+    sync::Arc,
+    time::Duration,
 };
 
+use rand::SeedableRng;
+
 use engine::{
     Action,
     Board,
@@ -44,18 +44,18 @@ impl Player for RandomPlayer {
     fn init_game(
         &mut self,
         player_id: PlayerId,
-        _context: &Context,
+        _context: &Arc<Context>,
         _board: &Board,
         _deck: Vec<Card>,
     ) {
         self.player_id = player_id;
     }
 
-    fn need_redeal_hands(&mut self, _dealed_cards: &[Card], _time_limit: &Duration) -> bool {
-        self.rng.gen_bool(0.5)
-    }
-
     fn get_action(&mut self, state: &State, hands: &[Card], _time_limit: &Duration) -> Action {
         choose_random_action(state, hands, self.player_id, &mut self.rng)
     }
+
+    fn reseed(&mut self, seed: u64) {
+        self.rng = WyRng::seed_from_u64(seed);
+    }
 }
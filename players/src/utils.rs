@@ -1,8 +1,9 @@
 use itertools::Itertools;
-use log::*;
 
+pub use engine::append_valid_actions;
 use engine::{
     Action,
+    ActionIndex,
     Card,
     CardPosition,
     PlayerId,
@@ -31,6 +32,30 @@ pub fn choose_random_action(
     cards: &[Card],
     player_id: PlayerId,
     rng: &mut impl Rng,
+) -> Action {
+    choose_random_action_impl(state, cards, player_id, None, rng)
+}
+
+/// Same as `choose_random_action`, including the chosen action for the same `rng`, but skips
+/// positions where `action_index` tells that cards stick out of the board or overlap walls
+/// without checking them.
+pub fn choose_random_action_with_index(
+    state: &State,
+    cards: &[Card],
+    player_id: PlayerId,
+    action_index: &ActionIndex,
+    rng: &mut impl Rng,
+) -> Action {
+    let action_index = Some(action_index).filter(|index| index.is_for(&state.board));
+    choose_random_action_impl(state, cards, player_id, action_index, rng)
+}
+
+fn choose_random_action_impl(
+    state: &State,
+    cards: &[Card],
+    player_id: PlayerId,
+    action_index: Option<&ActionIndex>,
+    rng: &mut impl Rng,
 ) -> Action {
     let mut cards = cards.iter().collect_vec();
     let mut acts = ActionType::VALUES.iter().collect_vec();
@@ -48,6 +73,7 @@ pub fn choose_random_action(
             if *act == ActionType::Pass {
                 return Action::Pass((*card).clone());
             }
+            let fitting = action_index.and_then(|index| index.get_fitting_positions(card));
             for rotation in rots.iter() {
                 for y in y_range.iter() {
                     for x in x_range.iter() {
@@ -56,6 +82,9 @@ pub fn choose_random_action(
                             y: **y,
                             rotation: **rotation,
                         };
+                        if fitting.is_some_and(|fitting| !fitting.contains(&pos)) {
+                            continue;
+                        }
                         let action = match act {
                             ActionType::Put => Action::Put((*card).clone(), pos),
                             ActionType::Special => Action::Special((*card).clone(), pos),
@@ -69,46 +98,8 @@ pub fn choose_random_action(
             }
         }
     }
-    unimplemented!();
-}
-
-pub fn append_valid_actions(
-    state: &State,
-    cards: &[Card],
-    player_id: PlayerId,
-    actions: &mut Vec<Action>,
-) {
-    let (width, height) = state.board.get_size();
-    for card in cards {
-        actions.push(Action::Pass(card.clone()));
-        for rotation in Rotation::VALUES {
-            let card_width = card.calculate_width(rotation);
-            let card_height = card.calculate_height(rotation);
-            for y in 1..height - card_height {
-                for x in 1..width - card_width {
-                    let pos = CardPosition {
-                        x,
-                        y,
-                        rotation,
-                    };
-
-                    // Normal
-                    let action = Action::Put(card.clone(), pos);
-                    if engine::is_valid_action(state, player_id, &action) {
-                        actions.push(action);
-                    }
-
-                    // Special
-                    let action = Action::Special(card.clone(), pos);
-                    if engine::is_valid_action(state, player_id, &action) {
-                        actions.push(action);
-                    }
-                }
-            }
-        }
-    }
-    debug!("Found {} valid actions", actions.len());
-    trace!("Found actions:\n{:?}", actions);
+    // `acts` always contains `ActionType::Pass` which returns the first card.
+    unreachable!("No legal moves without any cards in hands");
 }
 
 // Get list of Card references from card IDs and a Card list
@@ -122,3 +113,59 @@ pub fn ids_to_deck<'a>(ids: &[u32], all_cards: &[&'a Card]) -> Vec<&'a Card> {
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use rand::SeedableRng;
+    use wyhash::WyRng;
+
+    use engine::{
+        Context,
+        PlayerCardState,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_choose_random_action_with_index() {
+        let data_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../data");
+        let context = Context {
+            all_cards: engine::load_cards(data_dir.join("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        };
+        let deck = context.get_cards(&engine::load_deck(&data_dir.join("decks/starter")));
+
+        for board in engine::load_boards(data_dir.join("boards").to_str().unwrap()) {
+            let index = ActionIndex::new(&board, context.all_cards.values());
+            let (hands, rest) = deck.split_at(engine::HAND_SIZE);
+            let mut player_states = [PlayerId::South, PlayerId::North]
+                .map(|player_id| PlayerCardState::new(player_id, hands.to_vec(), rest.to_vec()));
+            let mut state = State::new(board.clone(), 0, 0, 0, vec![], vec![]);
+            let (mut rng, mut indexed_rng) = (WyRng::seed_from_u64(42), WyRng::seed_from_u64(42));
+            while !state.is_end() {
+                // The index only skips invalid positions, so the same action is chosen.
+                let actions = player_states.clone().map(|player_state| {
+                    let player_id = player_state.get_player_id();
+                    let hands = player_state.get_hands();
+                    let action = choose_random_action(&state, hands, player_id, &mut rng);
+                    let indexed = choose_random_action_with_index(
+                        &state,
+                        hands,
+                        player_id,
+                        &index,
+                        &mut indexed_rng,
+                    );
+                    assert_eq!(action, indexed, "{}\n{}", board.get_name(), state);
+                    action
+                });
+                engine::update_state(&mut state, &actions[0], &actions[1]).unwrap();
+                for (player_state, action) in player_states.iter_mut().zip(&actions) {
+                    engine::update_player_state(&state, player_state, action);
+                }
+            }
+        }
+    }
+}
@@ -3,13 +3,20 @@ use serde::{
     Deserialize,
     Serialize,
 };
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    task::{
+        Context,
+        Poll,
+        Waker,
+    },
+};
 use tokio::{
     self,
     io::{
         AsyncBufReadExt,
-        AsyncReadExt,
         AsyncWriteExt,
+        ReadBuf,
     },
     net::TcpStream,
 };
@@ -30,6 +37,8 @@ pub struct Connection {
     stream: tokio::io::BufReader<TcpStream>,
 
     preferred_format: WireFormat,
+
+    /// Bytes received but not parsed yet.
     buffer: Vec<u8>,
 }
 
@@ -47,29 +56,78 @@ impl Connection {
         self.preferred_format = format;
     }
 
+    /// Whether the peer has closed the connection. It neither blocks nor consumes received
+    /// bytes, so it can check a client which isn't expected to send anything for now.
+    pub fn is_closed(&self) -> bool {
+        let mut byte = [0; 1];
+        let mut buf = ReadBuf::new(&mut byte);
+        let mut cx = Context::from_waker(Waker::noop());
+        match self.stream.get_ref().poll_peek(&mut cx, &mut buf) {
+            Poll::Ready(Ok(0)) | Poll::Ready(Err(_)) => true,
+            Poll::Ready(Ok(_)) | Poll::Pending => false,
+        }
+    }
+
+    /// Receives a message in the preferred format.
+    ///
+    /// It's cancel safe. Bytes read by a cancelled call are kept for the next call, so it can be
+    /// used in `tokio::select!` or with a timeout without losing a part of a message.
     pub async fn recv<P>(&mut self) -> Result<P, Error>
     where
         P: for<'de> Deserialize<'de>,
     {
+        loop {
+            if let Some(frame_size) = self.get_frame_size() {
+                let frame: Vec<u8> = self.buffer.drain(..frame_size).collect();
+                return match self.preferred_format {
+                    WireFormat::Json => Self::parse_json(&frame),
+                    WireFormat::Flexbuffers => Self::parse_flexbuffers(&frame),
+                };
+            }
+            let read = match self.stream.fill_buf().await {
+                Ok(read) => read,
+                Err(e) => {
+                    return Err(Error {
+                        code: ErrorCode::NetworkError,
+                        message: format!("Failed to read data from the stream: {}", e),
+                    })
+                }
+            };
+            if read.is_empty() {
+                return Err(Error {
+                    code: ErrorCode::NetworkError,
+                    message: "The connection is closed".into(),
+                });
+            }
+            let size = read.len();
+            self.buffer.extend_from_slice(read);
+            self.stream.consume(size);
+        }
+    }
+
+    /// The size of the first message in the buffer including its delimiter, or None if the
+    /// message hasn't been received completely.
+    fn get_frame_size(&self) -> Option<usize> {
         match self.preferred_format {
-            WireFormat::Json => self.recv_json().await,
-            WireFormat::Flexbuffers => self.recv_flexbuffers().await,
+            WireFormat::Json => self
+                .buffer
+                .iter()
+                .position(|b| *b == b'\n')
+                .map(|position| position + 1),
+            WireFormat::Flexbuffers => {
+                let size: [u8; 4] = self.buffer.get(0..4)?.try_into().unwrap();
+                let frame_size = 4 + u32::from_be_bytes(size) as usize;
+                (self.buffer.len() >= frame_size).then_some(frame_size)
+            }
         }
     }
 
-    async fn recv_json<P>(&mut self) -> Result<P, Error>
+    fn parse_json<P>(frame: &[u8]) -> Result<P, Error>
     where
         P: for<'de> Deserialize<'de>,
     {
-        let mut line = String::new();
-        if let Err(e) = self.stream.read_line(&mut line).await {
-            return Err(Error {
-                code: ErrorCode::MalformedPayload, // network error?
-                message: e.to_string(),
-            });
-        }
-        debug!("Read line: {}", line.trim_end());
-        match serde_json::from_str::<P>(&line) {
+        debug!("Read line: {}", String::from_utf8_lossy(frame).trim_end());
+        match serde_json::from_slice::<P>(frame) {
             Ok(req) => Ok(req),
             Err(e) => Err(Error {
                 code: ErrorCode::MalformedPayload,
@@ -78,30 +136,13 @@ impl Connection {
         }
     }
 
-    async fn recv_flexbuffers<P>(&mut self) -> Result<P, Error>
+    /// The first 4 bytes of the frame must be the size of the following message, which is an
+    /// unsigned 32bit integer encoded as big-endian.
+    fn parse_flexbuffers<P>(frame: &[u8]) -> Result<P, Error>
     where
         P: for<'de> Deserialize<'de>,
     {
-        let size: u32 = match self.stream.read_u32().await {
-            Ok(v) => v,
-            Err(e) => return Err(Error{
-                code: ErrorCode::MalformedPayload,
-                message: format!("The first 4 bytes of payload must be a size of following message. This unsigned 32bit integer must be encoded as big-endian: {}", e),
-            }),
-        };
-
-        if let Err(e) = (&mut self.stream)
-            .take(size.into())
-            .read_to_end(&mut self.buffer)
-            .await
-        {
-            return Err(Error {
-                code: ErrorCode::MalformedPayload,
-                message: format!("Failed to read data from the stream: {}", e),
-            });
-        };
-
-        match flexbuffers::from_slice(&self.buffer) {
+        match flexbuffers::from_slice(&frame[4..]) {
             Ok(req) => Ok(req),
             Err(e) => Err(Error {
                 code: ErrorCode::MalformedPayload,
@@ -203,3 +244,36 @@ impl Display for Connection {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_is_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        let mut client = Connection::new(client);
+        let mut server = Connection::new(server);
+
+        // Received bytes are neither an end nor consumed.
+        client.send(&1).await.unwrap();
+        for _ in 0..10 {
+            assert!(!server.is_closed());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        drop(client);
+        assert_eq!(1, server.recv::<i32>().await.unwrap());
+        while !server.is_closed() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
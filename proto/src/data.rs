@@ -11,7 +11,11 @@ use serde_repr::{
 
 use engine;
 
+use crate::PlayerId;
+
 pub type GameId = u32;
+/// Unique ID of a game session assigned by the server.
+pub type GameUid = u64;
 pub type CardId = u32;
 
 #[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -26,6 +30,17 @@ pub enum ErrorCode {
 
     NetworkError,
     SerializationFailure,
+
+    /// The client took an action which is against the rules. The client loses the game.
+    IllegalAction,
+
+    /// The server doesn't support the protocol version of the client.
+    /// See `CURRENT_PROTOCOL_VERSION`.
+    UnsupportedVersion,
+
+    /// The server has as many games in progress as it can host. The client may retry later.
+    /// Clients older than `SERVER_BUSY_PROTOCOL_VERSION` receive `BadRequest` instead.
+    ServerBusy,
 }
 
 #[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -40,10 +55,15 @@ pub enum WireFormat {
     Flexbuffers,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct Scores {
     pub south_score: u32,
     pub north_score: u32,
+
+    /// The player who lost the game regardless of the scores, e.g. by taking an illegal action
+    /// or by disconnecting.
+    #[serde(default)]
+    pub forfeit: Option<PlayerId>,
 }
 
 impl Display for Scores {
@@ -53,6 +73,9 @@ impl Display for Scores {
             "Scores: (south: {}, north: {})",
             self.south_score, self.north_score
         )?;
+        if let Some(player_id) = self.forfeit {
+            write!(f, " ({:?} forfeited)", player_id)?;
+        }
         Ok(())
     }
 }
@@ -66,6 +89,14 @@ pub enum TimeControl {
     /// Players can spend `time_limit_in_seconds` seconds for each action.
     /// If a player exceeds the time limit, the player loses.
     PerAction { time_limit_in_seconds: u32 },
+
+    /// Each player has a clock which starts with `base_seconds` seconds. Time spent for each
+    /// action is deducted from the clock and `increment_seconds` seconds are added after the
+    /// action. If the clock runs out, the player loses.
+    Increment {
+        base_seconds: u32,
+        increment_seconds: u32,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -73,6 +104,23 @@ pub struct GameInfo {
     pub game_id: GameId,
     pub time_control: TimeControl,
     pub board: Board,
+
+    /// The number of cards in hands which the server deals.
+    /// Clients should check it rather than relying on their own `engine::HAND_SIZE`.
+    #[serde(default = "default_hand_size")]
+    pub hand_size: u32,
+
+    /// The number of cards the server requires for a deck.
+    #[serde(default = "default_deck_size")]
+    pub deck_size: u32,
+}
+
+fn default_hand_size() -> u32 {
+    engine::HAND_SIZE as u32
+}
+
+fn default_deck_size() -> u32 {
+    engine::DECK_SIZE as u32
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -94,21 +142,64 @@ pub struct Board {
     pub cells: Vec<Vec<BoardCell>>,
 }
 
-impl From<Board> for engine::Board {
-    fn from(val: Board) -> Self {
-        let (h, w) = (val.cells.len(), val.cells[0].len());
-        let mut cells = Vec::with_capacity(h as usize);
-        for y in 0..h {
-            let mut row = Vec::with_capacity(w as usize);
-            for x in 0..w {
-                row.push(val.cells[y][x].into());
-            }
-            cells.push(row);
+/// Reasons why a `Board` received from the network can't be used as an `engine::Board`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidBoardError {
+    /// The board has no rows, or its first row has no cells.
+    Empty,
+
+    /// Rows of the board have different widths.
+    Jagged {
+        row: usize,
+        width: usize,
+        expected_width: usize,
+    },
+}
+
+impl Display for InvalidBoardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidBoardError::Empty => write!(f, "The board has no cells"),
+            InvalidBoardError::Jagged {
+                row,
+                width,
+                expected_width,
+            } => write!(
+                f,
+                "The board isn't rectangular: row {} has {} cells but expected {}",
+                row, width, expected_width
+            ),
+        }
+    }
+}
+
+impl TryFrom<Board> for engine::Board {
+    type Error = InvalidBoardError;
+
+    fn try_from(val: Board) -> Result<Self, Self::Error> {
+        let w = match val.cells.first() {
+            Some(row) if !row.is_empty() => row.len(),
+            _ => return Err(InvalidBoardError::Empty),
+        };
+        if let Some((y, row)) = val.cells.iter().enumerate().find(|(_, row)| row.len() != w) {
+            return Err(InvalidBoardError::Jagged {
+                row: y,
+                width: row.len(),
+                expected_width: w,
+            });
         }
-        engine::Board::new(val.name, cells)
+
+        let cells = val
+            .cells
+            .into_iter()
+            .map(|row| row.into_iter().map(|cell| cell.into()).collect())
+            .collect();
+        Ok(engine::Board::new(val.name, cells))
     }
 }
 
+/// The conversion always emits rectangular cells since it visits every position in the size of
+/// the board.
 impl From<&engine::Board> for Board {
     fn from(b: &engine::Board) -> Self {
         let (w, h) = b.get_size();
@@ -133,6 +224,55 @@ impl From<&engine::Board> for Board {
     }
 }
 
+/// Cells changed by the last actions. See `engine::BoardDelta`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BoardDelta {
+    pub changes: Vec<BoardCellChange>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BoardCellChange {
+    pub x: i32,
+    pub y: i32,
+    pub cell: BoardCell,
+}
+
+impl From<&engine::BoardDelta> for BoardDelta {
+    fn from(delta: &engine::BoardDelta) -> Self {
+        BoardDelta {
+            changes: delta
+                .changes
+                .iter()
+                .map(|(position, cell)| BoardCellChange {
+                    x: position.x,
+                    y: position.y,
+                    cell: (*cell).into(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<BoardDelta> for engine::BoardDelta {
+    fn from(delta: BoardDelta) -> Self {
+        engine::BoardDelta {
+            changes: delta
+                .changes
+                .into_iter()
+                .map(|change| {
+                    (
+                        engine::BoardPosition {
+                            x: change.x,
+                            y: change.y,
+                        },
+                        change.cell.into(),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
 /// An enum reprecents each cell on a board.
 /// We do NOT use enum with fields (e.g. Ink(PlayerId)) to keep the serialized data small.
 #[derive(Serialize_repr, Deserialize_repr, Debug, Copy, Clone, PartialEq, Eq)]
@@ -174,22 +314,6 @@ impl From<engine::BoardCell> for BoardCell {
     }
 }
 
-#[derive(Serialize_repr, Deserialize_repr, Debug, Copy, Clone, PartialEq, Eq)]
-#[repr(i8)]
-pub enum PlayerId {
-    South = 1,
-    North = -1,
-}
-
-impl From<PlayerId> for engine::PlayerId {
-    fn from(val: PlayerId) -> Self {
-        match val {
-            PlayerId::South => engine::PlayerId::South,
-            PlayerId::North => engine::PlayerId::North,
-        }
-    }
-}
-
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Action {
     Pass(CardId),
@@ -208,6 +332,12 @@ impl From<engine::Action> for Action {
 }
 
 impl Action {
+    pub fn get_card_id(&self) -> CardId {
+        match self {
+            Action::Pass(cid) | Action::Put(cid, _) | Action::Special(cid, _) => *cid,
+        }
+    }
+
     pub fn convert(self, context: &engine::Context) -> engine::Action {
         match self {
             Action::Pass(cid) => engine::Action::Pass(context.get_card(cid)),
@@ -274,3 +404,77 @@ impl From<engine::Rotation> for Rotation {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_board(cells: Vec<Vec<BoardCell>>) -> Board {
+        Board {
+            name: String::from("test_board"),
+            cells,
+        }
+    }
+
+    #[test]
+    fn test_board_empty() {
+        assert_eq!(
+            Err(InvalidBoardError::Empty),
+            engine::Board::try_from(new_board(vec![]))
+        );
+        assert_eq!(
+            Err(InvalidBoardError::Empty),
+            engine::Board::try_from(new_board(vec![vec![]]))
+        );
+    }
+
+    #[test]
+    fn test_board_single_cell() {
+        let board =
+            engine::Board::try_from(new_board(vec![vec![BoardCell::SpecialSouth]])).unwrap();
+        assert_eq!((1, 1), board.get_size());
+        assert_eq!(
+            engine::BoardCell::Special(engine::PlayerId::South),
+            board.get_cell(engine::BoardPosition {
+                x: 0,
+                y: 0
+            })
+        );
+        assert_eq!(
+            new_board(vec![vec![BoardCell::SpecialSouth]]),
+            Board::from(&board)
+        );
+    }
+
+    #[test]
+    fn test_board_jagged() {
+        let board = new_board(vec![
+            vec![BoardCell::Wall, BoardCell::Wall, BoardCell::Wall],
+            vec![BoardCell::Wall, BoardCell::None, BoardCell::Wall],
+            vec![BoardCell::Wall, BoardCell::Wall],
+        ]);
+        assert_eq!(
+            Err(InvalidBoardError::Jagged {
+                row: 2,
+                width: 2,
+                expected_width: 3
+            }),
+            engine::Board::try_from(board)
+        );
+    }
+
+    #[test]
+    fn test_board_from_engine_is_rectangular() {
+        #[rustfmt::skip]
+        let board = engine::load_board_from_lines(
+            String::from("test_board"),
+            &[
+            "#####",
+            "#.P",
+            "#O.#",
+            ]);
+        let proto_board = Board::from(&board);
+        assert!(proto_board.cells.iter().all(|row| row.len() == 5));
+        assert_eq!(board, engine::Board::try_from(proto_board).unwrap());
+    }
+}
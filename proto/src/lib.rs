@@ -1,6 +1,8 @@
 pub mod connection;
 pub mod data;
 pub mod messages;
+pub mod player_id;
 
 pub use data::*;
 pub use messages::*;
+pub use player_id::*;
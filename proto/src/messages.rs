@@ -6,6 +6,54 @@ use serde::{
 // Do NOT import types from crate::engine to prvent changes in engine/ affects the wire format.
 use super::*;
 
+/// The version of the wire protocol this crate implements.
+///
+/// The client sends the version it implements in `ManmenmiRequest` and the server replies the
+/// version used for the connection in `ManmenmiResponse`, which is the lower one of both.
+/// Fields added to messages later must be `#[serde(default)]` so that peers of older versions can
+/// still parse the messages, and they are meaningful only if the selected version is the one
+/// they were introduced in or later. Bump this when adding such fields and list them below.
+///
+/// - 1: The version negotiation itself.
+/// - 2: `SpectateRequest` and `StateUpdate`.
+/// - 3: `Ping` and `Pong`.
+/// - 4: `ErrorCode::ServerBusy`.
+/// - 5: `StateUpdate` without actions of a forfeited game.
+pub const CURRENT_PROTOCOL_VERSION: u32 = 5;
+
+/// The first version which supports spectating games.
+pub const SPECTATE_PROTOCOL_VERSION: u32 = 2;
+
+/// The first version whose clients answer `Ping`s.
+pub const KEEPALIVE_PROTOCOL_VERSION: u32 = 3;
+
+/// The first version whose clients can parse `ErrorCode::ServerBusy`.
+pub const SERVER_BUSY_PROTOCOL_VERSION: u32 = 4;
+
+/// The first version whose spectators can parse `StateUpdate`s without actions.
+pub const FORFEIT_UPDATE_PROTOCOL_VERSION: u32 = 5;
+
+/// The oldest version the server talks with. Clients which don't send their version are
+/// regarded as version 0. Raise it only when a version changes the messages in a way older
+/// clients can't follow.
+pub const MIN_PROTOCOL_VERSION: u32 = 0;
+
+/// The version the server uses with a client of `client_version`.
+// No client is too old while `MIN_PROTOCOL_VERSION` is 0.
+#[allow(clippy::absurd_extreme_comparisons)]
+pub fn select_protocol_version(client_version: u32) -> Result<u32, ErrorResponse> {
+    if client_version < MIN_PROTOCOL_VERSION {
+        return Err(ErrorResponse {
+            code: ErrorCode::UnsupportedVersion,
+            message: format!(
+                "Protocol version {} is not supported. Use {} to {}",
+                client_version, MIN_PROTOCOL_VERSION, CURRENT_PROTOCOL_VERSION
+            ),
+        });
+    }
+    Ok(client_version.min(CURRENT_PROTOCOL_VERSION))
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub enum TakoyakiRequest {
     /// The first message sent from the client.
@@ -13,7 +61,7 @@ pub enum TakoyakiRequest {
     /// (i.e. the json message must be serialized in a single line and `'\n'` follows the message)
     /// Example:
     /// ```
-    /// r#"{"Manmenmi":{"preferred_format":"Json","name":"Ika"}}\n"#;
+    /// r#"{"Manmenmi":{"preferred_format":"Json","name":"Ika","protocol_version":1}}\n"#;
     /// ```
     Manmenmi(ManmenmiRequest),
 
@@ -22,6 +70,15 @@ pub enum TakoyakiRequest {
     AcceptHands(AcceptHandsRequest),
 
     SelectAction(SelectActionRequest),
+
+    /// Sent instead of `JoinGame` to watch the next game of `game_id` without playing.
+    /// The server sends a `StateUpdate` after every turn of the game and closes the connection
+    /// when the game ends.
+    Spectate(SpectateRequest),
+
+    /// The answer to a `Ping`. It may be sent at any time and doesn't replace the request the
+    /// server is waiting for.
+    Pong(Pong),
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -36,6 +93,14 @@ pub enum TakoyakiResponse {
     AcceptHands(AcceptHandsResponse),
 
     SelectAction(SelectActionResponse),
+
+    /// Sent to spectators. There is no request for it.
+    StateUpdate(StateUpdate),
+
+    /// Sent while the server waits for a request if keepalive is enabled. The client must
+    /// answer it with a `Pong` of the same ID before the next `Ping`, or it's regarded as dead.
+    /// It may arrive before the response to any request.
+    Ping(Ping),
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -57,23 +122,40 @@ impl ErrorResponse {
 pub struct ManmenmiRequest {
     pub preferred_format: WireFormat,
     pub name: String,
+
+    /// The version of the protocol the client implements. See `CURRENT_PROTOCOL_VERSION`.
+    #[serde(default)]
+    pub protocol_version: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct ManmenmiResponse {
     pub available_games: Vec<GameInfo>,
+
+    /// The version of the protocol used for the rest of the connection.
+    #[serde(default)]
+    pub protocol_version: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct JoinGameRequest {
     pub game_id: GameId,
     pub deck: Vec<CardId>,
+
+    /// Asks the server to send `board_delta` in every `SelectActionResponse`.
+    #[serde(default)]
+    pub board_delta: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct JoinGameResponse {
     pub player_id: PlayerId,
     pub initial_hands: Vec<CardId>,
+
+    /// Both players in a same session receive the same uid.
+    /// Clients can tag their own logs with it to correlate them with the server's log.
+    #[serde(default)]
+    pub game_uid: GameUid,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -93,10 +175,54 @@ pub struct SelectActionRequest {
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct SelectActionResponse {
+    /// The action taken by the opponent in this turn.
+    /// If the opponent forfeited without taking an action (e.g. disconnected), this is the
+    /// client's own action.
     pub opponent_action: Action,
     pub hands: Vec<CardId>,
 
     pub game_result: Option<Scores>,
+
+    /// Cells changed by actions of this turn, sent only if the client asked for it in
+    /// `JoinGameRequest`. Clients can use it to verify their own replay of the actions.
+    #[serde(default)]
+    pub board_delta: Option<BoardDelta>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct SpectateRequest {
+    pub game_id: GameId,
+}
+
+/// The state of a game after a turn, sent to spectators.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StateUpdate {
+    pub game_uid: GameUid,
+
+    /// The number of turns played so far.
+    pub turn: u32,
+
+    pub board: Board,
+    pub south_score: u32,
+    pub north_score: u32,
+
+    /// Actions taken in the turn. `None` if the player forfeited the game without taking an
+    /// action, which is sent only to spectators of `FORFEIT_UPDATE_PROTOCOL_VERSION` or later.
+    pub south_action: Option<Action>,
+    pub north_action: Option<Action>,
+
+    /// Set if the game ended at the turn, including by a forfeit.
+    pub game_result: Option<Scores>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Ping {
+    pub id: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Pong {
+    pub id: u32,
 }
 
 #[cfg(test)]
@@ -108,16 +234,40 @@ mod tests {
         let message = TakoyakiRequest::Manmenmi(ManmenmiRequest {
             preferred_format: WireFormat::Json,
             name: String::from("Ika"),
+            protocol_version: CURRENT_PROTOCOL_VERSION,
         });
         let serialized = serde_json::to_string(&message).unwrap();
         assert_eq!(
-            r#"{"Manmenmi":{"preferred_format":"Json","name":"Ika"}}"#,
+            r#"{"Manmenmi":{"preferred_format":"Json","name":"Ika","protocol_version":5}}"#,
             serialized
         );
         let deserialized: TakoyakiRequest = serde_json::from_str(&serialized).unwrap();
         assert_eq!(message, deserialized);
     }
 
+    #[test]
+    fn test_select_protocol_version() {
+        // Clients older than the negotiation don't send their version.
+        let old: TakoyakiRequest =
+            serde_json::from_str(r#"{"Manmenmi":{"preferred_format":"Json","name":"Ika"}}"#)
+                .unwrap();
+        let old_version = match old {
+            TakoyakiRequest::Manmenmi(req) => req.protocol_version,
+            req => panic!("Unexpected request: {:?}", req),
+        };
+        assert_eq!(Ok(0), select_protocol_version(old_version));
+
+        assert_eq!(
+            Ok(CURRENT_PROTOCOL_VERSION),
+            select_protocol_version(CURRENT_PROTOCOL_VERSION)
+        );
+        // The server can't speak versions newer than its own.
+        assert_eq!(
+            Ok(CURRENT_PROTOCOL_VERSION),
+            select_protocol_version(CURRENT_PROTOCOL_VERSION + 1)
+        );
+    }
+
     #[test]
     fn test_serialize_enum() {
         let message = TakoyakiResponse::Error(ErrorResponse {
@@ -0,0 +1,75 @@
+//! The only place which converts `PlayerId` between the wire format and the engine.
+//!
+//! Both sides use the same names:
+//!   - `South` is the first player. It's called "player" in the local runner and its index is 0.
+//!   - `North` is the second player. It's called "opponent" in the local runner and its index is 1.
+//!
+//! Conversions below must not use wildcard arms so that adding a variant fails to compile until
+//! it's handled here.
+
+use serde_repr::{
+    Deserialize_repr,
+    Serialize_repr,
+};
+
+use engine;
+
+#[derive(Serialize_repr, Deserialize_repr, Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(i8)]
+pub enum PlayerId {
+    South = 1,
+    North = -1,
+}
+
+impl From<PlayerId> for engine::PlayerId {
+    fn from(val: PlayerId) -> Self {
+        match val {
+            PlayerId::South => engine::PlayerId::South,
+            PlayerId::North => engine::PlayerId::North,
+        }
+    }
+}
+
+impl From<engine::PlayerId> for PlayerId {
+    fn from(val: engine::PlayerId) -> Self {
+        match val {
+            engine::PlayerId::South => PlayerId::South,
+            engine::PlayerId::North => PlayerId::North,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_from_proto() {
+        for pid in [PlayerId::South, PlayerId::North] {
+            let engine_pid: engine::PlayerId = pid.into();
+            assert_eq!(pid, PlayerId::from(engine_pid));
+        }
+        assert_eq!(
+            engine::PlayerId::South,
+            engine::PlayerId::from(PlayerId::South)
+        );
+        assert_eq!(
+            engine::PlayerId::North,
+            engine::PlayerId::from(PlayerId::North)
+        );
+    }
+
+    #[test]
+    fn test_round_trip_from_engine() {
+        for pid in [engine::PlayerId::South, engine::PlayerId::North] {
+            let proto_pid: PlayerId = pid.into();
+            assert_eq!(pid, engine::PlayerId::from(proto_pid));
+        }
+    }
+
+    #[test]
+    fn test_wire_format() {
+        assert_eq!("1", serde_json::to_string(&PlayerId::South).unwrap());
+        assert_eq!("-1", serde_json::to_string(&PlayerId::North).unwrap());
+    }
+}
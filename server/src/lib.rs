@@ -1,2 +1,6 @@
+pub mod lobby;
+pub mod matchmaker;
+pub mod results;
 pub mod session;
+pub mod session_loop;
 pub mod stats;
@@ -0,0 +1,106 @@
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use engine::{
+    Board,
+    Context,
+};
+use proto::{
+    connection::Error,
+    ErrorCode,
+    GameId,
+    GameInfo,
+    JoinGameRequest,
+    SpectateRequest,
+    TimeControl,
+};
+
+/// Games hosted by the server, one for each board. The ID of a game is its index.
+///
+/// Clients choose one of them in JoinGame before being matched, and are matched only with
+/// clients who chose the same game.
+#[derive(Debug)]
+pub struct Lobby {
+    context: Arc<Context>,
+    boards: Vec<Arc<Board>>,
+    time_control: TimeControl,
+
+    /// How often clients are pinged while the server waits for their requests.
+    keepalive_interval: Option<Duration>,
+}
+
+impl Lobby {
+    pub fn new(context: Arc<Context>, boards: Vec<Board>, time_control: TimeControl) -> Self {
+        Lobby {
+            context,
+            boards: boards.into_iter().map(Arc::new).collect(),
+            time_control,
+            keepalive_interval: None,
+        }
+    }
+
+    pub fn with_keepalive_interval(mut self, keepalive_interval: Option<Duration>) -> Self {
+        self.keepalive_interval = keepalive_interval;
+        self
+    }
+
+    pub fn get_context(&self) -> &Arc<Context> {
+        &self.context
+    }
+
+    pub fn get_board(&self, game_id: GameId) -> Option<&Arc<Board>> {
+        self.boards.get(game_id as usize)
+    }
+
+    pub fn get_time_control(&self) -> &TimeControl {
+        &self.time_control
+    }
+
+    pub fn get_keepalive_interval(&self) -> Option<Duration> {
+        self.keepalive_interval
+    }
+
+    /// Games advertised in the response to Manmenmi.
+    pub fn get_available_games(&self) -> Vec<GameInfo> {
+        self.boards
+            .iter()
+            .enumerate()
+            .map(|(game_id, board)| GameInfo {
+                game_id: game_id as GameId,
+                time_control: self.time_control.clone(),
+                board: proto::Board::from(board.as_ref()),
+                hand_size: engine::HAND_SIZE as u32,
+                deck_size: engine::DECK_SIZE as u32,
+            })
+            .collect()
+    }
+
+    /// Checks that the game a client chose exists and its deck can be used.
+    pub fn check_join_game(&self, join_game: &JoinGameRequest) -> Result<(), Error> {
+        self.check_game_id(join_game.game_id)?;
+        self.context
+            .validate_deck(&join_game.deck)
+            .map_err(|message| bad_request(format!("Invalid deck: {}", message)))
+    }
+
+    /// Checks that the game a spectator chose exists.
+    pub fn check_spectate(&self, spectate: &SpectateRequest) -> Result<(), Error> {
+        self.check_game_id(spectate.game_id)
+    }
+
+    fn check_game_id(&self, game_id: GameId) -> Result<(), Error> {
+        match self.get_board(game_id) {
+            Some(_) => Ok(()),
+            None => Err(bad_request(format!("Unknown game ID: {}", game_id))),
+        }
+    }
+}
+
+fn bad_request(message: String) -> Error {
+    Error {
+        code: ErrorCode::BadRequest,
+        message,
+    }
+}
@@ -1,64 +1,121 @@
 use clap::{
     self,
     Parser,
+    ValueHint,
 };
 use log::*;
 use proto::TimeControl;
 use rand_mt::Mt64;
 use std::{
+    fs::OpenOptions,
+    io::Write,
     path::PathBuf,
     sync::{
         Arc,
         Mutex,
     },
-    time::{
-        Duration,
-        Instant,
-    },
+    time::Duration,
 };
 use tokio::{
     self,
     net::TcpListener,
-    sync::mpsc::{
-        self,
-        Receiver,
-        Sender,
-    },
+    sync::mpsc,
 };
 
 use engine::{
     Board,
     Context,
+    DataDirArgs,
+    GameData,
+    RunConfig,
 };
 use server::{
-    session::{
+    lobby::Lobby,
+    matchmaker::MatchmakerType,
+    results::{
         self,
-        ClientConnection,
-        GameSession,
+        ResultsCsvWriter,
+    },
+    session_loop::{
+        accept_clients,
+        create_session_loop,
+    },
+    stats::{
+        StatsCounter,
+        DEFAULT_ELO_K,
     },
-    stats::StatsCounter,
 };
 
+// Use fixed seed for reproducible results.
+const SEED: u64 = 42;
+
+/// How long the server waits for games in progress on Ctrl-C.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(60);
+
 #[derive(Parser)]
 pub struct ServerArgs {
     #[clap(long, short, value_parser, default_value_t = 3333)]
     port: u32,
 
+    /// Board files. A game is hosted for each board and clients choose one of them.
+    /// Clients are matched only with others who chose the same game.
     #[clap(
         long,
         short,
         value_parser,
+        multiple_values = true,
         default_value = "data/boards/massugu_street"
     )]
-    board_path: PathBuf,
+    board_path: Vec<PathBuf>,
 
     /// a directory path where holds all card data. no need to specify for many cases.
     #[clap(long, value_parser, default_value_t = String::from("data/cards"))]
     card_dir: String,
 
+    #[clap(flatten)]
+    data: DataDirArgs,
+
     /// Specify the time limit in seconds.
-    #[clap(long, short, value_parser)]
+    #[clap(long, short, value_parser, conflicts_with = "base-time")]
     time_limit: Option<u32>,
+
+    /// Give each player a clock which starts with the specified seconds instead of the time
+    /// limit per action. The player loses when the clock runs out.
+    #[clap(long, value_parser)]
+    base_time: Option<u32>,
+
+    /// Seconds added to the clock after each action. Used with `--base-time`.
+    #[clap(long, value_parser, default_value_t = 0)]
+    increment: u32,
+
+    /// A policy to decide which waiting clients play a game together.
+    #[clap(long, value_parser, default_value = "fifo")]
+    matchmaker: MatchmakerType,
+
+    /// Ping clients at the interval while waiting for their requests, and forfeit a client
+    /// which doesn't answer a ping until the next one. Disabled if not specified.
+    #[clap(long, value_parser)]
+    keepalive_interval_ms: Option<u64>,
+
+    /// The maximum number of games played at once. Clients matched while the server is at
+    /// capacity are rejected with `ServerBusy`.
+    #[clap(long, value_parser)]
+    max_sessions: Option<usize>,
+
+    /// The K-factor of ELO ratings of players, i.e. how much a game can change a rating.
+    /// The `elo` matchmaker pairs players by the ratings.
+    #[clap(long, value_parser, default_value_t = DEFAULT_ELO_K)]
+    elo_k: f64,
+
+    /// Append decks, actions and scores of every game to the file.
+    /// Use `verify_log` of the local runner to check them against the current engine.
+    #[clap(long, value_parser, value_hint=ValueHint::FilePath)]
+    match_log: Option<PathBuf>,
+
+    /// Append players, scores and the winner of every game to the CSV file.
+    /// The header is written only if the file is empty.
+    #[clap(long, value_parser, value_hint=ValueHint::FilePath)]
+    results_csv: Option<PathBuf>,
 }
 
 fn main() {
@@ -67,119 +124,164 @@ fn main() {
     );
     let args = ServerArgs::parse();
 
-    let all_cards = engine::load_cards(&args.card_dir);
-    let context = Context {
-        all_cards,
-        enabled_step_execution: false,
+    let data: Option<GameData> = args.data.load_or_exit();
+    let (context, boards): (Context, Vec<Board>) = match data {
+        Some(data) => {
+            let boards = args
+                .board_path
+                .iter()
+                .map(|path| data.resolve_board(path))
+                .collect();
+            (data.context, boards)
+        }
+        None => (
+            Context {
+                all_cards: engine::load_cards(&args.card_dir).unwrap_or_else(|e| {
+                    error!("Failed to load cards: {}", e);
+                    std::process::exit(1);
+                }),
+                enabled_step_execution: false,
+                rules: engine::RuleSet::default(),
+            },
+            args.board_path.iter().map(engine::load_board).collect(),
+        ),
     };
-    run_server(context, args);
+    for board in &boards {
+        if let Err(e) = context.validate_board(board) {
+            error!("Invalid board {}: {}", board.get_name(), e);
+            std::process::exit(1);
+        }
+    }
+    info!("Config: {}", run_config(&args, &boards));
+    run_server(context, boards, args);
 }
 
-async fn create_session_loop(
-    context: Arc<Context>,
-    board: Board,
-    seed: u64,
-    args: ServerArgs,
-) -> Sender<ClientConnection> {
-    let mut rng = Mt64::from(seed);
-    let (sender, mut receiver): (Sender<ClientConnection>, Receiver<ClientConnection>) =
-        mpsc::channel(8);
-    info!("Create session loop is started");
-    tokio::spawn(async move {
-        let stats_counter = Arc::new(Mutex::new(StatsCounter::new()));
-        let print_interval = Arc::new(Mutex::new(Instant::now()));
-        loop {
-            let c0 = receiver
-                .recv()
-                .await
-                .expect("Server closed while receiving.");
-            info!("Client 0 joined: {:?}", c0.name);
-            let c1 = receiver
-                .recv()
-                .await
-                .expect("Server closed while receiving.");
-            info!("Client 1 joined: {:?}", c1.name);
-            let seed = rng.next_u64();
-            let board = board.clone();
-            let context = context.clone();
-            let stats_counter = stats_counter.clone();
-            let print_interval = print_interval.clone();
-            let time_control = match args.time_limit {
-                Some(secs) => TimeControl::PerAction {
-                    time_limit_in_seconds: secs,
-                },
-                None => TimeControl::Infinite,
-            };
-            tokio::spawn(async move {
-                let context = context;
-                let board = board;
-                let client_south = c0;
-                let client_north = c1;
-                let rng = Mt64::from(seed);
-                let session = Arc::new(GameSession::new(
-                    context,
-                    Arc::new(board),
-                    time_control,
-                    client_south,
-                    client_north,
-                    rng,
-                ));
-                let result = session.start().await;
-                match result {
-                    Ok(r) => {
-                        let mut sc = stats_counter.lock().unwrap();
-                        info!("Result: {} v.s. {}", r.0, r.1);
-                        sc.push_result(&r.0, &r.1);
-
-                        let mut print_interval = print_interval.lock().unwrap();
-
-                        // Print once per second at most.
-                        if print_interval.elapsed() > Duration::from_secs(1) {
-                            info!("{}", sc);
-                            *print_interval = Instant::now();
-                        }
-                    }
-                    Err(e) => todo!("Handle error: {:?}", e),
-                }
-            });
-        }
-    });
-    sender
+fn run_config(args: &ServerArgs, boards: &[Board]) -> RunConfig {
+    let board_names: Vec<&str> = boards.iter().map(|board| board.get_name()).collect();
+    let mut config = RunConfig::new("server", SEED)
+        .with_board(&board_names.join(","))
+        .with_param("port", args.port)
+        .with_param("matchmaker", format!("{:?}", args.matchmaker))
+        .with_param("elo_k", args.elo_k);
+    if let Some(keepalive_interval_ms) = args.keepalive_interval_ms {
+        config = config.with_param("keepalive_interval_ms", keepalive_interval_ms);
+    }
+    if let Some(max_sessions) = args.max_sessions {
+        config = config.with_param("max_sessions", max_sessions);
+    }
+    if let Some(time_limit) = args.time_limit {
+        config = config.with_param("time_limit", time_limit);
+    }
+    if let Some(base_time) = args.base_time {
+        config = config
+            .with_param("base_time", base_time)
+            .with_param("increment", args.increment);
+    }
+    if let Some(data_dir) = &args.data.data_dir {
+        config = config.with_param("data_dir", data_dir.display());
+    }
+    if let Some(match_log) = &args.match_log {
+        config = config.with_param("match_log", match_log.display());
+    }
+    if let Some(results_csv) = &args.results_csv {
+        config = config.with_param("results_csv", results_csv.display());
+    }
+    config
 }
 
-async fn run_server_async(context: Context, args: ServerArgs) {
-    let mut rng = Mt64::from(42);
-    let shared_context = Arc::new(context.clone());
+async fn run_server_async(context: Context, boards: Vec<Board>, args: ServerArgs) {
+    let mut rng = Mt64::from(SEED);
     let listener: TcpListener = TcpListener::bind(&format!("127.0.0.1:{}", args.port))
         .await
         .unwrap_or_else(|err| panic!("Failed to listen on the port: {}\n{}", args.port, err));
     info!("Listening at localhost:{}", args.port);
-    let board = engine::load_board(&args.board_path);
-
-    let client_sender =
-        create_session_loop(shared_context.clone(), board, rng.next_u64(), args).await;
-    loop {
-        debug!("Waiting for a new client.");
-        match listener.accept().await {
-            Ok((stream, addr)) => {
-                let sender = client_sender.clone();
-                let seed = rng.next_u64();
-                tokio::spawn(async move {
-                    info!("New client is coming from {}", addr);
-                    session::try_establish_connection(stream, sender, seed).await;
-                });
-            }
-            Err(e) => {
-                warn!("Listener is closed: {:?}", e);
-                break;
-            }
-        };
+
+    let stats_counter = Arc::new(Mutex::new(StatsCounter::new().with_elo_k(args.elo_k)));
+    let ratings = stats_counter.clone();
+    let matchmaker = args
+        .matchmaker
+        .create_matchmaker(move |name| ratings.lock().unwrap().get_rating(name));
+    let time_control = match (args.time_limit, args.base_time) {
+        (Some(secs), _) => TimeControl::PerAction {
+            time_limit_in_seconds: secs,
+        },
+        (None, Some(base_seconds)) => TimeControl::Increment {
+            base_seconds,
+            increment_seconds: args.increment,
+        },
+        (None, None) => TimeControl::Infinite,
+    };
+    // Each writer has its own channel so that no game is lost while another one is writing.
+    // Writers finish once all games are over.
+    let mut outcome_senders = vec![];
+    let mut writers = vec![];
+    if let Some(path) = args.results_csv.clone() {
+        let writer = ResultsCsvWriter::open(&path).unwrap_or_else(|e| {
+            error!("Failed to open the results CSV {:?}: {}", path, e);
+            std::process::exit(1);
+        });
+        let (outcome_sender, outcome_receiver) = mpsc::unbounded_channel();
+        outcome_senders.push(outcome_sender);
+        writers.push(tokio::spawn(results::write_results_csv(
+            writer,
+            path,
+            outcome_receiver,
+        )));
+    }
+    if let Some(path) = args.match_log.clone() {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .unwrap_or_else(|e| {
+                error!("Failed to open the match log {:?}: {}", path, e);
+                std::process::exit(1);
+            });
+        let (outcome_sender, outcome_receiver) = mpsc::unbounded_channel();
+        outcome_senders.push(outcome_sender);
+        writers.push(tokio::spawn(results::write_outcomes(
+            file,
+            format!("the match log {:?}", path),
+            outcome_receiver,
+            |file, event| write!(file, "{}", event.record),
+        )));
+    }
+    let lobby = Arc::new(
+        Lobby::new(Arc::new(context), boards, time_control)
+            .with_keepalive_interval(args.keepalive_interval_ms.map(Duration::from_millis)),
+    );
+    let session_loop = create_session_loop(
+        lobby.clone(),
+        rng.next_u64(),
+        matchmaker,
+        args.max_sessions,
+        stats_counter.clone(),
+        outcome_senders,
+    )
+    .await;
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .unwrap_or_else(|e| panic!("Failed to listen for Ctrl-C: {}", e));
+        info!("Shutting down. Waiting for games in progress...");
+    };
+    let client_sender = session_loop.get_client_sender().clone();
+    accept_clients(listener, lobby, client_sender, rng, ctrl_c).await;
+
+    let finished = session_loop.shutdown(SHUTDOWN_TIMEOUT).await;
+    info!("{}", stats_counter.lock().unwrap());
+    if !finished {
+        warn!("Games in progress didn't finish in {:?}", SHUTDOWN_TIMEOUT);
+        return;
+    }
+    for writer in writers {
+        writer.await.unwrap();
     }
 }
 
-pub fn run_server(context: Context, args: ServerArgs) {
+pub fn run_server(context: Context, boards: Vec<Board>, args: ServerArgs) {
     let rt = tokio::runtime::Runtime::new().unwrap();
 
-    rt.block_on(async move { run_server_async(context, args).await });
+    rt.block_on(async move { run_server_async(context, boards, args).await });
     info!("Server is exiting...");
 }
@@ -0,0 +1,158 @@
+use clap::ArgEnum;
+
+use crate::session::ClientConnection;
+
+/// The rating used for players who have never played a game.
+pub const DEFAULT_RATING: f64 = 1500.0;
+
+/// Information about a waiting client which matchmakers can use to pair clients.
+pub trait MatchCandidate {
+    fn get_name(&self) -> &str;
+}
+
+impl MatchCandidate for ClientConnection {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// A policy deciding which waiting clients play a game together.
+pub trait Matchmaker<C: MatchCandidate = ClientConnection>: Send {
+    /// Removes a pair of clients from `waiting` and returns them if there is a match.
+    /// The first one of the pair plays as South.
+    fn try_pair(&mut self, waiting: &mut Vec<C>) -> Option<(C, C)>;
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchmakerType {
+    /// Pairs clients in the order they joined.
+    Fifo,
+
+    /// Pairs the longest waiting client with the one who has the closest rating.
+    Elo,
+}
+
+impl MatchmakerType {
+    pub fn create_matchmaker<F>(&self, rating_of: F) -> Box<dyn Matchmaker>
+    where
+        F: Fn(&str) -> f64 + Send + 'static,
+    {
+        match self {
+            MatchmakerType::Fifo => Box::new(FifoMatchmaker),
+            MatchmakerType::Elo => Box::new(EloProximityMatchmaker::new(rating_of)),
+        }
+    }
+}
+
+pub struct FifoMatchmaker;
+
+impl<C: MatchCandidate> Matchmaker<C> for FifoMatchmaker {
+    fn try_pair(&mut self, waiting: &mut Vec<C>) -> Option<(C, C)> {
+        if waiting.len() < 2 {
+            return None;
+        }
+        let a = waiting.remove(0);
+        let b = waiting.remove(0);
+        Some((a, b))
+    }
+}
+
+pub struct EloProximityMatchmaker {
+    rating_of: Box<dyn Fn(&str) -> f64 + Send>,
+}
+
+impl EloProximityMatchmaker {
+    pub fn new<F>(rating_of: F) -> Self
+    where
+        F: Fn(&str) -> f64 + Send + 'static,
+    {
+        Self {
+            rating_of: Box::new(rating_of),
+        }
+    }
+}
+
+impl<C: MatchCandidate> Matchmaker<C> for EloProximityMatchmaker {
+    fn try_pair(&mut self, waiting: &mut Vec<C>) -> Option<(C, C)> {
+        if waiting.len() < 2 {
+            return None;
+        }
+        let rating = (self.rating_of)(waiting[0].get_name());
+        let mut best_index = 1;
+        let mut best_diff = f64::MAX;
+        for (i, c) in waiting.iter().enumerate().skip(1) {
+            let diff = ((self.rating_of)(c.get_name()) - rating).abs();
+            if diff < best_diff {
+                best_diff = diff;
+                best_index = i;
+            }
+        }
+        let b = waiting.remove(best_index);
+        let a = waiting.remove(0);
+        Some((a, b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct MockClient {
+        name: String,
+    }
+
+    impl MatchCandidate for MockClient {
+        fn get_name(&self) -> &str {
+            &self.name
+        }
+    }
+
+    fn new_waiting(names: &[&str]) -> Vec<MockClient> {
+        names
+            .iter()
+            .map(|name| MockClient {
+                name: name.to_string(),
+            })
+            .collect()
+    }
+
+    fn names(pair: Option<(MockClient, MockClient)>) -> Option<(String, String)> {
+        pair.map(|(a, b)| (a.name, b.name))
+    }
+
+    #[test]
+    fn test_fifo() {
+        let mut waiting = new_waiting(&["a", "b", "c"]);
+        let mut matchmaker = FifoMatchmaker;
+
+        assert_eq!(
+            Some(("a".into(), "b".into())),
+            names(matchmaker.try_pair(&mut waiting))
+        );
+        assert_eq!(None, names(matchmaker.try_pair(&mut waiting)));
+        assert_eq!(1, waiting.len());
+    }
+
+    #[test]
+    fn test_elo_proximity() {
+        let mut waiting = new_waiting(&["a", "b", "c", "d"]);
+        let mut matchmaker = EloProximityMatchmaker::new(|name| match name {
+            "a" => 1500.0,
+            "b" => 1800.0,
+            "c" => 1450.0,
+            "d" => 1850.0,
+            _ => unreachable!(),
+        });
+
+        assert_eq!(
+            Some(("a".into(), "c".into())),
+            names(matchmaker.try_pair(&mut waiting))
+        );
+        assert_eq!(
+            Some(("b".into(), "d".into())),
+            names(matchmaker.try_pair(&mut waiting))
+        );
+        assert_eq!(None, names(matchmaker.try_pair(&mut waiting)));
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::{
+        self,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use log::*;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use engine::PlayerId;
+
+use crate::session::GameOutcomeEvent;
+
+/// Columns written by `ResultsCsvWriter`.
+pub const RESULTS_CSV_HEADER: [&str; 9] = [
+    "game",
+    "timestamp",
+    "board",
+    "south_player",
+    "north_player",
+    "south_score",
+    "north_score",
+    "winner",
+    "duration_secs",
+];
+
+/// Appends a row to a CSV file for each game played on the server.
+/// The header is written only when the file is empty so that a restarted server keeps appending
+/// results to the same file.
+pub struct ResultsCsvWriter {
+    file: File,
+}
+
+impl ResultsCsvWriter {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", RESULTS_CSV_HEADER.join(","))?;
+            file.flush()?;
+        }
+        Ok(ResultsCsvWriter {
+            file,
+        })
+    }
+
+    /// Writes a row of the game which ended at `timestamp`.
+    pub fn write_event(
+        &mut self,
+        event: &GameOutcomeEvent,
+        timestamp: SystemTime,
+    ) -> io::Result<()> {
+        let outcome = &event.outcome;
+        let timestamp = timestamp
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let fields = [
+            format!("{:016x}", event.game_uid),
+            timestamp.to_string(),
+            event.board.get_name().to_string(),
+            outcome.south.name.clone(),
+            outcome.north.name.clone(),
+            outcome.south.score.to_string(),
+            outcome.north.score.to_string(),
+            outcome
+                .get_winner()
+                .map_or(String::new(), |p: PlayerId| p.to_string().to_lowercase()),
+            outcome
+                .duration
+                .map_or(String::new(), |d| format!("{:.3}", d.as_secs_f64())),
+        ];
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|field| engine::escape_csv_field(field))
+            .collect();
+        writeln!(self.file, "{}", fields.join(","))?;
+        self.file.flush()
+    }
+}
+
+/// Writes every game sent to `receiver` to the CSV file.
+pub async fn write_results_csv(
+    writer: ResultsCsvWriter,
+    path: PathBuf,
+    receiver: UnboundedReceiver<GameOutcomeEvent>,
+) {
+    write_outcomes(
+        writer,
+        format!("the results CSV {:?}", path),
+        receiver,
+        |writer, event| writer.write_event(event, SystemTime::now()),
+    )
+    .await
+}
+
+/// Writes every game sent to `receiver` with `write` until all senders are dropped.
+/// `write` runs in a blocking thread so that file writes never stall the runtime. Games are
+/// written by this task only, so sessions finishing at the same time never interleave.
+/// `name` tells the destination in logs.
+pub async fn write_outcomes<W, F>(
+    mut writer: W,
+    name: String,
+    mut receiver: UnboundedReceiver<GameOutcomeEvent>,
+    write: F,
+) where
+    W: Send + 'static,
+    F: Fn(&mut W, &GameOutcomeEvent) -> io::Result<()> + Copy + Send + 'static,
+{
+    while let Some(event) = receiver.recv().await {
+        let (returned, result) = tokio::task::spawn_blocking(move || {
+            let result = write(&mut writer, &event);
+            (writer, result)
+        })
+        .await
+        .expect("Writing a game never panics");
+        writer = returned;
+        if let Err(e) = result {
+            error!("Failed to write {}: {}", name, e);
+        }
+    }
+}
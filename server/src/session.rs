@@ -1,6 +1,5 @@
 use log::*;
 use paste::paste;
-use rand::seq::SliceRandom;
 use rand_mt::Mt64;
 use std::{
     sync::Arc,
@@ -12,6 +11,10 @@ use std::{
 use tokio::{
     net::TcpStream,
     sync::{
+        broadcast::{
+            self,
+            error::RecvError,
+        },
         mpsc::Sender,
         Mutex,
     },
@@ -22,6 +25,8 @@ use engine::{
     self,
     Board,
     Context,
+    GameOutcome,
+    MatchRecord,
     PlayerCardState,
     State,
 };
@@ -34,16 +39,39 @@ use proto::{
     *,
 };
 
-use crate::stats::NamedScore;
+use crate::lobby::Lobby;
+
+/// Extra time given to clients on top of the time limit for the network latency.
+const TIME_LIMIT_MARGIN: Duration = Duration::from_secs(1);
+
+/// How long the server waits for each request before a client joins a game.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a game session ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameOutcomeEvent {
+    pub game_uid: GameUid,
+    pub outcome: GameOutcome,
+
+    /// The final board.
+    pub board: Board,
+
+    /// Decks and actions of the game, which can be verified later.
+    pub record: MatchRecord,
+}
 
 /// An object represents a session of a game
 #[derive(Debug)]
 pub struct GameSession {
+    uid: GameUid,
     context: Arc<Context>,
     board: Arc<Board>,
-    time_control: TimeControl,
     client_south: Arc<Mutex<ClientConnection>>,
     client_north: Arc<Mutex<ClientConnection>>,
+
+    /// Publishes the state after each turn to spectators.
+    /// Spectators receive it in their own tasks so that slow ones never delay the players.
+    state_sender: broadcast::Sender<StateUpdate>,
 }
 
 impl GameSession {
@@ -53,55 +81,130 @@ impl GameSession {
         time_control: TimeControl,
         mut client_south: ClientConnection,
         mut client_north: ClientConnection,
-        _rng: Mt64,
+        mut rng: Mt64,
     ) -> Self {
         client_south.set_player_id(PlayerId::South);
         client_north.set_player_id(PlayerId::North);
+        client_south.set_time_control(&time_control);
+        client_north.set_time_control(&time_control);
+        // Large enough to hold all updates of a game even if a spectator hasn't read any.
+        let (state_sender, _) = broadcast::channel(engine::TURN_COUNT as usize);
         Self {
+            uid: rng.next_u64(),
             context,
             board,
-            time_control,
             client_south: Arc::new(Mutex::new(client_south)),
             client_north: Arc::new(Mutex::new(client_north)),
+            state_sender,
         }
     }
 
-    pub async fn start(&self) -> Result<(NamedScore, NamedScore), Error> {
-        info!("New game session is started.");
+    pub fn get_uid(&self) -> GameUid {
+        self.uid
+    }
+
+    /// Sends a `StateUpdate` to the spectator after every turn played from now on.
+    /// The connection is closed when the game ends.
+    pub fn add_spectator(&self, mut spectator: ClientConnection) {
+        let mut receiver = self.state_sender.subscribe();
+        let uid = self.uid;
+        tokio::spawn(async move {
+            loop {
+                let update = match receiver.recv().await {
+                    Ok(update) => update,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!(
+                            "Game {:016x}: {} missed {} updates",
+                            uid, spectator.name, skipped
+                        );
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let has_actions = update.south_action.is_some() && update.north_action.is_some();
+                if !has_actions
+                    && spectator.protocol_version < proto::FORFEIT_UPDATE_PROTOCOL_VERSION
+                {
+                    // The spectator can't parse it. The connection is closed as the game is over.
+                    break;
+                }
+                let res = TakoyakiResponse::StateUpdate(update);
+                if let Err(e) = spectator.send_response(&res).await {
+                    info!(
+                        "Game {:016x}: {} stopped spectating: {:?}",
+                        uid, spectator.name, e
+                    );
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Plays the game to the end.
+    ///
+    /// A client which disconnects, sends a broken request or runs out of time forfeits the game.
+    /// The other client is told the result in response to its next action.
+    pub async fn start(&self) -> GameOutcomeEvent {
+        info!("New game session is started. uid: {:016x}", self.uid);
 
-        let board = self.board.clone();
-        let time_control = self.time_control.clone();
+        let uid = self.uid;
         let south = self.client_south.clone();
         let ctx = self.context.clone();
-        let h_ps =
-            tokio::spawn(async move { Self::init_player(ctx, board, time_control, south).await });
+        let h_ps = tokio::spawn(async move { Self::init_player(uid, ctx, south).await });
 
-        let board = self.board.clone();
-        let time_control = self.time_control.clone();
         let north = self.client_north.clone();
         let ctx = self.context.clone();
-        let h_pn =
-            tokio::spawn(async move { Self::init_player(ctx, board, time_control, north).await });
-
-        let mut north_state: PlayerCardState = match h_pn.await {
-            Ok(Ok(v)) => v,
-            _ => todo!(),
-        };
-        let mut south_state: PlayerCardState = match h_ps.await {
-            Ok(Ok(v)) => v,
-            _ => todo!(),
+        let h_pn = tokio::spawn(async move { Self::init_player(uid, ctx, north).await });
+
+        let south_state = h_ps.await.unwrap();
+        let north_state = h_pn.await.unwrap();
+        let deck = |player_state: &Result<PlayerCardState, Error>| {
+            player_state
+                .as_ref()
+                .map_or(vec![], |s| engine::to_ids(&s.get_all_cards()))
         };
-
+        let mut record = MatchRecord::new(
+            self.uid,
+            (*self.board).clone(),
+            deck(&south_state),
+            deck(&north_state),
+        );
         let t_start_game = Instant::now();
 
-        let state = Arc::new(Mutex::new(State::new(
+        let state = Arc::new(Mutex::new(State::new_game(
             (*self.board).clone(),
-            0,
-            0,
-            0,
-            vec![],
-            vec![],
+            self.context.rules,
         )));
+        let (mut south_state, mut north_state) = match (south_state, north_state) {
+            (Ok(south_state), Ok(north_state)) => (south_state, north_state),
+            (Err(e), _) => {
+                return self
+                    .forfeit(
+                        engine::PlayerId::South,
+                        e,
+                        None,
+                        state,
+                        record,
+                        t_start_game,
+                    )
+                    .await
+            }
+            (_, Err(e)) => {
+                return self
+                    .forfeit(
+                        engine::PlayerId::North,
+                        e,
+                        None,
+                        state,
+                        record,
+                        t_start_game,
+                    )
+                    .await
+            }
+        };
+        // Board deltas are computed only if either client asked for them.
+        let south_wants_delta = self.client_south.lock().await.board_delta;
+        let north_wants_delta = self.client_north.lock().await.board_delta;
         for turn in 0..engine::TURN_COUNT {
             debug!(
                 "Turn {}, Player state: {}, {}",
@@ -113,149 +216,334 @@ impl GameSession {
             let north = self.client_north.clone();
             let action_n = tokio::spawn(async move { Self::get_action(north).await });
 
-            let action_s = action_s.await.unwrap()?;
-            let action_n = action_n.await.unwrap()?;
+            let action_s = action_s.await.unwrap();
+            let action_n = action_n.await.unwrap();
+            let (action_s, action_n) = match (action_s, action_n) {
+                (Ok(action_s), Ok(action_n)) => (action_s, action_n),
+                (Err(e), action_n) => {
+                    return self
+                        .forfeit(
+                            engine::PlayerId::South,
+                            e,
+                            action_n.ok(),
+                            state,
+                            record,
+                            t_start_game,
+                        )
+                        .await
+                }
+                (action_s, Err(e)) => {
+                    return self
+                        .forfeit(
+                            engine::PlayerId::North,
+                            e,
+                            action_s.ok(),
+                            state,
+                            record,
+                            t_start_game,
+                        )
+                        .await
+                }
+            };
             debug!("action_s: {:?}", action_s);
             debug!("action_n: {:?}", action_n);
 
             let south_action = action_s.convert(&self.context);
             let north_action = action_n.convert(&self.context);
-            {
+            let turn_result = {
                 let mut state = state.lock().await;
-                engine::update_state(&mut state, &south_action, &north_action);
-                engine::update_player_state(&state, &mut south_state, &south_action);
-                engine::update_player_state(&state, &mut north_state, &north_action);
-            }
+                // The hands are checked before the state is updated so that nothing is applied
+                // for an illegal action.
+                let result = engine::check_card_in_hands(&south_state, &south_action)
+                    .and_then(|()| engine::check_card_in_hands(&north_state, &north_action))
+                    .and_then(|()| {
+                        if south_wants_delta || north_wants_delta {
+                            engine::update_state_with_delta(
+                                &mut state,
+                                &south_action,
+                                &north_action,
+                            )
+                            .map(Some)
+                        } else {
+                            engine::update_state(&mut state, &south_action, &north_action)
+                                .map(|()| None)
+                        }
+                    });
+                match result {
+                    Ok(delta) => {
+                        engine::update_player_state(&state, &mut south_state, &south_action);
+                        engine::update_player_state(&state, &mut north_state, &north_action);
+                        record.push_turn(south_action, north_action, state.get_scores());
+                        self.publish_state(&state, Some(action_s), Some(action_n));
+                        Ok(delta.as_ref().map(proto::BoardDelta::from))
+                    }
+                    Err(violation) => {
+                        // The offending player loses the game immediately.
+                        state.forfeit(violation.player_id);
+                        self.publish_state(&state, Some(action_s), Some(action_n));
+                        Err(violation)
+                    }
+                }
+            };
+            let board_delta = match turn_result {
+                Ok(board_delta) => board_delta,
+                Err(violation) => {
+                    warn!("Game {:016x}: {}", self.uid, violation);
+                    self.reject_action(&violation, action_s, action_n, state.clone())
+                        .await;
+                    let st = state.lock().await;
+                    return self.finish(&st, t_start_game.elapsed(), record).await;
+                }
+            };
 
             let state_s = state.clone();
             let south = self.client_south.clone();
             let hands = engine::to_ids(south_state.get_hands());
             let opponent_action = action_n;
+            let delta = board_delta.clone().filter(|_| south_wants_delta);
             let send_result_s = tokio::spawn(async move {
-                Self::send_result(&opponent_action, hands, state_s, south).await
+                Self::send_result(&opponent_action, hands, delta, state_s, south).await
             });
             let state_n = state.clone();
             let north = self.client_north.clone();
             let hands = engine::to_ids(north_state.get_hands());
             let opponent_action = action_s;
+            let delta = board_delta.filter(|_| north_wants_delta);
             let send_result_n = tokio::spawn(async move {
-                Self::send_result(&opponent_action, hands, state_n, north).await
+                Self::send_result(&opponent_action, hands, delta, state_n, north).await
             });
 
-            send_result_s.await.unwrap().unwrap();
-            send_result_n.await.unwrap().unwrap();
-
-            let st = state.lock().await;
-            if st.is_end() {
-                info!("Elapsed time: {:?}", t_start_game.elapsed());
-                let scores = st.board.get_scores();
-                return Ok((
-                    NamedScore::new(&self.client_south.lock().await.name, scores.0),
-                    NamedScore::new(&self.client_north.lock().await.name, scores.1),
-                ));
+            let sent_results = [
+                (engine::PlayerId::South, send_result_s.await.unwrap()),
+                (engine::PlayerId::North, send_result_n.await.unwrap()),
+            ];
+            let is_end = state.lock().await.is_end();
+            for (player_id, sent) in sent_results {
+                match sent {
+                    Ok(()) => {}
+                    Err(e) if is_end => {
+                        warn!("Failed to send the game result to {}: {:?}", player_id, e)
+                    }
+                    Err(e) => {
+                        return self
+                            .forfeit(player_id, e, None, state, record, t_start_game)
+                            .await
+                    }
+                }
+            }
+            if is_end {
+                break;
             }
         }
-        panic!();
+        let st = state.lock().await;
+        self.finish(&st, t_start_game.elapsed(), record).await
+    }
+
+    /// Ends the game since `offender` failed to talk with the server, e.g. it disconnected.
+    /// The offender is told `error` if it's still connected.
+    ///
+    /// The other client receives the result in response to its action of the current turn,
+    /// which is read from the client unless it's already known as `survivor_action`.
+    async fn forfeit(
+        &self,
+        offender: engine::PlayerId,
+        error: Error,
+        survivor_action: Option<Action>,
+        state: Arc<Mutex<State>>,
+        record: MatchRecord,
+        t_start_game: Instant,
+    ) -> GameOutcomeEvent {
+        warn!(
+            "Game {:016x}: {} forfeited the game: {:?}",
+            self.uid, offender, error
+        );
+        state.lock().await.forfeit(offender);
+        let offender_id = offender;
+        let (offender, survivor) = match offender {
+            engine::PlayerId::South => (&self.client_south, &self.client_north),
+            engine::PlayerId::North => (&self.client_north, &self.client_south),
+        };
+        // The offender may be gone already.
+        let error = TakoyakiResponse::Error(err_to_res(error));
+        if let Err(e) = offender.lock().await.send_response(&error).await {
+            debug!("Failed to send an error response: {:?}", e);
+        }
+        let survivor_action = match survivor_action {
+            Some(action) => Ok(action),
+            None => Self::get_action(survivor.clone()).await,
+        };
+        match &survivor_action {
+            Ok(action) => {
+                // The offender took no action. The survivor's own action is sent back instead.
+                let result =
+                    Self::send_result(action, vec![], None, state.clone(), survivor.clone()).await;
+                if let Err(e) = result {
+                    warn!("Failed to send the game result: {:?}", e);
+                }
+            }
+            Err(e) => warn!("Failed to receive an action from the other client: {:?}", e),
+        }
+        let st = state.lock().await;
+        let survivor_action = survivor_action.ok();
+        let (south_action, north_action) = match offender_id {
+            engine::PlayerId::South => (None, survivor_action),
+            engine::PlayerId::North => (survivor_action, None),
+        };
+        self.publish_state(&st, south_action, north_action);
+        self.finish(&st, t_start_game.elapsed(), record).await
+    }
+
+    /// Sends the state after a turn, or after a forfeit which ended the game, to spectators
+    /// if any.
+    fn publish_state(
+        &self,
+        state: &State,
+        south_action: Option<Action>,
+        north_action: Option<Action>,
+    ) {
+        if self.state_sender.receiver_count() == 0 {
+            return;
+        }
+        let (south_score, north_score) = state.get_scores();
+        let update = StateUpdate {
+            game_uid: self.uid,
+            turn: state.get_turn() as u32,
+            board: proto::Board::from(&state.board),
+            south_score,
+            north_score,
+            south_action,
+            north_action,
+            game_result: get_game_result(state),
+        };
+        // An error only means that all spectators have left.
+        let _ = self.state_sender.send(update);
+    }
+
+    async fn finish(
+        &self,
+        state: &State,
+        duration: Duration,
+        record: MatchRecord,
+    ) -> GameOutcomeEvent {
+        info!("Elapsed time: {:?}", duration);
+        GameOutcomeEvent {
+            game_uid: self.uid,
+            outcome: GameOutcome::from_state(
+                &self.client_south.lock().await.name,
+                &self.client_north.lock().await.name,
+                state,
+            )
+            .with_duration(duration),
+            board: state.board.clone(),
+            record,
+        }
+    }
+
+    /// Tells the offending client why it lost with an `ErrorResponse` and sends the final result
+    /// to the other client. The illegal action isn't applied to the board.
+    async fn reject_action(
+        &self,
+        violation: &engine::RuleViolation,
+        action_s: Action,
+        action_n: Action,
+        state: Arc<Mutex<State>>,
+    ) {
+        let (offender, opponent, illegal_action) = match violation.player_id {
+            engine::PlayerId::South => (&self.client_south, &self.client_north, action_s),
+            engine::PlayerId::North => (&self.client_north, &self.client_south, action_n),
+        };
+        let error = TakoyakiResponse::Error(ErrorResponse {
+            code: ErrorCode::IllegalAction,
+            message: violation.to_string(),
+        });
+        if let Err(e) = offender.lock().await.send_response(&error).await {
+            warn!("Failed to send an error response: {:?}", e);
+        }
+        // The game is over so no hands are left to play.
+        let result =
+            Self::send_result(&illegal_action, vec![], None, state, opponent.clone()).await;
+        if let Err(e) = result {
+            warn!("Failed to send the game result: {:?}", e);
+        }
     }
 
     async fn init_player(
+        uid: GameUid,
         context: Arc<Context>,
-        board: Arc<Board>,
-        time_control: TimeControl,
         client: Arc<Mutex<ClientConnection>>,
     ) -> Result<PlayerCardState, Error> {
         let mut client = client.lock().await;
 
-        let mut deck_ids = Self::get_deck(board, time_control, &mut client).await?;
-        let state = Self::deal_hands(&context, &mut deck_ids, &mut client).await?;
+        let deck_ids = client.deck.clone();
+        let state = Self::deal_hands(uid, &context, &deck_ids, &mut client).await?;
         Ok(state)
     }
 
-    async fn get_deck(
-        board: Arc<Board>,
-        time_control: TimeControl,
-        client: &mut ClientConnection,
-    ) -> Result<Vec<u32>, Error> {
-        client
-            .send_response(&TakoyakiResponse::Manmenmi(
-                // TODO: Support multiple types of game with other boards.
-                ManmenmiResponse {
-                    available_games: vec![GameInfo {
-                        game_id: 0,
-                        time_control,
-                        board: proto::Board::from(board.as_ref()),
-                    }],
-                },
-            ))
-            .await?;
-
-        let join_game = client.recv_join_game().await?;
-        Ok(join_game.deck)
-    }
-
     async fn deal_hands<'a>(
+        uid: GameUid,
         context: &Arc<Context>,
-        deck_ids: &mut Vec<u32>,
+        deck_ids: &[u32],
         client: &mut ClientConnection,
     ) -> Result<PlayerCardState, Error> {
-        deck_ids.shuffle(&mut client.rng);
+        let (mut hand_ids, mut deck_ids) =
+            engine::deal(deck_ids, engine::HAND_SIZE, &mut client.rng);
 
         client
             .send_response(&TakoyakiResponse::JoinGame(JoinGameResponse {
                 player_id: client.player_id,
-                initial_hands: deck_ids[0..engine::HAND_SIZE].to_vec(),
+                initial_hands: hand_ids.clone(),
+                game_uid: uid,
             }))
             .await?;
 
         let accept_hands = client.recv_accept_hands().await?;
         if !accept_hands.accept {
             // The client has asked us to re-deal hands.
-            deck_ids.shuffle(&mut client.rng);
+            (hand_ids, deck_ids) = engine::redeal(hand_ids, deck_ids, &mut client.rng);
         }
 
-        let (hand_ids, deck_ids) = deck_ids.split_at(engine::HAND_SIZE);
         client
             .send_response(&TakoyakiResponse::AcceptHands(AcceptHandsResponse {
-                hands: hand_ids.to_vec(),
+                hands: hand_ids.clone(),
             }))
             .await?;
 
         Ok(PlayerCardState::new(
             client.player_id.into(),
-            context.get_cards(hand_ids),
-            context.get_cards(deck_ids),
+            context.get_cards(&hand_ids),
+            context.get_cards(&deck_ids),
         ))
     }
 
+    /// Receives an action with a card in the client's deck, which is validated in JoinGame, so
+    /// that the card can be converted without knowing whether it's in the hands yet.
     async fn get_action(client: Arc<Mutex<ClientConnection>>) -> Result<Action, Error> {
         let mut client = client.lock().await;
-        let select = client.recv_select_action().await?;
+        let select = client.recv_action().await?;
+        let card_id = select.action.get_card_id();
+        if !client.deck.contains(&card_id) {
+            return Err(Error {
+                code: ErrorCode::IllegalAction,
+                message: format!("The card {} isn't in the deck", card_id),
+            });
+        }
         Ok(select.action)
     }
 
     async fn send_result(
         opponent_action: &Action,
         hands: Vec<CardId>,
+        board_delta: Option<proto::BoardDelta>,
         state: Arc<Mutex<State>>,
         client: Arc<Mutex<ClientConnection>>,
     ) -> Result<(), Error> {
         let mut client = client.lock().await;
-        let game_result = {
-            let state = state.lock().await;
-            if state.is_end() {
-                let (s, n) = state.board.get_scores();
-                Some(Scores {
-                    south_score: s,
-                    north_score: n,
-                })
-            } else {
-                None
-            }
-        };
+        let game_result = get_game_result(&*state.lock().await);
         let res = SelectActionResponse {
             opponent_action: *opponent_action,
             hands,
             game_result,
+            board_delta,
         };
         client
             .send_response(&TakoyakiResponse::SelectAction(res))
@@ -264,17 +552,100 @@ impl GameSession {
     }
 }
 
+/// The result sent to clients, or None if the game hasn't ended yet.
+fn get_game_result(state: &State) -> Option<Scores> {
+    if !state.is_end() {
+        return None;
+    }
+    let (s, n) = state.get_scores();
+    Some(Scores {
+        south_score: s,
+        north_score: n,
+        forfeit: state.get_forfeited_player().map(PlayerId::from),
+    })
+}
+
+/// Tells the client games in the lobby and waits for the client to choose one of them, either to
+/// play or to spectate.
+async fn join_lobby(lobby: &Lobby, client: &mut ClientConnection) -> Result<(), Error> {
+    client
+        .send_response(&TakoyakiResponse::Manmenmi(ManmenmiResponse {
+            available_games: lobby.get_available_games(),
+            protocol_version: client.protocol_version,
+        }))
+        .await?;
+    if client.protocol_version >= proto::KEEPALIVE_PROTOCOL_VERSION {
+        client.keepalive_interval = lobby.get_keepalive_interval();
+    }
+
+    let request = match timeout(HANDSHAKE_TIMEOUT, client.recv_request()).await {
+        Ok(request) => request?,
+        Err(_elapsed) => {
+            return Err(Error {
+                code: ErrorCode::Timeout,
+                message: format!("JoinGame wasn't received in {:?}", HANDSHAKE_TIMEOUT),
+            })
+        }
+    };
+    match request {
+        TakoyakiRequest::JoinGame(join_game) => {
+            lobby.check_join_game(&join_game)?;
+            client.game_id = Some(join_game.game_id);
+            client.deck = join_game.deck;
+            client.board_delta = join_game.board_delta;
+        }
+        TakoyakiRequest::Spectate(spectate)
+            if client.protocol_version >= proto::SPECTATE_PROTOCOL_VERSION =>
+        {
+            lobby.check_spectate(&spectate)?;
+            client.game_id = Some(spectate.game_id);
+            client.spectating = true;
+        }
+        request => {
+            return Err(Error {
+                code: ErrorCode::BadRequest,
+                message: format!("Expected request type: JoinGame but {:?}", request),
+            })
+        }
+    }
+    Ok(())
+}
+
+/// Sends the client to `client_sender` once it joins one of the games in the lobby.
 pub async fn try_establish_connection(
     stream: TcpStream,
+    lobby: Arc<Lobby>,
     client_sender: Sender<ClientConnection>,
     seed: u64,
 ) {
     let mut conn = Connection::new(stream);
-    match timeout(Duration::from_secs(10), conn.recv()).await {
+    match timeout(HANDSHAKE_TIMEOUT, conn.recv()).await {
         Ok(Ok(TakoyakiRequest::Manmenmi(m))) => {
             conn.set_preferred_format(m.preferred_format);
-            let client = ClientConnection::new(m.name, Mt64::new(seed), conn);
-            client_sender.send(client).await.unwrap();
+            let protocol_version = match proto::select_protocol_version(m.protocol_version) {
+                Ok(version) => version,
+                Err(res) => {
+                    info!("Rejected {}: {}", m.name, res.message);
+                    conn.send(&TakoyakiResponse::Error(res))
+                        .await
+                        .unwrap_or_default();
+                    return;
+                }
+            };
+            let mut client = ClientConnection::new(m.name, Mt64::new(seed), conn);
+            client.protocol_version = protocol_version;
+            if let Err(e) = join_lobby(&lobby, &mut client).await {
+                info!("Rejected {}: {}", client.name, e.message);
+                client
+                    .send_response(&TakoyakiResponse::Error(err_to_res(e)))
+                    .await
+                    .unwrap_or_default();
+                return;
+            }
+            if let Err(e) = client_sender.send(client).await {
+                // The server is shutting down.
+                info!("Rejected {}: No game is started anymore", e.0.name);
+            }
         }
         Ok(Ok(_)) => {
             conn.send(&TakoyakiResponse::Error(ErrorResponse {
@@ -302,8 +673,47 @@ pub struct ClientConnection {
     pub name: String,
     pub player_id: PlayerId,
 
+    /// The game the client wants to join or spectate.
+    pub game_id: Option<GameId>,
+
+    /// The deck sent in JoinGame.
+    pub deck: Vec<CardId>,
+
+    /// Whether the client asked for board deltas in JoinGame.
+    pub board_delta: bool,
+
+    /// Whether the client sent Spectate instead of JoinGame to watch the game.
+    pub spectating: bool,
+
     pub rng: Mt64,
     pub connection: Connection,
+
+    /// How long the server waits for each request during a game. The client forfeits the game
+    /// if it doesn't send a request in time.
+    pub time_limit: Option<Duration>,
+
+    /// The clock of the client under `TimeControl::Increment`.
+    pub clock: Option<Clock>,
+
+    /// The protocol version selected for the client in Manmenmi.
+    pub protocol_version: u32,
+
+    /// How often the client is pinged while the server waits for a request. The client is
+    /// regarded as dead if it doesn't answer a ping until the next one.
+    pub keepalive_interval: Option<Duration>,
+
+    /// The ID of the last ping sent to the client.
+    last_ping_id: u32,
+}
+
+/// A chess clock with Fischer increment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Clock {
+    /// Time the client can spend for the rest of the game.
+    pub remaining: Duration,
+
+    /// Time added to the clock after each action.
+    pub increment: Duration,
 }
 
 fn err_to_res(e: Error) -> ErrorResponse {
@@ -338,6 +748,15 @@ impl ClientConnection {
             rng,
             connection,
             player_id: PlayerId::North,
+            game_id: None,
+            deck: vec![],
+            board_delta: false,
+            spectating: false,
+            time_limit: None,
+            clock: None,
+            protocol_version: proto::CURRENT_PROTOCOL_VERSION,
+            keepalive_interval: None,
+            last_ping_id: 0,
         }
     }
 
@@ -345,15 +764,190 @@ impl ClientConnection {
         self.player_id = pid;
     }
 
+    /// Sets up the time limit or the clock of the client for a game played with `time_control`.
+    pub fn set_time_control(&mut self, time_control: &TimeControl) {
+        (self.time_limit, self.clock) = match *time_control {
+            TimeControl::Infinite => (None, None),
+            TimeControl::PerAction {
+                time_limit_in_seconds,
+            } => (
+                Some(Duration::from_secs(time_limit_in_seconds.into()) + TIME_LIMIT_MARGIN),
+                None,
+            ),
+            TimeControl::Increment {
+                base_seconds,
+                increment_seconds,
+            } => (
+                None,
+                Some(Clock {
+                    remaining: Duration::from_secs(base_seconds.into()),
+                    increment: Duration::from_secs(increment_seconds.into()),
+                }),
+            ),
+        };
+    }
+
+    /// Receives an action of the client.
+    /// If the client has a clock, time spent until the action arrives is deducted from it and the
+    /// client gets the increment. Returns a `Timeout` error once the clock runs out.
+    pub async fn recv_action(&mut self) -> Result<SelectActionRequest, Error> {
+        let mut clock = match self.clock {
+            Some(clock) => clock,
+            None => return self.recv_select_action().await,
+        };
+        let timer = Instant::now();
+        let time_limit = clock.remaining + TIME_LIMIT_MARGIN;
+        let request = match timeout(time_limit, self.recv_select_action()).await {
+            Ok(request) => request?,
+            Err(_elapsed) => {
+                return Err(Error {
+                    code: ErrorCode::Timeout,
+                    message: format!("The clock ran out: {:?} was left", clock.remaining),
+                })
+            }
+        };
+        clock.remaining = clock.remaining.saturating_sub(timer.elapsed()) + clock.increment;
+        self.clock = Some(clock);
+        Ok(request)
+    }
+
     pub async fn recv_request(&mut self) -> Result<TakoyakiRequest, Error> {
-        self.connection.recv::<TakoyakiRequest>().await
+        let time_limit = match self.time_limit {
+            Some(time_limit) => time_limit,
+            None => return self.recv_request_alive().await,
+        };
+        match timeout(time_limit, self.recv_request_alive()).await {
+            Ok(result) => result,
+            Err(_elapsed) => Err(Error {
+                code: ErrorCode::Timeout,
+                message: format!("No request was received in {:?}", time_limit),
+            }),
+        }
+    }
+
+    /// Receives a request while pinging the client if keepalive is enabled.
+    /// Returns a `Timeout` error if the client doesn't answer a ping until the next one.
+    async fn recv_request_alive(&mut self) -> Result<TakoyakiRequest, Error> {
+        let interval = match self.keepalive_interval {
+            Some(interval) => interval,
+            None => return self.connection.recv::<TakoyakiRequest>().await,
+        };
+        let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+        let mut waiting_pong = None;
+        loop {
+            tokio::select! {
+                request = self.connection.recv::<TakoyakiRequest>() => match request? {
+                    TakoyakiRequest::Pong(pong) => {
+                        if waiting_pong == Some(pong.id) {
+                            waiting_pong = None;
+                        }
+                    }
+                    request => return Ok(request),
+                },
+                _ = ticker.tick() => {
+                    if let Some(id) = waiting_pong {
+                        return Err(Error {
+                            code: ErrorCode::Timeout,
+                            message: format!("No Pong was received for Ping {} in {:?}", id, interval),
+                        });
+                    }
+                    self.last_ping_id = self.last_ping_id.wrapping_add(1);
+                    let ping = TakoyakiResponse::Ping(Ping { id: self.last_ping_id });
+                    self.send_response(&ping).await?;
+                    waiting_pong = Some(self.last_ping_id);
+                }
+            }
+        }
     }
 
     pub async fn send_response(&mut self, response: &TakoyakiResponse) -> Result<(), Error> {
         self.connection.send::<TakoyakiResponse>(response).await
     }
 
-    def_rpc!(JoinGame);
     def_rpc!(AcceptHands);
     def_rpc!(SelectAction);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tokio::net::TcpListener;
+
+    use engine::Card;
+    use players::Player;
+
+    use super::*;
+
+    /// Always (or never) asks for a redeal.
+    struct RedealingPlayer {
+        redeal: bool,
+    }
+
+    impl Player for RedealingPlayer {
+        fn get_name(&self) -> &str {
+            "redealing"
+        }
+
+        fn init_game(&mut self, _: engine::PlayerId, _: &Arc<Context>, _: &Board, _: Vec<Card>) {}
+
+        fn need_redeal_hands(&mut self, _: &[Card], _: &Duration) -> bool {
+            self.redeal
+        }
+
+        fn get_action(&mut self, _: &State, hands: &[Card], _: &Duration) -> engine::Action {
+            engine::Action::Pass(hands[0].clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deal_hands_same_as_local() {
+        let data_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../data");
+        let context = Arc::new(Context {
+            all_cards: engine::load_cards(data_dir.join("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        });
+        let deck_ids = engine::load_deck(&data_dir.join("decks/starter"));
+        let deck = context.get_cards(&deck_ids);
+
+        for redeal in [false, true] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let mut conn = Connection::new(
+                TcpStream::connect(listener.local_addr().unwrap())
+                    .await
+                    .unwrap(),
+            );
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut client =
+                ClientConnection::new("p".into(), Mt64::new(42), Connection::new(stream));
+            client.player_id = PlayerId::South;
+
+            let answer_hands = async {
+                conn.recv::<TakoyakiResponse>().await.unwrap();
+                conn.send(&TakoyakiRequest::AcceptHands(AcceptHandsRequest {
+                    accept: !redeal,
+                }))
+                .await
+                .unwrap();
+                conn.recv::<TakoyakiResponse>().await.unwrap();
+            };
+            let (server_cards, _) = tokio::join!(
+                GameSession::deal_hands(0, &context, &deck_ids, &mut client),
+                answer_hands
+            );
+            let server_cards = server_cards.unwrap();
+
+            let local_cards = local::deal_hands(
+                &mut Mt64::new(42),
+                &deck,
+                engine::PlayerId::South,
+                &mut RedealingPlayer {
+                    redeal,
+                },
+                local::DealPolicy::Shuffle,
+            );
+            assert_eq!(local_cards, server_cards);
+        }
+    }
+}
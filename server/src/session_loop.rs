@@ -0,0 +1,1128 @@
+use log::*;
+use rand_mt::Mt64;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use tokio::{
+    net::TcpListener,
+    sync::{
+        mpsc::{
+            self,
+            Receiver,
+            Sender,
+            UnboundedSender,
+        },
+        Semaphore,
+    },
+    time::timeout,
+};
+
+use proto::{
+    ErrorCode,
+    ErrorResponse,
+    GameId,
+    TakoyakiResponse,
+};
+
+use crate::{
+    lobby::Lobby,
+    matchmaker::Matchmaker,
+    session::{
+        self,
+        ClientConnection,
+        GameOutcomeEvent,
+        GameSession,
+    },
+    stats::StatsCounter,
+};
+
+/// A handle of the task spawned by `create_session_loop`.
+pub struct SessionLoop {
+    client_sender: Sender<ClientConnection>,
+
+    /// Closed once the loop and all games it started are over.
+    finished: Receiver<()>,
+}
+
+impl SessionLoop {
+    /// Clients sent to it are paired and play games.
+    pub fn get_client_sender(&self) -> &Sender<ClientConnection> {
+        &self.client_sender
+    }
+
+    /// Waits for games in progress to finish. The loop stops pairing clients once all clones of
+    /// the client sender are dropped, and clients who are still waiting are disconnected.
+    ///
+    /// Returns false if the games didn't finish in `time_limit`.
+    pub async fn shutdown(self, time_limit: Duration) -> bool {
+        drop(self.client_sender);
+        let mut finished = self.finished;
+        timeout(time_limit, finished.recv()).await.is_ok()
+    }
+}
+
+/// Spawns a task which pairs clients sent to the returned sender and plays games between them.
+/// Clients wait in a queue for each game of `lobby` and are paired only with clients in the
+/// same queue. Spectators wait for the next game of the game ID they chose.
+///
+/// At most `max_sessions` games are played at once if specified. Pairs matched while the server
+/// is at capacity receive a `ServerBusy` error and are disconnected.
+///
+/// Results are counted in `stats_counter`, which is printed periodically.
+/// An event is sent to each of `outcome_senders` every time a game completes. The channels are
+/// unbounded so that a slow receiver, e.g. a file writer, never loses events. It's fine that a
+/// receiver is dropped.
+pub async fn create_session_loop(
+    lobby: Arc<Lobby>,
+    seed: u64,
+    mut matchmaker: Box<dyn Matchmaker>,
+    max_sessions: Option<usize>,
+    stats_counter: Arc<Mutex<StatsCounter>>,
+    outcome_senders: Vec<UnboundedSender<GameOutcomeEvent>>,
+) -> SessionLoop {
+    let mut rng = Mt64::from(seed);
+    let (sender, mut receiver): (Sender<ClientConnection>, Receiver<ClientConnection>) =
+        mpsc::channel(8);
+    // Every task holds a clone until it ends.
+    let (finished_sender, finished) = mpsc::channel(1);
+    let sessions = max_sessions.map(|max_sessions| Arc::new(Semaphore::new(max_sessions)));
+    info!("Create session loop is started");
+    tokio::spawn(async move {
+        let print_interval = Arc::new(Mutex::new(Instant::now()));
+        let mut waiting: HashMap<GameId, Vec<ClientConnection>> = HashMap::new();
+        let mut spectators: HashMap<GameId, Vec<ClientConnection>> = HashMap::new();
+        loop {
+            let client = match receiver.recv().await {
+                Some(client) => client,
+                None => {
+                    let left: usize = waiting
+                        .values()
+                        .chain(spectators.values())
+                        .map(Vec::len)
+                        .sum();
+                    info!(
+                        "Session loop is stopped. {} clients are left without games",
+                        left
+                    );
+                    break;
+                }
+            };
+            // Spectators may leave while their games are waiting for players. They are dropped
+            // here so that they don't pile up in the queues.
+            spectators.retain(|game_id, queue| {
+                queue.retain(|spectator| {
+                    let closed = spectator.connection.is_closed();
+                    if closed {
+                        info!("Spectator left the game {}: {:?}", game_id, spectator.name);
+                    }
+                    !closed
+                });
+                !queue.is_empty()
+            });
+            let game_id = client
+                .game_id
+                .expect("Clients join a game before being matched");
+            if client.spectating {
+                info!("Spectator joined the game {}: {:?}", game_id, client.name);
+                spectators.entry(game_id).or_default().push(client);
+                continue;
+            }
+            info!("Client joined the game {}: {:?}", game_id, client.name);
+            let queue = waiting.entry(game_id).or_default();
+            queue.push(client);
+            let (c0, c1) = match matchmaker.try_pair(queue) {
+                Some(pair) => pair,
+                None => continue,
+            };
+            info!("Matched: {:?} v.s. {:?}", c0.name, c1.name);
+            // Released when the game ends.
+            let permit = match sessions.as_ref().map(|s| s.clone().try_acquire_owned()) {
+                None => None,
+                Some(Ok(permit)) => Some(permit),
+                Some(Err(_)) => {
+                    warn!(
+                        "Too many games in progress. Rejected {:?} and {:?}",
+                        c0.name, c1.name
+                    );
+                    tokio::spawn(reject_busy(c0));
+                    tokio::spawn(reject_busy(c1));
+                    continue;
+                }
+            };
+            let seed = rng.next_u64();
+            let board = lobby
+                .get_board(game_id)
+                .expect("The lobby checks game IDs")
+                .clone();
+            let context = lobby.get_context().clone();
+            let stats_counter = stats_counter.clone();
+            let print_interval = print_interval.clone();
+            let time_control = lobby.get_time_control().clone();
+            let outcome_senders = outcome_senders.clone();
+            let spectators = spectators.remove(&game_id).unwrap_or_default();
+            let finished_sender = finished_sender.clone();
+            tokio::spawn(async move {
+                let client_south = c0;
+                let client_north = c1;
+                let rng = Mt64::from(seed);
+                let session = Arc::new(GameSession::new(
+                    context,
+                    board,
+                    time_control,
+                    client_south,
+                    client_north,
+                    rng,
+                ));
+                for spectator in spectators {
+                    session.add_spectator(spectator);
+                }
+                let event = session.start().await;
+                drop(permit);
+                {
+                    let mut sc = stats_counter.lock().unwrap();
+                    info!("Result of {:016x}: {}", event.game_uid, event.outcome);
+                    sc.push_outcome(&event.outcome);
+
+                    let mut print_interval = print_interval.lock().unwrap();
+
+                    // Print once per second at most.
+                    if print_interval.elapsed() > Duration::from_secs(1) {
+                        info!("{}", sc);
+                        *print_interval = Instant::now();
+                    }
+                }
+                for outcome_sender in &outcome_senders {
+                    // An error only means that the receiver is gone.
+                    let _ = outcome_sender.send(event.clone());
+                }
+                drop(finished_sender);
+            });
+        }
+    });
+    SessionLoop {
+        client_sender: sender,
+        finished,
+    }
+}
+
+async fn reject_busy(mut client: ClientConnection) {
+    // Older clients fail to parse the unknown error code.
+    let code = if client.protocol_version >= proto::SERVER_BUSY_PROTOCOL_VERSION {
+        ErrorCode::ServerBusy
+    } else {
+        ErrorCode::BadRequest
+    };
+    let res = TakoyakiResponse::Error(ErrorResponse {
+        code,
+        message: "Too many games are in progress. Try again later".into(),
+    });
+    if let Err(e) = client.send_response(&res).await {
+        debug!("Failed to send an error response: {:?}", e);
+    }
+}
+
+/// Accepts connections and sends clients to `client_sender` once they join a game in the lobby.
+/// Stops accepting new connections once `shutdown` completes.
+pub async fn accept_clients(
+    listener: TcpListener,
+    lobby: Arc<Lobby>,
+    client_sender: Sender<ClientConnection>,
+    mut rng: Mt64,
+    shutdown: impl Future<Output = ()>,
+) {
+    tokio::pin!(shutdown);
+    loop {
+        debug!("Waiting for a new client.");
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = &mut shutdown => {
+                info!("Stopped accepting new clients");
+                break;
+            }
+        };
+        match accepted {
+            Ok((stream, addr)) => {
+                let lobby = lobby.clone();
+                let sender = client_sender.clone();
+                let seed = rng.next_u64();
+                tokio::spawn(async move {
+                    info!("New client is coming from {}", addr);
+                    session::try_establish_connection(stream, lobby, sender, seed).await;
+                });
+            }
+            Err(e) => {
+                warn!("Listener is closed: {:?}", e);
+                break;
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tokio::{
+        net::TcpStream,
+        sync::oneshot,
+    };
+
+    use engine::Context;
+    use proto::{
+        connection::Connection,
+        *,
+    };
+
+    use super::*;
+    use crate::{
+        matchmaker::FifoMatchmaker,
+        results::{
+            self,
+            ResultsCsvWriter,
+            RESULTS_CSV_HEADER,
+        },
+        stats::NamedScore,
+    };
+
+    /// Connects to the server and sends Manmenmi. Returns the connection and the response.
+    async fn send_test_manmenmi(
+        host: String,
+        name: &str,
+        protocol_version: u32,
+    ) -> (Connection, TakoyakiResponse) {
+        let mut conn = Connection::new(TcpStream::connect(host).await.unwrap());
+        conn.send(&TakoyakiRequest::Manmenmi(ManmenmiRequest {
+            preferred_format: WireFormat::Json,
+            name: name.to_string(),
+            protocol_version,
+        }))
+        .await
+        .unwrap();
+        let res = conn.recv().await.unwrap();
+        (conn, res)
+    }
+
+    /// Sends JoinGame after Manmenmi. Returns the connection and the response.
+    async fn send_test_join_game(
+        host: String,
+        name: &str,
+        game_id: GameId,
+        deck: Vec<CardId>,
+    ) -> (Connection, TakoyakiResponse) {
+        let (mut conn, _) = send_test_manmenmi(host, name, CURRENT_PROTOCOL_VERSION).await;
+
+        conn.send(&TakoyakiRequest::JoinGame(JoinGameRequest {
+            game_id,
+            deck,
+            board_delta: false,
+        }))
+        .await
+        .unwrap();
+        let res = conn.recv().await.unwrap();
+        (conn, res)
+    }
+
+    /// Joins a game and accepts the initial hands. Returns the connection and the hands.
+    async fn join_test_game(
+        host: String,
+        name: &str,
+        game_id: GameId,
+        deck: Vec<CardId>,
+    ) -> (Connection, Vec<u32>) {
+        let (mut conn, _) = send_test_join_game(host, name, game_id, deck).await;
+
+        conn.send(&TakoyakiRequest::AcceptHands(AcceptHandsRequest {
+            accept: true,
+        }))
+        .await
+        .unwrap();
+        match conn.recv().await.unwrap() {
+            TakoyakiResponse::AcceptHands(res) => (conn, res.hands),
+            res => panic!("Unexpected response: {:?}", res),
+        }
+    }
+
+    /// A client which passes every turn after waiting for `delay`.
+    /// Returns the last response, which has the result of the game or an error.
+    async fn play_slow_client(
+        host: String,
+        name: &str,
+        game_id: GameId,
+        deck: Vec<CardId>,
+        delay: Duration,
+    ) -> TakoyakiResponse {
+        let (conn, hands) = join_test_game(host, name, game_id, deck).await;
+        finish_slow_game(conn, hands, delay).await
+    }
+
+    /// Passes every turn of a joined game after waiting for `delay`.
+    async fn finish_slow_game(
+        mut conn: Connection,
+        mut hands: Vec<u32>,
+        delay: Duration,
+    ) -> TakoyakiResponse {
+        loop {
+            // The server may end the game while the client is thinking.
+            if let Ok(res) = tokio::time::timeout(delay, conn.recv()).await {
+                return res.unwrap();
+            }
+            conn.send(&TakoyakiRequest::SelectAction(SelectActionRequest {
+                action: Action::Pass(hands[0]),
+            }))
+            .await
+            .unwrap();
+            match conn.recv().await.unwrap() {
+                TakoyakiResponse::SelectAction(res) if res.game_result.is_none() => {
+                    hands = res.hands;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// A client which passes every turn in the game 0. Returns the result of the game.
+    async fn play_passing_client(host: String, name: &str, deck: Vec<CardId>) -> Scores {
+        play_passing_client_in(host, name, 0, deck).await
+    }
+
+    async fn play_passing_client_in(
+        host: String,
+        name: &str,
+        game_id: GameId,
+        deck: Vec<CardId>,
+    ) -> Scores {
+        match play_slow_client(host, name, game_id, deck, Duration::ZERO).await {
+            TakoyakiResponse::SelectAction(res) => res.game_result.unwrap(),
+            res => panic!("Unexpected response: {:?}", res),
+        }
+    }
+
+    /// A client which passes every turn after thinking for `delay` while answering pings.
+    /// Returns the result of the game and the number of pings received.
+    async fn play_pong_client(
+        host: String,
+        name: &str,
+        deck: Vec<CardId>,
+        delay: Duration,
+    ) -> (Scores, u32) {
+        let (mut conn, mut hands) = join_test_game(host, name, 0, deck).await;
+        let mut pings = 0;
+        // Answers pings until a response other than Ping arrives.
+        async fn recv_answering(conn: &mut Connection, pings: &mut u32) -> TakoyakiResponse {
+            loop {
+                match conn.recv().await.unwrap() {
+                    TakoyakiResponse::Ping(ping) => {
+                        *pings += 1;
+                        conn.send(&TakoyakiRequest::Pong(Pong {
+                            id: ping.id,
+                        }))
+                        .await
+                        .unwrap();
+                    }
+                    res => return res,
+                }
+            }
+        }
+        loop {
+            // Thinking.
+            if let Ok(res) =
+                tokio::time::timeout(delay, recv_answering(&mut conn, &mut pings)).await
+            {
+                panic!("Unexpected response: {:?}", res);
+            }
+            conn.send(&TakoyakiRequest::SelectAction(SelectActionRequest {
+                action: Action::Pass(hands[0]),
+            }))
+            .await
+            .unwrap();
+            // A ping sent while the action is on the way arrives before the response.
+            match recv_answering(&mut conn, &mut pings).await {
+                TakoyakiResponse::SelectAction(res) => match res.game_result {
+                    Some(scores) => return (scores, pings),
+                    None => hands = res.hands,
+                },
+                res => panic!("Unexpected response: {:?}", res),
+            }
+        }
+    }
+
+    /// A client which joins a game and closes the connection without accepting hands.
+    async fn play_leaving_client(host: String, name: &str, deck: Vec<CardId>) {
+        send_test_join_game(host, name, 0, deck).await;
+    }
+
+    /// Starts a session loop for a game on `board`. Returns the address of the listener.
+    async fn start_test_server(
+        context: Context,
+        board: engine::Board,
+        time_control: TimeControl,
+        outcome_sender: UnboundedSender<GameOutcomeEvent>,
+    ) -> String {
+        let lobby = Lobby::new(Arc::new(context), vec![board], time_control);
+        start_test_lobby(lobby, None, outcome_sender).await
+    }
+
+    /// Starts a session loop and a listener which passes connections to it.
+    /// Returns the address of the listener.
+    async fn start_test_lobby(
+        lobby: Lobby,
+        max_sessions: Option<usize>,
+        outcome_sender: UnboundedSender<GameOutcomeEvent>,
+    ) -> String {
+        let lobby = Arc::new(lobby);
+        let session_loop = create_session_loop(
+            lobby.clone(),
+            42,
+            Box::new(FifoMatchmaker),
+            max_sessions,
+            Arc::new(Mutex::new(StatsCounter::new())),
+            vec![outcome_sender],
+        )
+        .await;
+        let client_sender = session_loop.get_client_sender().clone();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            // Clients are handled one by one so that they are matched in the order they connect.
+            for seed in 0.. {
+                let (stream, _) = listener.accept().await.unwrap();
+                session::try_establish_connection(
+                    stream,
+                    lobby.clone(),
+                    client_sender.clone(),
+                    seed,
+                )
+                .await;
+            }
+        });
+        host
+    }
+
+    fn load_test_data() -> (Context, engine::Board, Vec<CardId>) {
+        let data_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../data");
+        let context = Context {
+            all_cards: engine::load_cards(data_dir.join("cards").to_str().unwrap()).unwrap(),
+            enabled_step_execution: false,
+            rules: engine::RuleSet::default(),
+        };
+        let board = engine::load_board(&data_dir.join("boards/massugu_street"));
+        let deck = engine::load_deck(&data_dir.join("decks/starter"));
+        (context, board, deck)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_outcome_event_per_game() {
+        let (context, board, deck) = load_test_data();
+        let (outcome_sender, mut outcome_receiver) = mpsc::unbounded_channel();
+        let host = start_test_server(
+            context,
+            board.clone(),
+            TimeControl::Infinite,
+            outcome_sender,
+        )
+        .await;
+
+        for game in 0..2 {
+            let south = play_passing_client(host.clone(), "south", deck.clone());
+            let north = play_passing_client(host.clone(), "north", deck.clone());
+            tokio::join!(south, north);
+
+            let event = outcome_receiver.recv().await.unwrap();
+            let outcome = &event.outcome;
+            // Nobody inks any cell.
+            assert_eq!(
+                NamedScore::new("south", board.get_scores().0),
+                outcome.south,
+                "game: {}",
+                game
+            );
+            assert_eq!(
+                NamedScore::new("north", board.get_scores().1),
+                outcome.north,
+                "game: {}",
+                game
+            );
+            assert_eq!(engine::TURN_COUNT as u32, outcome.turns_played);
+            assert_eq!(None, outcome.forfeit);
+            assert!(outcome.duration.is_some());
+            assert_eq!(board, event.board);
+            assert_eq!(Ok(()), event.record.verify(engine::RuleSet::default()));
+            assert_eq!(
+                engine::TURN_COUNT as usize,
+                event.record.replay.get_turns().len()
+            );
+        }
+        assert!(outcome_receiver.try_recv().is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_results_csv() {
+        let (context, board, deck) = load_test_data();
+        let (outcome_sender, mut outcome_receiver) = mpsc::unbounded_channel();
+        let host = start_test_server(
+            context,
+            board.clone(),
+            TimeControl::Infinite,
+            outcome_sender,
+        )
+        .await;
+
+        let path =
+            std::env::temp_dir().join(format!("takoyaki-results-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        for _ in 0..2 {
+            let south = play_passing_client(host.clone(), "south", deck.clone());
+            let north = play_passing_client(host.clone(), "north, the 2nd", deck.clone());
+            tokio::join!(south, north);
+
+            // Reopened for each game as if the server restarted.
+            let event = outcome_receiver.recv().await.unwrap();
+            let mut writer = ResultsCsvWriter::open(&path).unwrap();
+            writer
+                .write_event(&event, std::time::UNIX_EPOCH + Duration::from_secs(1234))
+                .unwrap();
+        }
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(3, lines.len(), "{}", text);
+        assert_eq!(RESULTS_CSV_HEADER.join(","), lines[0]);
+        let (south_score, north_score) = board.get_scores();
+        for line in &lines[1..] {
+            let (uid, rest) = line.split_once(',').unwrap();
+            assert_eq!(16, uid.len(), "{}", line);
+            let (rest, duration) = rest.rsplit_once(',').unwrap();
+            assert!(duration.parse::<f64>().is_ok(), "{}", line);
+            // Nobody inks any cell, so the game is a draw.
+            assert_eq!(
+                format!(
+                    "1234,{},south,\"north, the 2nd\",{},{},",
+                    board.get_name(),
+                    south_score,
+                    north_score
+                ),
+                rest
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rules_in_records() {
+        let (mut context, board, deck) = load_test_data();
+        context.rules.score_offsets = [-1, 2];
+        let rules = context.rules;
+        let (outcome_sender, mut outcome_receiver) = mpsc::unbounded_channel();
+        let host = start_test_server(
+            context,
+            board.clone(),
+            TimeControl::Infinite,
+            outcome_sender,
+        )
+        .await;
+        let south = play_passing_client(host.clone(), "south", deck.clone());
+        let north = play_passing_client(host.clone(), "north", deck.clone());
+        tokio::join!(south, north);
+
+        // The recorded scores agree with the outcome, which counts the offsets.
+        let event = outcome_receiver.recv().await.unwrap();
+        let (south_score, north_score) = board.get_scores();
+        let scores = (south_score - 1, north_score + 2);
+        assert_eq!(
+            scores,
+            (event.outcome.south.score, event.outcome.north.score)
+        );
+        assert_eq!(Some(&scores), event.record.turn_scores.last());
+        assert_eq!(Ok(()), event.record.verify(rules));
+        assert!(event.record.verify(engine::RuleSet::default()).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_write_outcomes_without_loss() {
+        let (context, board, deck) = load_test_data();
+        let (outcome_sender, mut outcome_receiver) = mpsc::unbounded_channel();
+        let host = start_test_server(context, board, TimeControl::Infinite, outcome_sender).await;
+        let south = play_passing_client(host.clone(), "south", deck.clone());
+        let north = play_passing_client(host.clone(), "north", deck.clone());
+        tokio::join!(south, north);
+        let event = outcome_receiver.recv().await.unwrap();
+
+        // Games finishing while a slow write is in flight wait for the writer.
+        const GAMES: usize = 100;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        for _ in 0..GAMES {
+            sender.send(event.clone()).unwrap();
+        }
+        drop(sender);
+        let written = Arc::new(Mutex::new(vec![]));
+        results::write_outcomes(
+            written.clone(),
+            "the test log".into(),
+            receiver,
+            |written, event| {
+                std::thread::sleep(Duration::from_millis(1));
+                written.lock().unwrap().push(event.game_uid);
+                Ok(())
+            },
+        )
+        .await;
+        assert_eq!(vec![event.game_uid; GAMES], *written.lock().unwrap());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_forfeit_on_disconnection() {
+        let (context, board, deck) = load_test_data();
+        let (outcome_sender, mut outcome_receiver) = mpsc::unbounded_channel();
+        let host = start_test_server(
+            context,
+            board.clone(),
+            TimeControl::Infinite,
+            outcome_sender,
+        )
+        .await;
+
+        // The session is freed after the forfeit so the server keeps serving games.
+        for game in 0..2 {
+            let south = play_passing_client(host.clone(), "south", deck.clone());
+            let north = play_leaving_client(host.clone(), "north", deck.clone());
+            let (scores, ()) = tokio::join!(south, north);
+            assert_eq!(Some(PlayerId::North), scores.forfeit, "game: {}", game);
+
+            let event = outcome_receiver.recv().await.unwrap();
+            let outcome = &event.outcome;
+            assert_eq!(Some(engine::PlayerId::North), outcome.forfeit);
+            assert_eq!(Some(engine::PlayerId::South), outcome.get_winner());
+            assert_eq!(0, outcome.turns_played);
+            // The deck of North is unknown since it left before accepting hands.
+            let mut south_deck = event.record.decks[0].clone();
+            south_deck.sort();
+            assert_eq!(deck, south_deck);
+            assert!(event.record.decks[1].is_empty());
+            assert_eq!(Ok(()), event.record.verify(engine::RuleSet::default()));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_forfeit_on_flag_fall() {
+        let (context, board, deck) = load_test_data();
+        let (outcome_sender, mut outcome_receiver) = mpsc::unbounded_channel();
+        let time_control = TimeControl::Increment {
+            base_seconds: 1,
+            increment_seconds: 0,
+        };
+        let host = start_test_server(context, board, time_control, outcome_sender).await;
+
+        // The first action is in time thanks to the margin for the network latency but it uses up
+        // the clock. North runs out of the clock at the second turn.
+        let delay = Duration::from_millis(1200);
+        let south = play_passing_client(host.clone(), "south", deck.clone());
+        let north = play_slow_client(host.clone(), "north", 0, deck.clone(), delay);
+        let (scores, north_res) = tokio::join!(south, north);
+        assert_eq!(Some(PlayerId::North), scores.forfeit);
+        match north_res {
+            TakoyakiResponse::Error(res) => assert_eq!(ErrorCode::Timeout, res.code),
+            res => panic!("Unexpected response: {:?}", res),
+        }
+
+        let outcome = outcome_receiver.recv().await.unwrap().outcome;
+        assert_eq!(Some(engine::PlayerId::South), outcome.get_winner());
+        assert_eq!(1, outcome.turns_played);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_forfeit_on_card_not_in_hand() {
+        let (context, board, deck) = load_test_data();
+        let (outcome_sender, mut outcome_receiver) = mpsc::unbounded_channel();
+        let host = start_test_server(context, board, TimeControl::Infinite, outcome_sender).await;
+
+        // A card left in the deck, and a card which doesn't exist at all.
+        for illegal_card in [None, Some(9999)] {
+            let south = play_passing_client(host.clone(), "south", deck.clone());
+            let north = async {
+                let (mut conn, hands) =
+                    join_test_game(host.clone(), "north", 0, deck.clone()).await;
+                let card_id = illegal_card
+                    .unwrap_or_else(|| *deck.iter().find(|id| !hands.contains(id)).unwrap());
+                conn.send(&TakoyakiRequest::SelectAction(SelectActionRequest {
+                    action: Action::Pass(card_id),
+                }))
+                .await
+                .unwrap();
+                conn.recv::<TakoyakiResponse>().await.unwrap()
+            };
+            let (scores, north_res) = tokio::join!(south, north);
+            assert_eq!(Some(PlayerId::North), scores.forfeit);
+            match north_res {
+                TakoyakiResponse::Error(res) => assert_eq!(ErrorCode::IllegalAction, res.code),
+                res => panic!("Unexpected response: {:?}", res),
+            }
+
+            // The game is recorded as usual.
+            let event = outcome_receiver.recv().await.unwrap();
+            assert_eq!(Some(engine::PlayerId::North), event.outcome.forfeit);
+            assert_eq!(0, event.outcome.turns_played);
+            assert_eq!(Ok(()), event.record.verify(engine::RuleSet::default()));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_protocol_version() {
+        let (context, board, _) = load_test_data();
+        let (outcome_sender, _) = mpsc::unbounded_channel();
+        let host = start_test_server(context, board, TimeControl::Infinite, outcome_sender).await;
+
+        // Clients older than the negotiation are still served.
+        let (_, res) = send_test_manmenmi(host.clone(), "old", 0).await;
+        match res {
+            TakoyakiResponse::Manmenmi(res) => assert_eq!(0, res.protocol_version),
+            res => panic!("Unexpected response: {:?}", res),
+        }
+
+        // Newer clients fall back to the version of the server.
+        let south = send_test_manmenmi(host.clone(), "south", CURRENT_PROTOCOL_VERSION);
+        let north = send_test_manmenmi(host.clone(), "north", CURRENT_PROTOCOL_VERSION + 1);
+        let ((_, south_res), (_, north_res)) = tokio::join!(south, north);
+        for res in [south_res, north_res] {
+            match res {
+                TakoyakiResponse::Manmenmi(res) => {
+                    assert_eq!(CURRENT_PROTOCOL_VERSION, res.protocol_version)
+                }
+                res => panic!("Unexpected response: {:?}", res),
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_invalid_join_game() {
+        let (context, board, deck) = load_test_data();
+        let (outcome_sender, mut outcome_receiver) = mpsc::unbounded_channel();
+        let host = start_test_server(context, board, TimeControl::Infinite, outcome_sender).await;
+
+        let mut unknown = deck.clone();
+        unknown[0] = 9999;
+        let mut duplicated = deck.clone();
+        duplicated[0] = duplicated[1];
+        for (game_id, invalid_deck, error) in [
+            (0, deck[1..].to_vec(), "Invalid deck: The deck has 14 cards"),
+            (0, unknown, "Invalid deck: Unknown card ID: 9999"),
+            (0, duplicated, "Invalid deck: The card"),
+            (1, deck.clone(), "Unknown game ID: 1"),
+        ] {
+            let (mut conn, _) =
+                send_test_manmenmi(host.clone(), "invalid", CURRENT_PROTOCOL_VERSION).await;
+            conn.send(&TakoyakiRequest::JoinGame(JoinGameRequest {
+                game_id,
+                deck: invalid_deck,
+                board_delta: false,
+            }))
+            .await
+            .unwrap();
+            match conn.recv::<TakoyakiResponse>().await.unwrap() {
+                TakoyakiResponse::Error(res) => {
+                    assert_eq!(ErrorCode::BadRequest, res.code);
+                    assert!(res.message.starts_with(error), "{}", res.message);
+                }
+                res => panic!("Unexpected response: {:?}", res),
+            }
+        }
+
+        // Rejected clients never wait for a match.
+        let south = play_passing_client(host.clone(), "south", deck.clone());
+        let north = play_passing_client(host.clone(), "north", deck.clone());
+        let (scores, _) = tokio::join!(south, north);
+        assert_eq!(None, scores.forfeit);
+        let outcome = outcome_receiver.recv().await.unwrap().outcome;
+        assert_eq!("north", outcome.north.name);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_spectate() {
+        let (context, board, deck) = load_test_data();
+        let (outcome_sender, mut outcome_receiver) = mpsc::unbounded_channel();
+        let host = start_test_server(context, board, TimeControl::Infinite, outcome_sender).await;
+
+        let (mut spectator, _) =
+            send_test_manmenmi(host.clone(), "spectator", CURRENT_PROTOCOL_VERSION).await;
+        spectator
+            .send(&TakoyakiRequest::Spectate(SpectateRequest {
+                game_id: 0,
+            }))
+            .await
+            .unwrap();
+
+        let south = play_passing_client(host.clone(), "south", deck.clone());
+        let north = play_passing_client(host.clone(), "north", deck.clone());
+        let (scores, _) = tokio::join!(south, north);
+        let event = outcome_receiver.recv().await.unwrap();
+
+        let mut updates = vec![];
+        while let Ok(res) = spectator.recv::<TakoyakiResponse>().await {
+            match res {
+                TakoyakiResponse::StateUpdate(update) => updates.push(update),
+                res => panic!("Unexpected response: {:?}", res),
+            }
+        }
+        assert_eq!(engine::TURN_COUNT as usize, updates.len());
+        for (turn, update) in updates.iter().enumerate() {
+            assert_eq!(event.game_uid, update.game_uid);
+            assert_eq!(turn as u32 + 1, update.turn);
+            assert!(matches!(update.south_action, Some(Action::Pass(_))));
+        }
+        let last = updates.last().unwrap();
+        assert_eq!(Some(scores), last.game_result);
+        assert_eq!(proto::Board::from(&event.board), last.board);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_spectate_forfeit() {
+        let (context, board, deck) = load_test_data();
+        let (outcome_sender, _) = mpsc::unbounded_channel();
+        let host = start_test_server(context, board, TimeControl::Infinite, outcome_sender).await;
+
+        let mut spectators = vec![];
+        for version in [
+            CURRENT_PROTOCOL_VERSION,
+            FORFEIT_UPDATE_PROTOCOL_VERSION - 1,
+        ] {
+            let (mut spectator, _) = send_test_manmenmi(host.clone(), "spectator", version).await;
+            spectator
+                .send(&TakoyakiRequest::Spectate(SpectateRequest {
+                    game_id: 0,
+                }))
+                .await
+                .unwrap();
+            spectators.push(spectator);
+        }
+        // Leaves before the game starts.
+        let (mut leaving, _) =
+            send_test_manmenmi(host.clone(), "leaving", CURRENT_PROTOCOL_VERSION).await;
+        leaving
+            .send(&TakoyakiRequest::Spectate(SpectateRequest {
+                game_id: 0,
+            }))
+            .await
+            .unwrap();
+        drop(leaving);
+
+        let south = play_passing_client(host.clone(), "south", deck.clone());
+        let north = play_leaving_client(host.clone(), "north", deck.clone());
+        let (scores, ()) = tokio::join!(south, north);
+
+        let mut updates = vec![];
+        while let Ok(res) = spectators[0].recv::<TakoyakiResponse>().await {
+            match res {
+                TakoyakiResponse::StateUpdate(update) => updates.push(update),
+                res => panic!("Unexpected response: {:?}", res),
+            }
+        }
+        assert_eq!(1, updates.len());
+        assert_eq!(0, updates[0].turn);
+        assert!(matches!(updates[0].south_action, Some(Action::Pass(_))));
+        assert_eq!(None, updates[0].north_action);
+        assert_eq!(Some(scores), updates[0].game_result);
+        // An older spectator can't parse the update without actions.
+        assert!(spectators[1].recv::<TakoyakiResponse>().await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_graceful_shutdown() {
+        let (context, board, deck) = load_test_data();
+        let lobby = Arc::new(Lobby::new(
+            Arc::new(context),
+            vec![board],
+            TimeControl::Infinite,
+        ));
+        let (outcome_sender, mut outcome_receiver) = mpsc::unbounded_channel();
+        let session_loop = create_session_loop(
+            lobby.clone(),
+            42,
+            Box::new(FifoMatchmaker),
+            None,
+            Arc::new(Mutex::new(StatsCounter::new())),
+            vec![outcome_sender],
+        )
+        .await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+        let acceptor = tokio::spawn(accept_clients(
+            listener,
+            lobby,
+            session_loop.get_client_sender().clone(),
+            Mt64::new(42),
+            async move {
+                shutdown_receiver.await.unwrap();
+            },
+        ));
+
+        let south = join_test_game(host.clone(), "south", 0, deck.clone());
+        let north = join_test_game(host.clone(), "north", 0, deck.clone());
+        let ((south, south_hands), (north, north_hands)) = tokio::join!(south, north);
+
+        shutdown_sender.send(()).unwrap();
+        acceptor.await.unwrap();
+        assert!(TcpStream::connect(&host).await.is_err());
+
+        // The shutdown waits for the game in progress.
+        let delay = Duration::from_millis(10);
+        let south = finish_slow_game(south, south_hands, delay);
+        let north = finish_slow_game(north, north_hands, delay);
+        let shutdown = session_loop.shutdown(Duration::from_secs(10));
+        let (south_res, _, finished) = tokio::join!(south, north, shutdown);
+        assert!(finished);
+        match south_res {
+            TakoyakiResponse::SelectAction(res) => {
+                assert_eq!(None, res.game_result.unwrap().forfeit)
+            }
+            res => panic!("Unexpected response: {:?}", res),
+        }
+        let outcome = outcome_receiver.recv().await.unwrap().outcome;
+        assert_eq!(engine::TURN_COUNT as u32, outcome.turns_played);
+        assert!(outcome_receiver.recv().await.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_max_sessions() {
+        let (context, board, deck) = load_test_data();
+        let lobby = Lobby::new(Arc::new(context), vec![board], TimeControl::Infinite);
+        let (outcome_sender, mut outcome_receiver) = mpsc::unbounded_channel();
+        let host = start_test_lobby(lobby, Some(1), outcome_sender).await;
+
+        let south = join_test_game(host.clone(), "south", 0, deck.clone());
+        let north = join_test_game(host.clone(), "north", 0, deck.clone());
+        let ((south, south_hands), (north, north_hands)) = tokio::join!(south, north);
+
+        // The second pair is rejected while the first game is in progress.
+        let a = send_test_join_game(host.clone(), "a", 0, deck.clone());
+        let b = async {
+            let (mut conn, _) =
+                send_test_manmenmi(host.clone(), "b", SERVER_BUSY_PROTOCOL_VERSION - 1).await;
+            conn.send(&TakoyakiRequest::JoinGame(JoinGameRequest {
+                game_id: 0,
+                deck: deck.clone(),
+                board_delta: false,
+            }))
+            .await
+            .unwrap();
+            conn.recv().await.unwrap()
+        };
+        let ((_, a_res), b_res) = tokio::join!(a, b);
+        match a_res {
+            TakoyakiResponse::Error(res) => assert_eq!(ErrorCode::ServerBusy, res.code),
+            res => panic!("Unexpected response: {:?}", res),
+        }
+        // The older client doesn't know `ServerBusy`.
+        match b_res {
+            TakoyakiResponse::Error(res) => assert_eq!(ErrorCode::BadRequest, res.code),
+            res => panic!("Unexpected response: {:?}", res),
+        }
+
+        let south = finish_slow_game(south, south_hands, Duration::ZERO);
+        let north = finish_slow_game(north, north_hands, Duration::ZERO);
+        tokio::join!(south, north);
+        outcome_receiver.recv().await.unwrap();
+
+        // The session is freed once the game ends.
+        let a = play_passing_client(host.clone(), "a", deck.clone());
+        let b = play_passing_client(host.clone(), "b", deck.clone());
+        let (scores, _) = tokio::join!(a, b);
+        assert_eq!(None, scores.forfeit);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_keepalive() {
+        let (context, board, deck) = load_test_data();
+        let interval = Duration::from_millis(50);
+        let lobby = Lobby::new(Arc::new(context), vec![board], TimeControl::Infinite)
+            .with_keepalive_interval(Some(interval));
+        let (outcome_sender, mut outcome_receiver) = mpsc::unbounded_channel();
+        let host = start_test_lobby(lobby, None, outcome_sender).await;
+
+        // Clients thinking longer than the interval are alive as long as they answer pings.
+        let south = play_passing_client(host.clone(), "south", deck.clone());
+        let north = play_pong_client(host.clone(), "north", deck.clone(), interval * 3);
+        let (_, (scores, pings)) = tokio::join!(south, north);
+        assert_eq!(None, scores.forfeit);
+        assert!(pings >= engine::TURN_COUNT as u32, "pings: {}", pings);
+        outcome_receiver.recv().await.unwrap();
+
+        // North stops reading, so it never answers pings.
+        let south = play_passing_client(host.clone(), "south", deck.clone());
+        let north = join_test_game(host.clone(), "north", 0, deck.clone());
+        let (scores, (mut north, _)) = tokio::join!(south, north);
+        assert_eq!(Some(PlayerId::North), scores.forfeit);
+        let outcome = outcome_receiver.recv().await.unwrap().outcome;
+        assert_eq!(0, outcome.turns_played);
+        loop {
+            match north.recv().await.unwrap() {
+                TakoyakiResponse::Ping(_) => {}
+                TakoyakiResponse::Error(res) => {
+                    assert_eq!(ErrorCode::Timeout, res.code);
+                    break;
+                }
+                res => panic!("Unexpected response: {:?}", res),
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_games_per_board() {
+        let (context, board, deck) = load_test_data();
+        let data_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../data");
+        let another_board = engine::load_board(&data_dir.join("boards/kaminari_station"));
+        let lobby = Lobby::new(
+            Arc::new(context),
+            vec![board.clone(), another_board.clone()],
+            TimeControl::Infinite,
+        );
+        let (outcome_sender, mut outcome_receiver) = mpsc::unbounded_channel();
+        let host = start_test_lobby(lobby, None, outcome_sender).await;
+
+        let (_, res) = send_test_manmenmi(host.clone(), "viewer", CURRENT_PROTOCOL_VERSION).await;
+        match res {
+            TakoyakiResponse::Manmenmi(res) => {
+                let games: Vec<(GameId, String)> = res
+                    .available_games
+                    .into_iter()
+                    .map(|game| (game.game_id, game.board.name))
+                    .collect();
+                assert_eq!(
+                    vec![
+                        (0, board.get_name().to_string()),
+                        (1, another_board.get_name().to_string())
+                    ],
+                    games
+                );
+            }
+            res => panic!("Unexpected response: {:?}", res),
+        }
+
+        // "b" chooses the other board, so "a" and "c" play together.
+        let b = tokio::spawn(play_passing_client_in(host.clone(), "b", 1, deck.clone()));
+        let a = play_passing_client_in(host.clone(), "a", 0, deck.clone());
+        let c = play_passing_client_in(host.clone(), "c", 0, deck.clone());
+        tokio::join!(a, c);
+        let event = outcome_receiver.recv().await.unwrap();
+        assert_eq!(board.get_name(), event.board.get_name());
+        let mut names = [event.outcome.south.name, event.outcome.north.name];
+        names.sort();
+        assert_eq!(["a", "c"], names);
+        assert!(!b.is_finished());
+
+        let d = play_passing_client_in(host.clone(), "d", 1, deck.clone());
+        let (b, _) = tokio::join!(b, d);
+        assert_eq!(None, b.unwrap().forfeit);
+        let event = outcome_receiver.recv().await.unwrap();
+        assert_eq!(another_board.get_name(), event.board.get_name());
+    }
+}
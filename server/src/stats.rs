@@ -1,30 +1,21 @@
 use std::{
+    cmp::Ordering,
     collections::HashMap,
     fmt::Display,
 };
 
 use itertools::Itertools;
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd)]
-pub struct NamedScore {
-    pub name: String,
-    pub score: u32,
-}
+pub use engine::NamedScore;
+use engine::{
+    GameOutcome,
+    PlayerId,
+};
 
-impl NamedScore {
-    pub fn new(name: &str, score: u32) -> Self {
-        Self {
-            name: name.to_string(),
-            score,
-        }
-    }
-}
+use crate::matchmaker::DEFAULT_RATING;
 
-impl Display for NamedScore {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}: ({})", self.name, self.score)
-    }
-}
+/// The K-factor of ELO ratings, i.e. the maximum change of a rating by a game.
+pub const DEFAULT_ELO_K: f64 = 32.0;
 
 struct Stats {
     pub win: u32,
@@ -39,6 +30,10 @@ pub struct StatsCounter {
 
     // per player
     totals: HashMap<String, Stats>,
+
+    // ELO rating per player
+    ratings: HashMap<String, f64>,
+    elo_k: f64,
 }
 
 impl StatsCounter {
@@ -46,58 +41,103 @@ impl StatsCounter {
         Self {
             counts: HashMap::new(),
             totals: HashMap::new(),
+            ratings: HashMap::new(),
+            elo_k: DEFAULT_ELO_K,
         }
     }
 
+    pub fn with_elo_k(mut self, elo_k: f64) -> Self {
+        self.elo_k = elo_k;
+        self
+    }
+
+    /// The ELO rating of the player. Players who have never played start at `DEFAULT_RATING`.
+    pub fn get_rating(&self, name: &str) -> f64 {
+        self.ratings.get(name).copied().unwrap_or(DEFAULT_RATING)
+    }
+
     pub fn push_result(&mut self, a: &NamedScore, b: &NamedScore) {
+        self.push_ordering(&a.name, &b.name, a.score.cmp(&b.score));
+    }
+
+    /// Counts a game by its winner, so a player who forfeited loses even if it had more cells.
+    pub fn push_outcome(&mut self, outcome: &GameOutcome) {
+        let ordering = match outcome.get_winner() {
+            Some(PlayerId::South) => Ordering::Greater,
+            Some(PlayerId::North) => Ordering::Less,
+            None => Ordering::Equal,
+        };
+        self.push_ordering(&outcome.south.name, &outcome.north.name, ordering);
+    }
+
+    /// `ordering` is `Greater` if `a` won.
+    fn push_ordering(&mut self, a: &str, b: &str, ordering: Ordering) {
         // We need a consistent player order.
-        if a.name > b.name {
-            self.push_result(b, a);
+        if a > b {
+            self.push_ordering(b, a, ordering.reverse());
             return;
         }
 
-        let key = (a.name.clone(), b.name.clone());
+        let key = (a.to_string(), b.to_string());
         let mut entry_pair = self.counts.entry(key).or_insert(Stats {
             win: 0,
             draw: 0,
             lose: 0,
         });
-        let mut entry_total_0 = self.totals.entry(a.name.clone()).or_insert(Stats {
+        let mut entry_total_0 = self.totals.entry(a.to_string()).or_insert(Stats {
             win: 0,
             draw: 0,
             lose: 0,
         });
-        match a.score.cmp(&b.score) {
-            std::cmp::Ordering::Less => {
+        match ordering {
+            Ordering::Less => {
                 entry_pair.lose += 1;
                 entry_total_0.lose += 1;
             }
-            std::cmp::Ordering::Equal => {
+            Ordering::Equal => {
                 entry_pair.draw += 1;
                 entry_total_0.draw += 1;
             }
-            std::cmp::Ordering::Greater => {
+            Ordering::Greater => {
                 entry_pair.win += 1;
                 entry_total_0.win += 1;
             }
         };
 
-        let mut entry_total_1 = self.totals.entry(b.name.clone()).or_insert(Stats {
+        let mut entry_total_1 = self.totals.entry(b.to_string()).or_insert(Stats {
             win: 0,
             draw: 0,
             lose: 0,
         });
-        match a.score.cmp(&b.score) {
-            std::cmp::Ordering::Less => {
+        match ordering {
+            Ordering::Less => {
                 entry_total_1.win += 1;
             }
-            std::cmp::Ordering::Equal => {
+            Ordering::Equal => {
                 entry_total_1.draw += 1;
             }
-            std::cmp::Ordering::Greater => {
+            Ordering::Greater => {
                 entry_total_1.lose += 1;
             }
         }
+
+        self.update_ratings(a, b, ordering);
+    }
+
+    /// Moves ratings of `a` and `b` toward the result by the standard expected-score formula.
+    /// `a` gains exactly what `b` loses.
+    fn update_ratings(&mut self, a: &str, b: &str, ordering: Ordering) {
+        let score_a = match ordering {
+            Ordering::Less => 0.0,
+            Ordering::Equal => 0.5,
+            Ordering::Greater => 1.0,
+        };
+        let rating_a = self.get_rating(a);
+        let rating_b = self.get_rating(b);
+        let expected_a = 1.0 / (1.0 + 10f64.powf((rating_b - rating_a) / 400.0));
+        let delta = self.elo_k * (score_a - expected_a);
+        *self.ratings.entry(a.to_string()).or_insert(DEFAULT_RATING) += delta;
+        *self.ratings.entry(b.to_string()).or_insert(DEFAULT_RATING) -= delta;
     }
 }
 
@@ -130,7 +170,47 @@ impl Display for StatsCounter {
         {
             writeln!(f, r#"  {:<20} | {:.3}"#, k, v)?;
         }
+        writeln!(f, "* Ratings")?;
+        for (k, v) in self
+            .ratings
+            .iter()
+            .sorted_by(|a, b| b.1.partial_cmp(a.1).unwrap())
+        {
+            writeln!(f, r#"  {:<20} | {:.1}"#, k, v)?;
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elo_ratings() {
+        let mut stats = StatsCounter::new().with_elo_k(16.0);
+        let winner = NamedScore::new("winner", 10);
+        let loser = NamedScore::new("loser", 5);
+        let mut last_rating = DEFAULT_RATING;
+        for _ in 0..10 {
+            stats.push_result(&loser, &winner);
+            let rating = stats.get_rating("winner");
+            assert!(rating > last_rating, "{} -> {}", last_rating, rating);
+            let loser_rating = stats.get_rating("loser");
+            assert!((DEFAULT_RATING * 2.0 - rating - loser_rating).abs() < 1e-9);
+            last_rating = rating;
+        }
+        // The first win against an equal-rated opponent is worth half of the K-factor.
+        let mut stats = StatsCounter::new().with_elo_k(16.0);
+        stats.push_result(&winner, &loser);
+        assert_eq!(DEFAULT_RATING + 8.0, stats.get_rating("winner"));
+
+        // A draw between equals changes nothing.
+        let draw = NamedScore::new("another", 10);
+        stats.push_result(&draw, &NamedScore::new("newcomer", 10));
+        assert_eq!(DEFAULT_RATING, stats.get_rating("another"));
+        assert_eq!(DEFAULT_RATING, stats.get_rating("newcomer"));
+        assert_eq!(DEFAULT_RATING, stats.get_rating("unknown"));
+    }
+}